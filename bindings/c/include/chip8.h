@@ -0,0 +1,118 @@
+/* Generated by cbindgen from bindings/c/src/lib.rs, do not edit. */
+
+#ifndef CHIP8_H
+#define CHIP8_H
+
+#include <stdarg.h>
+#include <stdbool.h>
+#include <stddef.h>
+#include <stdint.h>
+#include <stdlib.h>
+
+#define CHIP8_WIDTH 64
+
+#define CHIP8_HEIGHT 32
+
+#define CHIP8_MEMORY_SIZE 4096
+
+typedef enum {
+  CHIP8_OK = 0,
+  // The ROM faulted, or does not fit in memory.
+  CHIP8_ERROR,
+  // An address, key, register or timing out of range.
+  CHIP8_OUT_OF_RANGE,
+} chip8_status_t;
+
+// Values of chip8_set_timing.
+typedef enum {
+  CHIP8_TIMING_FIXED = 0,
+  CHIP8_TIMING_COSMAC_VIP = 1,
+} chip8_timing_t;
+
+// Opaque handle of a machine.
+typedef struct chip8_t chip8_t;
+
+// Opaque saved state of a machine.
+typedef struct chip8_snapshot_t chip8_snapshot_t;
+
+// The quirks of the emulator configuration, see the README.
+typedef struct {
+  bool shift;
+  bool memory_increment;
+  bool jump;
+  bool vf_reset;
+  bool clip;
+} chip8_quirks_t;
+
+typedef struct {
+  uint8_t v[16];
+  uint16_t i;
+  uint16_t pc;
+  uint8_t delay_timer;
+  uint8_t sound_timer;
+} chip8_registers_t;
+
+#ifdef __cplusplus
+extern "C" {
+#endif // __cplusplus
+
+// Create a machine with the default quirks and timing, to be freed with chip8_destroy().
+chip8_t *chip8_create(void);
+
+// Free a machine. NULL is ignored.
+void chip8_destroy(chip8_t *machine);
+
+// The message of the last failure, valid until the next call with this machine.
+const char *chip8_last_error(const chip8_t *machine);
+
+void chip8_set_quirks(chip8_t *machine, chip8_quirks_t quirks);
+
+// Set the timing to one of chip8_timing_t, taken as an integer as C may pass any value.
+chip8_status_t chip8_set_timing(chip8_t *machine, uint32_t timing);
+
+// Seed the random generator of CXNN, for reproducible runs.
+void chip8_seed(chip8_t *machine, uint64_t seed);
+
+// Copy `len` bytes of ROM at 0x200.
+chip8_status_t chip8_load_rom(chip8_t *machine, const uint8_t *rom, size_t len);
+
+// Execute one instruction.
+chip8_status_t chip8_step(chip8_t *machine);
+
+// Run one 60 Hz frame of `ipf` instructions, then update the timers.
+chip8_status_t chip8_run_frame(chip8_t *machine, uint32_t ipf);
+
+chip8_status_t chip8_set_key(chip8_t *machine, uint8_t key, bool pressed);
+
+// Copy the screen to `rows`, CHIP8_HEIGHT rows with the leftmost pixel in the most significant
+// bit.
+void chip8_framebuffer(const chip8_t *machine, uint64_t *rows);
+
+chip8_status_t chip8_read_memory(chip8_t *machine, uint16_t address, uint8_t *buffer, size_t len);
+
+chip8_status_t chip8_write_memory(chip8_t *machine,
+                                  uint16_t address,
+                                  const uint8_t *data,
+                                  size_t len);
+
+void chip8_get_registers(const chip8_t *machine, chip8_registers_t *registers);
+
+void chip8_set_registers(chip8_t *machine, const chip8_registers_t *registers);
+
+// Number of executed instructions.
+uint64_t chip8_cycles(const chip8_t *machine);
+
+// Save the whole state of a machine, to be freed with chip8_snapshot_destroy().
+chip8_snapshot_t *chip8_snapshot(const chip8_t *machine);
+
+// Restore a saved state, which stays valid to be restored again.
+void chip8_restore(chip8_t *machine, const chip8_snapshot_t *snapshot);
+
+// Free a snapshot. NULL is ignored.
+void chip8_snapshot_destroy(chip8_snapshot_t *snapshot);
+
+#ifdef __cplusplus
+}  // extern "C"
+#endif  // __cplusplus
+
+#endif  /* CHIP8_H */
@@ -0,0 +1,302 @@
+// C interface of the emulator core. include/chip8.h is generated from this file by cbindgen,
+// see cbindgen.toml.
+//
+// Handles and pointers given to these functions must be valid and not NULL, except where noted.
+// The functions which can fail return a chip8_status_t, and chip8_last_error() describes the
+// last failure.
+#![allow(clippy::missing_safety_doc)]
+
+use chip8_core::{Quirks, Timing, HEIGHT, MEM_SIZE, WIDTH};
+use std::ffi::{c_char, CString};
+use std::slice;
+
+pub const CHIP8_WIDTH: usize = 64;
+pub const CHIP8_HEIGHT: usize = 32;
+pub const CHIP8_MEMORY_SIZE: usize = 4096;
+const _: () = assert!(CHIP8_WIDTH == WIDTH && CHIP8_HEIGHT == HEIGHT);
+const _: () = assert!(CHIP8_MEMORY_SIZE == MEM_SIZE);
+
+// The variants carry their C prefix, as cbindgen would prefix them with the renamed type.
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[allow(clippy::enum_variant_names)]
+pub enum Chip8Status {
+    Chip8Ok = 0,
+    /// The ROM faulted, or does not fit in memory.
+    Chip8Error,
+    /// An address, key, register or timing out of range.
+    Chip8OutOfRange,
+}
+
+/// Values of chip8_set_timing.
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[allow(clippy::enum_variant_names)]
+pub enum Chip8Timing {
+    Chip8TimingFixed = 0,
+    Chip8TimingCosmacVip = 1,
+}
+
+/// The quirks of the emulator configuration, see the README.
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub struct Chip8Quirks {
+    pub shift: bool,
+    pub memory_increment: bool,
+    pub jump: bool,
+    pub vf_reset: bool,
+    pub clip: bool,
+}
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Chip8Registers {
+    pub v: [u8; 16],
+    pub i: u16,
+    pub pc: u16,
+    pub delay_timer: u8,
+    pub sound_timer: u8,
+}
+
+/// Opaque handle of a machine.
+pub struct Machine {
+    chip8: chip8_core::Chip8,
+    error: CString,
+}
+
+/// Opaque saved state of a machine.
+pub struct Snapshot {
+    chip8: chip8_core::Chip8,
+}
+
+impl Machine {
+    fn status<T>(&mut self, result: Result<T, chip8_core::Error>) -> Chip8Status {
+        match result {
+            Ok(_) => Chip8Status::Chip8Ok,
+            Err(e) => self.fail(Chip8Status::Chip8Error, e.to_string()),
+        }
+    }
+
+    fn fail(&mut self, status: Chip8Status, message: String) -> Chip8Status {
+        // messages are built by this crate and never hold a NUL
+        self.error = CString::new(message).unwrap_or_default();
+        status
+    }
+
+    fn check_range(&mut self, address: u16, len: usize) -> Result<(), Chip8Status> {
+        if address as usize + len <= MEM_SIZE {
+            Ok(())
+        } else {
+            Err(self.fail(
+                Chip8Status::Chip8OutOfRange,
+                format!("{} bytes at {:#05X} are past the memory", len, address),
+            ))
+        }
+    }
+}
+
+/// Create a machine with the default quirks and timing, to be freed with chip8_destroy().
+#[no_mangle]
+pub extern "C" fn chip8_create() -> *mut Machine {
+    Box::into_raw(Box::new(Machine {
+        chip8: chip8_core::init(),
+        error: CString::default(),
+    }))
+}
+
+/// Free a machine. NULL is ignored.
+#[no_mangle]
+pub unsafe extern "C" fn chip8_destroy(machine: *mut Machine) {
+    if !machine.is_null() {
+        drop(Box::from_raw(machine));
+    }
+}
+
+/// The message of the last failure, valid until the next call with this machine.
+#[no_mangle]
+pub unsafe extern "C" fn chip8_last_error(machine: *const Machine) -> *const c_char {
+    (*machine).error.as_ptr()
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn chip8_set_quirks(machine: *mut Machine, quirks: Chip8Quirks) {
+    (*machine).chip8.set_quirks(Quirks {
+        shift: quirks.shift,
+        memory_increment: quirks.memory_increment,
+        jump: quirks.jump,
+        vf_reset: quirks.vf_reset,
+        clip: quirks.clip,
+    });
+}
+
+/// Set the timing to one of chip8_timing_t, taken as an integer as C may pass any value.
+#[no_mangle]
+pub unsafe extern "C" fn chip8_set_timing(machine: *mut Machine, timing: u32) -> Chip8Status {
+    let machine = &mut *machine;
+    let timing = match timing {
+        t if t == Chip8Timing::Chip8TimingFixed as u32 => Timing::Fixed,
+        t if t == Chip8Timing::Chip8TimingCosmacVip as u32 => Timing::CosmacVip,
+        _ => {
+            let message = format!("no timing {}", timing);
+            return machine.fail(Chip8Status::Chip8OutOfRange, message);
+        }
+    };
+    machine.chip8.set_timing(timing);
+    Chip8Status::Chip8Ok
+}
+
+/// Seed the random generator of CXNN, for reproducible runs.
+#[no_mangle]
+pub unsafe extern "C" fn chip8_seed(machine: *mut Machine, seed: u64) {
+    (*machine).chip8.seed_rng(seed);
+}
+
+/// Copy `len` bytes of ROM at 0x200.
+#[no_mangle]
+pub unsafe extern "C" fn chip8_load_rom(
+    machine: *mut Machine,
+    rom: *const u8,
+    len: usize,
+) -> Chip8Status {
+    let machine = &mut *machine;
+    // an empty ROM may come with a NULL pointer
+    let rom = if len == 0 {
+        Vec::new()
+    } else {
+        slice::from_raw_parts(rom, len).to_vec()
+    };
+    let result = machine.chip8.load_rom(rom);
+    machine.status(result)
+}
+
+/// Execute one instruction.
+#[no_mangle]
+pub unsafe extern "C" fn chip8_step(machine: *mut Machine) -> Chip8Status {
+    let machine = &mut *machine;
+    let result = machine.chip8.tick();
+    machine.status(result)
+}
+
+/// Run one 60 Hz frame of `ipf` instructions, then update the timers.
+#[no_mangle]
+pub unsafe extern "C" fn chip8_run_frame(machine: *mut Machine, ipf: u32) -> Chip8Status {
+    let machine = &mut *machine;
+    let result = machine.chip8.run_frame(ipf);
+    machine.status(result)
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn chip8_set_key(
+    machine: *mut Machine,
+    key: u8,
+    pressed: bool,
+) -> Chip8Status {
+    let machine = &mut *machine;
+    match key {
+        0..=0xF if pressed => machine.chip8.press_key(key as usize),
+        0..=0xF => machine.chip8.release_key(key as usize),
+        _ => return machine.fail(Chip8Status::Chip8OutOfRange, format!("no key {}", key)),
+    }
+    Chip8Status::Chip8Ok
+}
+
+/// Copy the screen to `rows`, CHIP8_HEIGHT rows with the leftmost pixel in the most significant
+/// bit.
+#[no_mangle]
+pub unsafe extern "C" fn chip8_framebuffer(machine: *const Machine, rows: *mut u64) {
+    slice::from_raw_parts_mut(rows, HEIGHT).copy_from_slice((*machine).chip8.gfx_rows());
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn chip8_read_memory(
+    machine: *mut Machine,
+    address: u16,
+    buffer: *mut u8,
+    len: usize,
+) -> Chip8Status {
+    let machine = &mut *machine;
+    if let Err(status) = machine.check_range(address, len) {
+        return status;
+    }
+    let buffer = slice::from_raw_parts_mut(buffer, len);
+    for (i, byte) in buffer.iter_mut().enumerate() {
+        *byte = machine.chip8.memory(address as usize + i);
+    }
+    Chip8Status::Chip8Ok
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn chip8_write_memory(
+    machine: *mut Machine,
+    address: u16,
+    data: *const u8,
+    len: usize,
+) -> Chip8Status {
+    let machine = &mut *machine;
+    if let Err(status) = machine.check_range(address, len) {
+        return status;
+    }
+    for (i, &byte) in slice::from_raw_parts(data, len).iter().enumerate() {
+        machine.chip8.set_memory(address as usize + i, byte);
+    }
+    Chip8Status::Chip8Ok
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn chip8_get_registers(
+    machine: *const Machine,
+    registers: *mut Chip8Registers,
+) {
+    let chip8 = &(*machine).chip8;
+    *registers = Chip8Registers {
+        v: chip8.registers(),
+        i: chip8.index(),
+        pc: chip8.program_counter(),
+        delay_timer: chip8.delay_timer(),
+        sound_timer: chip8.sound_timer(),
+    };
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn chip8_set_registers(
+    machine: *mut Machine,
+    registers: *const Chip8Registers,
+) {
+    let chip8 = &mut (*machine).chip8;
+    let registers = &*registers;
+    for (x, &value) in registers.v.iter().enumerate() {
+        chip8.set_register(x, value);
+    }
+    chip8.set_index(registers.i);
+    chip8.set_program_counter(registers.pc);
+    chip8.set_delay_timer(registers.delay_timer);
+    chip8.set_sound_timer(registers.sound_timer);
+}
+
+/// Number of executed instructions.
+#[no_mangle]
+pub unsafe extern "C" fn chip8_cycles(machine: *const Machine) -> u64 {
+    (*machine).chip8.cycles()
+}
+
+/// Save the whole state of a machine, to be freed with chip8_snapshot_destroy().
+#[no_mangle]
+pub unsafe extern "C" fn chip8_snapshot(machine: *const Machine) -> *mut Snapshot {
+    Box::into_raw(Box::new(Snapshot {
+        chip8: (*machine).chip8.clone(),
+    }))
+}
+
+/// Restore a saved state, which stays valid to be restored again.
+#[no_mangle]
+pub unsafe extern "C" fn chip8_restore(machine: *mut Machine, snapshot: *const Snapshot) {
+    (*machine).chip8.clone_from(&(*snapshot).chip8);
+}
+
+/// Free a snapshot. NULL is ignored.
+#[no_mangle]
+pub unsafe extern "C" fn chip8_snapshot_destroy(snapshot: *mut Snapshot) {
+    if !snapshot.is_null() {
+        drop(Box::from_raw(snapshot));
+    }
+}
@@ -0,0 +1,82 @@
+/* Checks the C interface, built and run by scripts/test-c.sh. */
+#include <assert.h>
+#include <stdio.h>
+#include <string.h>
+
+#include "chip8.h"
+
+/* LD V0, 5 ; LD F, V0 ; DRW V1, V1, 5 ; ADD V2, 1 ; JP 0x206 */
+static const uint8_t ROM[] = {0x60, 0x05, 0xF0, 0x29, 0xD1, 0x15, 0x72, 0x01, 0x12, 0x06};
+
+int main(void) {
+    chip8_t *machine = chip8_create();
+    assert(machine != NULL);
+    assert(chip8_load_rom(machine, ROM, sizeof ROM) == CHIP8_OK);
+
+    /* one instruction */
+    chip8_registers_t registers;
+    assert(chip8_step(machine) == CHIP8_OK);
+    chip8_get_registers(machine, &registers);
+    assert(registers.v[0] == 5);
+    assert(registers.pc == 0x202);
+
+    /* a frame draws the 5 glyph */
+    assert(chip8_run_frame(machine, 10) == CHIP8_OK);
+    uint64_t rows[CHIP8_HEIGHT];
+    chip8_framebuffer(machine, rows);
+    assert(rows[0] == 0xF000000000000000ull);
+    assert(rows[1] == 0x8000000000000000ull);
+    assert(rows[5] == 0);
+    assert(chip8_cycles(machine) == 11);
+
+    /* memory */
+    uint8_t bytes[2] = {0xAB, 0xCD};
+    assert(chip8_write_memory(machine, 0x300, bytes, 2) == CHIP8_OK);
+    memset(bytes, 0, sizeof bytes);
+    assert(chip8_read_memory(machine, 0x300, bytes, 2) == CHIP8_OK);
+    assert(bytes[0] == 0xAB && bytes[1] == 0xCD);
+    assert(chip8_read_memory(machine, 0xFFF, bytes, 2) == CHIP8_OUT_OF_RANGE);
+    assert(strstr(chip8_last_error(machine), "past the memory") != NULL);
+
+    /* keys */
+    assert(chip8_set_key(machine, 5, true) == CHIP8_OK);
+    assert(chip8_set_key(machine, 16, true) == CHIP8_OUT_OF_RANGE);
+
+    /* snapshots */
+    chip8_snapshot_t *snapshot = chip8_snapshot(machine);
+    assert(chip8_run_frame(machine, 10) == CHIP8_OK);
+    chip8_get_registers(machine, &registers);
+    uint8_t v2 = registers.v[2];
+    chip8_restore(machine, snapshot);
+    chip8_get_registers(machine, &registers);
+    assert(registers.v[2] != v2);
+    assert(chip8_run_frame(machine, 10) == CHIP8_OK);
+    chip8_get_registers(machine, &registers);
+    assert(registers.v[2] == v2);
+    chip8_snapshot_destroy(snapshot);
+
+    registers.v[0xF] = 0x42;
+    registers.i = 0x123;
+    chip8_set_registers(machine, &registers);
+    chip8_get_registers(machine, &registers);
+    assert(registers.v[0xF] == 0x42 && registers.i == 0x123);
+
+    /* faults */
+    static const uint8_t BAD[] = {0xFF, 0xFF};
+    chip8_t *bad = chip8_create();
+    chip8_quirks_t quirks = {.shift = false, .memory_increment = false, .jump = false, .vf_reset = true, .clip = true};
+    chip8_set_quirks(bad, quirks);
+    assert(chip8_set_timing(bad, CHIP8_TIMING_COSMAC_VIP) == CHIP8_OK);
+    assert(chip8_set_timing(bad, 2) == CHIP8_OUT_OF_RANGE);
+    assert(strcmp(chip8_last_error(bad), "no timing 2") == 0);
+    chip8_seed(bad, 1);
+    assert(chip8_load_rom(bad, BAD, sizeof BAD) == CHIP8_OK);
+    assert(chip8_run_frame(bad, 10) == CHIP8_ERROR);
+    assert(strcmp(chip8_last_error(bad), "unknown opcode FFFF at 0x200") == 0);
+    chip8_destroy(bad);
+
+    chip8_destroy(machine);
+    chip8_destroy(NULL);
+    puts("ok");
+    return 0;
+}
@@ -0,0 +1,649 @@
+// libretro core, to run ROMs in RetroArch and the other libretro frontends.
+//
+// The 16 keys are on the RetroPad of the first port, the beep is a square wave, and the quirks,
+// speed, timing and palette are core options. Save states are the ones of the core, see
+// Chip8::save_state().
+#![allow(clippy::missing_safety_doc)]
+
+mod libretro;
+
+use chip8_core::{MachineCode, Quirks, Timing, HEIGHT, STATE_SIZE, WIDTH};
+use libretro::*;
+use std::ffi::{c_char, c_uint, c_void, CStr, CString};
+use std::ptr;
+use std::slice;
+use std::sync::Mutex;
+
+const FPS: f64 = 60.0;
+const SAMPLE_RATE: u32 = 44100;
+const SAMPLES_PER_FRAME: usize = SAMPLE_RATE as usize / 60;
+const BEEP_HZ: u32 = 440;
+const BEEP_VOLUME: i16 = 0x1000;
+
+// RetroPad button of each key, with the directions on 2, 4, 6 and 8 as most games use them.
+const KEYPAD: [(c_uint, &CStr); 16] = [
+    (RETRO_DEVICE_ID_JOYPAD_B, c"Key 0"),
+    (RETRO_DEVICE_ID_JOYPAD_Y, c"Key 1"),
+    (RETRO_DEVICE_ID_JOYPAD_UP, c"Key 2 (up)"),
+    (RETRO_DEVICE_ID_JOYPAD_X, c"Key 3"),
+    (RETRO_DEVICE_ID_JOYPAD_LEFT, c"Key 4 (left)"),
+    (RETRO_DEVICE_ID_JOYPAD_A, c"Key 5"),
+    (RETRO_DEVICE_ID_JOYPAD_RIGHT, c"Key 6 (right)"),
+    (RETRO_DEVICE_ID_JOYPAD_L, c"Key 7"),
+    (RETRO_DEVICE_ID_JOYPAD_DOWN, c"Key 8 (down)"),
+    (RETRO_DEVICE_ID_JOYPAD_R, c"Key 9"),
+    (RETRO_DEVICE_ID_JOYPAD_L2, c"Key A"),
+    (RETRO_DEVICE_ID_JOYPAD_R2, c"Key B"),
+    (RETRO_DEVICE_ID_JOYPAD_L3, c"Key C"),
+    (RETRO_DEVICE_ID_JOYPAD_R3, c"Key D"),
+    (RETRO_DEVICE_ID_JOYPAD_SELECT, c"Key E"),
+    (RETRO_DEVICE_ID_JOYPAD_START, c"Key F"),
+];
+
+// Core options, with their default value first.
+const OPTIONS: [(&CStr, &CStr); 9] = [
+    (
+        c"chip8_speed",
+        c"Speed (instructions per second); 500|600|700|800|900|1000|1200|1500|2000|3000|5000|10000|100|200|300|400",
+    ),
+    (c"chip8_timing", c"Timing; fixed|cosmac-vip"),
+    (c"chip8_machine_code", c"0NNN machine code calls; fault|ignore|cdp1802"),
+    (
+        c"chip8_palette",
+        c"Palette; white on black|black on white|green phosphor|amber|blue",
+    ),
+    (c"chip8_quirk_shift", c"8XY6/8XYE shift VX instead of VY; enabled|disabled"),
+    (c"chip8_quirk_memory_increment", c"FX55/FX65 increment I; enabled|disabled"),
+    (c"chip8_quirk_jump", c"BNNN jumps to XNN + VX; disabled|enabled"),
+    (c"chip8_quirk_vf_reset", c"8XY1/8XY2/8XY3 reset VF; disabled|enabled"),
+    (c"chip8_quirk_clip", c"Sprites are clipped instead of wrapped; enabled|disabled"),
+];
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Options {
+    ipf: u32,
+    timing: Timing,
+    machine_code: MachineCode,
+    quirks: Quirks,
+    // background, foreground
+    palette: [u32; 2],
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Options {
+            ipf: ipf(500),
+            timing: Timing::default(),
+            machine_code: MachineCode::default(),
+            quirks: Quirks::default(),
+            palette: [0x000000, 0xFFFFFF],
+        }
+    }
+}
+
+// Instructions per frame of a speed in instructions per second, as in the emulator.
+fn ipf(speed: u32) -> u32 {
+    ((speed as f64 / FPS).round() as u32).max(1)
+}
+
+struct Callbacks {
+    environment: Option<retro_environment_t>,
+    video_refresh: Option<retro_video_refresh_t>,
+    audio_sample_batch: Option<retro_audio_sample_batch_t>,
+    input_poll: Option<retro_input_poll_t>,
+    input_state: Option<retro_input_state_t>,
+}
+
+static CALLBACKS: Mutex<Callbacks> = Mutex::new(Callbacks {
+    environment: None,
+    video_refresh: None,
+    audio_sample_batch: None,
+    input_poll: None,
+    input_state: None,
+});
+
+struct Core {
+    chip8: chip8_core::Chip8,
+    rom: Vec<u8>,
+    options: Options,
+    // the error the ROM stopped on, shown once then kept frozen
+    error: Option<chip8_core::Error>,
+    video: Vec<u32>,
+    audio: Vec<i16>,
+    // samples played since the start, for the phase of the square wave
+    samples: u64,
+}
+
+static CORE: Mutex<Option<Core>> = Mutex::new(None);
+
+fn environment(cmd: c_uint, data: *mut c_void) -> bool {
+    match CALLBACKS.lock().unwrap().environment {
+        Some(environment) => unsafe { environment(cmd, data) },
+        None => false,
+    }
+}
+
+// The value of a core option, None when the frontend does not know it.
+fn variable(key: &CStr) -> Option<String> {
+    let mut variable = retro_variable {
+        key: key.as_ptr(),
+        value: ptr::null(),
+    };
+    if !environment(
+        RETRO_ENVIRONMENT_GET_VARIABLE,
+        &mut variable as *mut _ as *mut c_void,
+    ) || variable.value.is_null()
+    {
+        return None;
+    }
+    Some(
+        unsafe { CStr::from_ptr(variable.value) }
+            .to_string_lossy()
+            .into_owned(),
+    )
+}
+
+fn read_options() -> Options {
+    let mut options = Options::default();
+    if let Some(speed) = variable(c"chip8_speed").and_then(|speed| speed.parse().ok()) {
+        options.ipf = ipf(speed);
+    }
+    if let Some(timing) = variable(c"chip8_timing").and_then(|timing| timing.parse().ok()) {
+        options.timing = timing;
+    }
+    if let Some(machine_code) = variable(c"chip8_machine_code").and_then(|code| code.parse().ok()) {
+        options.machine_code = machine_code;
+    }
+    options.palette = match variable(c"chip8_palette").as_deref() {
+        Some("black on white") => [0xFFFFFF, 0x000000],
+        Some("green phosphor") => [0x0A1A0A, 0x33FF66],
+        Some("amber") => [0x1A1000, 0xFFB000],
+        Some("blue") => [0x000033, 0x66CCFF],
+        _ => options.palette,
+    };
+    let quirk = |key: &CStr, default: bool| match variable(key).as_deref() {
+        Some("enabled") => true,
+        Some("disabled") => false,
+        _ => default,
+    };
+    let default = options.quirks;
+    options.quirks = Quirks {
+        shift: quirk(c"chip8_quirk_shift", default.shift),
+        memory_increment: quirk(c"chip8_quirk_memory_increment", default.memory_increment),
+        jump: quirk(c"chip8_quirk_jump", default.jump),
+        vf_reset: quirk(c"chip8_quirk_vf_reset", default.vf_reset),
+        clip: quirk(c"chip8_quirk_clip", default.clip),
+    };
+    options
+}
+
+fn show_message(text: &str) {
+    let text = CString::new(text).unwrap_or_default();
+    let mut message = retro_message {
+        msg: text.as_ptr(),
+        frames: 180,
+    };
+    environment(
+        RETRO_ENVIRONMENT_SET_MESSAGE,
+        &mut message as *mut _ as *mut c_void,
+    );
+}
+
+impl Core {
+    fn new(rom: Vec<u8>, options: Options) -> Result<Core, chip8_core::Error> {
+        let mut core = Core {
+            chip8: chip8_core::init(),
+            rom,
+            options,
+            error: None,
+            video: vec![0; WIDTH * HEIGHT],
+            audio: vec![0; 2 * SAMPLES_PER_FRAME],
+            samples: 0,
+        };
+        core.reset()?;
+        Ok(core)
+    }
+
+    fn reset(&mut self) -> Result<(), chip8_core::Error> {
+        let mut chip8 = chip8_core::init();
+        chip8.load_rom(self.rom.clone())?;
+        self.chip8 = chip8;
+        self.error = None;
+        self.apply_options();
+        Ok(())
+    }
+
+    fn apply_options(&mut self) {
+        self.chip8.set_quirks(self.options.quirks);
+        self.chip8.set_timing(self.options.timing);
+        self.chip8.set_machine_code(self.options.machine_code);
+    }
+
+    fn run(&mut self, keys: u16) {
+        for key in 0..16 {
+            if keys & 1 << key != 0 {
+                self.chip8.press_key(key);
+            } else {
+                self.chip8.release_key(key);
+            }
+        }
+        let mut sound = false;
+        if self.error.is_none() {
+            match self.chip8.run_frame(self.options.ipf) {
+                Ok(active) => sound = active,
+                Err(e) => {
+                    show_message(&format!("{}, the emulation is stopped", e));
+                    self.error = Some(e);
+                }
+            }
+        }
+
+        let [background, foreground] = self.options.palette;
+        for (y, row) in self.chip8.gfx_rows().iter().enumerate() {
+            for x in 0..WIDTH {
+                let lit = row >> (WIDTH - 1 - x) & 1 != 0;
+                self.video[y * WIDTH + x] = if lit { foreground } else { background };
+            }
+        }
+
+        let half_period = (SAMPLE_RATE / BEEP_HZ / 2) as u64;
+        for frame in self.audio.chunks_mut(2) {
+            let high = (self.samples / half_period).is_multiple_of(2);
+            let sample = match (sound, high) {
+                (false, _) => 0,
+                (true, true) => BEEP_VOLUME,
+                (true, false) => -BEEP_VOLUME,
+            };
+            frame.fill(sample);
+            self.samples += 1;
+        }
+    }
+}
+
+#[no_mangle]
+pub extern "C" fn retro_api_version() -> c_uint {
+    RETRO_API_VERSION
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn retro_set_environment(callback: retro_environment_t) {
+    CALLBACKS.lock().unwrap().environment = Some(callback);
+    let mut variables: Vec<retro_variable> = OPTIONS
+        .iter()
+        .map(|(key, value)| retro_variable {
+            key: key.as_ptr(),
+            value: value.as_ptr(),
+        })
+        .collect();
+    variables.push(retro_variable {
+        key: ptr::null(),
+        value: ptr::null(),
+    });
+    callback(
+        RETRO_ENVIRONMENT_SET_VARIABLES,
+        variables.as_mut_ptr() as *mut c_void,
+    );
+}
+
+#[no_mangle]
+pub extern "C" fn retro_set_video_refresh(callback: retro_video_refresh_t) {
+    CALLBACKS.lock().unwrap().video_refresh = Some(callback);
+}
+
+// Audio goes out a frame at a time, with the batch callback.
+#[no_mangle]
+pub extern "C" fn retro_set_audio_sample(_callback: retro_audio_sample_t) {}
+
+#[no_mangle]
+pub extern "C" fn retro_set_audio_sample_batch(callback: retro_audio_sample_batch_t) {
+    CALLBACKS.lock().unwrap().audio_sample_batch = Some(callback);
+}
+
+#[no_mangle]
+pub extern "C" fn retro_set_input_poll(callback: retro_input_poll_t) {
+    CALLBACKS.lock().unwrap().input_poll = Some(callback);
+}
+
+#[no_mangle]
+pub extern "C" fn retro_set_input_state(callback: retro_input_state_t) {
+    CALLBACKS.lock().unwrap().input_state = Some(callback);
+}
+
+#[no_mangle]
+pub extern "C" fn retro_init() {}
+
+#[no_mangle]
+pub extern "C" fn retro_deinit() {
+    *CORE.lock().unwrap() = None;
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn retro_get_system_info(info: *mut retro_system_info) {
+    *info = retro_system_info {
+        library_name: c"chip8".as_ptr(),
+        library_version: c"0.1.0".as_ptr(),
+        valid_extensions: c"ch8|c8".as_ptr(),
+        need_fullpath: false,
+        block_extract: false,
+    };
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn retro_get_system_av_info(info: *mut retro_system_av_info) {
+    *info = retro_system_av_info {
+        geometry: retro_game_geometry {
+            base_width: WIDTH as c_uint,
+            base_height: HEIGHT as c_uint,
+            max_width: WIDTH as c_uint,
+            max_height: HEIGHT as c_uint,
+            aspect_ratio: WIDTH as f32 / HEIGHT as f32,
+        },
+        timing: retro_system_timing {
+            fps: FPS,
+            sample_rate: SAMPLE_RATE as f64,
+        },
+    };
+}
+
+#[no_mangle]
+pub extern "C" fn retro_set_controller_port_device(_port: c_uint, _device: c_uint) {}
+
+#[no_mangle]
+pub extern "C" fn retro_reset() {
+    if let Some(core) = CORE.lock().unwrap().as_mut() {
+        // the ROM was loaded once already, it fits
+        let _ = core.reset();
+    }
+}
+
+#[no_mangle]
+pub extern "C" fn retro_run() {
+    let callbacks = {
+        let callbacks = CALLBACKS.lock().unwrap();
+        (
+            callbacks.video_refresh,
+            callbacks.audio_sample_batch,
+            callbacks.input_poll,
+            callbacks.input_state,
+        )
+    };
+    let (Some(video_refresh), Some(audio_sample_batch), Some(input_poll), Some(input_state)) =
+        callbacks
+    else {
+        return;
+    };
+
+    let mut updated = false;
+    environment(
+        RETRO_ENVIRONMENT_GET_VARIABLE_UPDATE,
+        &mut updated as *mut bool as *mut c_void,
+    );
+    let options = updated.then(read_options);
+
+    let mut core = CORE.lock().unwrap();
+    let Some(core) = core.as_mut() else {
+        return;
+    };
+    if let Some(options) = options {
+        core.options = options;
+        core.apply_options();
+    }
+    unsafe {
+        input_poll();
+        let keys = KEYPAD
+            .iter()
+            .enumerate()
+            .filter(|(_, (id, _))| input_state(0, RETRO_DEVICE_JOYPAD, 0, *id) != 0)
+            .fold(0u16, |keys, (key, _)| keys | 1 << key);
+        core.run(keys);
+        video_refresh(
+            core.video.as_ptr() as *const c_void,
+            WIDTH as c_uint,
+            HEIGHT as c_uint,
+            WIDTH * 4,
+        );
+        audio_sample_batch(core.audio.as_ptr(), SAMPLES_PER_FRAME);
+    }
+}
+
+#[no_mangle]
+pub extern "C" fn retro_serialize_size() -> usize {
+    STATE_SIZE
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn retro_serialize(data: *mut c_void, size: usize) -> bool {
+    let mut core = CORE.lock().unwrap();
+    match core.as_mut() {
+        Some(core) if size >= STATE_SIZE => {
+            let state = core.chip8.save_state();
+            slice::from_raw_parts_mut(data as *mut u8, STATE_SIZE).copy_from_slice(&state);
+            true
+        }
+        _ => false,
+    }
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn retro_unserialize(data: *const c_void, size: usize) -> bool {
+    let mut core = CORE.lock().unwrap();
+    match core.as_mut() {
+        Some(core) if size >= STATE_SIZE => {
+            let state = slice::from_raw_parts(data as *const u8, STATE_SIZE);
+            let loaded = core.chip8.load_state(state).is_ok();
+            if loaded {
+                core.error = None;
+            }
+            loaded
+        }
+        _ => false,
+    }
+}
+
+#[no_mangle]
+pub extern "C" fn retro_cheat_reset() {}
+
+#[no_mangle]
+pub extern "C" fn retro_cheat_set(_index: c_uint, _enabled: bool, _code: *const c_char) {}
+
+#[no_mangle]
+pub unsafe extern "C" fn retro_load_game(game: *const retro_game_info) -> bool {
+    if game.is_null() || (*game).data.is_null() {
+        return false;
+    }
+    let mut format = RETRO_PIXEL_FORMAT_XRGB8888;
+    if !environment(
+        RETRO_ENVIRONMENT_SET_PIXEL_FORMAT,
+        &mut format as *mut c_uint as *mut c_void,
+    ) {
+        return false;
+    }
+    let mut descriptors: Vec<retro_input_descriptor> = KEYPAD
+        .iter()
+        .map(|(id, description)| retro_input_descriptor {
+            port: 0,
+            device: RETRO_DEVICE_JOYPAD,
+            index: 0,
+            id: *id,
+            description: description.as_ptr(),
+        })
+        .collect();
+    descriptors.push(retro_input_descriptor {
+        port: 0,
+        device: 0,
+        index: 0,
+        id: 0,
+        description: ptr::null(),
+    });
+    environment(
+        RETRO_ENVIRONMENT_SET_INPUT_DESCRIPTORS,
+        descriptors.as_mut_ptr() as *mut c_void,
+    );
+
+    let rom = slice::from_raw_parts((*game).data as *const u8, (*game).size).to_vec();
+    match Core::new(rom, read_options()) {
+        Ok(core) => {
+            *CORE.lock().unwrap() = Some(core);
+            true
+        }
+        Err(e) => {
+            show_message(&e.to_string());
+            false
+        }
+    }
+}
+
+#[no_mangle]
+pub extern "C" fn retro_load_game_special(
+    _game_type: c_uint,
+    _info: *const retro_game_info,
+    _num_info: usize,
+) -> bool {
+    false
+}
+
+#[no_mangle]
+pub extern "C" fn retro_unload_game() {
+    *CORE.lock().unwrap() = None;
+}
+
+#[no_mangle]
+pub extern "C" fn retro_get_region() -> c_uint {
+    RETRO_REGION_NTSC
+}
+
+#[no_mangle]
+pub extern "C" fn retro_get_memory_data(_id: c_uint) -> *mut c_void {
+    ptr::null_mut()
+}
+
+#[no_mangle]
+pub extern "C" fn retro_get_memory_size(_id: c_uint) -> usize {
+    0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // What a frontend would see, through the callbacks below.
+    static FRAME: Mutex<Vec<u32>> = Mutex::new(Vec::new());
+    static AUDIO: Mutex<Vec<i16>> = Mutex::new(Vec::new());
+    static KEYS: Mutex<u16> = Mutex::new(0);
+
+    unsafe extern "C" fn environment(cmd: c_uint, data: *mut c_void) -> bool {
+        match cmd {
+            RETRO_ENVIRONMENT_GET_VARIABLE => {
+                let variable = &mut *(data as *mut retro_variable);
+                variable.value = match CStr::from_ptr(variable.key).to_bytes() {
+                    b"chip8_speed" => c"600".as_ptr(),
+                    b"chip8_palette" => c"amber".as_ptr(),
+                    _ => ptr::null(),
+                };
+                true
+            }
+            RETRO_ENVIRONMENT_SET_PIXEL_FORMAT => {
+                *(data as *const c_uint) == RETRO_PIXEL_FORMAT_XRGB8888
+            }
+            _ => true,
+        }
+    }
+
+    unsafe extern "C" fn video_refresh(
+        data: *const c_void,
+        width: c_uint,
+        height: c_uint,
+        _: usize,
+    ) {
+        let pixels = slice::from_raw_parts(data as *const u32, (width * height) as usize);
+        *FRAME.lock().unwrap() = pixels.to_vec();
+    }
+
+    unsafe extern "C" fn audio_sample_batch(data: *const i16, frames: usize) -> usize {
+        *AUDIO.lock().unwrap() = slice::from_raw_parts(data, 2 * frames).to_vec();
+        frames
+    }
+
+    unsafe extern "C" fn input_poll() {}
+
+    unsafe extern "C" fn input_state(_: c_uint, _: c_uint, _: c_uint, id: c_uint) -> i16 {
+        let key = KEYPAD.iter().position(|(button, _)| *button == id).unwrap();
+        (*KEYS.lock().unwrap() >> key & 1) as i16
+    }
+
+    #[test]
+    fn run_core() {
+        // 0x200: LD V0, 5 ; LD F, V0 ; DRW V1, V1, 5
+        // 0x206: SKP V0 (key 5) ; JP 0x206
+        // 0x20A: LD ST, V0 ; JP 0x20C
+        let rom: [u8; 14] = [
+            0x60, 0x05, 0xF0, 0x29, 0xD1, 0x15, 0xE0, 0x9E, 0x12, 0x06, 0xF0, 0x18, 0x12, 0x0C,
+        ];
+        unsafe {
+            retro_set_environment(environment);
+            retro_set_video_refresh(video_refresh);
+            retro_set_audio_sample_batch(audio_sample_batch);
+            retro_set_input_poll(input_poll);
+            retro_set_input_state(input_state);
+            retro_init();
+            let game = retro_game_info {
+                path: ptr::null(),
+                data: rom.as_ptr() as *const c_void,
+                size: rom.len(),
+                meta: ptr::null(),
+            };
+            assert!(retro_load_game(&game));
+        }
+        assert_eq!(CORE.lock().unwrap().as_ref().unwrap().options.ipf, 10);
+
+        // the 5 glyph, in amber
+        retro_run();
+        let frame = FRAME.lock().unwrap().clone();
+        assert_eq!(frame.len(), WIDTH * HEIGHT);
+        assert_eq!(
+            &frame[..5],
+            [0xFFB000, 0xFFB000, 0xFFB000, 0xFFB000, 0x1A1000]
+        );
+        assert_eq!(AUDIO.lock().unwrap().len(), 2 * SAMPLES_PER_FRAME);
+        assert!(AUDIO.lock().unwrap().iter().all(|&sample| sample == 0));
+
+        // key 5 starts the beep
+        let mut state = vec![0; retro_serialize_size()];
+        unsafe {
+            assert!(retro_serialize(
+                state.as_mut_ptr() as *mut c_void,
+                state.len()
+            ));
+        }
+        *KEYS.lock().unwrap() = 1 << 5;
+        retro_run();
+        let audio = AUDIO.lock().unwrap().clone();
+        assert_eq!(audio[..2], [BEEP_VOLUME, BEEP_VOLUME]);
+        assert!(audio.contains(&-BEEP_VOLUME));
+
+        // back to the state saved before the key press, which waits for it again
+        *KEYS.lock().unwrap() = 0;
+        unsafe {
+            assert!(retro_unserialize(
+                state.as_ptr() as *const c_void,
+                state.len()
+            ));
+            assert!(!retro_unserialize(state.as_ptr() as *const c_void, 10));
+        }
+        retro_run();
+        assert!(AUDIO.lock().unwrap().iter().all(|&sample| sample == 0));
+        let pc = CORE
+            .lock()
+            .unwrap()
+            .as_ref()
+            .unwrap()
+            .chip8
+            .program_counter();
+        assert!((0x206..=0x208).contains(&pc));
+
+        retro_reset();
+        retro_unload_game();
+        assert!(CORE.lock().unwrap().is_none());
+        retro_deinit();
+    }
+}
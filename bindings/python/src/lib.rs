@@ -0,0 +1,262 @@
+// Python bindings of the emulator core, built with maturin (see pyproject.toml).
+//
+//     import chip8
+//     machine = chip8.Chip8(quirks={"shift": False})
+//     machine.load_rom(open("pong.ch8", "rb").read())
+//     machine.set_keys(1 << 5)
+//     machine.run_frame(10)
+//     screen = machine.framebuffer()  # numpy uint8 array of 32 rows of 64 pixels
+use chip8_core::{MachineCode, Quirks, Timing, HEIGHT, MEM_SIZE, WIDTH};
+use numpy::ndarray::Array2;
+use numpy::{IntoPyArray, PyArray2};
+use pyo3::create_exception;
+use pyo3::exceptions::{PyException, PyIndexError, PyValueError};
+use pyo3::prelude::*;
+use pyo3::types::{PyBytes, PyDict};
+
+create_exception!(chip8, Chip8Error, PyException);
+
+fn error(e: chip8_core::Error) -> PyErr {
+    Chip8Error::new_err(e.to_string())
+}
+
+fn register(x: usize) -> PyResult<usize> {
+    if x < 16 {
+        Ok(x)
+    } else {
+        Err(PyIndexError::new_err(format!("no register V{}", x)))
+    }
+}
+
+fn keypad(key: usize) -> PyResult<usize> {
+    if key < 16 {
+        Ok(key)
+    } else {
+        Err(PyIndexError::new_err(format!("no key {}", key)))
+    }
+}
+
+// Checks that `length` bytes from `address` are in memory.
+fn memory_range(address: usize, length: usize) -> PyResult<std::ops::Range<usize>> {
+    match address.checked_add(length) {
+        Some(end) if end <= MEM_SIZE => Ok(address..end),
+        _ => Err(PyIndexError::new_err(format!(
+            "{} bytes at {:#05X} are past the memory",
+            length, address
+        ))),
+    }
+}
+
+/// A CHIP-8 machine.
+///
+/// `quirks` maps the names of the quirks (shift, memory_increment, jump, vf_reset, clip) to
+/// booleans, `timing` is "fixed" or "cosmac-vip", and `machine_code` is "fault", "ignore" or
+/// "cdp1802", as in the emulator configuration.
+#[pyclass(name = "Chip8", module = "chip8")]
+#[derive(Clone)]
+struct Chip8 {
+    inner: chip8_core::Chip8,
+}
+
+/// A saved machine state, returned by `Chip8.save_state`.
+#[pyclass(module = "chip8", frozen)]
+struct State {
+    inner: chip8_core::Chip8,
+}
+
+#[pymethods]
+impl Chip8 {
+    #[new]
+    #[pyo3(signature = (quirks=None, timing="fixed", machine_code="fault"))]
+    fn new(quirks: Option<&Bound<'_, PyDict>>, timing: &str, machine_code: &str) -> PyResult<Self> {
+        let mut inner = chip8_core::init();
+        let mut settings = Quirks::default();
+        for (name, value) in quirks.into_iter().flatten() {
+            let value: bool = value.extract()?;
+            settings
+                .set(&name.extract::<String>()?, value)
+                .map_err(PyValueError::new_err)?;
+        }
+        inner.set_quirks(settings);
+        inner.set_timing(timing.parse::<Timing>().map_err(PyValueError::new_err)?);
+        inner.set_machine_code(
+            machine_code
+                .parse::<MachineCode>()
+                .map_err(PyValueError::new_err)?,
+        );
+        Ok(Chip8 { inner })
+    }
+
+    fn load_rom(&mut self, rom: &[u8]) -> PyResult<()> {
+        self.inner.load_rom(rom.to_vec()).map_err(error)
+    }
+
+    /// Seed the random generator of CXNN, for reproducible runs.
+    fn seed(&mut self, seed: u64) {
+        self.inner.seed_rng(seed);
+    }
+
+    /// Execute one instruction.
+    fn step(&mut self) -> PyResult<()> {
+        self.inner.tick().map_err(error)
+    }
+
+    /// Run one 60 Hz frame of `ipf` instructions, then update the timers. Returns True while
+    /// the sound timer is active.
+    #[pyo3(signature = (ipf=10))]
+    fn run_frame(&mut self, ipf: u32) -> PyResult<bool> {
+        self.inner.run_frame(ipf).map_err(error)
+    }
+
+    fn press_key(&mut self, key: usize) -> PyResult<()> {
+        self.inner.press_key(keypad(key)?);
+        Ok(())
+    }
+
+    fn release_key(&mut self, key: usize) -> PyResult<()> {
+        self.inner.release_key(keypad(key)?);
+        Ok(())
+    }
+
+    /// Hold the keys whose bit is set in `mask`, bit 0 for key 0, and release the others.
+    fn set_keys(&mut self, mask: u16) {
+        self.inner.reset_keypad();
+        for key in (0..16).filter(|key| mask & 1 << key != 0) {
+            self.inner.press_key(key);
+        }
+    }
+
+    /// The keys held, as a mask.
+    #[getter]
+    fn keys(&self) -> u16 {
+        (0..16)
+            .filter(|&key| self.inner.is_key_down(key))
+            .fold(0, |mask, key| mask | 1 << key)
+    }
+
+    /// The screen, as a uint8 array of shape (32, 64) holding 0 or 1 per pixel.
+    fn framebuffer<'py>(&self, py: Python<'py>) -> Bound<'py, PyArray2<u8>> {
+        let rows = self.inner.gfx_rows();
+        Array2::from_shape_fn((HEIGHT, WIDTH), |(y, x)| {
+            (rows[y] >> (WIDTH - 1 - x) & 1) as u8
+        })
+        .into_pyarray(py)
+    }
+
+    fn register(&self, x: usize) -> PyResult<u8> {
+        Ok(self.inner.register(register(x)?))
+    }
+
+    fn set_register(&mut self, x: usize, value: u8) -> PyResult<()> {
+        self.inner.set_register(register(x)?, value);
+        Ok(())
+    }
+
+    /// V0 to VF.
+    #[getter]
+    fn registers(&self) -> Vec<u8> {
+        self.inner.registers().to_vec()
+    }
+
+    #[getter]
+    fn index(&self) -> u16 {
+        self.inner.index()
+    }
+
+    #[setter]
+    fn set_index(&mut self, value: u16) {
+        self.inner.set_index(value);
+    }
+
+    #[getter]
+    fn pc(&self) -> u16 {
+        self.inner.program_counter()
+    }
+
+    #[setter]
+    fn set_pc(&mut self, value: u16) {
+        self.inner.set_program_counter(value);
+    }
+
+    #[getter]
+    fn delay_timer(&self) -> u8 {
+        self.inner.delay_timer()
+    }
+
+    #[setter]
+    fn set_delay_timer(&mut self, value: u8) {
+        self.inner.set_delay_timer(value);
+    }
+
+    #[getter]
+    fn sound_timer(&self) -> u8 {
+        self.inner.sound_timer()
+    }
+
+    #[setter]
+    fn set_sound_timer(&mut self, value: u8) {
+        self.inner.set_sound_timer(value);
+    }
+
+    /// The addresses of the pending CALL instructions, outermost first. Their subroutines
+    /// return 2 bytes after them.
+    #[getter]
+    fn stack(&self) -> Vec<u16> {
+        self.inner.call_stack().to_vec()
+    }
+
+    /// Number of executed instructions.
+    #[getter]
+    fn cycles(&self) -> u64 {
+        self.inner.cycles()
+    }
+
+    /// `length` bytes of memory from `address`, up to the end of the memory by default.
+    #[pyo3(signature = (address=0, length=None))]
+    fn read<'py>(
+        &self,
+        py: Python<'py>,
+        address: usize,
+        length: Option<usize>,
+    ) -> PyResult<Bound<'py, PyBytes>> {
+        let length = length.unwrap_or(MEM_SIZE.saturating_sub(address));
+        let range = memory_range(address, length)?;
+        let bytes: Vec<u8> = range.map(|a| self.inner.memory(a)).collect();
+        Ok(PyBytes::new(py, &bytes))
+    }
+
+    fn write(&mut self, address: usize, data: &[u8]) -> PyResult<()> {
+        for (a, &value) in memory_range(address, data.len())?.zip(data) {
+            self.inner.set_memory(a, value);
+        }
+        Ok(())
+    }
+
+    fn save_state(&self) -> State {
+        State {
+            inner: self.inner.clone(),
+        }
+    }
+
+    fn load_state(&mut self, state: &State) {
+        self.inner.clone_from(&state.inner);
+    }
+
+    fn __copy__(&self) -> Self {
+        self.clone()
+    }
+
+    fn __deepcopy__(&self, _memo: &Bound<'_, PyAny>) -> Self {
+        self.clone()
+    }
+}
+
+#[pymodule]
+fn chip8(m: &Bound<'_, PyModule>) -> PyResult<()> {
+    m.add_class::<Chip8>()?;
+    m.add_class::<State>()?;
+    m.add("Chip8Error", m.py().get_type::<Chip8Error>())?;
+    m.add("WIDTH", WIDTH)?;
+    m.add("HEIGHT", HEIGHT)?;
+    Ok(())
+}
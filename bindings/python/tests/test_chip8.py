@@ -0,0 +1,85 @@
+# Run with `maturin develop && pytest` from bindings/python.
+import copy
+
+import pytest
+
+import chip8
+
+# LD V0, 5 ; LD F, V0 ; DRW V1, V1, 5 ; ADD V2, 1 ; JP 0x206
+ROM = bytes([0x60, 0x05, 0xF0, 0x29, 0xD1, 0x15, 0x72, 0x01, 0x12, 0x06])
+
+
+def machine():
+    m = chip8.Chip8()
+    m.load_rom(ROM)
+    return m
+
+
+def test_step_and_registers():
+    m = machine()
+    m.step()
+    assert m.register(0) == 5
+    assert m.pc == 0x202
+    m.step()
+    assert m.index == 0x50 + 5 * 5
+    m.set_register(3, 0xAB)
+    assert m.registers[3] == 0xAB
+    with pytest.raises(IndexError):
+        m.register(16)
+
+
+def test_framebuffer():
+    np = pytest.importorskip("numpy")
+    m = machine()
+    m.run_frame(10)
+    screen = m.framebuffer()
+    assert screen.shape == (chip8.HEIGHT, chip8.WIDTH)
+    assert screen.dtype == np.uint8
+    # the top of the 5 glyph
+    assert list(screen[0, :4]) == [1, 1, 1, 1]
+    assert screen.sum() == 14
+
+
+def test_memory():
+    m = machine()
+    assert m.read(0x200, 2) == ROM[:2]
+    assert len(m.read()) == 4096
+    m.write(0x300, b"\x01\x02")
+    assert m.read(0x300, 2) == b"\x01\x02"
+    with pytest.raises(IndexError):
+        m.read(0xFFF, 2)
+
+
+def test_keys():
+    m = machine()
+    m.set_keys(1 << 5 | 1 << 0xA)
+    assert m.keys == 0x0420
+    m.release_key(5)
+    assert m.keys == 0x0400
+    with pytest.raises(IndexError, match="no key 16"):
+        m.press_key(16)
+
+
+def test_state():
+    m = machine()
+    m.run_frame(10)
+    state = m.save_state()
+    other = copy.copy(m)
+    m.run_frame(10)
+    after = m.registers
+    m.load_state(state)
+    m.run_frame(10)
+    assert m.registers == after
+    other.run_frame(10)
+    assert other.registers == after
+
+
+def test_errors():
+    m = chip8.Chip8(quirks={"jump": True}, timing="cosmac-vip")
+    m.load_rom(bytes([0xFF, 0xFF]))
+    with pytest.raises(chip8.Chip8Error, match="unknown opcode FFFF"):
+        m.step()
+    with pytest.raises(ValueError):
+        chip8.Chip8(quirks={"wrap": True})
+    with pytest.raises(chip8.Chip8Error):
+        m.load_rom(bytes(4096))
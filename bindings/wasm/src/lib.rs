@@ -0,0 +1,132 @@
+// WebAssembly build of the emulator core, for the page of www/ (see scripts/build-wasm.sh).
+use chip8_core::{Timing, HEIGHT, WIDTH};
+use wasm_bindgen::prelude::*;
+
+#[wasm_bindgen]
+pub struct Emulator {
+    chip8: chip8_core::Chip8,
+    seed: u64,
+}
+
+#[wasm_bindgen]
+impl Emulator {
+    /// A machine with the default quirks and fixed timing. `seed` seeds the random generator,
+    /// which has no other source of entropy in the browser.
+    #[wasm_bindgen(constructor)]
+    pub fn new(seed: u32) -> Emulator {
+        let mut chip8 = chip8_core::init();
+        chip8.seed_rng(seed as u64);
+        Emulator {
+            chip8,
+            seed: seed as u64,
+        }
+    }
+
+    #[wasm_bindgen(getter)]
+    pub fn width() -> usize {
+        WIDTH
+    }
+
+    #[wasm_bindgen(getter)]
+    pub fn height() -> usize {
+        HEIGHT
+    }
+
+    /// Load a ROM, given as a Uint8Array, on a reset machine with the same settings and seed.
+    pub fn load(&mut self, rom: &[u8]) -> Result<(), JsError> {
+        let mut chip8 = chip8_core::init();
+        chip8.set_quirks(self.chip8.quirks());
+        chip8.set_timing(self.chip8.timing());
+        chip8.seed_rng(self.seed);
+        chip8.load_rom(rom.to_vec())?;
+        self.chip8 = chip8;
+        Ok(())
+    }
+
+    /// Enable or disable a quirk: shift, memory_increment, jump, vf_reset or clip.
+    #[wasm_bindgen(js_name = setQuirk)]
+    pub fn set_quirk(&mut self, name: &str, enabled: bool) -> Result<(), JsError> {
+        let mut quirks = self.chip8.quirks();
+        quirks.set(name, enabled).map_err(|e| JsError::new(&e))?;
+        self.chip8.set_quirks(quirks);
+        Ok(())
+    }
+
+    /// "fixed" or "cosmac-vip".
+    #[wasm_bindgen(js_name = setTiming)]
+    pub fn set_timing(&mut self, timing: &str) -> Result<(), JsError> {
+        let timing: Timing = timing.parse().map_err(|e: String| JsError::new(&e))?;
+        self.chip8.set_timing(timing);
+        Ok(())
+    }
+
+    /// Execute one instruction.
+    pub fn step(&mut self) -> Result<(), JsError> {
+        Ok(self.chip8.tick()?)
+    }
+
+    /// Run one 60 Hz frame of `ipf` instructions, then update the timers. Returns true while the
+    /// sound timer is active.
+    #[wasm_bindgen(js_name = runFrame)]
+    pub fn run_frame(&mut self, ipf: u32) -> Result<bool, JsError> {
+        Ok(self.chip8.run_frame(ipf)?)
+    }
+
+    #[wasm_bindgen(js_name = setKey)]
+    pub fn set_key(&mut self, key: usize, pressed: bool) {
+        match key {
+            0..=0xF if pressed => self.chip8.press_key(key),
+            0..=0xF => self.chip8.release_key(key),
+            _ => {}
+        }
+    }
+
+    /// The screen as a Uint8Array of `height` rows of `width` pixels, 1 when lit.
+    pub fn framebuffer(&self) -> Vec<u8> {
+        self.chip8
+            .gfx_rows()
+            .iter()
+            .flat_map(|row| (0..WIDTH).map(move |x| (row >> (WIDTH - 1 - x) & 1) as u8))
+            .collect()
+    }
+
+    #[wasm_bindgen(getter)]
+    pub fn pc(&self) -> u16 {
+        self.chip8.program_counter()
+    }
+
+    /// V0 to VF.
+    #[wasm_bindgen(getter)]
+    pub fn registers(&self) -> Vec<u8> {
+        self.chip8.registers().to_vec()
+    }
+
+    #[wasm_bindgen(getter)]
+    pub fn index(&self) -> u16 {
+        self.chip8.index()
+    }
+}
+
+#[cfg(all(test, not(target_arch = "wasm32")))]
+mod tests {
+    use super::*;
+
+    // The JS glue is not there natively, so these tests stick to the calls which do not fail.
+    #[test]
+    fn run_rom() {
+        let mut emulator = Emulator::new(0);
+        // LD V0, 5 ; LD F, V0 ; DRW V1, V1, 5 ; JP 0x206
+        emulator
+            .load(&[0x60, 0x05, 0xF0, 0x29, 0xD1, 0x15, 0x12, 0x06])
+            .unwrap();
+        emulator.step().unwrap();
+        assert_eq!(emulator.registers()[0], 5);
+        emulator.set_key(5, true);
+        emulator.run_frame(10).unwrap();
+        let screen = emulator.framebuffer();
+        assert_eq!(screen.len(), WIDTH * HEIGHT);
+        assert_eq!(&screen[..5], [1, 1, 1, 1, 0]);
+        assert_eq!(&screen[WIDTH..WIDTH + 2], [1, 0]);
+        assert_eq!(screen.iter().filter(|&&pixel| pixel == 1).count(), 14);
+    }
+}
@@ -0,0 +1,47 @@
+// Run with `cargo test -p chip8-wasm --target wasm32-unknown-unknown`, which runs the tests on
+// node through wasm-bindgen-test-runner (see .cargo/config.toml).
+#![cfg(target_arch = "wasm32")]
+
+use chip8_wasm::Emulator;
+use wasm_bindgen_test::wasm_bindgen_test;
+
+// CXNN draws from the generator seeded by the page, the same seed gives the same numbers.
+// RND V0, 0xFF ; RND V1, 0xFF ; JP 0x204
+const RANDOM: [u8; 6] = [0xC0, 0xFF, 0xC1, 0xFF, 0x12, 0x04];
+
+#[wasm_bindgen_test]
+fn seeded_random() {
+    let run = |seed| {
+        let mut emulator = Emulator::new(seed);
+        emulator.load(&RANDOM).unwrap();
+        emulator.run_frame(2).unwrap();
+        emulator.registers()[..2].to_vec()
+    };
+    assert_eq!(run(1), run(1));
+    assert_ne!(run(1), run(2));
+}
+
+#[wasm_bindgen_test]
+fn draw() {
+    let mut emulator = Emulator::new(0);
+    // LD V0, 5 ; LD F, V0 ; DRW V1, V1, 5 ; JP 0x206
+    emulator
+        .load(&[0x60, 0x05, 0xF0, 0x29, 0xD1, 0x15, 0x12, 0x06])
+        .unwrap();
+    emulator.run_frame(10).unwrap();
+    let screen = emulator.framebuffer();
+    assert_eq!(screen.len(), Emulator::width() * Emulator::height());
+    assert_eq!(&screen[..5], [1, 1, 1, 1, 0]);
+}
+
+#[wasm_bindgen_test]
+fn errors() {
+    let mut emulator = Emulator::new(0);
+    assert!(emulator.set_quirk("wrap", true).is_err());
+    assert!(emulator.set_timing("pal").is_err());
+    emulator.set_quirk("jump", true).unwrap();
+    emulator.set_timing("cosmac-vip").unwrap();
+    emulator.load(&[0xFF, 0xFF]).unwrap();
+    assert!(emulator.step().is_err());
+    assert!(emulator.load(&[0; 4096]).is_err());
+}
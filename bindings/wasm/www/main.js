@@ -0,0 +1,138 @@
+// Page of the WebAssembly build, see scripts/build-wasm.sh.
+import init, { Emulator } from "./pkg/chip8_wasm.js";
+
+// keyboard keys of the chip8 keys 0 to F, as in the default keymap of the emulator
+const KEYMAP = ["x", "1", "2", "3", "q", "w", "e", "a", "s", "d", "z", "c", "4", "r", "f", "v"];
+const FRAME = 1000 / 60;
+
+await init();
+
+const canvas = document.getElementById("screen");
+const context = canvas.getContext("2d");
+canvas.width = Emulator.width;
+canvas.height = Emulator.height;
+const image = context.createImageData(Emulator.width, Emulator.height);
+const status = document.getElementById("status");
+const pause = document.getElementById("pause");
+const ipf = document.getElementById("ipf");
+
+let emulator = new Emulator((Math.random() * 2 ** 32) >>> 0);
+let running = false;
+let last = 0;
+
+// a square wave, audible while the sound timer runs; browsers only start audio after a user
+// gesture, so it is created with the first ROM
+let audio = null;
+let gain = null;
+
+function beep(on) {
+  if (gain) {
+    gain.gain.setTargetAtTime(on ? 0.1 : 0, audio.currentTime, 0.005);
+  }
+}
+
+function startAudio() {
+  if (audio) {
+    return;
+  }
+  audio = new AudioContext();
+  const oscillator = audio.createOscillator();
+  oscillator.type = "square";
+  oscillator.frequency.value = 440;
+  gain = audio.createGain();
+  gain.gain.value = 0;
+  oscillator.connect(gain).connect(audio.destination);
+  oscillator.start();
+}
+
+function draw() {
+  const pixels = emulator.framebuffer();
+  for (let i = 0; i < pixels.length; i++) {
+    const value = pixels[i] ? 255 : 0;
+    image.data[4 * i] = value;
+    image.data[4 * i + 1] = value;
+    image.data[4 * i + 2] = value;
+    image.data[4 * i + 3] = 255;
+  }
+  context.putImageData(image, 0, 0);
+}
+
+function frame(time) {
+  if (!running) {
+    return;
+  }
+  // catch up with the 60 Hz clock, without running away after the tab was in the background
+  let frames = Math.min(Math.floor((time - last) / FRAME), 4);
+  last = frames === 4 ? time : last + frames * FRAME;
+  try {
+    let sound = false;
+    while (frames-- > 0) {
+      sound = emulator.runFrame(Number(ipf.value) || 10);
+    }
+    beep(sound);
+    draw();
+  } catch (e) {
+    stop();
+    status.textContent = `${e.message}, the emulation is stopped`;
+    return;
+  }
+  requestAnimationFrame(frame);
+}
+
+function stop() {
+  running = false;
+  pause.textContent = "resume";
+  beep(false);
+}
+
+function start() {
+  running = true;
+  pause.textContent = "pause";
+  last = performance.now();
+  requestAnimationFrame(frame);
+}
+
+function load(rom) {
+  try {
+    emulator.load(rom);
+  } catch (e) {
+    status.textContent = e.message;
+    return;
+  }
+  status.textContent = "";
+  pause.disabled = false;
+  draw();
+  if (!running) {
+    start();
+  }
+}
+
+document.getElementById("rom").addEventListener("change", async (event) => {
+  const file = event.target.files[0];
+  if (file) {
+    startAudio();
+    load(new Uint8Array(await file.arrayBuffer()));
+  }
+});
+
+pause.addEventListener("click", () => (running ? stop() : start()));
+
+for (const [type, pressed] of [["keydown", true], ["keyup", false]]) {
+  document.addEventListener(type, (event) => {
+    const key = KEYMAP.indexOf(event.key.toLowerCase());
+    if (key >= 0) {
+      emulator.setKey(key, pressed);
+      event.preventDefault();
+    }
+  });
+}
+
+const url = new URLSearchParams(location.search).get("rom");
+if (url) {
+  const response = await fetch(url);
+  if (response.ok) {
+    load(new Uint8Array(await response.arrayBuffer()));
+  } else {
+    status.textContent = `cannot fetch ${url}: ${response.status}`;
+  }
+}
@@ -0,0 +1,16 @@
+// The core against the reference model: they must agree after every instruction.
+#![no_main]
+
+use chip8_fuzz::{Input, IPF};
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    let Some(input) = Input::parse(data) else {
+        return;
+    };
+    if let Err(difference) =
+        chip8::compare_with_reference(input.rom, input.quirks, input.seed, IPF, &input.keys)
+    {
+        panic!("{:?}: {}", input.quirks, difference);
+    }
+});
@@ -0,0 +1,30 @@
+// Arbitrary ROMs and key presses, with every tracking enabled: any panic is a bug, the core
+// must report faults as errors.
+#![no_main]
+
+use chip8_fuzz::{Input, IPF};
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    let Some(input) = Input::parse(data) else {
+        return;
+    };
+    let mut chip8 = chip8::init();
+    chip8.set_quirks(input.quirks);
+    chip8.seed_rng(input.seed);
+    chip8.set_access_tracking(true);
+    chip8.set_coverage_tracking(true);
+    if chip8.load_rom(input.rom.to_vec()).is_err() {
+        return;
+    }
+    for mask in input.keys {
+        chip8.reset_keypad();
+        for key in (0..16).filter(|key| mask & 1 << key != 0) {
+            chip8.press_key(key);
+        }
+        if chip8.run_frame(IPF).is_err() {
+            break;
+        }
+        let _ = chip8.gfx_buffer([0, 1]);
+    }
+});
@@ -0,0 +1,44 @@
+// Decoding of the fuzzer inputs, shared by the fuzz targets.
+use chip8::Quirks;
+
+// Instructions per frame.
+pub const IPF: u32 = 20;
+// Frames run at most, to keep each input fast.
+const MAX_FRAMES: usize = 64;
+
+pub struct Input<'a> {
+    pub quirks: Quirks,
+    pub seed: u64,
+    // keypad of each frame, one bit per key
+    pub keys: Vec<u16>,
+    pub rom: &'a [u8],
+}
+
+impl<'a> Input<'a> {
+    // Layout: quirk flags, random seed, number of frames N, N keypad words, then the ROM.
+    pub fn parse(data: &'a [u8]) -> Option<Input<'a>> {
+        let (&flags, data) = data.split_first()?;
+        let (&seed, data) = data.split_first()?;
+        let (&frames, data) = data.split_first()?;
+        let frames = (frames as usize % MAX_FRAMES) + 1;
+        if data.len() < 2 * frames {
+            return None;
+        }
+        let (keys, rom) = data.split_at(2 * frames);
+        Some(Input {
+            quirks: Quirks {
+                shift: flags & 1 != 0,
+                memory_increment: flags & 2 != 0,
+                jump: flags & 4 != 0,
+                vf_reset: flags & 8 != 0,
+                clip: flags & 16 != 0,
+            },
+            seed: seed as u64,
+            keys: keys
+                .chunks(2)
+                .map(|k| u16::from_le_bytes([k[0], k[1]]))
+                .collect(),
+            rom,
+        })
+    }
+}
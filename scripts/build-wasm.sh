@@ -0,0 +1,10 @@
+#!/bin/sh
+# Build the WebAssembly module and its JS glue into bindings/wasm/www/pkg, next to the page.
+# Needs the wasm32-unknown-unknown target and the wasm-bindgen CLI of the same version as the
+# wasm-bindgen crate: cargo install wasm-bindgen-cli --version 0.2.100
+set -e
+cd "$(dirname "$0")/.."
+
+cargo build --release -p chip8-wasm --target wasm32-unknown-unknown
+wasm-bindgen --target web --out-dir bindings/wasm/www/pkg \
+    target/wasm32-unknown-unknown/release/chip8_wasm.wasm
@@ -0,0 +1,10 @@
+#!/bin/sh
+# Download the ROM database of https://github.com/chip-8/chip-8-database, into the directory
+# where the emulator looks for it by default, or into the given one.
+set -e
+url=https://raw.githubusercontent.com/chip-8/chip-8-database/master/database
+dir=${1:-${XDG_DATA_HOME:-$HOME/.local/share}/chip8/database}
+mkdir -p "$dir"
+for file in programs.json sha1-hashes.json platforms.json; do
+    curl -sSfL -o "$dir/$file" "$url/$file"
+done
@@ -0,0 +1,21 @@
+#!/bin/sh
+# Download the ROMs of Timendus' chip8-test-suite into tests/suite/, run by tests/suite.rs,
+# and check them against tests/suite.sha256. `--record` writes the checksums instead, when
+# moving to another release of the suite; they then have to be committed.
+set -e
+cd "$(dirname "$0")/.."
+tag=v4.1
+url=https://raw.githubusercontent.com/Timendus/chip8-test-suite/$tag/bin
+roms="1-chip8-logo.ch8 2-ibm-logo.ch8 3-corax+.ch8 4-flags.ch8 5-quirks.ch8 6-keypad.ch8"
+
+mkdir -p tests/suite
+for rom in $roms; do
+    curl -sSfL -o "tests/suite/$rom" "$url/$(echo "$rom" | sed 's/+/%2B/')"
+done
+cd tests/suite
+if [ "$1" = --record ]; then
+    sha256sum $roms > ../suite.sha256
+    echo "recorded the checksums of $tag in tests/suite.sha256, commit them" >&2
+else
+    sha256sum -c ../suite.sha256
+fi
@@ -0,0 +1,13 @@
+#!/bin/sh
+# Build the C interface, check that its header is up to date and run the C test program.
+set -e
+cd "$(dirname "$0")/.."
+
+cargo build -p chip8-c
+if command -v cbindgen > /dev/null; then
+    cbindgen --quiet --config bindings/c/cbindgen.toml --crate chip8-c --output target/chip8.h
+    diff -u bindings/c/include/chip8.h target/chip8.h
+fi
+cc -std=c99 -Wall -Wextra -Werror -o target/test-c bindings/c/tests/test.c \
+    -Ibindings/c/include -Ltarget/debug -lchip8
+LD_LIBRARY_PATH=target/debug target/test-c
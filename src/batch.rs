@@ -0,0 +1,365 @@
+// Batch runs of ROM collections, to catch regressions.
+//
+// Every ROM runs headless with no key pressed and a seeded random generator, so that two runs
+// of the same build give the same report. Reports are written as JSON, to be compared with the
+// next run, and as HTML with a thumbnail of the final frame of each ROM.
+use crate::config::Config;
+use chip8::{HEIGHT, WIDTH};
+use serde::{Deserialize, Serialize};
+use sha1::{Digest, Sha1};
+use std::collections::HashMap;
+use std::fmt::Write;
+use std::path::Path;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+
+// A ROM to run, or the reason it cannot be.
+pub struct Job {
+    // relative to the batch directory
+    pub path: String,
+    pub setup: Result<(Vec<u8>, Config), String>,
+    pub sha1: String,
+    pub title: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RomResult {
+    pub path: String,
+    pub sha1: String,
+    pub title: Option<String>,
+    // None when the ROM ran every frame
+    pub error: Option<String>,
+    pub frames: u32,
+    pub instructions: u64,
+    pub frame_hash: String,
+    // final frame, one hex u64 per row
+    pub screen: Vec<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Change {
+    New,
+    Removed,
+    // now fails, or fails differently
+    Failing,
+    Fixed,
+    Screen,
+    Instructions,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct Report {
+    pub frames: u32,
+    pub roms: Vec<RomResult>,
+    // differences with the previous report, by path
+    #[serde(default)]
+    pub changes: Vec<(String, Change)>,
+}
+
+// Run the jobs on `threads` threads, for `frames` frames each.
+pub fn run(jobs: Vec<Job>, frames: u32, threads: usize) -> Report {
+    let next = AtomicUsize::new(0);
+    let results = Mutex::new(Vec::with_capacity(jobs.len()));
+    std::thread::scope(|scope| {
+        for _ in 0..threads.max(1) {
+            scope.spawn(|| {
+                while let Some(job) = jobs.get(next.fetch_add(1, Ordering::Relaxed)) {
+                    let result = run_job(job, frames);
+                    results.lock().unwrap().push(result);
+                }
+            });
+        }
+    });
+    let mut roms = results.into_inner().unwrap();
+    roms.sort_by(|a, b| a.path.cmp(&b.path));
+    Report {
+        frames,
+        roms,
+        changes: Vec::new(),
+    }
+}
+
+fn run_job(job: &Job, frames: u32) -> RomResult {
+    let mut result = RomResult {
+        path: job.path.clone(),
+        sha1: job.sha1.clone(),
+        title: job.title.clone(),
+        error: None,
+        frames: 0,
+        instructions: 0,
+        frame_hash: String::new(),
+        screen: Vec::new(),
+    };
+    let (rom, config) = match &job.setup {
+        Ok(setup) => setup,
+        Err(e) => {
+            result.error = Some(e.clone());
+            return result;
+        }
+    };
+
+    let mut chip8 = chip8::init();
+    chip8.set_quirks(config.quirks);
+    chip8.set_timing(config.timing);
+    chip8.set_machine_code(config.machine_code);
+    chip8.seed_rng(0);
+    let ipf = crate::speed::Speed::new(config.speed, config.fast_forward, config.timing).ipf();
+    // a panic is a bug of the emulator, reported like any other failure
+    let outcome = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+        chip8.load_rom(rom.clone()).map_err(|e| e.to_string())?;
+        for frame in 0..frames {
+            chip8
+                .run_frame(ipf)
+                .map_err(|e| format!("frame {}: {}", frame, e))?;
+            result.frames = frame + 1;
+        }
+        Ok(())
+    }));
+    result.error = match outcome {
+        Ok(outcome) => outcome.err(),
+        Err(panic) => Some(format!(
+            "panic: {}",
+            panic
+                .downcast_ref::<String>()
+                .map(String::as_str)
+                .or_else(|| panic.downcast_ref::<&str>().copied())
+                .unwrap_or("unknown")
+        )),
+    };
+    result.instructions = chip8.cycles();
+    let rows = chip8.gfx_rows();
+    let bytes: Vec<u8> = rows.iter().flat_map(|row| row.to_be_bytes()).collect();
+    result.frame_hash = Sha1::digest(&bytes)[..8]
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect();
+    result.screen = rows.iter().map(|row| format!("{:016x}", row)).collect();
+    result
+}
+
+impl Report {
+    pub fn load(path: &Path) -> Result<Report, String> {
+        let text = std::fs::read_to_string(path)
+            .map_err(|e| format!("cannot read {}: {}", path.display(), e))?;
+        serde_json::from_str(&text).map_err(|e| format!("{}: {}", path.display(), e))
+    }
+
+    pub fn json(&self) -> String {
+        serde_json::to_string_pretty(self).unwrap()
+    }
+
+    // Compare with the report of a previous run, by path.
+    pub fn diff(&mut self, previous: &Report) {
+        let before: HashMap<&str, &RomResult> = previous
+            .roms
+            .iter()
+            .map(|rom| (rom.path.as_str(), rom))
+            .collect();
+        self.changes.clear();
+        for rom in &self.roms {
+            let change = match before.get(rom.path.as_str()) {
+                None => Some(Change::New),
+                Some(old) if rom.error.is_some() && rom.error != old.error => Some(Change::Failing),
+                Some(old) if rom.error.is_none() && old.error.is_some() => Some(Change::Fixed),
+                Some(old) if rom.frame_hash != old.frame_hash => Some(Change::Screen),
+                Some(old) if rom.instructions != old.instructions => Some(Change::Instructions),
+                Some(_) => None,
+            };
+            if let Some(change) = change {
+                self.changes.push((rom.path.clone(), change));
+            }
+        }
+        let paths: Vec<&str> = self.roms.iter().map(|rom| rom.path.as_str()).collect();
+        for rom in &previous.roms {
+            if !paths.contains(&rom.path.as_str()) {
+                self.changes.push((rom.path.clone(), Change::Removed));
+            }
+        }
+    }
+
+    // Changes which need a look: anything but new ROMs and fixes.
+    pub fn regressions(&self) -> usize {
+        self.changes
+            .iter()
+            .filter(|(_, change)| !matches!(change, Change::New | Change::Fixed))
+            .count()
+    }
+
+    pub fn summary(&self) -> String {
+        let failed = self.roms.iter().filter(|rom| rom.error.is_some()).count();
+        let mut text = format!(
+            "{} ROMs, {} ok, {} failed, {} frames each\n",
+            self.roms.len(),
+            self.roms.len() - failed,
+            failed,
+            self.frames
+        );
+        for rom in self.roms.iter().filter(|rom| rom.error.is_some()) {
+            writeln!(
+                text,
+                "  {:<12} {}: {}",
+                "failed",
+                rom.path,
+                rom.error.as_ref().unwrap()
+            )
+            .unwrap();
+        }
+        for (path, change) in &self.changes {
+            writeln!(text, "  {:<12} {}", name(change), path).unwrap();
+        }
+        text
+    }
+
+    pub fn html(&self) -> String {
+        let changes: HashMap<&str, &Change> = self
+            .changes
+            .iter()
+            .map(|(path, change)| (path.as_str(), change))
+            .collect();
+        let mut html = String::from(
+            "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n<title>chip8 batch report</title>\n<style>\n\
+             body { font-family: sans-serif; }\n\
+             td { padding: 2px 8px; }\n\
+             svg { background: #000; }\n\
+             .failed { background: #fdd; }\n\
+             .changed { background: #ffd; }\n\
+             </style>\n</head>\n<body>\n",
+        );
+        writeln!(html, "<pre>{}</pre>", escape(&self.summary())).unwrap();
+        html.push_str("<table>\n<tr><th>ROM</th><th>Final frame</th><th>Instructions</th><th>Result</th><th>Change</th></tr>\n");
+        for rom in &self.roms {
+            let change = changes.get(rom.path.as_str());
+            let class = if rom.error.is_some() {
+                " class=\"failed\""
+            } else if change.is_some() {
+                " class=\"changed\""
+            } else {
+                ""
+            };
+            writeln!(
+                html,
+                "<tr{}><td>{}<br><small>{}</small></td><td>{}</td><td>{}</td><td>{}</td><td>{}</td></tr>",
+                class,
+                escape(&rom.path),
+                escape(rom.title.as_deref().unwrap_or("")),
+                thumbnail(&rom.screen),
+                rom.instructions,
+                escape(rom.error.as_deref().unwrap_or("ok")),
+                change.map(|change| name(change)).unwrap_or(""),
+            )
+            .unwrap();
+        }
+        html.push_str("</table>\n</body>\n</html>\n");
+        html
+    }
+}
+
+fn name(change: &Change) -> &'static str {
+    match change {
+        Change::New => "new",
+        Change::Removed => "removed",
+        Change::Failing => "failing",
+        Change::Fixed => "fixed",
+        Change::Screen => "screen",
+        Change::Instructions => "instructions",
+    }
+}
+
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+}
+
+// The final frame as an SVG, twice its size, with one rectangle per horizontal run of pixels.
+fn thumbnail(screen: &[String]) -> String {
+    let mut path = String::new();
+    for (y, row) in screen.iter().enumerate() {
+        let row = u64::from_str_radix(row, 16).unwrap_or(0);
+        let mut x = 0;
+        while x < WIDTH {
+            let length = (x..WIDTH)
+                .take_while(|&x| row & 1 << (WIDTH - 1 - x) != 0)
+                .count();
+            if length > 0 {
+                write!(path, "M{} {}h{}v1h-{}z", x, y, length, length).unwrap();
+            }
+            x += length.max(1);
+        }
+    }
+    format!(
+        "<svg width=\"{}\" height=\"{}\" viewBox=\"0 0 {} {}\"><path fill=\"#fff\" d=\"{}\"/></svg>",
+        2 * WIDTH,
+        2 * HEIGHT,
+        WIDTH,
+        HEIGHT,
+        path
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn job(path: &str, rom: Vec<u8>) -> Job {
+        Job {
+            path: path.to_string(),
+            setup: Ok((rom, Config::default())),
+            sha1: String::new(),
+            title: None,
+        }
+    }
+
+    #[test]
+    fn run_and_diff() {
+        let jobs = vec![
+            // LD F, V0 ; DRW V0, V0, 5 ; JP 0x204
+            job("zero.ch8", vec![0xF0, 0x29, 0xD0, 0x05, 0x12, 0x04]),
+            job("bad.ch8", vec![0xFF, 0xFF]),
+            Job {
+                setup: Err("cannot read".to_string()),
+                ..job("missing.ch8", Vec::new())
+            },
+        ];
+        let report = run(jobs, 10, 2);
+        let paths: Vec<&str> = report.roms.iter().map(|r| r.path.as_str()).collect();
+        assert_eq!(paths, ["bad.ch8", "missing.ch8", "zero.ch8"]);
+        let zero = &report.roms[2];
+        assert_eq!(zero.error, None);
+        assert_eq!(zero.frames, 10);
+        // the top of the 0 glyph
+        assert_eq!(zero.screen[0], "f000000000000000");
+        assert_eq!(
+            report.roms[0].error.as_deref(),
+            Some("frame 0: unknown opcode FFFF at 0x200")
+        );
+        assert!(thumbnail(&zero.screen).contains("M0 0h4v1h-4z"));
+
+        // the same run has no changes
+        let mut again = Report::load_str(&report.json());
+        again.diff(&report);
+        assert!(again.changes.is_empty());
+
+        let mut changed = Report::load_str(&report.json());
+        changed.roms[2].frame_hash = "0".to_string();
+        changed.roms[0].error = None;
+        changed.roms.remove(1);
+        changed.diff(&report);
+        assert_eq!(
+            changed.changes,
+            [
+                ("bad.ch8".to_string(), Change::Fixed),
+                ("zero.ch8".to_string(), Change::Screen),
+                ("missing.ch8".to_string(), Change::Removed),
+            ]
+        );
+        assert_eq!(changed.regressions(), 2);
+    }
+
+    impl Report {
+        fn load_str(text: &str) -> Report {
+            serde_json::from_str(text).unwrap()
+        }
+    }
+}
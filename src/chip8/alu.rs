@@ -0,0 +1,118 @@
+// The 8XYN arithmetic and logic instructions.
+//
+// Both operands are read before anything is written, then the result is written to VX, then
+// the flag to VF. When X is F the flag wins, and when Y is F the old VF is the operand.
+use super::Quirks;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Operation {
+    // 8XY0
+    Load,
+    // 8XY1
+    Or,
+    // 8XY2
+    And,
+    // 8XY3
+    Xor,
+    // 8XY4, VF is the carry
+    Add,
+    // 8XY5, VF is set when there is no borrow
+    Subtract,
+    // 8XY6, VF is the bit shifted out
+    ShiftRight,
+    // 8XY7, VX = VY - VX, VF is set when there is no borrow
+    SubtractReversed,
+    // 8XYE, VF is the bit shifted out
+    ShiftLeft,
+}
+
+// The value to write to VX, then the flag to write to VF, None when VF is left unchanged.
+pub fn execute(operation: Operation, vx: u8, vy: u8, quirks: Quirks) -> (u8, Option<u8>) {
+    // the shifts read VY unless the shift quirk is enabled
+    let shifted = if quirks.shift { vx } else { vy };
+    let logic_flag = quirks.vf_reset.then_some(0);
+    match operation {
+        Operation::Load => (vy, None),
+        Operation::Or => (vx | vy, logic_flag),
+        Operation::And => (vx & vy, logic_flag),
+        Operation::Xor => (vx ^ vy, logic_flag),
+        Operation::Add => {
+            let (value, carry) = vx.overflowing_add(vy);
+            (value, Some(carry as u8))
+        }
+        Operation::Subtract => {
+            let (value, borrow) = vx.overflowing_sub(vy);
+            (value, Some(!borrow as u8))
+        }
+        Operation::ShiftRight => (shifted >> 1, Some(shifted & 0x01)),
+        Operation::SubtractReversed => {
+            let (value, borrow) = vy.overflowing_sub(vx);
+            (value, Some(!borrow as u8))
+        }
+        Operation::ShiftLeft => (shifted << 1, Some(shifted >> 7)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const OPERATIONS: [Operation; 9] = [
+        Operation::Load,
+        Operation::Or,
+        Operation::And,
+        Operation::Xor,
+        Operation::Add,
+        Operation::Subtract,
+        Operation::ShiftRight,
+        Operation::SubtractReversed,
+        Operation::ShiftLeft,
+    ];
+
+    // The expected results, computed on wider integers, for every pair of operands.
+    #[test]
+    fn exhaustive() {
+        for quirks in [
+            Quirks::default(),
+            Quirks {
+                shift: false,
+                vf_reset: true,
+                ..Quirks::default()
+            },
+        ] {
+            for vx in 0..=255u16 {
+                for vy in 0..=255u16 {
+                    let shifted = if quirks.shift { vx } else { vy };
+                    let logic_flag = quirks.vf_reset.then_some(0);
+                    for operation in OPERATIONS {
+                        let expected = match operation {
+                            Operation::Load => (vy, None),
+                            Operation::Or => (vx | vy, logic_flag),
+                            Operation::And => (vx & vy, logic_flag),
+                            Operation::Xor => (vx ^ vy, logic_flag),
+                            Operation::Add => ((vx + vy) % 256, Some((vx + vy >= 256) as u8)),
+                            Operation::Subtract => ((vx + 256 - vy) % 256, Some((vx >= vy) as u8)),
+                            Operation::ShiftRight => (shifted / 2, Some((shifted % 2) as u8)),
+                            Operation::SubtractReversed => {
+                                ((vy + 256 - vx) % 256, Some((vy >= vx) as u8))
+                            }
+                            Operation::ShiftLeft => {
+                                (shifted * 2 % 256, Some((shifted >= 128) as u8))
+                            }
+                        };
+                        let (value, flag) = execute(operation, vx as u8, vy as u8, quirks);
+                        assert_eq!(
+                            (value as u16, flag),
+                            expected,
+                            "{:?} {:#04X} {:#04X} {:?}",
+                            operation,
+                            vx,
+                            vy,
+                            quirks
+                        );
+                    }
+                }
+            }
+        }
+    }
+}
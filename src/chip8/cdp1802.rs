@@ -0,0 +1,479 @@
+// RCA CDP1802, the CPU of the COSMAC VIP, for the machine code routines called by 0NNN.
+//
+// The routine runs against the shared memory, with the registers set up the way the original
+// interpreter leaves them: R3 is the program counter, R4 the interpreter loop (SEP R4 returns to
+// it), R5 the CHIP-8 PC, R6 and R7 point at VX and VY, R8 holds the timers and RA is I. As on a
+// 4 KiB VIP, V0-VF are at 0xEF0 and the display at 0xF00, synchronized around the call.
+use super::{Chip8, Error, HEIGHT, MEM_SIZE};
+
+const VARIABLES: usize = 0xEF0;
+const DISPLAY: usize = 0xF00;
+// the interpreter stack pointer, its stack grows down from there
+const STACK: u16 = 0xECF;
+// machine cycles a routine may run for, about one second, before it is deemed stuck
+const MAX_CYCLES: u64 = 220_000;
+
+// What to do with 0NNN.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum MachineCode {
+    // stop with an error
+    #[default]
+    Fault,
+    // skip the instruction
+    Ignore,
+    // run the routine on the CDP1802
+    Cdp1802,
+}
+
+impl MachineCode {
+    pub fn name(&self) -> &'static str {
+        match self {
+            MachineCode::Fault => "fault",
+            MachineCode::Ignore => "ignore",
+            MachineCode::Cdp1802 => "cdp1802",
+        }
+    }
+}
+
+impl std::str::FromStr for MachineCode {
+    type Err = String;
+
+    fn from_str(name: &str) -> Result<MachineCode, String> {
+        match name {
+            "fault" => Ok(MachineCode::Fault),
+            "ignore" => Ok(MachineCode::Ignore),
+            "cdp1802" | "1802" => Ok(MachineCode::Cdp1802),
+            _ => Err(format!(
+                "unknown machine code mode '{}', expected fault, ignore or cdp1802",
+                name
+            )),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Cdp1802 {
+    pub r: [u16; 16],
+    pub d: u8,
+    pub df: bool,
+    // index of the program counter register
+    pub p: u8,
+    // index of the data pointer register
+    pub x: u8,
+    pub t: u8,
+    pub ie: bool,
+    pub q: bool,
+    // keypad key selected by OUT 2, read back through EF3
+    key_latch: u8,
+}
+
+impl Cdp1802 {
+    fn fetch(&mut self, memory: &[u8]) -> u8 {
+        let p = self.p as usize;
+        let value = memory[self.r[p] as usize % MEM_SIZE];
+        self.r[p] = self.r[p].wrapping_add(1);
+        value
+    }
+
+    // Execute one instruction, returning its machine cycles.
+    pub fn step(&mut self, memory: &mut [u8], keys: &[bool; 16]) -> u32 {
+        let opcode = self.fetch(memory);
+        let (i, n) = (opcode >> 4, (opcode & 0xF) as usize);
+        let rx = self.x as usize;
+        let at = |address: u16| address as usize % MEM_SIZE;
+        let m_rx = memory[at(self.r[rx])];
+        match i {
+            // IDL waits for an interrupt or a DMA request, there are none here
+            0x0 if n == 0 => {}
+            0x0 => self.d = memory[at(self.r[n])],
+            0x1 => self.r[n] = self.r[n].wrapping_add(1),
+            0x2 => self.r[n] = self.r[n].wrapping_sub(1),
+            0x3 => {
+                let condition = self.condition(n, keys);
+                let target = self.fetch(memory);
+                if condition {
+                    let p = self.p as usize;
+                    // the target is in the page of the branch address byte
+                    self.r[p] = (self.r[p].wrapping_sub(1) & 0xFF00) | target as u16;
+                }
+            }
+            0x4 => {
+                self.d = memory[at(self.r[n])];
+                self.r[n] = self.r[n].wrapping_add(1);
+            }
+            0x5 => memory[at(self.r[n])] = self.d,
+            0x6 => match n {
+                // IRX
+                0x0 => self.r[rx] = self.r[rx].wrapping_add(1),
+                // OUT, OUT 2 latches the keypad key
+                0x1..=0x7 => {
+                    if n == 2 {
+                        self.key_latch = m_rx & 0xF;
+                    }
+                    self.r[rx] = self.r[rx].wrapping_add(1);
+                }
+                // undefined on the 1802
+                0x8 => {}
+                // INP, nothing is connected to the bus
+                _ => {
+                    memory[at(self.r[rx])] = 0;
+                    self.d = 0;
+                }
+            },
+            0x7 => self.execute_7n(n, memory, m_rx),
+            0x8 => self.d = self.r[n] as u8,
+            0x9 => self.d = (self.r[n] >> 8) as u8,
+            0xA => self.r[n] = self.r[n] & 0xFF00 | self.d as u16,
+            0xB => self.r[n] = self.r[n] & 0x00FF | (self.d as u16) << 8,
+            0xC => return self.execute_long(n, memory),
+            0xD => self.p = n as u8,
+            0xE => self.x = n as u8,
+            _ => {
+                // F8-FF, but SHL, take their operand after the opcode instead of at R(X)
+                let operand = if n >= 8 && n != 0xE {
+                    self.fetch(memory)
+                } else {
+                    m_rx
+                };
+                match n & 7 {
+                    0x0 => self.d = operand,
+                    0x1 => self.d |= operand,
+                    0x2 => self.d &= operand,
+                    0x3 => self.d ^= operand,
+                    0x4 => self.add(operand, self.d, false),
+                    // SD: operand minus D
+                    0x5 => self.add(operand, !self.d, true),
+                    0x6 if n == 0x6 => {
+                        self.df = self.d & 1 != 0;
+                        self.d >>= 1;
+                    }
+                    0x6 => {
+                        self.df = self.d & 0x80 != 0;
+                        self.d <<= 1;
+                    }
+                    // SM: D minus operand
+                    _ => self.add(self.d, !operand, true),
+                }
+            }
+        }
+        2
+    }
+
+    // D = a + b + carry, DF being the carry out. Subtractions add the complement, so that DF
+    // is set when there is no borrow.
+    fn add(&mut self, a: u8, b: u8, carry: bool) {
+        let sum = a as u16 + b as u16 + carry as u16;
+        self.d = sum as u8;
+        self.df = sum > 0xFF;
+    }
+
+    fn execute_7n(&mut self, n: usize, memory: &mut [u8], m_rx: u8) {
+        let rx = self.x as usize;
+        let at = |address: u16| address as usize % MEM_SIZE;
+        let p = self.p as usize;
+        match n {
+            // RET and DIS
+            0x0 | 0x1 => {
+                self.x = m_rx >> 4;
+                self.p = m_rx & 0xF;
+                self.r[rx] = self.r[rx].wrapping_add(1);
+                self.ie = n == 0;
+            }
+            // LDXA
+            0x2 => {
+                self.d = m_rx;
+                self.r[rx] = self.r[rx].wrapping_add(1);
+            }
+            // STXD
+            0x3 => {
+                memory[at(self.r[rx])] = self.d;
+                self.r[rx] = self.r[rx].wrapping_sub(1);
+            }
+            // ADC
+            0x4 => self.add(m_rx, self.d, self.df),
+            // SDB
+            0x5 => self.add(m_rx, !self.d, self.df),
+            // SHRC
+            0x6 => {
+                let carry = self.df;
+                self.df = self.d & 1 != 0;
+                self.d = self.d >> 1 | (carry as u8) << 7;
+            }
+            // SMB
+            0x7 => self.add(self.d, !m_rx, self.df),
+            // SAV
+            0x8 => memory[at(self.r[rx])] = self.t,
+            // MARK
+            0x9 => {
+                self.t = self.x << 4 | self.p;
+                memory[at(self.r[2])] = self.t;
+                self.x = self.p;
+                self.r[2] = self.r[2].wrapping_sub(1);
+            }
+            0xA => self.q = false,
+            0xB => self.q = true,
+            // ADCI, SDBI, SHLC and SMBI
+            _ => {
+                if n == 0xE {
+                    let carry = self.df;
+                    self.df = self.d & 0x80 != 0;
+                    self.d = self.d << 1 | carry as u8;
+                    return;
+                }
+                let operand = memory[at(self.r[p])];
+                self.r[p] = self.r[p].wrapping_add(1);
+                match n {
+                    0xC => self.add(operand, self.d, self.df),
+                    0xD => self.add(operand, !self.d, self.df),
+                    _ => self.add(self.d, !operand, self.df),
+                }
+            }
+        }
+    }
+
+    // Long branches and skips, which take 3 machine cycles.
+    fn execute_long(&mut self, n: usize, memory: &[u8]) -> u32 {
+        let p = self.p as usize;
+        let condition = match n & 3 {
+            0 => true,
+            1 => self.q,
+            2 => self.d == 0,
+            _ => self.df,
+        };
+        match n {
+            // NOP
+            0x4 => {}
+            // LBR, LBQ, LBZ, LBDF, and their negations
+            0x0..=0x3 | 0x8..=0xB => {
+                // C8, the negation of LBR, is LSKP
+                if condition == (n >= 8) {
+                    self.r[p] = self.r[p].wrapping_add(2);
+                } else {
+                    let high = memory[self.r[p] as usize % MEM_SIZE] as u16;
+                    let low = memory[self.r[p].wrapping_add(1) as usize % MEM_SIZE] as u16;
+                    self.r[p] = high << 8 | low;
+                }
+            }
+            // LSIE
+            0xC => {
+                if self.ie {
+                    self.r[p] = self.r[p].wrapping_add(2);
+                }
+            }
+            // LSNQ, LSNZ, LSNF, LSQ, LSZ and LSDF
+            _ => {
+                let skip = if n < 8 { !condition } else { condition };
+                if skip {
+                    self.r[p] = self.r[p].wrapping_add(2);
+                }
+            }
+        }
+        3
+    }
+
+    // Condition of the short branches 3N.
+    fn condition(&self, n: usize, keys: &[bool; 16]) -> bool {
+        let condition = match n & 7 {
+            0 => true,
+            1 => self.q,
+            2 => self.d == 0,
+            3 => self.df,
+            // EF3 is set while the latched key is held, EF1, EF2 and EF4 are never set
+            6 => keys[self.key_latch as usize],
+            _ => false,
+        };
+        // 38 is SKP, which never branches
+        if n == 8 {
+            false
+        } else {
+            condition != (n >= 8)
+        }
+    }
+}
+
+// Run the machine code routine at `address` called by the 0NNN at PC, until it returns to the
+// interpreter. Returns the machine cycles it took.
+pub fn call(chip8: &mut Chip8, address: u16) -> Result<u64, Error> {
+    let pc = chip8.program_counter();
+    let opcode = chip8.opcode().unwrap_or(0);
+    let mut cpu = Cdp1802 {
+        p: 3,
+        x: 2,
+        ..Cdp1802::default()
+    };
+    cpu.r[2] = STACK;
+    cpu.r[3] = address;
+    cpu.r[5] = pc.wrapping_add(2);
+    cpu.r[6] = (VARIABLES + (opcode >> 8 & 0xF) as usize) as u16;
+    cpu.r[7] = (VARIABLES + (opcode >> 4 & 0xF) as usize) as u16;
+    cpu.r[8] = (chip8.delay_timer() as u16) << 8 | chip8.sound_timer() as u16;
+    cpu.r[0xA] = chip8.index();
+    // RB.1 is the page of the display
+    cpu.r[0xB] = DISPLAY as u16;
+
+    let registers = chip8.registers();
+    let keys = chip8.keypad();
+    let gfx = *chip8.gfx_rows();
+    let memory = chip8.memory_mut();
+    memory[VARIABLES..VARIABLES + 16].copy_from_slice(&registers);
+    // 8 bytes per row, the leftmost pixel in the most significant bit, as the framebuffer
+    for (y, row) in gfx.iter().enumerate() {
+        memory[DISPLAY + 8 * y..DISPLAY + 8 * (y + 1)].copy_from_slice(&row.to_be_bytes());
+    }
+
+    let mut cycles = 0;
+    while cpu.p != 4 {
+        if cycles > MAX_CYCLES {
+            return Err(Error::MachineCodeTimeout {
+                pc,
+                address: cpu.r[cpu.p as usize],
+            });
+        }
+        cycles += cpu.step(memory, &keys) as u64;
+    }
+
+    let mut registers = [0; 16];
+    registers.copy_from_slice(&memory[VARIABLES..VARIABLES + 16]);
+    let mut gfx = [0; HEIGHT];
+    for (y, row) in gfx.iter_mut().enumerate() {
+        let bytes = &memory[DISPLAY + 8 * y..DISPLAY + 8 * (y + 1)];
+        *row = u64::from_be_bytes(bytes.try_into().unwrap());
+    }
+    for (r, &value) in registers.iter().enumerate() {
+        chip8.set_register(r, value);
+    }
+    for (y, &row) in gfx.iter().enumerate() {
+        chip8.set_gfx_row(y, row);
+    }
+    chip8.set_draw_flag();
+    chip8.set_index(cpu.r[0xA]);
+    chip8.set_delay_timer((cpu.r[8] >> 8) as u8);
+    chip8.set_sound_timer(cpu.r[8] as u8);
+    chip8.set_program_counter(cpu.r[5]);
+    Ok(cycles)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chip8::{init, WIDTH};
+
+    // A ROM calling the routine at 0x300 with `call`, then looping.
+    fn machine(call: [u8; 2], routine: &[u8]) -> Chip8 {
+        let mut rom = vec![0; 0x100 + routine.len()];
+        rom[..4].copy_from_slice(&[call[0], call[1], 0x12, 0x02]);
+        rom[0x100..].copy_from_slice(routine);
+        let mut chip8 = init();
+        chip8.set_machine_code(MachineCode::Cdp1802);
+        chip8.load_rom(rom).unwrap();
+        chip8
+    }
+
+    // Run a routine on a bare CPU, with the program counter in R3.
+    fn run(program: &[u8], steps: usize) -> Cdp1802 {
+        let mut memory = [0; MEM_SIZE];
+        memory[..program.len()].copy_from_slice(program);
+        let mut cpu = Cdp1802 {
+            p: 3,
+            ..Cdp1802::default()
+        };
+        cpu.r[2] = 0x100;
+        for _ in 0..steps {
+            cpu.step(&mut memory, &[false; 16]);
+        }
+        cpu
+    }
+
+    #[test]
+    fn modes() {
+        let mut chip8 = machine([0x03, 0x00], &[0xD4]);
+        chip8.set_machine_code(MachineCode::Fault);
+        assert_eq!(
+            chip8.tick(),
+            Err(Error::MachineCode {
+                pc: 0x200,
+                address: 0x300
+            })
+        );
+        chip8.set_machine_code(MachineCode::Ignore);
+        chip8.tick().unwrap();
+        assert_eq!(chip8.program_counter(), 0x202);
+    }
+
+    #[test]
+    fn registers() {
+        // LDI 0x42, STR R6 (V3), LDI 0x05, PLO RA (I), SEP R4
+        let mut chip8 = machine([0x03, 0x00], &[0xF8, 0x42, 0x56, 0xF8, 0x05, 0xAA, 0xD4]);
+        chip8.set_index(0x123);
+        chip8.tick().unwrap();
+        assert_eq!(chip8.register(3), 0x42);
+        assert_eq!(chip8.index(), 0x105);
+        assert_eq!(chip8.program_counter(), 0x202);
+        assert!(chip8.machine_cycles() > 5 * 2);
+    }
+
+    #[test]
+    fn display() {
+        // RF = 0xF00, LDI 0xFF, STR RF, SEP R4
+        let routine = [0xF8, 0x0F, 0xBF, 0xF8, 0x00, 0xAF, 0xF8, 0xFF, 0x5F, 0xD4];
+        let mut chip8 = machine([0x03, 0x00], &routine);
+        chip8.set_gfx(WIDTH, true);
+        chip8.tick().unwrap();
+        assert!((0..8).all(|position| chip8.gfx(position)));
+        assert!(!chip8.gfx(8));
+        // the rest of the display is kept
+        assert!(chip8.gfx(WIDTH));
+        assert!(chip8.draw_flag());
+    }
+
+    #[test]
+    fn stuck_routine() {
+        // BR 0x00, looping on itself
+        let mut chip8 = machine([0x03, 0x00], &[0x30, 0x00]);
+        assert_eq!(
+            chip8.tick(),
+            Err(Error::MachineCodeTimeout {
+                pc: 0x200,
+                address: 0x300
+            })
+        );
+    }
+
+    #[test]
+    fn arithmetic() {
+        // LDI 0x10, SMI 0x20: borrow
+        let cpu = run(&[0xF8, 0x10, 0xFF, 0x20], 2);
+        assert_eq!((cpu.d, cpu.df), (0xF0, false));
+        // LDI 0x10, SDI 0x20: 0x20 - 0x10, no borrow
+        let cpu = run(&[0xF8, 0x10, 0xFD, 0x20], 2);
+        assert_eq!((cpu.d, cpu.df), (0x10, true));
+        // LDI 0xFF, ADI 0x02, ADCI 0x00: the carry is added
+        let cpu = run(&[0xF8, 0xFF, 0xFC, 0x02, 0x7C, 0x00], 3);
+        assert_eq!((cpu.d, cpu.df), (0x02, false));
+        // LDI 0x81, SHL, SHRC: the carry goes around
+        let cpu = run(&[0xF8, 0x81, 0xFE, 0x76], 3);
+        assert_eq!((cpu.d, cpu.df), (0x81, false));
+    }
+
+    #[test]
+    fn branches() {
+        // LDI 0, BZ 0x10 ; at 0x10: LBNZ 0x0100, LDI 1
+        let mut program = vec![0xF8, 0x00, 0x32, 0x10];
+        program.resize(0x10, 0);
+        program.extend([0xCA, 0x01, 0x00, 0xF8, 0x01]);
+        let cpu = run(&program, 4);
+        assert_eq!((cpu.r[3], cpu.d), (0x15, 1));
+        // LDI 1, LSNZ, LDI 2, LDI 3: the skipped LDI 2 is two bytes
+        let cpu = run(&[0xF8, 0x01, 0xC6, 0xF8, 0x02, 0xF8, 0x03], 3);
+        assert_eq!(cpu.d, 3);
+    }
+
+    #[test]
+    fn mark_and_return() {
+        // SEX 3, MARK, SEX 5, RET: T holds X=3, P=3, restored by RET from the stack
+        let cpu = run(&[0xE3, 0x79, 0xE2, 0x12, 0x70], 5);
+        assert_eq!(cpu.t, 0x33);
+        assert_eq!((cpu.x, cpu.p), (3, 3));
+        assert!(cpu.ie);
+    }
+}
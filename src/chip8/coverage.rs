@@ -0,0 +1,63 @@
+use super::{AccessKind, MEM_SIZE};
+
+const EXECUTED: u8 = 1;
+const READ: u8 = 2;
+const WRITTEN: u8 = 4;
+
+// How each memory byte has been used since coverage tracking was enabled.
+#[derive(Debug, Clone)]
+pub struct Coverage {
+    flags: Vec<u8>,
+    // number of executions of the instruction starting at each address
+    executions: Vec<u32>,
+}
+
+impl Coverage {
+    pub fn new() -> Coverage {
+        Coverage {
+            flags: vec![0; MEM_SIZE],
+            executions: vec![0; MEM_SIZE],
+        }
+    }
+
+    pub(super) fn execute(&mut self, pc: usize) {
+        self.executions[pc] = self.executions[pc].saturating_add(1);
+        self.flags[pc] |= EXECUTED;
+        self.flags[pc + 1] |= EXECUTED;
+    }
+
+    pub(super) fn access(&mut self, kind: AccessKind, address: usize) {
+        self.flags[address] |= match kind {
+            AccessKind::Read => READ,
+            AccessKind::Write => WRITTEN,
+        };
+    }
+
+    pub fn executions(&self, address: usize) -> u32 {
+        self.executions[address]
+    }
+
+    // Part of an executed instruction.
+    pub fn is_executed(&self, address: usize) -> bool {
+        self.flags[address] & EXECUTED != 0
+    }
+
+    // Read as data, by DXYN, FX65 or any other memory_at_index read.
+    pub fn is_read(&self, address: usize) -> bool {
+        self.flags[address] & READ != 0
+    }
+
+    pub fn is_written(&self, address: usize) -> bool {
+        self.flags[address] & WRITTEN != 0
+    }
+
+    pub fn is_covered(&self, address: usize) -> bool {
+        self.flags[address] != 0
+    }
+}
+
+impl Default for Coverage {
+    fn default() -> Coverage {
+        Coverage::new()
+    }
+}
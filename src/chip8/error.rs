@@ -0,0 +1,52 @@
+use std::fmt;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Error {
+    // ROM size, in bytes
+    RomTooBig(usize),
+    PcOutOfBounds(u16),
+    // the following errors hold the address of the faulty instruction
+    StackOverflow(u16),
+    StackUnderflow(u16),
+    UnknownOpcode { pc: u16, opcode: u16 },
+    // 0NNN, calls a RCA 1802 machine code routine at NNN
+    MachineCode { pc: u16, address: u16 },
+    // the machine code routine called at `pc` did not return, `address` is where the CDP1802 was
+    MachineCodeTimeout { pc: u16, address: u16 },
+    // I points too close to the end of the memory for the access at `address`
+    MemoryOutOfBounds { pc: u16, address: usize },
+    // a save state of another size or version
+    InvalidState,
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::RomTooBig(size) => write!(f, "ROM is too big ({} bytes)", size),
+            Error::PcOutOfBounds(pc) => write!(f, "PC points outside of the memory ({:#05X})", pc),
+            Error::StackOverflow(pc) => write!(f, "stack overflow at {:#05X}", pc),
+            Error::StackUnderflow(pc) => write!(f, "stack underflow at {:#05X}", pc),
+            Error::UnknownOpcode { pc, opcode } => {
+                write!(f, "unknown opcode {:04X} at {:#05X}", opcode, pc)
+            }
+            Error::MachineCode { pc, address } => write!(
+                f,
+                "RCA 1802 machine code call to {:#05X} at {:#05X} not emulated",
+                address, pc
+            ),
+            Error::MachineCodeTimeout { pc, address } => write!(
+                f,
+                "RCA 1802 machine code called at {:#05X} did not return, stuck at {:#05X}",
+                pc, address
+            ),
+            Error::MemoryOutOfBounds { pc, address } => write!(
+                f,
+                "memory access at {:#06X} outside of the memory at {:#05X}",
+                address, pc
+            ),
+            Error::InvalidState => write!(f, "invalid save state"),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
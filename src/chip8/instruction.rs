@@ -0,0 +1,184 @@
+// Decoded instructions, so that an opcode is split into its operands once and not on every
+// execution. `tick` caches them per address, see processor.rs.
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Instruction {
+    // 00E0 - Clear the screen.
+    ClearScreen,
+    // 00EE - Returns from a subroutine.
+    Return,
+    // 0NNN - Calls machine code routine (RCA 1802 for COSMAC VIP) at address NNN.
+    MachineCode(u16),
+    // 1NNN - Jump to address NNN.
+    Jump(u16),
+    // 2NNN - Calls subroutine at NNN.
+    Call(u16),
+    // 3XNN - Skips next instruction if VX equals NN.
+    SkipEqual(u8, u8),
+    // 4XNN - Skips the next instruction if VX does not equals NN.
+    SkipNotEqual(u8, u8),
+    // 5XY0 - Skips the next instruction if VX equals VY.
+    SkipEqualRegisters(u8, u8),
+    // 6XNN - Set VX to NN.
+    Load(u8, u8),
+    // 7XNN - Adds NN to VX (VF is not changed).
+    Add(u8, u8),
+    // 8XY0 - Sets VX to the value of VY.
+    LoadRegister(u8, u8),
+    // 8XY1 - Sets VX to VX or VY.
+    Or(u8, u8),
+    // 8XY2 - Sets VX to VX and VY.
+    And(u8, u8),
+    // 8XY3 - Sets VX to VX xor VY.
+    Xor(u8, u8),
+    // 8XY4 - Adds VY to VX. VF is set to 1 when there's a carry, and to 0 when there is not.
+    AddRegister(u8, u8),
+    // 8XY5 - VY is subtracted from VX. VF is set to 0 when there's a borrow, and 1 when there is not.
+    Subtract(u8, u8),
+    // 8XY6 - Stores the least significant bit of VX in VF and then shifts VX to the right by 1.
+    ShiftRight(u8, u8),
+    // 8XY7 - Sets VX to VY minus VX. VF is set to 0 when there's a borrow, and 1 when there is not.
+    SubtractReversed(u8, u8),
+    // 8XYE - Stores the most significant bit of VX in VF and then shifts VX to the left by 1.
+    ShiftLeft(u8, u8),
+    // 9XY0 - Skips the next instruction if VX does not equal VY.
+    SkipNotEqualRegisters(u8, u8),
+    // ANNN - Sets I to the address NNN
+    LoadIndex(u16),
+    // BNNN - Jumps to the address NNN plus V0 (or XNN plus VX with the jump quirk).
+    JumpOffset(u8, u16),
+    // CXNN - Sets VX to the result of a bitwise and operation on a random number (Typically: 0 to 255) and NN.
+    Random(u8, u8),
+    // DXYN - Draws a sprite at coordinate (VX, VY) that has a width of 8 pixels and a height of
+    // N pixels. Each row of 8 pixels is read as bit-coded starting from memory location I; I
+    // value does not change after the execution of this instruction. VF is set to 1 if any
+    // screen pixels are flipped from set to unset when the sprite is drawn, and to 0 if that
+    // does not happen.
+    Draw(u8, u8, u8),
+    // EX9E - Skips the next instruction if the key stored in VX is pressed.
+    SkipKeyPressed(u8),
+    // EXA1 - Skips the next instruction if the key stored in VX is not pressed.
+    SkipKeyNotPressed(u8),
+    // FX07 - Sets VX to the value of the delay timer.
+    LoadDelayTimer(u8),
+    // FX0A - A key press is awaited, and then stored in VX.
+    WaitKey(u8),
+    // FX15 - Sets the delay timer to VX.
+    SetDelayTimer(u8),
+    // FX18 - Sets the sound timer to VX.
+    SetSoundTimer(u8),
+    // FX1E - Adds VX to I. VF is not affected.
+    AddIndex(u8),
+    // FX29 - Sets I to the location of the sprite for the character in VX.
+    LoadFont(u8),
+    // FX33 - Stores the binary-coded decimal representation of VX, with the most significant of three digits at the address in I, the middle digit at I plus 1, and the least significant digit at I plus 2.
+    StoreBcd(u8),
+    // FX55 - Stores from V0 to VX (including VX) in memory, starting at address I. The offset from I is increased by 1 for each value written.
+    StoreRegisters(u8),
+    // FX65 - Fills from V0 to VX (including VX) with values from memory, starting at address I. The offset from I is increased by 1 for each value read.
+    LoadRegisters(u8),
+    Unknown(u16),
+}
+
+impl Instruction {
+    pub fn decode(opcode: u16) -> Instruction {
+        let x = (opcode >> 8 & 0xF) as u8;
+        let y = (opcode >> 4 & 0xF) as u8;
+        let n = (opcode & 0xF) as u8;
+        let nn = (opcode & 0xFF) as u8;
+        let nnn = opcode & 0xFFF;
+
+        match (opcode >> 12, x, y, n) {
+            (0x0, 0x0, 0xE, 0x0) => Instruction::ClearScreen,
+            (0x0, 0x0, 0xE, 0xE) => Instruction::Return,
+            (0x0, _, _, _) => Instruction::MachineCode(nnn),
+            (0x1, _, _, _) => Instruction::Jump(nnn),
+            (0x2, _, _, _) => Instruction::Call(nnn),
+            (0x3, _, _, _) => Instruction::SkipEqual(x, nn),
+            (0x4, _, _, _) => Instruction::SkipNotEqual(x, nn),
+            (0x5, _, _, 0x0) => Instruction::SkipEqualRegisters(x, y),
+            (0x6, _, _, _) => Instruction::Load(x, nn),
+            (0x7, _, _, _) => Instruction::Add(x, nn),
+            (0x8, _, _, 0x0) => Instruction::LoadRegister(x, y),
+            (0x8, _, _, 0x1) => Instruction::Or(x, y),
+            (0x8, _, _, 0x2) => Instruction::And(x, y),
+            (0x8, _, _, 0x3) => Instruction::Xor(x, y),
+            (0x8, _, _, 0x4) => Instruction::AddRegister(x, y),
+            (0x8, _, _, 0x5) => Instruction::Subtract(x, y),
+            (0x8, _, _, 0x6) => Instruction::ShiftRight(x, y),
+            (0x8, _, _, 0x7) => Instruction::SubtractReversed(x, y),
+            (0x8, _, _, 0xE) => Instruction::ShiftLeft(x, y),
+            (0x9, _, _, 0x0) => Instruction::SkipNotEqualRegisters(x, y),
+            (0xA, _, _, _) => Instruction::LoadIndex(nnn),
+            (0xB, _, _, _) => Instruction::JumpOffset(x, nnn),
+            (0xC, _, _, _) => Instruction::Random(x, nn),
+            (0xD, _, _, _) => Instruction::Draw(x, y, n),
+            (0xE, _, 0x9, 0xE) => Instruction::SkipKeyPressed(x),
+            (0xE, _, 0xA, 0x1) => Instruction::SkipKeyNotPressed(x),
+            (0xF, _, 0x0, 0x7) => Instruction::LoadDelayTimer(x),
+            (0xF, _, 0x0, 0xA) => Instruction::WaitKey(x),
+            (0xF, _, 0x1, 0x5) => Instruction::SetDelayTimer(x),
+            (0xF, _, 0x1, 0x8) => Instruction::SetSoundTimer(x),
+            (0xF, _, 0x1, 0xE) => Instruction::AddIndex(x),
+            (0xF, _, 0x2, 0x9) => Instruction::LoadFont(x),
+            (0xF, _, 0x3, 0x3) => Instruction::StoreBcd(x),
+            (0xF, _, 0x5, 0x5) => Instruction::StoreRegisters(x),
+            (0xF, _, 0x6, 0x5) => Instruction::LoadRegisters(x),
+            _ => Instruction::Unknown(opcode),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chip8::{disassemble, init};
+
+    // Every opcode decodes to an instruction exactly when the disassembler knows it.
+    #[test]
+    fn decode_matches_disassembly() {
+        for opcode in 0..=u16::MAX {
+            let unknown = Instruction::decode(opcode) == Instruction::Unknown(opcode);
+            assert_eq!(
+                unknown,
+                disassemble(opcode).starts_with("DW"),
+                "{:04X}",
+                opcode
+            );
+        }
+        assert_eq!(Instruction::decode(0xD12F), Instruction::Draw(1, 2, 0xF));
+        assert_eq!(
+            Instruction::decode(0xB345),
+            Instruction::JumpOffset(3, 0x345)
+        );
+    }
+
+    // Code overwriting itself runs the new instructions, with or without the cache.
+    #[test]
+    fn self_modifying_code() {
+        // 0x200: LD V0, 0x62
+        // 0x202: LD V1, 0x23
+        // 0x204: LD I, 0x20A
+        // 0x206: LD [I], V1
+        // 0x208: JP 0x20A
+        // 0x20A: LD V2, 0x00, overwritten with LD V2, 0x23
+        let rom = vec![
+            0x60, 0x62, 0x61, 0x23, 0xA2, 0x0A, 0xF1, 0x55, 0x12, 0x0A, 0x62, 0x00,
+        ];
+        for cache in [true, false] {
+            let mut chip8 = init();
+            chip8.set_decode_cache(cache);
+            chip8.load_rom(rom.clone()).unwrap();
+            // decode the instruction at 0x20A before it is overwritten
+            chip8.set_program_counter(0x20A);
+            chip8.tick().unwrap();
+            assert_eq!(chip8.register(2), 0x00);
+            chip8.set_program_counter(0x200);
+            for _ in 0..6 {
+                chip8.tick().unwrap();
+            }
+            assert_eq!(chip8.register(2), 0x23, "cache {}", cache);
+            assert_eq!(chip8.program_counter(), 0x20C);
+        }
+    }
+}
@@ -0,0 +1,473 @@
+// x86-64 JIT compiler, for large headless workloads.
+//
+// Runs of straight-line arithmetic (6XNN, 7XNN, 8XYN, ANNN, FX1E) are translated into native
+// blocks, ended by the jump or skip following them. Every other instruction is left to tick(),
+// so that the memory, the display, the timers and the keypad are only touched by the
+// interpreter, in between blocks. A block is recompiled once a page of memory it was compiled
+// from is written to.
+use super::instruction::Instruction;
+use super::processor::PAGE_SIZE;
+use super::{timing, Chip8, Error, Quirks, MEM_SIZE};
+use dynasmrt::x64::Assembler;
+use dynasmrt::{dynasm, AssemblyOffset, DynasmApi, ExecutableBuffer};
+
+// longest block, in instructions, so that a block and the instruction after it span at most
+// two pages
+const MAX_BLOCK: usize = PAGE_SIZE / 2 - 1;
+const VF: i32 = 0xF;
+
+// A block takes V0-VF and I, and returns the next PC.
+type Entry = extern "sysv64" fn(*mut u8, *mut u16) -> u32;
+
+struct Code {
+    // kept alive for the entry point
+    buffer: ExecutableBuffer,
+    entry: AssemblyOffset,
+    instructions: u64,
+    // machine cycles of the instructions before the last one, and of the last one
+    cycles: u64,
+    last_cycles: u64,
+    // address of the skip ending the block, which costs more when it skips
+    skip: Option<u16>,
+}
+
+struct Block {
+    // None when the instruction at the address cannot be compiled
+    code: Option<Code>,
+    // the bytes compiled, up to the instruction ending the block
+    start: usize,
+    source: Vec<u8>,
+    // write generations of the first and last pages of the source, when compiled
+    generations: [(usize, u32); 2],
+    // the shift and VF reset quirks are compiled in
+    quirks: Quirks,
+}
+
+impl Block {
+    // Whether the memory still holds the source, and the quirks are the same. Writes to its
+    // pages are usually data written next to the code, so the source is compared before
+    // recompiling.
+    fn is_current(&mut self, chip8: &Chip8) -> bool {
+        if self.quirks != chip8.quirks() {
+            return false;
+        }
+        let written = |&(page, generation): &(usize, u32)| {
+            chip8.write_generation(page * PAGE_SIZE) != generation
+        };
+        if !self.generations.iter().any(written) {
+            return true;
+        }
+        let unchanged = self
+            .source
+            .iter()
+            .enumerate()
+            .all(|(offset, &byte)| chip8.memory(self.start + offset) == byte);
+        if unchanged {
+            self.generations = generations(chip8, self.start, self.source.len());
+        }
+        unchanged
+    }
+}
+
+fn generations(chip8: &Chip8, start: usize, length: usize) -> [(usize, u32); 2] {
+    [start, start + length - 1]
+        .map(|address| (address / PAGE_SIZE, chip8.write_generation(address)))
+}
+
+pub struct Jit {
+    blocks: Vec<Option<Block>>,
+    cross_check: bool,
+}
+
+impl Default for Jit {
+    fn default() -> Self {
+        Jit::new()
+    }
+}
+
+impl Jit {
+    pub fn new() -> Jit {
+        Jit {
+            blocks: (0..MEM_SIZE).map(|_| None).collect(),
+            cross_check: false,
+        }
+    }
+
+    // Check every block against the interpreter, panicking on the first difference. For tests.
+    pub fn set_cross_check(&mut self, enabled: bool) {
+        self.cross_check = enabled;
+    }
+
+    // Same as Chip8::run_frame.
+    pub fn run_frame(&mut self, chip8: &mut Chip8, ipf: u32) -> Result<bool, Error> {
+        chip8.start_frame(ipf);
+        while chip8.frame_pending() {
+            self.step(chip8)?;
+        }
+        Ok(chip8.update_timer())
+    }
+
+    // Run the block at PC, or a single instruction with tick() when there is no block or it
+    // would not start within the frame.
+    pub fn step(&mut self, chip8: &mut Chip8) -> Result<(), Error> {
+        let pc = chip8.program_counter();
+        // coverage is recorded by tick()
+        if chip8.coverage().is_some() || pc as usize + 1 >= MEM_SIZE {
+            return chip8.tick();
+        }
+        let current = match &mut self.blocks[pc as usize] {
+            Some(block) => block.is_current(chip8),
+            None => false,
+        };
+        if !current {
+            self.blocks[pc as usize] = Some(compile(chip8, pc));
+        }
+        let code = match &self.blocks[pc as usize] {
+            Some(Block {
+                code: Some(code), ..
+            }) if chip8.fits_in_frame(code.instructions, code.cycles) => code,
+            _ => return chip8.tick(),
+        };
+
+        let saved = self.cross_check.then(|| (chip8.registers(), chip8.index()));
+        let entry: Entry = unsafe { std::mem::transmute(code.buffer.ptr(code.entry)) };
+        let (registers, index) = chip8.registers_and_index_mut();
+        let next = entry(registers.as_mut_ptr(), index) as u16;
+        if let Some(saved) = saved {
+            return cross_check(chip8, pc, code.instructions, saved, next);
+        }
+        let skipped = code.skip.is_some_and(|skip| next == skip.wrapping_add(4));
+        let cycles = code.cycles + code.last_cycles + if skipped { 4 } else { 0 };
+        chip8.set_program_counter(next);
+        chip8.retire(code.instructions, cycles);
+        Ok(())
+    }
+}
+
+// Run the block again with tick(), from the state it started with, and compare.
+fn cross_check(
+    chip8: &mut Chip8,
+    pc: u16,
+    instructions: u64,
+    (registers, index): ([u8; 16], u16),
+    next: u16,
+) -> Result<(), Error> {
+    let compiled = (chip8.registers(), chip8.index(), next);
+    for (x, &value) in registers.iter().enumerate() {
+        chip8.set_register(x, value);
+    }
+    chip8.set_index(index);
+    for _ in 0..instructions {
+        chip8.tick()?;
+    }
+    let interpreted = (chip8.registers(), chip8.index(), chip8.program_counter());
+    assert_eq!(
+        compiled, interpreted,
+        "block at {:#05X} differs from the interpreter",
+        pc
+    );
+    Ok(())
+}
+
+fn compile(chip8: &Chip8, start: u16) -> Block {
+    let quirks = chip8.quirks();
+    let mut ops = Assembler::new().expect("cannot allocate the JIT buffer");
+    let entry = ops.offset();
+    let mut pc = start;
+    let mut instructions = 0;
+    let mut cycles = 0;
+    let mut last_cycles = 0;
+    let mut skip = None;
+    // V0-VF are at [rdi], I at [rsi]
+    loop {
+        if instructions == MAX_BLOCK || pc as usize + 1 >= MEM_SIZE {
+            dynasm!(ops ; .arch x64 ; mov eax, pc as i32 ; ret);
+            break;
+        }
+        let opcode = (chip8.memory(pc as usize) as u16) << 8 | chip8.memory(pc as usize + 1) as u16;
+        let cost = timing::cost(opcode, 0, false) as u64;
+        let next = pc.wrapping_add(2) as i32;
+        let (x, y) = ((opcode >> 8 & 0xF) as i32, (opcode >> 4 & 0xF) as i32);
+        // the source of the shifts
+        let shifted = if quirks.shift { x } else { y };
+        match Instruction::decode(opcode) {
+            Instruction::Load(_, nn) => dynasm!(ops ; .arch x64 ; mov BYTE [rdi + x], nn as i8),
+            Instruction::Add(_, nn) => dynasm!(ops ; .arch x64 ; add BYTE [rdi + x], nn as i8),
+            Instruction::LoadRegister(..) => dynasm!(ops
+                ; .arch x64
+                ; mov al, [rdi + y]
+                ; mov [rdi + x], al
+            ),
+            Instruction::Or(..) | Instruction::And(..) | Instruction::Xor(..) => {
+                dynasm!(ops ; .arch x64 ; mov al, [rdi + x]);
+                match opcode & 0xF {
+                    0x1 => dynasm!(ops ; .arch x64 ; or al, [rdi + y]),
+                    0x2 => dynasm!(ops ; .arch x64 ; and al, [rdi + y]),
+                    _ => dynasm!(ops ; .arch x64 ; xor al, [rdi + y]),
+                }
+                dynasm!(ops ; .arch x64 ; mov [rdi + x], al);
+                if quirks.vf_reset {
+                    dynasm!(ops ; .arch x64 ; mov BYTE [rdi + VF], 0);
+                }
+            }
+            // the flags are the carry, or its negation for the subtractions; VF is written last
+            Instruction::AddRegister(..) => dynasm!(ops
+                ; .arch x64
+                ; mov al, [rdi + x]
+                ; add al, [rdi + y]
+                ; setc cl
+                ; mov [rdi + x], al
+                ; mov [rdi + VF], cl
+            ),
+            Instruction::Subtract(..) => dynasm!(ops
+                ; .arch x64
+                ; mov al, [rdi + x]
+                ; sub al, [rdi + y]
+                ; setnc cl
+                ; mov [rdi + x], al
+                ; mov [rdi + VF], cl
+            ),
+            Instruction::SubtractReversed(..) => dynasm!(ops
+                ; .arch x64
+                ; mov al, [rdi + y]
+                ; sub al, [rdi + x]
+                ; setnc cl
+                ; mov [rdi + x], al
+                ; mov [rdi + VF], cl
+            ),
+            Instruction::ShiftRight(..) => dynasm!(ops
+                ; .arch x64
+                ; mov al, [rdi + shifted]
+                ; shr al, 1
+                ; setc cl
+                ; mov [rdi + x], al
+                ; mov [rdi + VF], cl
+            ),
+            Instruction::ShiftLeft(..) => dynasm!(ops
+                ; .arch x64
+                ; mov al, [rdi + shifted]
+                ; shl al, 1
+                ; setc cl
+                ; mov [rdi + x], al
+                ; mov [rdi + VF], cl
+            ),
+            Instruction::LoadIndex(nnn) => dynasm!(ops ; .arch x64 ; mov WORD [rsi], nnn as i16),
+            Instruction::AddIndex(_) => dynasm!(ops
+                ; .arch x64
+                ; movzx eax, BYTE [rdi + x]
+                ; add [rsi], ax
+            ),
+            Instruction::Jump(nnn) => {
+                dynasm!(ops ; .arch x64 ; mov eax, nnn as i32 ; ret);
+                instructions += 1;
+                cycles += last_cycles;
+                last_cycles = cost;
+                break;
+            }
+            Instruction::SkipEqual(..)
+            | Instruction::SkipNotEqual(..)
+            | Instruction::SkipEqualRegisters(..)
+            | Instruction::SkipNotEqualRegisters(..) => {
+                match Instruction::decode(opcode) {
+                    Instruction::SkipEqual(_, nn) | Instruction::SkipNotEqual(_, nn) => {
+                        dynasm!(ops ; .arch x64 ; cmp BYTE [rdi + x], nn as i8)
+                    }
+                    _ => dynasm!(ops ; .arch x64 ; mov al, [rdi + x] ; cmp al, [rdi + y]),
+                }
+                dynasm!(ops ; .arch x64 ; mov eax, next ; mov ecx, next + 2);
+                match opcode >> 12 {
+                    0x3 | 0x5 => dynasm!(ops ; .arch x64 ; cmove eax, ecx),
+                    _ => dynasm!(ops ; .arch x64 ; cmovne eax, ecx),
+                }
+                dynasm!(ops ; .arch x64 ; ret);
+                instructions += 1;
+                cycles += last_cycles;
+                last_cycles = cost;
+                skip = Some(pc);
+                break;
+            }
+            // left to the interpreter
+            _ => {
+                dynasm!(ops ; .arch x64 ; mov eax, pc as i32 ; ret);
+                break;
+            }
+        }
+        instructions += 1;
+        cycles += last_cycles;
+        last_cycles = cost;
+        pc = next as u16;
+    }
+
+    let start = start as usize;
+    let end = (start + 2 * (instructions + 1)).min(MEM_SIZE);
+    let source = (start..end).map(|address| chip8.memory(address)).collect();
+    let generations = generations(chip8, start, end - start);
+    if instructions == 0 {
+        return Block {
+            code: None,
+            start,
+            source,
+            generations,
+            quirks,
+        };
+    }
+    let buffer = ops
+        .finalize()
+        .unwrap_or_else(|_| panic!("cannot finalize the JIT buffer"));
+    Block {
+        code: Some(Code {
+            buffer,
+            entry,
+            instructions: instructions as u64,
+            cycles,
+            last_cycles,
+            skip,
+        }),
+        start,
+        source,
+        generations,
+        quirks,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chip8::{init, Timing};
+    use rand::rngs::StdRng;
+    use rand::{Rng, SeedableRng};
+
+    fn machine(rom: &[u8]) -> Chip8 {
+        let mut chip8 = init();
+        chip8.load_rom(rom.to_vec()).unwrap();
+        chip8
+    }
+
+    #[test]
+    fn arithmetic_loop() {
+        // 0x200: LD V0, 0x00
+        // 0x202: ADD V0, 0x01
+        // 0x204: LD V1, V0
+        // 0x206: SHL V1, V1
+        // 0x208: SE V0, 0x80
+        // 0x20A: JP 0x202
+        // 0x20C: JP 0x20C
+        let rom = [
+            0x60, 0x00, 0x70, 0x01, 0x81, 0x00, 0x81, 0x1E, 0x30, 0x80, 0x12, 0x02, 0x12, 0x0C,
+        ];
+        let mut jit = Jit::new();
+        let mut chip8 = machine(&rom);
+        let mut interpreted = machine(&rom);
+        for _ in 0..10 {
+            jit.run_frame(&mut chip8, 100).unwrap();
+            interpreted.run_frame(100).unwrap();
+        }
+        assert_eq!(chip8.registers(), interpreted.registers());
+        assert_eq!(chip8.program_counter(), 0x20C);
+        assert_eq!(chip8.cycles(), interpreted.cycles());
+        assert_eq!(chip8.machine_cycles(), interpreted.machine_cycles());
+    }
+
+    #[test]
+    fn self_modifying_code() {
+        // 0x200: LD V2, 0x01
+        // 0x202: LD V0, 0x62
+        // 0x204: LD V1, 0x07
+        // 0x206: LD I, 0x200
+        // 0x208: LD [I], V1, rewriting 0x200 into LD V2, 0x07
+        // 0x20A: JP 0x200
+        let rom = [
+            0x62, 0x01, 0x60, 0x62, 0x61, 0x07, 0xA2, 0x00, 0xF1, 0x55, 0x12, 0x00,
+        ];
+        let mut jit = Jit::new();
+        let mut chip8 = machine(&rom);
+        chip8.start_frame(100);
+        // the first block runs up to FX55
+        jit.step(&mut chip8).unwrap();
+        assert_eq!(chip8.register(2), 0x01);
+        assert_eq!(chip8.program_counter(), 0x208);
+        // FX55, then the jump
+        jit.step(&mut chip8).unwrap();
+        jit.step(&mut chip8).unwrap();
+        assert_eq!(chip8.program_counter(), 0x200);
+        jit.step(&mut chip8).unwrap();
+        assert_eq!(chip8.register(2), 0x07);
+    }
+
+    #[test]
+    fn vip_timing() {
+        let rom = [0x70, 0x01, 0x81, 0x04, 0x12, 0x00];
+        let mut jit = Jit::new();
+        let mut chip8 = machine(&rom);
+        let mut interpreted = machine(&rom);
+        chip8.set_timing(Timing::CosmacVip);
+        interpreted.set_timing(Timing::CosmacVip);
+        for _ in 0..10 {
+            jit.run_frame(&mut chip8, 0).unwrap();
+            interpreted.run_frame(0).unwrap();
+            assert_eq!(chip8.cycles(), interpreted.cycles());
+            assert_eq!(chip8.machine_cycles(), interpreted.machine_cycles());
+        }
+    }
+
+    #[test]
+    fn quirks_change() {
+        // 0x200: LD V1, 0x02
+        // 0x202: LD V2, 0x10
+        // 0x204: SHR V1, V2
+        // 0x206: JP 0x200
+        let rom = [0x61, 0x02, 0x62, 0x10, 0x81, 0x26, 0x12, 0x00];
+        let mut jit = Jit::new();
+        let mut chip8 = machine(&rom);
+        let mut interpreted = machine(&rom);
+        for shift in [true, false, true] {
+            let quirks = Quirks {
+                shift,
+                ..Quirks::default()
+            };
+            chip8.set_quirks(quirks);
+            interpreted.set_quirks(quirks);
+            jit.run_frame(&mut chip8, 8).unwrap();
+            interpreted.run_frame(8).unwrap();
+            assert_eq!(chip8.register(1), if shift { 0x01 } else { 0x08 });
+            assert_eq!(chip8.registers(), interpreted.registers());
+        }
+    }
+
+    // Random arithmetic programs, cross-checked against the interpreter.
+    #[test]
+    fn cross_check_random_programs() {
+        let mut rng = StdRng::seed_from_u64(0x11);
+        for _ in 0..200 {
+            let mut rom = Vec::new();
+            for _ in 0..64 {
+                let opcode: u16 = match rng.gen_range(0..10) {
+                    0 => 0x1000 | rng.gen_range(0x200..0x280) & !1,
+                    1 => 0x3000 + (rng.gen::<u16>() & 0x1FFF),
+                    2 => [0x5000, 0x9000][rng.gen_range(0..2)] | rng.gen::<u16>() & 0x0FF0,
+                    3 => 0xA000 | rng.gen::<u16>() & 0x0FFF,
+                    4 => 0xF01E | rng.gen::<u16>() & 0x0F00,
+                    5..=7 => {
+                        let n = [0, 1, 2, 3, 4, 5, 6, 7, 0xE][rng.gen_range(0..9)];
+                        0x8000 | rng.gen::<u16>() & 0x0FF0 | n
+                    }
+                    _ => 0x6000 + (rng.gen::<u16>() & 0x1FFF),
+                };
+                rom.extend(opcode.to_be_bytes());
+            }
+            // loop at the end, even after a skip
+            rom.extend([0x12, 0x80, 0x12, 0x82]);
+            let mut chip8 = machine(&rom);
+            chip8.set_quirks(Quirks {
+                shift: rng.gen(),
+                vf_reset: rng.gen(),
+                ..Quirks::default()
+            });
+            let mut jit = Jit::new();
+            jit.set_cross_check(true);
+            for _ in 0..5 {
+                jit.run_frame(&mut chip8, 50).unwrap();
+            }
+        }
+    }
+}
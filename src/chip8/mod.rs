@@ -1,6 +1,32 @@
 pub const WIDTH: usize = 64;
 pub const HEIGHT: usize = 32;
+pub const MEM_SIZE: usize = 4096;
+// where ROMs are loaded, and the execution starts
+pub const START_ROM: usize = 0x0200;
 
+mod access;
+mod alu;
+mod cdp1802;
+mod coverage;
+mod disasm;
+mod error;
+mod instruction;
+#[cfg(all(feature = "jit", target_arch = "x86_64"))]
+mod jit;
 mod opcodes;
 mod processor;
-pub use processor::{init, Chip8};
+mod quirks;
+mod reference;
+mod timing;
+pub use access::{AccessKind, MemoryAccess};
+pub use cdp1802::MachineCode;
+pub use coverage::Coverage;
+pub use disasm::disassemble;
+pub use error::Error;
+pub use instruction::Instruction;
+#[cfg(all(feature = "jit", target_arch = "x86_64"))]
+pub use jit::Jit;
+pub use processor::{init, Chip8, STATE_SIZE};
+pub use quirks::{QuirkSettings, Quirks};
+pub use reference::{compare_with_reference, Reference};
+pub use timing::Timing;
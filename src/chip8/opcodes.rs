@@ -1,266 +1,763 @@
-use super::{Chip8, HEIGHT, WIDTH};
-use rand::Rng;
+use super::alu::{self, Operation};
+use super::{cdp1802, Chip8, Error, MachineCode, HEIGHT, WIDTH};
 
 pub trait InstructionSet {
-    fn process_00e0(&mut self);
-    fn process_00ee(&mut self);
-    fn process_1nnn(&mut self, nnn: u16);
-    fn process_2nnn(&mut self, nnn: u16);
-    fn process_3xnn(&mut self, x: usize, nn: u8);
-    fn process_4xnn(&mut self, x: usize, nn: u8);
-    fn process_5xy0(&mut self, x: usize, y: usize);
-    fn process_6xnn(&mut self, x: usize, nn: u8);
-    fn process_7xnn(&mut self, x: usize, nn: u8);
-    fn process_8xy0(&mut self, x: usize, y: usize);
-    fn process_8xy1(&mut self, x: usize, y: usize);
-    fn process_8xy2(&mut self, x: usize, y: usize);
-    fn process_8xy3(&mut self, x: usize, y: usize);
-    fn process_8xy4(&mut self, x: usize, y: usize);
-    fn process_8xy5(&mut self, x: usize, y: usize);
-    fn process_8xy6(&mut self, x: usize, y: usize);
-    fn process_8xy7(&mut self, x: usize, y: usize);
-    fn process_8xye(&mut self, x: usize, y: usize);
-    fn process_9xy0(&mut self, x: usize, y: usize);
-    fn process_annn(&mut self, nnn: u16);
-    fn process_bnnn(&mut self, nnn: u16);
-    fn process_cxnn(&mut self, x: usize, nn: u8);
-    fn process_dxyn(&mut self, x: usize, y: usize, n: u8);
-    fn process_ex9e(&mut self, x: usize);
-    fn process_exa1(&mut self, x: usize);
-    fn process_fx07(&mut self, x: usize);
-    fn process_fx0a(&mut self, x: usize);
-    fn process_fx15(&mut self, x: usize);
-    fn process_fx18(&mut self, x: usize);
-    fn process_fx1e(&mut self, x: usize);
-    fn process_fx29(&mut self, x: usize);
-    fn process_fx33(&mut self, x: usize);
-    fn process_fx55(&mut self, x: usize);
-    fn process_fx65(&mut self, x: usize);
+    fn process_0nnn(&mut self, nnn: u16) -> Result<(), Error>;
+    fn process_00e0(&mut self) -> Result<(), Error>;
+    fn process_00ee(&mut self) -> Result<(), Error>;
+    fn process_1nnn(&mut self, nnn: u16) -> Result<(), Error>;
+    fn process_2nnn(&mut self, nnn: u16) -> Result<(), Error>;
+    fn process_3xnn(&mut self, x: usize, nn: u8) -> Result<(), Error>;
+    fn process_4xnn(&mut self, x: usize, nn: u8) -> Result<(), Error>;
+    fn process_5xy0(&mut self, x: usize, y: usize) -> Result<(), Error>;
+    fn process_6xnn(&mut self, x: usize, nn: u8) -> Result<(), Error>;
+    fn process_7xnn(&mut self, x: usize, nn: u8) -> Result<(), Error>;
+    fn process_8xy0(&mut self, x: usize, y: usize) -> Result<(), Error>;
+    fn process_8xy1(&mut self, x: usize, y: usize) -> Result<(), Error>;
+    fn process_8xy2(&mut self, x: usize, y: usize) -> Result<(), Error>;
+    fn process_8xy3(&mut self, x: usize, y: usize) -> Result<(), Error>;
+    fn process_8xy4(&mut self, x: usize, y: usize) -> Result<(), Error>;
+    fn process_8xy5(&mut self, x: usize, y: usize) -> Result<(), Error>;
+    fn process_8xy6(&mut self, x: usize, y: usize) -> Result<(), Error>;
+    fn process_8xy7(&mut self, x: usize, y: usize) -> Result<(), Error>;
+    fn process_8xye(&mut self, x: usize, y: usize) -> Result<(), Error>;
+    fn process_9xy0(&mut self, x: usize, y: usize) -> Result<(), Error>;
+    fn process_annn(&mut self, nnn: u16) -> Result<(), Error>;
+    fn process_bnnn(&mut self, x: usize, nnn: u16) -> Result<(), Error>;
+    fn process_cxnn(&mut self, x: usize, nn: u8) -> Result<(), Error>;
+    fn process_dxyn(&mut self, x: usize, y: usize, n: u8) -> Result<(), Error>;
+    fn process_ex9e(&mut self, x: usize) -> Result<(), Error>;
+    fn process_exa1(&mut self, x: usize) -> Result<(), Error>;
+    fn process_fx07(&mut self, x: usize) -> Result<(), Error>;
+    fn process_fx0a(&mut self, x: usize) -> Result<(), Error>;
+    fn process_fx15(&mut self, x: usize) -> Result<(), Error>;
+    fn process_fx18(&mut self, x: usize) -> Result<(), Error>;
+    fn process_fx1e(&mut self, x: usize) -> Result<(), Error>;
+    fn process_fx29(&mut self, x: usize) -> Result<(), Error>;
+    fn process_fx33(&mut self, x: usize) -> Result<(), Error>;
+    fn process_fx55(&mut self, x: usize) -> Result<(), Error>;
+    fn process_fx65(&mut self, x: usize) -> Result<(), Error>;
+}
+
+impl Chip8 {
+    // 8XYN, see alu.rs for the order of the writes.
+    fn alu(&mut self, operation: Operation, x: usize, y: usize) -> Result<(), Error> {
+        let (value, flag) =
+            alu::execute(operation, self.register(x), self.register(y), self.quirks());
+        self.set_register(x, value);
+        if let Some(flag) = flag {
+            self.set_register(0xF, flag);
+        }
+        self.next_instruction();
+        Ok(())
+    }
 }
 
 impl InstructionSet for Chip8 {
-    fn process_00e0(&mut self) {
+    fn process_0nnn(&mut self, nnn: u16) -> Result<(), Error> {
+        match self.machine_code() {
+            MachineCode::Fault => Err(Error::MachineCode {
+                pc: self.program_counter(),
+                address: nnn,
+            }),
+            MachineCode::Ignore => {
+                self.next_instruction();
+                Ok(())
+            }
+            MachineCode::Cdp1802 => {
+                let cycles = cdp1802::call(self, nnn)?;
+                self.add_machine_cycles(cycles);
+                Ok(())
+            }
+        }
+    }
+
+    fn process_00e0(&mut self) -> Result<(), Error> {
         self.reset_gfx();
+        self.set_draw_flag();
         self.next_instruction();
+        Ok(())
     }
 
-    fn process_00ee(&mut self) {
-        let i = self.stack_pop();
+    fn process_00ee(&mut self) -> Result<(), Error> {
+        let i = self.stack_pop()?;
         self.set_program_counter(i);
         self.next_instruction();
+        Ok(())
     }
 
-    fn process_1nnn(&mut self, nnn: u16) {
+    fn process_1nnn(&mut self, nnn: u16) -> Result<(), Error> {
         self.set_program_counter(nnn);
+        Ok(())
     }
 
-    fn process_2nnn(&mut self, nnn: u16) {
-        self.stack();
+    fn process_2nnn(&mut self, nnn: u16) -> Result<(), Error> {
+        self.stack()?;
         self.set_program_counter(nnn);
+        Ok(())
     }
 
-    fn process_3xnn(&mut self, x: usize, nn: u8) {
+    fn process_3xnn(&mut self, x: usize, nn: u8) -> Result<(), Error> {
         if self.register(x) == nn {
             self.next_instruction();
         }
         self.next_instruction();
+        Ok(())
     }
 
-    fn process_4xnn(&mut self, x: usize, nn: u8) {
+    fn process_4xnn(&mut self, x: usize, nn: u8) -> Result<(), Error> {
         if self.register(x) != nn {
             self.next_instruction();
         }
         self.next_instruction();
+        Ok(())
     }
 
-    fn process_5xy0(&mut self, x: usize, y: usize) {
+    fn process_5xy0(&mut self, x: usize, y: usize) -> Result<(), Error> {
         if self.register(x) == self.register(y) {
             self.next_instruction();
         }
         self.next_instruction();
+        Ok(())
     }
 
-    fn process_6xnn(&mut self, x: usize, nn: u8) {
+    fn process_6xnn(&mut self, x: usize, nn: u8) -> Result<(), Error> {
         self.set_register(x, nn);
         self.next_instruction();
+        Ok(())
     }
 
-    fn process_7xnn(&mut self, x: usize, nn: u8) {
+    fn process_7xnn(&mut self, x: usize, nn: u8) -> Result<(), Error> {
         let (value, _) = self.register(x).overflowing_add(nn);
         self.set_register(x, value);
         self.next_instruction();
+        Ok(())
     }
 
-    fn process_8xy0(&mut self, x: usize, y: usize) {
-        self.set_register(x, self.register(y));
-        self.next_instruction();
+    fn process_8xy0(&mut self, x: usize, y: usize) -> Result<(), Error> {
+        self.alu(Operation::Load, x, y)
     }
 
-    fn process_8xy1(&mut self, x: usize, y: usize) {
-        self.set_register(x, self.register(x) | self.register(y));
-        self.next_instruction();
+    fn process_8xy1(&mut self, x: usize, y: usize) -> Result<(), Error> {
+        self.alu(Operation::Or, x, y)
     }
 
-    fn process_8xy2(&mut self, x: usize, y: usize) {
-        self.set_register(x, self.register(x) & self.register(y));
-        self.next_instruction();
+    fn process_8xy2(&mut self, x: usize, y: usize) -> Result<(), Error> {
+        self.alu(Operation::And, x, y)
     }
 
-    fn process_8xy3(&mut self, x: usize, y: usize) {
-        self.set_register(x, self.register(x) ^ self.register(y));
-        self.next_instruction();
+    fn process_8xy3(&mut self, x: usize, y: usize) -> Result<(), Error> {
+        self.alu(Operation::Xor, x, y)
     }
 
-    fn process_8xy4(&mut self, x: usize, y: usize) {
-        let (value, overflow) = self.register(x).overflowing_add(self.register(y));
-        self.set_register(x, value);
-        self.set_register(0xF, overflow as u8);
-        self.next_instruction();
+    fn process_8xy4(&mut self, x: usize, y: usize) -> Result<(), Error> {
+        self.alu(Operation::Add, x, y)
     }
 
-    fn process_8xy5(&mut self, x: usize, y: usize) {
-        let (value, overflow) = self.register(x).overflowing_sub(self.register(y));
-        self.set_register(x, value);
-        self.set_register(0xF, overflow as u8);
-        self.next_instruction();
+    fn process_8xy5(&mut self, x: usize, y: usize) -> Result<(), Error> {
+        self.alu(Operation::Subtract, x, y)
     }
 
-    // Ambiguous instruction, some implementations use VX = VY >> 1, some use VX >>= 1
-    fn process_8xy6(&mut self, x: usize, _y: usize) {
-        self.set_register(0xF, self.register(x) & 0x01);
-        self.set_register(x, self.register(x) >> 1);
-        //self.set_register(x, self.register(y) >> 1);
-        self.next_instruction();
+    fn process_8xy6(&mut self, x: usize, y: usize) -> Result<(), Error> {
+        self.alu(Operation::ShiftRight, x, y)
     }
 
-    fn process_8xy7(&mut self, x: usize, y: usize) {
-        let (value, overflow) = self.register(y).overflowing_sub(self.register(x));
-        self.set_register(x, value);
-        self.set_register(0xF, if overflow { 0 } else { 1 });
-        self.next_instruction();
+    fn process_8xy7(&mut self, x: usize, y: usize) -> Result<(), Error> {
+        self.alu(Operation::SubtractReversed, x, y)
     }
 
-    // Ambiguous instruction, some implementations use VX = VY << 1, some use VX <<= 1
-    fn process_8xye(&mut self, x: usize, _y: usize) {
-        self.set_register(0xF, self.register(x) & 0x80);
-        self.set_register(x, self.register(x) << 1);
-        //self.set_register(x, self.register(y) << 1);
-        self.next_instruction();
+    fn process_8xye(&mut self, x: usize, y: usize) -> Result<(), Error> {
+        self.alu(Operation::ShiftLeft, x, y)
     }
 
-    fn process_9xy0(&mut self, x: usize, y: usize) {
+    fn process_9xy0(&mut self, x: usize, y: usize) -> Result<(), Error> {
         if self.register(x) != self.register(y) {
             self.next_instruction();
         }
         self.next_instruction();
+        Ok(())
     }
 
-    fn process_annn(&mut self, nnn: u16) {
+    fn process_annn(&mut self, nnn: u16) -> Result<(), Error> {
         self.set_index(nnn);
         self.next_instruction();
+        Ok(())
     }
 
-    fn process_bnnn(&mut self, nnn: u16) {
-        self.set_program_counter(nnn + self.register(0) as u16)
+    // Ambiguous instruction, some implementations use NNN + V0, some use XNN + VX
+    fn process_bnnn(&mut self, x: usize, nnn: u16) -> Result<(), Error> {
+        let offset = if self.quirks().jump { x } else { 0 };
+        self.set_program_counter(nnn + self.register(offset) as u16);
+        Ok(())
     }
 
-    fn process_cxnn(&mut self, x: usize, nn: u8) {
-        let random: u8 = rand::thread_rng().gen();
+    fn process_cxnn(&mut self, x: usize, nn: u8) -> Result<(), Error> {
+        let random = self.random();
         self.set_register(x, random & nn);
         self.next_instruction();
+        Ok(())
     }
 
-    fn process_dxyn(&mut self, x: usize, y: usize, n: u8) {
+    fn process_dxyn(&mut self, x: usize, y: usize, n: u8) -> Result<(), Error> {
         let vx = (self.register(x) & (WIDTH as u8 - 1)) as usize;
-        let vy = (self.register(y) & (WIDTH as u8 - 1)) as usize;
+        let vy = (self.register(y) & (HEIGHT as u8 - 1)) as usize;
+        let clip = self.quirks().clip;
+        // read the whole sprite first, so nothing is drawn when it does not fit in the memory
+        let n = n as usize;
+        let mut sprite = [0; 15];
+        if n > 0 {
+            self.index_range(n)?;
+        }
+        for (i, byte) in sprite[..n].iter_mut().enumerate() {
+            *byte = self.memory_at_index(i)?;
+        }
         self.set_register(0xF, 0);
 
-        for i in 0..n as usize {
-            let src_pixel = self.memory_at_index(i);
-            for j in 0..8 {
-                // clip sprite drawn outside the screen
-                if vx + j >= WIDTH || vy + i >= HEIGHT {
-                    continue;
-                }
-                // if current pixel is not 0
-                if (src_pixel & (0x80 >> j)) != 0 {
-                    let dst = (vx + j) + (vy + i) * WIDTH;
-                    // collision: set VF flag to 1
-                    if self.gfx(dst) {
-                        self.set_register(0xF, 1);
-                    }
-                    // set current pixel on framebuffer
-                    self.set_gfx(dst, self.gfx(dst) ^ true);
-                }
+        for (i, &byte) in sprite[..n].iter().enumerate() {
+            // clip sprite drawn outside the screen
+            if clip && vy + i >= HEIGHT {
+                break;
+            }
+            let y = (vy + i) % HEIGHT;
+            // the sprite row, in the leftmost byte, moved to column vx
+            let left = (byte as u64) << (WIDTH - 8);
+            let bits = if clip {
+                left >> vx
+            } else {
+                left.rotate_right(vx as u32)
+            };
+            let row = self.gfx_row(y);
+            // collision: set VF flag to 1
+            if row & bits != 0 {
+                self.set_register(0xF, 1);
             }
+            self.set_gfx_row(y, row ^ bits);
         }
         self.set_draw_flag();
         self.next_instruction();
+        Ok(())
     }
 
-    fn process_ex9e(&mut self, x: usize) {
-        if self.is_key_down(self.register(x) as usize) {
+    fn process_ex9e(&mut self, x: usize) -> Result<(), Error> {
+        // only the low nibble of VX selects a key
+        if self.is_key_down((self.register(x) & 0xF) as usize) {
             self.next_instruction();
         }
         self.next_instruction();
+        Ok(())
     }
 
-    fn process_exa1(&mut self, x: usize) {
-        if !self.is_key_down(self.register(x) as usize) {
+    fn process_exa1(&mut self, x: usize) -> Result<(), Error> {
+        if !self.is_key_down((self.register(x) & 0xF) as usize) {
             self.next_instruction();
         }
         self.next_instruction();
+        Ok(())
     }
 
-    fn process_fx07(&mut self, x: usize) {
+    fn process_fx07(&mut self, x: usize) -> Result<(), Error> {
         self.set_register(x, self.delay_timer());
         self.next_instruction();
+        Ok(())
     }
 
-    fn process_fx0a(&mut self, x: usize) {
+    fn process_fx0a(&mut self, x: usize) -> Result<(), Error> {
         if let Some(i) = self.keypad().iter().position(|&v| v) {
             self.set_register(x, i as u8);
             self.next_instruction();
         }
         // stay in place to await, no pc increments
+        Ok(())
     }
 
-    fn process_fx15(&mut self, x: usize) {
+    fn process_fx15(&mut self, x: usize) -> Result<(), Error> {
         self.set_delay_timer(self.register(x));
         self.next_instruction();
+        Ok(())
     }
 
-    fn process_fx18(&mut self, x: usize) {
+    fn process_fx18(&mut self, x: usize) -> Result<(), Error> {
         self.set_sound_timer(self.register(x));
         self.next_instruction();
+        Ok(())
     }
 
-    fn process_fx1e(&mut self, x: usize) {
+    fn process_fx1e(&mut self, x: usize) -> Result<(), Error> {
         self.add_index(self.register(x) as u16);
         self.next_instruction();
+        Ok(())
     }
 
-    fn process_fx29(&mut self, x: usize) {
+    fn process_fx29(&mut self, x: usize) -> Result<(), Error> {
         self.set_index(0x0050 + (self.register(x) as u16) * 5);
         self.next_instruction();
+        Ok(())
     }
 
-    fn process_fx33(&mut self, x: usize) {
-        self.set_memory_at_index(0, self.register(x) / 100);
-        self.set_memory_at_index(1, (self.register(x) / 10) % 10);
-        self.set_memory_at_index(2, self.register(x) % 10);
+    fn process_fx33(&mut self, x: usize) -> Result<(), Error> {
+        self.index_range(3)?;
+        self.set_memory_at_index(0, self.register(x) / 100)?;
+        self.set_memory_at_index(1, (self.register(x) / 10) % 10)?;
+        self.set_memory_at_index(2, self.register(x) % 10)?;
         self.next_instruction();
+        Ok(())
     }
 
     // !! Ambiguous instruction, some implementations left I inchanged, some left I incremented.
-    fn process_fx55(&mut self, x: usize) {
-        self.copy_n_reg_to_mem_from_index(x);
+    fn process_fx55(&mut self, x: usize) -> Result<(), Error> {
+        self.copy_n_reg_to_mem_from_index(x)?;
         self.next_instruction();
+        Ok(())
     }
 
     // !! Ambiguous instruction, some implementations left I inchanged, some left I incremented.
-    fn process_fx65(&mut self, x: usize) {
-        self.copy_mem_from_index_to_n_reg(x);
+    fn process_fx65(&mut self, x: usize) -> Result<(), Error> {
+        self.copy_mem_from_index_to_n_reg(x)?;
         self.next_instruction();
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chip8::{init, Quirks};
+
+    const PC: u16 = 0x200;
+
+    fn machine() -> Chip8 {
+        init()
+    }
+
+    // VX and VF after running an 8XYN instruction on the given operands.
+    fn alu(op: fn(&mut Chip8, usize, usize) -> Result<(), Error>, vx: u8, vy: u8) -> (u8, u8) {
+        let mut chip8 = machine();
+        chip8.set_register(1, vx);
+        chip8.set_register(2, vy);
+        op(&mut chip8, 1, 2).unwrap();
+        assert_eq!(chip8.program_counter(), PC + 2);
+        (chip8.register(1), chip8.register(0xF))
+    }
+
+    // Whether a skip instruction skipped the next one.
+    fn skipped(chip8: &mut Chip8, op: impl FnOnce(&mut Chip8) -> Result<(), Error>) -> bool {
+        chip8.set_program_counter(PC);
+        op(chip8).unwrap();
+        match chip8.program_counter() - PC {
+            2 => false,
+            4 => true,
+            n => panic!("unexpected PC increment {}", n),
+        }
+    }
+
+    #[test]
+    fn clear_screen() {
+        let mut chip8 = machine();
+        chip8.set_gfx(10, true);
+        chip8.process_00e0().unwrap();
+        assert!(!chip8.gfx(10));
+        assert!(chip8.draw_flag());
+        assert_eq!(chip8.program_counter(), PC + 2);
+    }
+
+    #[test]
+    fn call_and_return() {
+        let mut chip8 = machine();
+        chip8.process_2nnn(0x345).unwrap();
+        assert_eq!(chip8.program_counter(), 0x345);
+        assert_eq!(chip8.call_stack(), &[PC]);
+        chip8.process_00ee().unwrap();
+        assert_eq!(chip8.program_counter(), PC + 2);
+        assert_eq!(chip8.stack_pointer(), 0);
+        assert!(matches!(
+            chip8.process_00ee(),
+            Err(Error::StackUnderflow(_))
+        ));
+    }
+
+    #[test]
+    fn call_overflow() {
+        let mut chip8 = machine();
+        for _ in 0..16 {
+            chip8.process_2nnn(0x200).unwrap();
+        }
+        assert!(matches!(
+            chip8.process_2nnn(0x200),
+            Err(Error::StackOverflow(_))
+        ));
+    }
+
+    #[test]
+    fn jump() {
+        let mut chip8 = machine();
+        chip8.process_1nnn(0xABC).unwrap();
+        assert_eq!(chip8.program_counter(), 0xABC);
+    }
+
+    #[test]
+    fn jump_with_offset() {
+        let mut chip8 = machine();
+        chip8.set_register(0, 0x10);
+        chip8.set_register(3, 0x20);
+        chip8.process_bnnn(3, 0x300).unwrap();
+        assert_eq!(chip8.program_counter(), 0x310);
+
+        chip8.set_quirks(Quirks {
+            jump: true,
+            ..Quirks::default()
+        });
+        chip8.process_bnnn(3, 0x300).unwrap();
+        assert_eq!(chip8.program_counter(), 0x320);
+    }
+
+    #[test]
+    fn skip_if_equal_immediate() {
+        let mut chip8 = machine();
+        chip8.set_register(4, 0x42);
+        assert!(skipped(&mut chip8, |c| c.process_3xnn(4, 0x42)));
+        assert!(!skipped(&mut chip8, |c| c.process_3xnn(4, 0x43)));
+    }
+
+    #[test]
+    fn skip_if_not_equal_immediate() {
+        let mut chip8 = machine();
+        chip8.set_register(4, 0x42);
+        assert!(!skipped(&mut chip8, |c| c.process_4xnn(4, 0x42)));
+        assert!(skipped(&mut chip8, |c| c.process_4xnn(4, 0x43)));
+    }
+
+    #[test]
+    fn skip_if_registers_equal() {
+        let mut chip8 = machine();
+        chip8.set_register(1, 7);
+        chip8.set_register(2, 7);
+        assert!(skipped(&mut chip8, |c| c.process_5xy0(1, 2)));
+        chip8.set_register(2, 8);
+        assert!(!skipped(&mut chip8, |c| c.process_5xy0(1, 2)));
+    }
+
+    #[test]
+    fn skip_if_registers_not_equal() {
+        let mut chip8 = machine();
+        chip8.set_register(1, 7);
+        chip8.set_register(2, 7);
+        assert!(!skipped(&mut chip8, |c| c.process_9xy0(1, 2)));
+        chip8.set_register(2, 8);
+        assert!(skipped(&mut chip8, |c| c.process_9xy0(1, 2)));
+    }
+
+    #[test]
+    fn load_immediate() {
+        let mut chip8 = machine();
+        chip8.process_6xnn(5, 0xAB).unwrap();
+        assert_eq!(chip8.register(5), 0xAB);
+        assert_eq!(chip8.program_counter(), PC + 2);
+    }
+
+    #[test]
+    fn add_immediate_does_not_touch_vf() {
+        let mut chip8 = machine();
+        chip8.set_register(5, 0xFF);
+        chip8.set_register(0xF, 0x42);
+        chip8.process_7xnn(5, 0x02).unwrap();
+        assert_eq!(chip8.register(5), 0x01);
+        assert_eq!(chip8.register(0xF), 0x42);
+    }
+
+    #[test]
+    fn load_register() {
+        assert_eq!(alu(Chip8::process_8xy0, 0x11, 0x22).0, 0x22);
+    }
+
+    #[test]
+    fn bitwise() {
+        assert_eq!(alu(Chip8::process_8xy1, 0b1100, 0b1010).0, 0b1110);
+        assert_eq!(alu(Chip8::process_8xy2, 0b1100, 0b1010).0, 0b1000);
+        assert_eq!(alu(Chip8::process_8xy3, 0b1100, 0b1010).0, 0b0110);
+    }
+
+    #[test]
+    fn bitwise_vf_reset() {
+        for op in [
+            Chip8::process_8xy1,
+            Chip8::process_8xy2,
+            Chip8::process_8xy3,
+        ] {
+            let mut chip8 = machine();
+            chip8.set_register(0xF, 1);
+            op(&mut chip8, 1, 2).unwrap();
+            assert_eq!(chip8.register(0xF), 1);
+
+            chip8.set_quirks(Quirks {
+                vf_reset: true,
+                ..Quirks::default()
+            });
+            op(&mut chip8, 1, 2).unwrap();
+            assert_eq!(chip8.register(0xF), 0);
+        }
+    }
+
+    #[test]
+    fn add_with_carry() {
+        assert_eq!(alu(Chip8::process_8xy4, 0x10, 0x20), (0x30, 0));
+        assert_eq!(alu(Chip8::process_8xy4, 0xFF, 0x01), (0x00, 1));
+        assert_eq!(alu(Chip8::process_8xy4, 0xFF, 0xFF), (0xFE, 1));
+    }
+
+    #[test]
+    fn subtract_with_borrow() {
+        // VF is set when there is no borrow
+        assert_eq!(alu(Chip8::process_8xy5, 0x30, 0x10), (0x20, 1));
+        assert_eq!(alu(Chip8::process_8xy5, 0x10, 0x10), (0x00, 1));
+        assert_eq!(alu(Chip8::process_8xy5, 0x10, 0x30), (0xE0, 0));
+    }
+
+    #[test]
+    fn subtract_reversed_with_borrow() {
+        assert_eq!(alu(Chip8::process_8xy7, 0x10, 0x30), (0x20, 1));
+        assert_eq!(alu(Chip8::process_8xy7, 0x10, 0x10), (0x00, 1));
+        assert_eq!(alu(Chip8::process_8xy7, 0x30, 0x10), (0xE0, 0));
+    }
+
+    #[test]
+    fn shift_right() {
+        assert_eq!(alu(Chip8::process_8xy6, 0b0000_0101, 0), (0b0000_0010, 1));
+        assert_eq!(alu(Chip8::process_8xy6, 0b0000_0100, 0), (0b0000_0010, 0));
+
+        // without the shift quirk, VY is shifted into VX
+        let mut chip8 = machine();
+        chip8.set_quirks(Quirks {
+            shift: false,
+            ..Quirks::default()
+        });
+        chip8.set_register(2, 0b11);
+        chip8.process_8xy6(1, 2).unwrap();
+        assert_eq!((chip8.register(1), chip8.register(0xF)), (0b1, 1));
+    }
+
+    #[test]
+    fn shift_left() {
+        assert_eq!(alu(Chip8::process_8xye, 0b1000_0001, 0), (0b0000_0010, 1));
+        assert_eq!(alu(Chip8::process_8xye, 0b0100_0001, 0), (0b1000_0010, 0));
+
+        let mut chip8 = machine();
+        chip8.set_quirks(Quirks {
+            shift: false,
+            ..Quirks::default()
+        });
+        chip8.set_register(2, 0b1100_0000);
+        chip8.process_8xye(1, 2).unwrap();
+        assert_eq!((chip8.register(1), chip8.register(0xF)), (0b1000_0000, 1));
+    }
+
+    // VF as the target ends up holding the flag, VF as an operand is read before any write.
+    #[test]
+    fn vf_operand() {
+        type Op = fn(&mut Chip8, usize, usize) -> Result<(), Error>;
+        let run = |op: Op, x: usize, y: usize, vx: u8, vy: u8, shift: bool| {
+            let mut chip8 = machine();
+            chip8.set_quirks(Quirks {
+                shift,
+                ..Quirks::default()
+            });
+            chip8.set_register(x, vx);
+            chip8.set_register(y, vy);
+            op(&mut chip8, x, y).unwrap();
+            (chip8.register(x), chip8.register(0xF))
+        };
+        // 8FYN: VF is the flag, or the result when the flag is unchanged
+        assert_eq!(run(Chip8::process_8xy4, 0xF, 1, 0xF0, 0x20, true).1, 1);
+        assert_eq!(run(Chip8::process_8xy5, 0xF, 1, 0x10, 0x20, true).1, 0);
+        assert_eq!(run(Chip8::process_8xy7, 0xF, 1, 0x10, 0x20, true).1, 1);
+        assert_eq!(run(Chip8::process_8xy6, 0xF, 1, 0x02, 0, true).1, 0);
+        assert_eq!(run(Chip8::process_8xye, 0xF, 1, 0x40, 0, true).1, 0);
+        assert_eq!(run(Chip8::process_8xy1, 0xF, 1, 0x02, 0x01, true).1, 0x03);
+        // 8XFN: the old VF is the operand
+        assert_eq!(
+            run(Chip8::process_8xy4, 1, 0xF, 0xF0, 0x20, true),
+            (0x10, 1)
+        );
+        assert_eq!(
+            run(Chip8::process_8xy5, 1, 0xF, 0x10, 0x20, true),
+            (0xF0, 0)
+        );
+        assert_eq!(run(Chip8::process_8xy6, 1, 0xF, 0, 0x02, false), (0x01, 0));
+        assert_eq!(run(Chip8::process_8xye, 1, 0xF, 0, 0x40, false), (0x80, 0));
+    }
+
+    #[test]
+    fn load_index() {
+        let mut chip8 = machine();
+        chip8.process_annn(0x123).unwrap();
+        assert_eq!(chip8.index(), 0x123);
+    }
+
+    #[test]
+    fn random_is_masked() {
+        let mut chip8 = machine();
+        for _ in 0..32 {
+            chip8.process_cxnn(1, 0x0F).unwrap();
+            assert_eq!(chip8.register(1) & 0xF0, 0);
+            chip8.process_cxnn(2, 0x00).unwrap();
+            assert_eq!(chip8.register(2), 0);
+        }
+    }
+
+    #[test]
+    fn draw_sprite() {
+        let mut chip8 = machine();
+        chip8.set_memory(0x300, 0b1100_0000);
+        chip8.set_index(0x300);
+        chip8.set_register(1, 2);
+        chip8.set_register(2, 3);
+        chip8.process_dxyn(1, 2, 1).unwrap();
+        assert!(chip8.gfx(2 + 3 * WIDTH) && chip8.gfx(3 + 3 * WIDTH));
+        assert!(!chip8.gfx(4 + 3 * WIDTH));
+        assert_eq!(chip8.register(0xF), 0);
+        assert!(chip8.draw_flag());
+
+        // drawing again erases the sprite and reports the collision
+        chip8.process_dxyn(1, 2, 1).unwrap();
+        assert!(!chip8.gfx(2 + 3 * WIDTH));
+        assert_eq!(chip8.register(0xF), 1);
+    }
+
+    #[test]
+    fn draw_sprite_clipping() {
+        let mut chip8 = machine();
+        chip8.set_memory(0x300, 0xFF);
+        chip8.set_index(0x300);
+        // the origin wraps around, the sprite itself is clipped
+        chip8.set_register(1, WIDTH as u8 + 60);
+        chip8.process_dxyn(1, 2, 1).unwrap();
+        assert!(chip8.gfx(63));
+        assert!(!chip8.gfx(0));
+        assert_eq!(chip8.gfx_row(0), 0x0000_0000_0000_000F);
+
+        chip8.set_quirks(Quirks {
+            clip: false,
+            ..Quirks::default()
+        });
+        chip8.process_00e0().unwrap();
+        chip8.process_dxyn(1, 2, 1).unwrap();
+        assert!(chip8.gfx(63) && chip8.gfx(0) && chip8.gfx(3));
+        assert_eq!(chip8.gfx_row(0), 0xF000_0000_0000_000F);
+    }
+
+    #[test]
+    fn draw_sprite_rows() {
+        let mut chip8 = machine();
+        chip8.set_memory(0x300, 0x81);
+        chip8.set_memory(0x301, 0x3C);
+        chip8.set_index(0x300);
+        chip8.set_register(1, 4);
+        chip8.set_register(2, HEIGHT as u8 - 1);
+        chip8.set_quirks(Quirks {
+            clip: false,
+            ..Quirks::default()
+        });
+        chip8.process_dxyn(1, 2, 2).unwrap();
+        let mut expected = [0; HEIGHT];
+        expected[HEIGHT - 1] = 0x81 << 52;
+        // the second row wraps to the top
+        expected[0] = 0x3C << 52;
+        assert_eq!(chip8.gfx_rows(), &expected);
+        assert!(chip8.gfx(4 + (HEIGHT - 1) * WIDTH) && chip8.gfx(11 + (HEIGHT - 1) * WIDTH));
+    }
+
+    #[test]
+    fn skip_if_key() {
+        let mut chip8 = machine();
+        chip8.set_register(1, 0xA);
+        assert!(!skipped(&mut chip8, |c| c.process_ex9e(1)));
+        assert!(skipped(&mut chip8, |c| c.process_exa1(1)));
+        chip8.press_key(0xA);
+        assert!(skipped(&mut chip8, |c| c.process_ex9e(1)));
+        assert!(!skipped(&mut chip8, |c| c.process_exa1(1)));
+    }
+
+    #[test]
+    fn timers() {
+        let mut chip8 = machine();
+        chip8.set_register(1, 10);
+        chip8.process_fx15(1).unwrap();
+        chip8.process_fx18(1).unwrap();
+        assert_eq!((chip8.delay_timer(), chip8.sound_timer()), (10, 10));
+        assert!(chip8.update_timer());
+        chip8.process_fx07(2).unwrap();
+        assert_eq!(chip8.register(2), 9);
+    }
+
+    #[test]
+    fn wait_for_key() {
+        let mut chip8 = machine();
+        chip8.process_fx0a(1).unwrap();
+        assert_eq!(chip8.program_counter(), PC);
+        chip8.press_key(0xC);
+        chip8.process_fx0a(1).unwrap();
+        assert_eq!(chip8.register(1), 0xC);
+        assert_eq!(chip8.program_counter(), PC + 2);
+    }
+
+    #[test]
+    fn add_to_index_does_not_touch_vf() {
+        let mut chip8 = machine();
+        chip8.set_index(0x2FF);
+        chip8.set_register(1, 0x02);
+        chip8.process_fx1e(1).unwrap();
+        assert_eq!(chip8.index(), 0x301);
+        assert_eq!(chip8.register(0xF), 0);
+    }
+
+    #[test]
+    fn font_character() {
+        let mut chip8 = machine();
+        chip8.set_register(1, 0xA);
+        chip8.process_fx29(1).unwrap();
+        // "A" is 0xF0 0x90 0xF0 0x90 0x90
+        let index = chip8.index() as usize;
+        let glyph: Vec<u8> = (index..index + 5).map(|a| chip8.memory(a)).collect();
+        assert_eq!(glyph, vec![0xF0, 0x90, 0xF0, 0x90, 0x90]);
+    }
+
+    #[test]
+    fn binary_coded_decimal() {
+        let mut chip8 = machine();
+        chip8.set_index(0x300);
+        for (value, digits) in [(254, [2, 5, 4]), (7, [0, 0, 7]), (100, [1, 0, 0])] {
+            chip8.set_register(1, value);
+            chip8.process_fx33(1).unwrap();
+            let bcd = [
+                chip8.memory(0x300),
+                chip8.memory(0x301),
+                chip8.memory(0x302),
+            ];
+            assert_eq!(bcd, digits);
+            assert_eq!(chip8.index(), 0x300);
+        }
+    }
+
+    #[test]
+    fn store_and_load_registers() {
+        let mut chip8 = machine();
+        for x in 0..4 {
+            chip8.set_register(x, x as u8 + 1);
+        }
+        chip8.set_index(0x300);
+        chip8.process_fx55(2).unwrap();
+        assert_eq!(
+            (0x300..0x304).map(|a| chip8.memory(a)).collect::<Vec<_>>(),
+            vec![1, 2, 3, 0]
+        );
+        assert_eq!(chip8.index(), 0x303);
+
+        chip8.set_quirks(Quirks {
+            memory_increment: false,
+            ..Quirks::default()
+        });
+        chip8.set_index(0x301);
+        chip8.process_fx65(1).unwrap();
+        assert_eq!((chip8.register(0), chip8.register(1)), (2, 3));
+        assert_eq!(chip8.index(), 0x301);
     }
 }
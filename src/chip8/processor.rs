@@ -1,16 +1,30 @@
+use super::instruction::Instruction;
 use super::opcodes::InstructionSet;
-use super::{HEIGHT, WIDTH};
+use super::timing::{self, Timing};
+use super::{
+    AccessKind, Coverage, Error, MachineCode, MemoryAccess, Quirks, HEIGHT, MEM_SIZE, START_ROM,
+    WIDTH,
+};
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
 
 const N_REG: usize = 16;
-const MEM_SIZE: usize = 4096;
 const STACK_SIZE: usize = 16;
 const N_KEY: usize = 16;
 
-const START_ROM: usize = 0x0200;
+// granularity of the write generations, which tell compiled code it is stale
+pub(super) const PAGE_SIZE: usize = 64;
+
+// Save states start with this tag, to be bumped whenever their layout changes.
+const STATE_TAG: &[u8; 4] = b"C8S1";
+// Size of a save state, see save_state().
+pub const STATE_SIZE: usize =
+    4 + MEM_SIZE + N_REG + 2 * STACK_SIZE + 3 * 2 + 8 * HEIGHT + 1 + 2 + 2 + 3 * 8 + 8;
+
 const START_FONT: usize = 0x0050;
 const END_FONT: usize = 0x00A0;
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct Chip8 {
     /*
     MEMORY MAP:
@@ -26,13 +40,41 @@ pub struct Chip8 {
     pc: u16,
     sp: u16,
 
-    gfx: [bool; WIDTH * HEIGHT],
+    // one row per u64, the leftmost pixel in the most significant bit
+    gfx: [u64; HEIGHT],
     draw_flag: bool,
 
     key: [bool; N_KEY],
 
     delay_timer: u8,
     sound_timer: u8,
+
+    quirks: Quirks,
+    // number of instructions executed so far
+    cycles: u64,
+    // COSMAC VIP machine cycles of the instructions executed so far
+    machine_cycles: u64,
+    timing: Timing,
+    machine_code: MachineCode,
+    // instructions or machine cycles left in the current frame
+    frame_budget: i64,
+    // source of CXNN, seeded from the OS unless seeded explicitly
+    rng: StdRng,
+
+    // memory accesses of the last instruction, only recorded when tracking is enabled
+    track_accesses: bool,
+    accesses: Vec<MemoryAccess>,
+    // only tracked when enabled
+    coverage: Option<Box<Coverage>>,
+    // instructions decoded at each address, None when the cache is disabled
+    decoded: Option<Box<[Option<Instruction>; MEM_SIZE]>>,
+    // number of writes to each page of the memory
+    write_generations: [u32; MEM_SIZE / PAGE_SIZE],
+}
+
+// Bit of the pixel in column x of a framebuffer row.
+fn pixel_bit(x: usize) -> u64 {
+    1 << (WIDTH - 1 - x)
 }
 
 pub fn init() -> Chip8 {
@@ -45,25 +87,53 @@ pub fn init() -> Chip8 {
         pc: START_ROM as u16,
         sp: 0,
 
-        gfx: [false; WIDTH * HEIGHT],
+        gfx: [0; HEIGHT],
         draw_flag: false,
 
         key: [false; N_KEY],
 
         delay_timer: 0,
         sound_timer: 0,
+
+        quirks: Quirks::default(),
+        cycles: 0,
+        machine_cycles: 0,
+        timing: Timing::default(),
+        machine_code: MachineCode::default(),
+        frame_budget: 0,
+        rng: new_rng(),
+
+        track_accesses: false,
+        accesses: Vec::new(),
+        coverage: None,
+        decoded: Some(Box::new([None; MEM_SIZE])),
+        write_generations: [0; MEM_SIZE / PAGE_SIZE],
     };
     // load fontset
     chip8.mem[START_FONT..END_FONT].copy_from_slice(include_bytes!("fontset.bin"));
-    return chip8;
+    chip8
+}
+
+// Seeded from the OS, except on wasm32-unknown-unknown which has no entropy source: seed_rng()
+// is up to the page there.
+#[cfg(not(target_arch = "wasm32"))]
+fn new_rng() -> StdRng {
+    StdRng::from_entropy()
+}
+
+#[cfg(target_arch = "wasm32")]
+fn new_rng() -> StdRng {
+    StdRng::seed_from_u64(0)
 }
 
 impl Chip8 {
-    pub fn load_rom(&mut self, bytes: Vec<u8>) {
+    pub fn load_rom(&mut self, bytes: Vec<u8>) -> Result<(), Error> {
         if bytes.len() > self.mem.len() - START_ROM {
-            panic!("ROM is too big");
+            return Err(Error::RomTooBig(bytes.len()));
         }
         self.mem[START_ROM..START_ROM + bytes.len()].copy_from_slice(&bytes);
+        self.invalidate(START_ROM, bytes.len());
+        Ok(())
     }
 
     //pub fn reset(&mut self) {
@@ -78,6 +148,39 @@ impl Chip8 {
     //    self.delay_timer = 0;
     //}
 
+    pub fn quirks(&self) -> Quirks {
+        self.quirks
+    }
+
+    pub fn set_quirks(&mut self, quirks: Quirks) {
+        self.quirks = quirks;
+    }
+
+    pub fn timing(&self) -> Timing {
+        self.timing
+    }
+
+    pub fn set_timing(&mut self, timing: Timing) {
+        self.timing = timing;
+    }
+
+    pub fn machine_code(&self) -> MachineCode {
+        self.machine_code
+    }
+
+    pub fn set_machine_code(&mut self, machine_code: MachineCode) {
+        self.machine_code = machine_code;
+    }
+
+    // Make CXNN deterministic, for replays and tests.
+    pub fn seed_rng(&mut self, seed: u64) {
+        self.rng = StdRng::seed_from_u64(seed);
+    }
+
+    pub fn random(&mut self) -> u8 {
+        self.rng.gen()
+    }
+
     pub fn keypad(&self) -> [bool; N_KEY] {
         self.key
     }
@@ -90,16 +193,39 @@ impl Chip8 {
         self.key[key] = true;
     }
 
+    pub fn release_key(&mut self, key: usize) {
+        self.key[key] = false;
+    }
+
     pub fn is_key_down(&self, key: usize) -> bool {
         self.key[key]
     }
 
     pub fn gfx(&self, position: usize) -> bool {
-        self.gfx[position]
+        let (x, y) = (position % WIDTH, position / WIDTH);
+        self.gfx[y] & pixel_bit(x) != 0
     }
 
     pub fn set_gfx(&mut self, position: usize, value: bool) {
-        self.gfx[position] = value;
+        let (x, y) = (position % WIDTH, position / WIDTH);
+        if value {
+            self.gfx[y] |= pixel_bit(x);
+        } else {
+            self.gfx[y] &= !pixel_bit(x);
+        }
+    }
+
+    pub fn gfx_row(&self, y: usize) -> u64 {
+        self.gfx[y]
+    }
+
+    pub fn set_gfx_row(&mut self, y: usize, row: u64) {
+        self.gfx[y] = row;
+    }
+
+    // The whole framebuffer, for comparisons and hashing.
+    pub fn gfx_rows(&self) -> &[u64; HEIGHT] {
+        &self.gfx
     }
 
     pub fn set_draw_flag(&mut self) {
@@ -107,15 +233,16 @@ impl Chip8 {
     }
 
     pub fn reset_gfx(&mut self) {
-        self.gfx.fill(false);
+        self.gfx.fill(0);
         self.draw_flag = false;
     }
 
-    pub fn gfx_buffer(&mut self) -> Vec<u32> {
+    // palette is [background, foreground] as 0RGB
+    pub fn gfx_buffer(&mut self, palette: [u32; 2]) -> Vec<u32> {
         self.draw_flag = false;
         self.gfx
-            .into_iter()
-            .map(|x| if x { 0xFFFFFFFF } else { 0 })
+            .iter()
+            .flat_map(|&row| (0..WIDTH).map(move |x| palette[(row & pixel_bit(x) != 0) as usize]))
             .collect()
     }
 
@@ -141,28 +268,46 @@ impl Chip8 {
         self.delay_timer = value;
     }
 
-    //pub fn sound_timer(&self) -> u8 {
-    //    self.sound_timer
-    //}
+    pub fn sound_timer(&self) -> u8 {
+        self.sound_timer
+    }
 
     pub fn set_sound_timer(&mut self, value: u8) {
         self.sound_timer = value;
     }
 
-    pub fn stack_pop(&mut self) -> u16 {
+    pub fn stack_pop(&mut self) -> Result<u16, Error> {
         if self.sp == 0 {
-            panic!("Stack is empty")
+            return Err(Error::StackUnderflow(self.pc));
         }
         self.sp -= 1;
-        self.stack[self.sp as usize]
+        Ok(self.stack[self.sp as usize])
     }
 
-    pub fn stack(&mut self) {
-        if self.sp == STACK_SIZE as u16 {
-            panic!("Stack is full")
+    pub fn stack(&mut self) -> Result<(), Error> {
+        if self.sp >= STACK_SIZE as u16 {
+            return Err(Error::StackOverflow(self.pc));
         }
         self.stack[self.sp as usize] = self.pc;
         self.sp += 1;
+        Ok(())
+    }
+
+    // Addresses of the pending CALL instructions, outermost first.
+    pub fn call_stack(&self) -> &[u16] {
+        &self.stack[..self.sp as usize]
+    }
+
+    pub fn stack_pointer(&self) -> u16 {
+        self.sp
+    }
+
+    pub fn set_stack_pointer(&mut self, value: u16) {
+        self.sp = value.min(STACK_SIZE as u16);
+    }
+
+    pub fn program_counter(&self) -> u16 {
+        self.pc
     }
 
     pub fn set_program_counter(&mut self, value: u16) {
@@ -181,131 +326,423 @@ impl Chip8 {
         self.reg[x] = nn;
     }
 
+    pub fn registers(&self) -> [u8; N_REG] {
+        self.reg
+    }
+
+    pub fn index(&self) -> u16 {
+        self.index
+    }
+
     pub fn set_index(&mut self, value: u16) {
         self.index = value;
     }
 
     pub fn add_index(&mut self, value: u16) {
-        self.index += value;
+        self.index = self.index.wrapping_add(value);
+    }
+
+    // First address of the `length` bytes starting at I, if they all fit in the memory.
+    pub fn index_range(&self, length: usize) -> Result<usize, Error> {
+        let address = self.index as usize;
+        if address + length > MEM_SIZE {
+            return Err(Error::MemoryOutOfBounds {
+                pc: self.pc,
+                address: (address + length - 1).max(address),
+            });
+        }
+        Ok(address)
+    }
+
+    pub fn set_memory_at_index(&mut self, offset: usize, value: u8) -> Result<(), Error> {
+        let address = self.index_range(offset + 1)? + offset;
+        self.mem[address] = value;
+        self.invalidate(address, 1);
+        self.record_access(AccessKind::Write, address, value);
+        Ok(())
+    }
+
+    pub fn memory_at_index(&mut self, offset: usize) -> Result<u8, Error> {
+        let address = self.index_range(offset + 1)? + offset;
+        let value = self.mem[address];
+        self.record_access(AccessKind::Read, address, value);
+        Ok(value)
+    }
+
+    pub fn set_access_tracking(&mut self, enabled: bool) {
+        self.track_accesses = enabled;
+        self.accesses.clear();
+    }
+
+    // Memory accesses performed by the last executed instruction.
+    pub fn accesses(&self) -> &[MemoryAccess] {
+        &self.accesses
+    }
+
+    // Start tracking the coverage of the memory from scratch, or stop tracking it.
+    pub fn set_coverage_tracking(&mut self, enabled: bool) {
+        self.coverage = enabled.then(Box::default);
     }
 
-    pub fn set_memory_at_index(&mut self, offset: usize, value: u8) {
-        self.mem[self.index as usize + offset] = value;
+    pub fn coverage(&self) -> Option<&Coverage> {
+        self.coverage.as_deref()
     }
 
-    pub fn memory_at_index(&self, offset: usize) -> u8 {
-        self.mem[self.index as usize + offset]
+    fn record_access(&mut self, kind: AccessKind, address: usize, value: u8) {
+        if let Some(coverage) = &mut self.coverage {
+            coverage.access(kind, address);
+        }
+        if self.track_accesses {
+            self.accesses.push(MemoryAccess {
+                kind,
+                address: address as u16,
+                value,
+            });
+        }
+    }
+
+    pub fn memory(&self, address: usize) -> u8 {
+        self.mem[address]
+    }
+
+    // The whole memory, for the CDP1802 routines, which may write anywhere.
+    pub(super) fn memory_mut(&mut self) -> &mut [u8; MEM_SIZE] {
+        self.invalidate(0, MEM_SIZE);
+        &mut self.mem
+    }
+
+    // Enable or disable the decode cache, which is enabled by default.
+    pub fn set_decode_cache(&mut self, enabled: bool) {
+        self.decoded = enabled.then(|| Box::new([None; MEM_SIZE]));
+    }
+
+    // Forget the instructions decoded over the given bytes, including the one starting on the
+    // byte before.
+    fn invalidate(&mut self, address: usize, length: usize) {
+        let end = (address + length).min(MEM_SIZE);
+        if let Some(decoded) = &mut self.decoded {
+            decoded[address.saturating_sub(1)..end].fill(None);
+        }
+        for page in address.saturating_sub(1) / PAGE_SIZE..end.div_ceil(PAGE_SIZE) {
+            self.write_generations[page] = self.write_generations[page].wrapping_add(1);
+        }
+    }
+
+    // Number of writes to the page of the given address, so far.
+    #[cfg_attr(not(feature = "jit"), allow(dead_code))]
+    pub(super) fn write_generation(&self, address: usize) -> u32 {
+        self.write_generations[address / PAGE_SIZE]
+    }
+
+    // V0-VF and I, for the JIT compiled code.
+    #[cfg_attr(not(feature = "jit"), allow(dead_code))]
+    pub(super) fn registers_and_index_mut(&mut self) -> (&mut [u8; N_REG], &mut u16) {
+        (&mut self.reg, &mut self.index)
+    }
+
+    // Whether `instructions` more instructions, the ones before the last taking `cycles`, all
+    // start within the current frame.
+    #[cfg_attr(not(feature = "jit"), allow(dead_code))]
+    pub(super) fn fits_in_frame(&self, instructions: u64, cycles: u64) -> bool {
+        match self.timing {
+            Timing::Fixed => self.frame_budget >= instructions as i64,
+            Timing::CosmacVip => self.frame_budget > cycles as i64,
+        }
+    }
+
+    // Account for instructions executed outside of tick(), by the JIT.
+    #[cfg_attr(not(feature = "jit"), allow(dead_code))]
+    pub(super) fn retire(&mut self, instructions: u64, cycles: u64) {
+        self.cycles += instructions;
+        self.machine_cycles += cycles;
+        self.frame_budget -= match self.timing {
+            Timing::Fixed => instructions as i64,
+            Timing::CosmacVip => cycles as i64,
+        };
+    }
+
+    pub(super) fn add_machine_cycles(&mut self, cycles: u64) {
+        self.machine_cycles += cycles;
+    }
+
+    pub fn set_memory(&mut self, address: usize, value: u8) {
+        self.mem[address] = value;
+        self.invalidate(address, 1);
+    }
+
+    // Opcode at the current PC, None if PC points outside of the memory.
+    pub fn opcode(&self) -> Option<u16> {
+        let pc = self.pc as usize;
+        if pc + 1 >= MEM_SIZE {
+            return None;
+        }
+        Some((self.mem[pc] as u16) << 8 | self.mem[pc + 1] as u16)
+    }
+
+    pub fn cycles(&self) -> u64 {
+        self.cycles
+    }
+
+    pub fn machine_cycles(&self) -> u64 {
+        self.machine_cycles
+    }
+
+    // The machine state, STATE_SIZE bytes in little-endian order. The settings (quirks, timing,
+    // machine code) are left to the frontend, and so are the debugging tools.
+    // The random generator cannot be saved as it is, so it is reseeded with a number it draws,
+    // which goes to the state: a machine restored from it draws the same numbers as this one.
+    pub fn save_state(&mut self) -> Vec<u8> {
+        let seed: u64 = self.rng.gen();
+        self.rng = StdRng::seed_from_u64(seed);
+
+        let mut state = Vec::with_capacity(STATE_SIZE);
+        state.extend_from_slice(STATE_TAG);
+        state.extend_from_slice(&self.mem);
+        state.extend_from_slice(&self.reg);
+        state.extend(self.stack.iter().flat_map(|address| address.to_le_bytes()));
+        for value in [self.index, self.pc, self.sp] {
+            state.extend_from_slice(&value.to_le_bytes());
+        }
+        state.extend(self.gfx.iter().flat_map(|row| row.to_le_bytes()));
+        state.push(self.draw_flag as u8);
+        let keys = (0..N_KEY).fold(0u16, |keys, key| keys | (self.key[key] as u16) << key);
+        state.extend_from_slice(&keys.to_le_bytes());
+        state.extend_from_slice(&[self.delay_timer, self.sound_timer]);
+        state.extend_from_slice(&self.cycles.to_le_bytes());
+        state.extend_from_slice(&self.machine_cycles.to_le_bytes());
+        state.extend_from_slice(&self.frame_budget.to_le_bytes());
+        state.extend_from_slice(&seed.to_le_bytes());
+        debug_assert_eq!(state.len(), STATE_SIZE);
+        state
+    }
+
+    // Restore a state written by save_state(), leaving the machine as it was if it is invalid.
+    pub fn load_state(&mut self, state: &[u8]) -> Result<(), Error> {
+        if state.len() != STATE_SIZE || &state[..4] != STATE_TAG {
+            return Err(Error::InvalidState);
+        }
+        // the stack pointer indexes the stack, it is checked before anything is restored
+        let sp_at = 4 + MEM_SIZE + N_REG + 2 * STACK_SIZE + 2 * 2;
+        if u16::from_le_bytes([state[sp_at], state[sp_at + 1]]) as usize > STACK_SIZE {
+            return Err(Error::InvalidState);
+        }
+        let mut rest = &state[4..];
+        let mut take = |length: usize| {
+            let (bytes, tail) = rest.split_at(length);
+            rest = tail;
+            bytes
+        };
+        let u16_at = |bytes: &[u8], i: usize| u16::from_le_bytes([bytes[2 * i], bytes[2 * i + 1]]);
+        let u64_of = |bytes: &[u8]| u64::from_le_bytes(bytes.try_into().unwrap());
+
+        self.mem.copy_from_slice(take(MEM_SIZE));
+        self.reg.copy_from_slice(take(N_REG));
+        let stack = take(2 * STACK_SIZE);
+        for (i, address) in self.stack.iter_mut().enumerate() {
+            *address = u16_at(stack, i);
+        }
+        let pointers = take(3 * 2);
+        self.index = u16_at(pointers, 0);
+        self.pc = u16_at(pointers, 1);
+        self.sp = u16_at(pointers, 2);
+        for row in self.gfx.iter_mut() {
+            *row = u64_of(take(8));
+        }
+        self.draw_flag = take(1)[0] != 0;
+        let keys = u16_at(take(2), 0);
+        for (key, down) in self.key.iter_mut().enumerate() {
+            *down = keys & 1 << key != 0;
+        }
+        let timers = take(2);
+        self.delay_timer = timers[0];
+        self.sound_timer = timers[1];
+        self.cycles = u64_of(take(8));
+        self.machine_cycles = u64_of(take(8));
+        self.frame_budget = u64_of(take(8)) as i64;
+        self.rng = StdRng::seed_from_u64(u64_of(take(8)));
+        self.invalidate(0, MEM_SIZE);
+        Ok(())
     }
 
-    pub fn copy_n_reg_to_mem_from_index(&mut self, n: usize) {
-        let i = self.index as usize;
+    pub fn copy_n_reg_to_mem_from_index(&mut self, n: usize) -> Result<(), Error> {
+        let i = self.index_range(n + 1)?;
         self.mem[i..=(i + n)].copy_from_slice(&self.reg[0..=n]);
-        self.index += n as u16 + 1;
+        self.invalidate(i, n + 1);
+        for x in 0..=n {
+            self.record_access(AccessKind::Write, i + x, self.reg[x]);
+        }
+        if self.quirks.memory_increment {
+            self.add_index(n as u16 + 1);
+        }
+        Ok(())
     }
 
-    pub fn copy_mem_from_index_to_n_reg(&mut self, n: usize) {
-        let i = self.index as usize;
+    pub fn copy_mem_from_index_to_n_reg(&mut self, n: usize) -> Result<(), Error> {
+        let i = self.index_range(n + 1)?;
         self.reg[0..=n].copy_from_slice(&self.mem[i..=(i + n)]);
-        self.index += n as u16 + 1;
+        for x in 0..=n {
+            self.record_access(AccessKind::Read, i + x, self.reg[x]);
+        }
+        if self.quirks.memory_increment {
+            self.add_index(n as u16 + 1);
+        }
+        Ok(())
     }
 
-    pub fn tick(&mut self) {
+    // Run one 60 Hz frame: `ipf` instructions, or as many as fit in the frame with the COSMAC
+    // VIP timing, then a timers update. Returns true while the sound timer is active.
+    pub fn run_frame(&mut self, ipf: u32) -> Result<bool, Error> {
+        self.start_frame(ipf);
+        while self.frame_pending() {
+            self.tick()?;
+        }
+        Ok(self.update_timer())
+    }
+
+    // Start a frame, to be run with tick() while frame_pending(), then ended by update_timer().
+    pub fn start_frame(&mut self, ipf: u32) {
+        // the cycles overrun by the last instruction are taken from this frame
+        let overrun = self.frame_budget.clamp(-timing::FRAME_CYCLES, 0);
+        self.frame_budget = self.timing.frame_budget(ipf)
+            + match self.timing {
+                Timing::Fixed => 0,
+                Timing::CosmacVip => overrun,
+            };
+    }
+
+    pub fn frame_pending(&self) -> bool {
+        self.frame_budget > 0
+    }
+
+    fn execute(&mut self, instruction: Instruction) -> Result<(), Error> {
+        let r = |x: u8| x as usize;
+        match instruction {
+            Instruction::ClearScreen => self.process_00e0(),
+            Instruction::Return => self.process_00ee(),
+            Instruction::MachineCode(nnn) => self.process_0nnn(nnn),
+            Instruction::Jump(nnn) => self.process_1nnn(nnn),
+            Instruction::Call(nnn) => self.process_2nnn(nnn),
+            Instruction::SkipEqual(x, nn) => self.process_3xnn(r(x), nn),
+            Instruction::SkipNotEqual(x, nn) => self.process_4xnn(r(x), nn),
+            Instruction::SkipEqualRegisters(x, y) => self.process_5xy0(r(x), r(y)),
+            Instruction::Load(x, nn) => self.process_6xnn(r(x), nn),
+            Instruction::Add(x, nn) => self.process_7xnn(r(x), nn),
+            Instruction::LoadRegister(x, y) => self.process_8xy0(r(x), r(y)),
+            Instruction::Or(x, y) => self.process_8xy1(r(x), r(y)),
+            Instruction::And(x, y) => self.process_8xy2(r(x), r(y)),
+            Instruction::Xor(x, y) => self.process_8xy3(r(x), r(y)),
+            Instruction::AddRegister(x, y) => self.process_8xy4(r(x), r(y)),
+            Instruction::Subtract(x, y) => self.process_8xy5(r(x), r(y)),
+            Instruction::ShiftRight(x, y) => self.process_8xy6(r(x), r(y)),
+            Instruction::SubtractReversed(x, y) => self.process_8xy7(r(x), r(y)),
+            Instruction::ShiftLeft(x, y) => self.process_8xye(r(x), r(y)),
+            Instruction::SkipNotEqualRegisters(x, y) => self.process_9xy0(r(x), r(y)),
+            Instruction::LoadIndex(nnn) => self.process_annn(nnn),
+            Instruction::JumpOffset(x, nnn) => self.process_bnnn(r(x), nnn),
+            Instruction::Random(x, nn) => self.process_cxnn(r(x), nn),
+            Instruction::Draw(x, y, n) => self.process_dxyn(r(x), r(y), n),
+            Instruction::SkipKeyPressed(x) => self.process_ex9e(r(x)),
+            Instruction::SkipKeyNotPressed(x) => self.process_exa1(r(x)),
+            Instruction::LoadDelayTimer(x) => self.process_fx07(r(x)),
+            Instruction::WaitKey(x) => self.process_fx0a(r(x)),
+            Instruction::SetDelayTimer(x) => self.process_fx15(r(x)),
+            Instruction::SetSoundTimer(x) => self.process_fx18(r(x)),
+            Instruction::AddIndex(x) => self.process_fx1e(r(x)),
+            Instruction::LoadFont(x) => self.process_fx29(r(x)),
+            Instruction::StoreBcd(x) => self.process_fx33(r(x)),
+            Instruction::StoreRegisters(x) => self.process_fx55(r(x)),
+            Instruction::LoadRegisters(x) => self.process_fx65(r(x)),
+            Instruction::Unknown(opcode) => Err(Error::UnknownOpcode {
+                pc: self.pc,
+                opcode,
+            }),
+        }
+    }
+
+    pub fn tick(&mut self) -> Result<(), Error> {
         // check if pc overflow
-        if self.pc >= MEM_SIZE as u16 {
-            panic!("PC points outside of the memory");
+        if self.pc as usize + 1 >= MEM_SIZE {
+            return Err(Error::PcOutOfBounds(self.pc));
         }
 
         // fetch opcode
-        let opcode_u8 = (self.mem[self.pc as usize], self.mem[self.pc as usize + 1]);
-        let opcode_u4 = (
-            opcode_u8.0 >> 4,
-            opcode_u8.0 & 0x0F,
-            opcode_u8.1 >> 4,
-            opcode_u8.1 & 0x0F,
-        );
-        let x = opcode_u4.1 as usize;
-        let y = opcode_u4.2 as usize;
-        let n = opcode_u4.3;
-        let nn = opcode_u8.1;
-        let nnn = ((opcode_u8.0 & 0x0F) as u16) << 8 | opcode_u8.1 as u16;
-
-        match opcode_u4 {
-            // 00E0 - Clear the screen.
-            (0x00, 0x00, 0x0e, 0x00) => self.process_00e0(),
-            // 00EE - Returns from a subroutine.
-            (0x00, 0x00, 0x0e, 0x0e) => self.process_00ee(),
-            // 0NNN - Calls machine code routine (RCA 1802 for COSMAC VIP) at address NNN.
-            (0x00, _, _, _) => panic!("RCA 1802 not implemented."),
-            // 1NNN - Jump to address NNN.
-            (0x01, _, _, _) => self.process_1nnn(nnn),
-            // 2NNN - Calls subroutine at NNN.
-            (0x02, _, _, _) => self.process_2nnn(nnn),
-            // 3XNN - Skips next instruction if VX equals NN.
-            (0x03, _, _, _) => self.process_3xnn(x, nn),
-            // 4XNN - Skips the next instruction if VX does not equals NN.
-            (0x04, _, _, _) => self.process_4xnn(x, nn),
-            // 5XY0 - Skips the next instruction if VX equals VY.
-            (0x05, _, _, 0x00) => self.process_5xy0(x, y),
-            // 6XNN - Set VX to NN.
-            (0x06, _, _, _) => self.process_6xnn(x, nn),
-            // 7XNN - Adds NN to VX (VF is not changed).
-            (0x07, _, _, _) => self.process_7xnn(x, nn),
-            // 8XY0 - Sets VX to the value of VY.
-            (0x08, _, _, 0x00) => self.process_8xy0(x, y),
-            // 8XY1 - Sets VX to VX or VY.
-            (0x08, _, _, 0x01) => self.process_8xy1(x, y),
-            // 8XY2 - Sets VX to VX and VY.
-            (0x08, _, _, 0x02) => self.process_8xy2(x, y),
-            // 8XY3 - Sets VX to VX xor VY.
-            (0x08, _, _, 0x03) => self.process_8xy3(x, y),
-            // 8XY4 - Adds VY to VX. VF is set to 1 when there's a carry, and to 0 when there is not.
-            (0x08, _, _, 0x04) => self.process_8xy4(x, y),
-            // 8XY5 - VY is subtracted from VX. VF is set to 0 when there's a borrow, and 1 when there is not.
-            (0x08, _, _, 0x05) => self.process_8xy5(x, y),
-            // 8XY6 - Stores the least significant bit of VX in VF and then shifts VX to the right by 1.
-            (0x08, _, _, 0x06) => self.process_8xy6(x, y),
-            // 8XY7 - Sets VX to VY minus VX. VF is set to 0 when there's a borrow, and 1 when there is not.
-            (0x08, _, _, 0x07) => self.process_8xy7(x, y),
-            // 8XYE - Stores the most significant bit of VX in VF and then shifts VX to the left by 1.
-            (0x08, _, _, 0x0e) => self.process_8xye(x, y),
-            // 9XY0 - Skips the next instruction if VX does not equal VY.
-            (0x09, _, _, 0x00) => self.process_9xy0(x, y),
-            // ANNN - Sets I to the address NNN
-            (0x0a, _, _, _) => self.process_annn(nnn),
-            // BNNN - Jumps to the address NNN plus V0.
-            (0x0b, _, _, _) => self.process_bnnn(nnn),
-            // CXNN - Sets VX to the result of a bitwise and operation on a random number (Typically: 0 to 255) and NN.
-            (0x0c, _, _, _) => self.process_cxnn(x, nn),
-            // DXYN - Draws a sprite at coordinate (VX, VY) that has a width of 8 pixels and a
-            // height of N pixels. Each row of 8 pixels is read as bit-coded starting from memory
-            // location I; I value does not change after the execution of this instruction. VF is
-            // set to 1 if any screen pixels are flipped from set to unset when the sprite is
-            // drawn, and to 0 if that does not happen.
-            (0x0d, _, _, _) => self.process_dxyn(x, y, n),
-            // EX9E - Skips the next instruction if the key stored in VX is pressed.
-            (0x0e, _, 0x09, 0x0e) => self.process_ex9e(x),
-            // EXA1 - Skips the next instruction if the key stored in VX is not pressed.
-            (0x0e, _, 0x0a, 0x01) => self.process_exa1(x),
-            // FX07 - Sets VX to the value of the delay timer.
-            (0x0f, _, 0x00, 0x07) => self.process_fx07(x),
-            // FX0A - A key press is awaited, and then stored in VX.
-            (0x0f, _, 0x00, 0x0a) => self.process_fx0a(x),
-            // FX15 - Sets the delay timer to VX.
-            (0x0f, _, 0x01, 0x05) => self.process_fx15(x),
-            // FX18 - Sets the sound timer to VX.
-            (0x0f, _, 0x01, 0x08) => self.process_fx18(x),
-            // FX1E - Adds VX to I. VF is not affected.
-            (0x0f, _, 0x01, 0x0e) => self.process_fx1e(x),
-            // FX29 - Sets I to the location of the sprite for the character in VX.
-            (0x0f, _, 0x02, 0x09) => self.process_fx29(x),
-            // FX33 - Stores the binary-coded decimal representation of VX, with the most significant of three digits at the address in I, the middle digit at I plus 1, and the least significant digit at I plus 2.
-            (0x0f, _, 0x03, 0x03) => self.process_fx33(x),
-            // FX55 - Stores from V0 to VX (including VX) in memory, starting at address I. The offset from I is increased by 1 for each value written.
-            (0x0f, _, 0x05, 0x05) => self.process_fx55(x),
-            // FX65 - Fills from V0 to VX (including VX) with values from memory, starting at address I. The offset from I is increased by 1 for each value read.
-            (0x0f, _, 0x06, 0x05) => self.process_fx65(x),
-            // Unknown opcode
-            _ => panic!("Unknown opcode {:02X}{:02X}", opcode_u8.0, opcode_u8.1),
+        let address = self.pc as usize;
+        let opcode = (self.mem[address] as u16) << 8 | self.mem[address + 1] as u16;
+        let instruction = match &mut self.decoded {
+            Some(decoded) => *decoded[address].get_or_insert_with(|| Instruction::decode(opcode)),
+            None => Instruction::decode(opcode),
+        };
+        let x = (opcode >> 8 & 0xF) as usize;
+        let (pc, vx, machine_cycles) = (self.pc, self.reg[x], self.machine_cycles);
+        self.cycles += 1;
+        self.accesses.clear();
+        if let Some(coverage) = &mut self.coverage {
+            coverage.execute(self.pc as usize);
+        }
+
+        self.execute(instruction)?;
+
+        // plus the cycles of the CDP1802 routine of 0NNN
+        let cost = timing::cost(opcode, vx, self.pc == pc.wrapping_add(4)) as u64
+            + (self.machine_cycles - machine_cycles);
+        self.machine_cycles = machine_cycles + cost;
+        match self.timing {
+            Timing::Fixed => self.frame_budget -= 1,
+            Timing::CosmacVip => {
+                self.frame_budget -= cost as i64;
+                if timing::waits_for_vblank(opcode) {
+                    self.frame_budget = self.frame_budget.min(0);
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn save_and_load_state() {
+        // RND V0, 0xFF ; LD I, 0x300 ; LD B, V0 ; DRW V0, V0, 1 ; JP 0x200
+        let rom = vec![0xC0, 0xFF, 0xA3, 0x00, 0xF0, 0x33, 0xD0, 0x01, 0x12, 0x00];
+        let mut chip8 = init();
+        chip8.load_rom(rom).unwrap();
+        chip8.press_key(0xA);
+        chip8.set_delay_timer(30);
+        chip8.run_frame(7).unwrap();
+        let state = chip8.save_state();
+        assert_eq!(state.len(), STATE_SIZE);
+
+        let mut restored = init();
+        restored.load_state(&state).unwrap();
+        for _ in 0..20 {
+            chip8.run_frame(7).unwrap();
+            restored.run_frame(7).unwrap();
         }
+        assert_eq!(restored.registers(), chip8.registers());
+        assert_eq!(restored.gfx_rows(), chip8.gfx_rows());
+        assert_eq!(restored.memory(0x300), chip8.memory(0x300));
+        assert_eq!(restored.cycles(), chip8.cycles());
+        assert_eq!(restored.delay_timer(), 9);
+        assert!(restored.is_key_down(0xA));
+
+        assert_eq!(restored.load_state(&state[1..]), Err(Error::InvalidState));
+        let mut other = state.clone();
+        other[3] = b'0';
+        assert_eq!(restored.load_state(&other), Err(Error::InvalidState));
+
+        // a corrupted stack pointer is refused, and the machine is left as it was
+        let sp_at = 4 + MEM_SIZE + N_REG + 2 * STACK_SIZE + 2 * 2;
+        let mut corrupted = state.clone();
+        corrupted[sp_at] = STACK_SIZE as u8 + 1;
+        corrupted[4 + 0x300] ^= 0xFF;
+        let memory = restored.memory(0x300);
+        assert_eq!(restored.load_state(&corrupted), Err(Error::InvalidState));
+        assert_eq!(restored.memory(0x300), memory);
+        corrupted[sp_at] = STACK_SIZE as u8;
+        restored.load_state(&corrupted).unwrap();
+        assert_eq!(restored.call_stack().len(), STACK_SIZE);
     }
 }
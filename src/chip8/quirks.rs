@@ -0,0 +1,85 @@
+// Behaviours that differ between CHIP-8 implementations.
+// The default values stick to the CHIP-48 flavour described in the README.
+use serde::Deserialize;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Quirks {
+    // 8XY6/8XYE shift VX in place instead of storing VY shifted into VX.
+    pub shift: bool,
+    // FX55/FX65 leave I incremented by X + 1.
+    pub memory_increment: bool,
+    // BNNN is read as BXNN and jumps to XNN plus VX instead of NNN plus V0.
+    pub jump: bool,
+    // 8XY1, 8XY2 and 8XY3 reset VF to 0.
+    pub vf_reset: bool,
+    // Sprites are clipped at the screen edges instead of wrapping around.
+    pub clip: bool,
+}
+
+impl Default for Quirks {
+    fn default() -> Self {
+        Quirks {
+            shift: true,
+            memory_increment: true,
+            jump: false,
+            vf_reset: false,
+            clip: true,
+        }
+    }
+}
+
+impl Quirks {
+    // Set a quirk by its name, as used in the configuration files.
+    pub fn set(&mut self, name: &str, value: bool) -> Result<(), String> {
+        let mut settings = QuirkSettings::default();
+        settings.set(name, value)?;
+        *self = settings.apply(*self);
+        Ok(())
+    }
+}
+
+// Some of the quirks, as found in a configuration file, the others keep their value.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct QuirkSettings {
+    pub shift: Option<bool>,
+    pub memory_increment: Option<bool>,
+    pub jump: Option<bool>,
+    pub vf_reset: Option<bool>,
+    pub clip: Option<bool>,
+}
+
+impl QuirkSettings {
+    // Override self with every quirk set in other.
+    pub fn merge(&mut self, other: &QuirkSettings) {
+        self.shift = other.shift.or(self.shift);
+        self.memory_increment = other.memory_increment.or(self.memory_increment);
+        self.jump = other.jump.or(self.jump);
+        self.vf_reset = other.vf_reset.or(self.vf_reset);
+        self.clip = other.clip.or(self.clip);
+    }
+
+    pub fn set(&mut self, name: &str, value: bool) -> Result<(), String> {
+        let quirk = match name {
+            "shift" => &mut self.shift,
+            "memory_increment" => &mut self.memory_increment,
+            "jump" => &mut self.jump,
+            "vf_reset" => &mut self.vf_reset,
+            "clip" => &mut self.clip,
+            _ => return Err(format!("unknown quirk '{}'", name)),
+        };
+        *quirk = Some(value);
+        Ok(())
+    }
+
+    // The given quirks, with the ones set here overridden.
+    pub fn apply(&self, quirks: Quirks) -> Quirks {
+        Quirks {
+            shift: self.shift.unwrap_or(quirks.shift),
+            memory_increment: self.memory_increment.unwrap_or(quirks.memory_increment),
+            jump: self.jump.unwrap_or(quirks.jump),
+            vf_reset: self.vf_reset.unwrap_or(quirks.vf_reset),
+            clip: self.clip.unwrap_or(quirks.clip),
+        }
+    }
+}
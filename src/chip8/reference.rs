@@ -0,0 +1,364 @@
+// Reference model of the machine, written after the specification rather than for speed.
+//
+// It shares nothing with the core but the error type, so that tests and fuzzing can run both
+// side by side and compare their state after each instruction.
+use super::{Chip8, Error, Quirks, HEIGHT, MEM_SIZE, START_ROM, WIDTH};
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+
+const FONT: usize = 0x050;
+const STACK_SIZE: usize = 16;
+
+#[derive(Debug, Clone)]
+pub struct Reference {
+    pub memory: [u8; MEM_SIZE],
+    pub v: [u8; 16],
+    pub i: u16,
+    pub pc: u16,
+    // addresses of the pending CALL instructions
+    pub stack: Vec<u16>,
+    pub delay_timer: u8,
+    pub sound_timer: u8,
+    pub screen: [[bool; WIDTH]; HEIGHT],
+    pub keys: [bool; 16],
+    pub quirks: Quirks,
+    pub cycles: u64,
+    rng: StdRng,
+}
+
+impl Reference {
+    // CXNN draws the same numbers as a core seeded with `seed`.
+    pub fn new(rom: &[u8], quirks: Quirks, seed: u64) -> Result<Reference, Error> {
+        if rom.len() > MEM_SIZE - START_ROM {
+            return Err(Error::RomTooBig(rom.len()));
+        }
+        let mut memory = [0; MEM_SIZE];
+        let font = include_bytes!("fontset.bin");
+        memory[FONT..FONT + font.len()].copy_from_slice(font);
+        memory[START_ROM..START_ROM + rom.len()].copy_from_slice(rom);
+        Ok(Reference {
+            memory,
+            v: [0; 16],
+            i: 0,
+            pc: START_ROM as u16,
+            stack: Vec::new(),
+            delay_timer: 0,
+            sound_timer: 0,
+            screen: [[false; WIDTH]; HEIGHT],
+            keys: [false; 16],
+            quirks,
+            cycles: 0,
+            rng: StdRng::seed_from_u64(seed),
+        })
+    }
+
+    // Same as Chip8::run_frame.
+    pub fn frame(&mut self, ipf: u32) -> Result<bool, Error> {
+        for _ in 0..ipf {
+            self.step()?;
+        }
+        Ok(self.update_timers())
+    }
+
+    // Same as Chip8::update_timer.
+    pub fn update_timers(&mut self) -> bool {
+        self.delay_timer = self.delay_timer.saturating_sub(1);
+        self.sound_timer = self.sound_timer.saturating_sub(1);
+        self.sound_timer > 0
+    }
+
+    // Execute one instruction.
+    pub fn step(&mut self) -> Result<(), Error> {
+        let pc = self.pc;
+        if pc as usize + 1 >= MEM_SIZE {
+            return Err(Error::PcOutOfBounds(pc));
+        }
+        let opcode = u16::from_be_bytes([self.memory[pc as usize], self.memory[pc as usize + 1]]);
+        let x = (opcode >> 8 & 0xF) as usize;
+        let y = (opcode >> 4 & 0xF) as usize;
+        let n = opcode & 0xF;
+        let nn = (opcode & 0xFF) as u8;
+        let nnn = opcode & 0xFFF;
+        self.cycles += 1;
+
+        let mut next = pc + 2;
+        let skip = pc + 4;
+        match opcode >> 12 {
+            0x0 if opcode == 0x00E0 => self.screen = [[false; WIDTH]; HEIGHT],
+            0x0 if opcode == 0x00EE => {
+                let call = self.stack.pop().ok_or(Error::StackUnderflow(pc))?;
+                next = call + 2;
+            }
+            0x0 => return Err(Error::MachineCode { pc, address: nnn }),
+            0x1 => next = nnn,
+            0x2 => {
+                if self.stack.len() == STACK_SIZE {
+                    return Err(Error::StackOverflow(pc));
+                }
+                self.stack.push(pc);
+                next = nnn;
+            }
+            0x3 if self.v[x] == nn => next = skip,
+            0x4 if self.v[x] != nn => next = skip,
+            0x5 if n == 0 && self.v[x] == self.v[y] => next = skip,
+            0x3 | 0x4 => {}
+            0x5 if n == 0 => {}
+            0x6 => self.v[x] = nn,
+            0x7 => self.v[x] = self.v[x].wrapping_add(nn),
+            0x8 if matches!(n, 0x0..=0x7 | 0xE) => self.alu(x, y, n),
+            0x9 if n == 0 && self.v[x] != self.v[y] => next = skip,
+            0x9 if n == 0 => {}
+            0xA => self.i = nnn,
+            0xB => {
+                let offset = if self.quirks.jump {
+                    self.v[x]
+                } else {
+                    self.v[0]
+                };
+                next = nnn + offset as u16;
+            }
+            0xC => self.v[x] = self.rng.gen::<u8>() & nn,
+            0xD => self.draw(x, y, n as usize)?,
+            0xE if nn == 0x9E && self.keys[(self.v[x] & 0xF) as usize] => next = skip,
+            0xE if nn == 0xA1 && !self.keys[(self.v[x] & 0xF) as usize] => next = skip,
+            0xE if nn == 0x9E || nn == 0xA1 => {}
+            0xF => match nn {
+                0x07 => self.v[x] = self.delay_timer,
+                0x0A => match self.keys.iter().position(|&k| k) {
+                    Some(key) => self.v[x] = key as u8,
+                    None => next = pc,
+                },
+                0x15 => self.delay_timer = self.v[x],
+                0x18 => self.sound_timer = self.v[x],
+                0x1E => self.i = self.i.wrapping_add(self.v[x] as u16),
+                0x29 => self.i = FONT as u16 + self.v[x] as u16 * 5,
+                0x33 => {
+                    let start = self.range(pc, 3)?;
+                    let value = self.v[x];
+                    self.memory[start] = value / 100;
+                    self.memory[start + 1] = value / 10 % 10;
+                    self.memory[start + 2] = value % 10;
+                }
+                0x55 | 0x65 => {
+                    let start = self.range(pc, x + 1)?;
+                    for r in 0..=x {
+                        if nn == 0x55 {
+                            self.memory[start + r] = self.v[r];
+                        } else {
+                            self.v[r] = self.memory[start + r];
+                        }
+                    }
+                    if self.quirks.memory_increment {
+                        self.i = self.i.wrapping_add(x as u16 + 1);
+                    }
+                }
+                _ => return Err(Error::UnknownOpcode { pc, opcode }),
+            },
+            _ => return Err(Error::UnknownOpcode { pc, opcode }),
+        }
+        self.pc = next;
+        Ok(())
+    }
+
+    fn alu(&mut self, x: usize, y: usize, n: u16) {
+        let (vx, vy) = (self.v[x], self.v[y]);
+        let (result, flag) = match n {
+            0x0 => (vy, None),
+            0x1..=0x3 => {
+                let result = match n {
+                    0x1 => vx | vy,
+                    0x2 => vx & vy,
+                    _ => vx ^ vy,
+                };
+                (result, self.quirks.vf_reset.then_some(0))
+            }
+            0x4 => (
+                vx.wrapping_add(vy),
+                Some((vx as u16 + vy as u16 > 0xFF) as u8),
+            ),
+            0x5 => (vx.wrapping_sub(vy), Some((vx >= vy) as u8)),
+            0x7 => (vy.wrapping_sub(vx), Some((vy >= vx) as u8)),
+            _ => {
+                let source = if self.quirks.shift { vx } else { vy };
+                if n == 0x6 {
+                    (source >> 1, Some(source & 1))
+                } else {
+                    (source << 1, Some(source >> 7))
+                }
+            }
+        };
+        self.v[x] = result;
+        if let Some(flag) = flag {
+            self.v[0xF] = flag;
+        }
+    }
+
+    fn draw(&mut self, x: usize, y: usize, height: usize) -> Result<(), Error> {
+        if height == 0 {
+            self.v[0xF] = 0;
+            return Ok(());
+        }
+        let start = self.range(self.pc, height)?;
+        let left = self.v[x] as usize % WIDTH;
+        let top = self.v[y] as usize % HEIGHT;
+        let mut collision = false;
+        for row in 0..height {
+            let bits = self.memory[start + row];
+            for column in 0..8 {
+                if bits & (0x80 >> column) == 0 {
+                    continue;
+                }
+                let (px, py) = (left + column, top + row);
+                if self.quirks.clip && (px >= WIDTH || py >= HEIGHT) {
+                    continue;
+                }
+                let pixel = &mut self.screen[py % HEIGHT][px % WIDTH];
+                collision |= *pixel;
+                *pixel = !*pixel;
+            }
+        }
+        self.v[0xF] = collision as u8;
+        Ok(())
+    }
+
+    // First address of the `length` bytes at I, which must fit in the memory.
+    fn range(&self, pc: u16, length: usize) -> Result<usize, Error> {
+        let start = self.i as usize;
+        if start + length > MEM_SIZE {
+            return Err(Error::MemoryOutOfBounds {
+                pc,
+                address: start + length - 1,
+            });
+        }
+        Ok(start)
+    }
+
+    // First difference with the state of the core, if any.
+    pub fn diff(&self, chip8: &Chip8) -> Option<String> {
+        let differs = |what: String, core: String, reference: String| {
+            Some(format!(
+                "{}: {} (core) != {} (reference)",
+                what, core, reference
+            ))
+        };
+        if chip8.program_counter() != self.pc {
+            return differs("PC".into(), hex(chip8.program_counter()), hex(self.pc));
+        }
+        if chip8.index() != self.i {
+            return differs("I".into(), hex(chip8.index()), hex(self.i));
+        }
+        for (r, &value) in self.v.iter().enumerate() {
+            if chip8.register(r) != value {
+                return differs(format!("V{:X}", r), hex(chip8.register(r)), hex(value));
+            }
+        }
+        if chip8.call_stack() != self.stack {
+            return differs(
+                "stack".into(),
+                format!("{:X?}", chip8.call_stack()),
+                format!("{:X?}", self.stack),
+            );
+        }
+        let timers = (chip8.delay_timer(), chip8.sound_timer());
+        if timers != (self.delay_timer, self.sound_timer) {
+            return differs(
+                "timers".into(),
+                format!("{:?}", timers),
+                format!("{:?}", (self.delay_timer, self.sound_timer)),
+            );
+        }
+        if chip8.cycles() != self.cycles {
+            return differs(
+                "cycles".into(),
+                chip8.cycles().to_string(),
+                self.cycles.to_string(),
+            );
+        }
+        for address in 0..MEM_SIZE {
+            if chip8.memory(address) != self.memory[address] {
+                return differs(
+                    format!("memory at {}", hex(address as u64)),
+                    hex(chip8.memory(address)),
+                    hex(self.memory[address]),
+                );
+            }
+        }
+        for (py, row) in self.screen.iter().enumerate() {
+            for (px, &pixel) in row.iter().enumerate() {
+                if chip8.gfx(px + py * WIDTH) != pixel {
+                    return differs(
+                        format!("pixel ({}, {})", px, py),
+                        chip8.gfx(px + py * WIDTH).to_string(),
+                        pixel.to_string(),
+                    );
+                }
+            }
+        }
+        None
+    }
+}
+
+// Run a ROM on the core and on the reference side by side, comparing them after each
+// instruction, until `keys.len()` frames are run or an error stops both. `keys` holds the
+// keypad of each frame, one bit per key. Returns the first difference.
+pub fn compare_with_reference(
+    rom: &[u8],
+    quirks: Quirks,
+    seed: u64,
+    ipf: u32,
+    keys: &[u16],
+) -> Result<(), String> {
+    let mut chip8 = super::init();
+    chip8.set_quirks(quirks);
+    chip8.seed_rng(seed);
+    let loaded = chip8.load_rom(rom.to_vec());
+    let mut reference = match Reference::new(rom, quirks, seed) {
+        Ok(reference) if loaded.is_ok() => reference,
+        Err(e) if loaded == Err(e) => return Ok(()),
+        reference => {
+            return Err(format!(
+                "loading: {:?} (core) != {:?} (reference)",
+                loaded,
+                reference.map(|_| ())
+            ))
+        }
+    };
+    for (frame, &mask) in keys.iter().enumerate() {
+        chip8.reset_keypad();
+        for key in 0..16 {
+            reference.keys[key] = mask & 1 << key != 0;
+            if reference.keys[key] {
+                chip8.press_key(key);
+            }
+        }
+        for _ in 0..ipf {
+            let pc = chip8.program_counter();
+            let opcode = chip8.opcode().unwrap_or(0);
+            let core = chip8.tick();
+            let expected = reference.step();
+            let location = || format!("frame {}, {:04X} at {:#05X}", frame, opcode, pc);
+            if core != expected {
+                return Err(format!(
+                    "{}: {:?} (core) != {:?} (reference)",
+                    location(),
+                    core,
+                    expected
+                ));
+            }
+            if let Some(difference) = reference.diff(&chip8) {
+                return Err(format!("{}: {}", location(), difference));
+            }
+            if core.is_err() {
+                return Ok(());
+            }
+        }
+        if chip8.update_timer() != reference.update_timers() {
+            return Err(format!("frame {}: sound differs", frame));
+        }
+    }
+    Ok(())
+}
+
+fn hex(value: impl Into<u64>) -> String {
+    format!("{:#X}", value.into())
+}
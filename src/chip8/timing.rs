@@ -0,0 +1,153 @@
+// How long instructions take, which sets how many of them fit in a 60 Hz frame.
+//
+// The COSMAC VIP runs its 1802 at 1.76 MHz, 3668 machine cycles per frame. The display DMA and
+// the interrupt routine steal part of them, the interpreter spends the rest on its fetch and
+// decode loop and on the routine of each instruction. The costs below approximate the ones of
+// the original interpreter; DXYN also waits for the vertical blank, so at most one sprite is
+// drawn per frame.
+
+pub const FRAME_CYCLES: i64 = 3668;
+// machine cycles stolen by the display DMA (8 bytes for each of the 128 lines) and the
+// interrupt routine, each frame
+const DISPLAY_CYCLES: i64 = 8 * 128 + 46;
+// machine cycles of the fetch and decode loop, before any instruction routine
+const FETCH_CYCLES: u32 = 68;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Timing {
+    // a fixed number of instructions per frame
+    #[default]
+    Fixed,
+    // the machine cycles of each instruction on a COSMAC VIP
+    CosmacVip,
+}
+
+impl Timing {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Timing::Fixed => "fixed",
+            Timing::CosmacVip => "cosmac-vip",
+        }
+    }
+
+    // Budget of a frame, in instructions or machine cycles.
+    pub(super) fn frame_budget(&self, ipf: u32) -> i64 {
+        match self {
+            Timing::Fixed => ipf as i64,
+            Timing::CosmacVip => FRAME_CYCLES - DISPLAY_CYCLES,
+        }
+    }
+}
+
+impl std::str::FromStr for Timing {
+    type Err = String;
+
+    fn from_str(name: &str) -> Result<Timing, String> {
+        match name {
+            "fixed" => Ok(Timing::Fixed),
+            "cosmac-vip" | "vip" => Ok(Timing::CosmacVip),
+            _ => Err(format!(
+                "unknown timing '{}', expected fixed or cosmac-vip",
+                name
+            )),
+        }
+    }
+}
+
+// Machine cycles of an executed instruction on a COSMAC VIP. `vx` is VX before the execution.
+pub fn cost(opcode: u16, vx: u8, skipped: bool) -> u32 {
+    let n = (opcode & 0xF) as u32;
+    let x = (opcode >> 8 & 0xF) as u32;
+    let skip = if skipped { 4 } else { 0 };
+    FETCH_CYCLES
+        + match opcode >> 12 {
+            // clearing the 256 bytes of the display buffer
+            0x0 if opcode == 0x00E0 => 3078,
+            0x0 if opcode == 0x00EE => 10,
+            0x0 => 0,
+            0x1 => 12,
+            0x2 => 26,
+            0x3 | 0x4 => 10 + skip,
+            0x5 | 0x9 => 14 + skip,
+            0x6 => 6,
+            0x7 => 10,
+            0x8 => 44,
+            0xA => 12,
+            0xB => 22,
+            0xC => 36,
+            // sprites not aligned on a byte are shifted across two bytes
+            0xD => 26 + n * if vx.is_multiple_of(8) { 46 } else { 68 },
+            0xE => 18 + skip,
+            0xF => match opcode & 0xFF {
+                0x1E | 0x29 => 16,
+                // the digits are found by repeated subtractions
+                0x33 => {
+                    let digits = (vx / 100 + vx / 10 % 10 + vx % 10) as u32;
+                    80 + 16 * digits
+                }
+                0x55 | 0x65 => 14 + 14 * (x + 1),
+                _ => 10,
+            },
+            _ => 0,
+        }
+}
+
+// Whether the instruction makes the interpreter wait for the next frame.
+pub fn waits_for_vblank(opcode: u16) -> bool {
+    opcode >> 12 == 0xD
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chip8::init;
+
+    fn machine(rom: Vec<u8>) -> crate::chip8::Chip8 {
+        let mut chip8 = init();
+        chip8.set_timing(Timing::CosmacVip);
+        chip8.load_rom(rom).unwrap();
+        chip8
+    }
+
+    #[test]
+    fn frame_budget() {
+        // 0x200: ADD V0, 1
+        // 0x202: JP 0x200
+        let mut chip8 = machine(vec![0x70, 0x01, 0x12, 0x00]);
+        let budget = FRAME_CYCLES - DISPLAY_CYCLES;
+        let pair = (cost(0x7001, 0, false) + cost(0x1200, 0, false)) as i64;
+        chip8.run_frame(1).unwrap();
+        // the last instruction overruns the frame
+        let used = chip8.machine_cycles() as i64;
+        assert!(used >= budget && used < budget + pair / 2 + 1);
+        assert_eq!(chip8.cycles(), 33);
+
+        // the overrun is taken from the next frame
+        for _ in 0..59 {
+            chip8.run_frame(1).unwrap();
+        }
+        let expected = 60 * budget;
+        assert!((chip8.machine_cycles() as i64 - expected).abs() < pair);
+    }
+
+    #[test]
+    fn draw_waits_for_vblank() {
+        // 0x200: DRW V0, V0, 1
+        // 0x202: JP 0x200
+        let mut chip8 = machine(vec![0xD0, 0x01, 0x12, 0x00]);
+        chip8.run_frame(1).unwrap();
+        assert_eq!(chip8.cycles(), 1);
+        chip8.run_frame(1).unwrap();
+        assert_eq!(chip8.cycles(), 3);
+    }
+
+    #[test]
+    fn costs() {
+        assert_eq!(cost(0x6012, 0, false), FETCH_CYCLES + 6);
+        assert_eq!(cost(0x3012, 0, true), cost(0x3012, 0, false) + 4);
+        // unaligned sprites are slower
+        assert!(cost(0xD015, 3, false) > cost(0xD015, 8, false));
+        assert_eq!(cost(0xF255, 0, false), cost(0xF055, 0, false) + 28);
+        assert!(cost(0xF033, 199, false) > cost(0xF033, 100, false));
+    }
+}
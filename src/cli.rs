@@ -0,0 +1,331 @@
+use crate::config::Settings;
+use crate::trace;
+use clap::{Args, CommandFactory, Parser, Subcommand};
+use std::path::PathBuf;
+
+#[derive(Debug, Parser)]
+#[command(version, about = "Yet another Chip8 emulator")]
+#[command(args_conflicts_with_subcommands = true, arg_required_else_help = true)]
+pub struct Cli {
+    #[command(subcommand)]
+    pub command: Option<Command>,
+
+    // `chip8 rom.ch8` is a shortcut for `chip8 run rom.ch8`
+    /// Path to the ROM to run
+    pub rom: Option<PathBuf>,
+
+    #[command(flatten)]
+    pub settings: SettingsArgs,
+
+    #[command(flatten)]
+    pub trace: TraceArgs,
+
+    /// Rhai script hooked to the emulation, see the README
+    #[arg(long, value_name = "FILE")]
+    pub script: Option<PathBuf>,
+}
+
+#[derive(Debug, Subcommand)]
+pub enum Command {
+    /// Run a ROM in a window
+    Run(RunArgs),
+    /// Print what is known about a ROM
+    Info(InfoArgs),
+    /// Print a binary execution trace as text
+    TraceDump(TraceDumpArgs),
+    /// Run a ROM headless, under the control of a GDB remote protocol debugger
+    Gdb(GdbArgs),
+    /// Run a ROM headless and report where its time is spent
+    Profile(ProfileArgs),
+    /// Run a ROM headless and report which of its bytes were executed, read or written
+    Coverage(CoverageArgs),
+    /// Run a ROM headless as fast as possible, with and without the decode cache
+    Bench(BenchArgs),
+    /// Run every ROM of a directory headless, in parallel, and report how they ended
+    Batch(BatchArgs),
+}
+
+#[derive(Debug, Args)]
+pub struct RunArgs {
+    /// Path to the ROM to run
+    pub rom: PathBuf,
+
+    #[command(flatten)]
+    pub settings: SettingsArgs,
+
+    #[command(flatten)]
+    pub trace: TraceArgs,
+
+    /// Rhai script hooked to the emulation, see the README
+    #[arg(long, value_name = "FILE")]
+    pub script: Option<PathBuf>,
+}
+
+#[derive(Debug, Args)]
+pub struct InfoArgs {
+    /// Path to the ROM to inspect
+    pub rom: PathBuf,
+
+    #[command(flatten)]
+    pub settings: SettingsArgs,
+}
+
+#[derive(Debug, Args)]
+pub struct GdbArgs {
+    /// Path to the ROM to debug
+    pub rom: PathBuf,
+
+    /// Local TCP port to listen on
+    #[arg(long, default_value_t = 1234)]
+    pub port: u16,
+
+    /// Symbol file, with one "ADDRESS LABEL" pair per line
+    #[arg(long, value_name = "FILE")]
+    pub symbols: Option<PathBuf>,
+
+    #[command(flatten)]
+    pub settings: SettingsArgs,
+}
+
+#[derive(Debug, Args)]
+pub struct ProfileArgs {
+    /// Path to the ROM to profile
+    pub rom: PathBuf,
+
+    /// Number of 60 Hz frames to run
+    #[arg(long, default_value_t = 600)]
+    pub frames: u32,
+
+    /// Symbol file, with one "ADDRESS LABEL" pair per line
+    #[arg(long, value_name = "FILE")]
+    pub symbols: Option<PathBuf>,
+
+    /// Also write the folded call stacks, for flamegraph tools
+    #[arg(long, value_name = "FILE")]
+    pub folded: Option<PathBuf>,
+
+    #[command(flatten)]
+    pub settings: SettingsArgs,
+}
+
+#[derive(Debug, Args)]
+pub struct BenchArgs {
+    /// Path to the ROM to run
+    pub rom: PathBuf,
+
+    /// Number of 60 Hz frames to run
+    #[arg(long, default_value_t = 6000)]
+    pub frames: u32,
+
+    /// Instructions per frame, instead of the ones given by --speed
+    #[arg(long, default_value_t = 1000)]
+    pub ipf: u32,
+
+    #[command(flatten)]
+    pub settings: SettingsArgs,
+}
+
+#[derive(Debug, Args)]
+pub struct BatchArgs {
+    /// Directory searched recursively for .ch8 ROMs
+    pub dir: PathBuf,
+
+    /// Number of 60 Hz frames to run each ROM for
+    #[arg(long, default_value_t = 600)]
+    pub frames: u32,
+
+    /// Number of ROMs run at the same time, defaults to the number of CPUs
+    #[arg(long)]
+    pub jobs: Option<usize>,
+
+    /// Write the report as JSON
+    #[arg(long, value_name = "FILE")]
+    pub json: Option<PathBuf>,
+
+    /// Write the report as HTML, with thumbnails of the final frames
+    #[arg(long, value_name = "FILE")]
+    pub html: Option<PathBuf>,
+
+    /// JSON report of a previous run to compare with, fails when ROMs regressed
+    #[arg(long, value_name = "FILE")]
+    pub previous: Option<PathBuf>,
+
+    #[command(flatten)]
+    pub settings: SettingsArgs,
+}
+
+#[derive(Debug, Args)]
+pub struct CoverageArgs {
+    /// Path to the ROM to cover
+    pub rom: PathBuf,
+
+    /// Number of 60 Hz frames to run
+    #[arg(long, default_value_t = 600)]
+    pub frames: u32,
+
+    /// Input movie, with the keys held on each frame
+    #[arg(long, value_name = "FILE")]
+    pub input: Option<PathBuf>,
+
+    /// Also write an lcov tracefile, whose line numbers are the listing lines
+    #[arg(long, value_name = "FILE")]
+    pub lcov: Option<PathBuf>,
+
+    #[command(flatten)]
+    pub settings: SettingsArgs,
+}
+
+#[derive(Debug, Args)]
+pub struct TraceDumpArgs {
+    /// Binary trace written with `--trace-format binary`
+    pub trace: PathBuf,
+}
+
+#[derive(Debug, Args)]
+pub struct TraceArgs {
+    /// Write an execution trace, one record per executed instruction
+    #[arg(long, value_name = "FILE")]
+    pub trace: Option<PathBuf>,
+
+    /// Trace format
+    #[arg(long, value_enum, default_value = "text", requires = "trace")]
+    pub trace_format: trace::Format,
+
+    /// Only trace instructions within an address range, e.g. "0x200-0x2FF"
+    #[arg(long, value_name = "START-END", value_parser = trace::parse_range, requires = "trace")]
+    pub trace_range: Option<std::ops::RangeInclusive<u16>>,
+
+    /// Only trace these opcode classes (first hex digit), e.g. "8,D"
+    #[arg(long, value_name = "CLASSES", value_delimiter = ',', value_parser = parse_class, requires = "trace")]
+    pub trace_opcodes: Option<Vec<u8>>,
+
+    /// Only trace from this cycle on
+    #[arg(long, value_name = "CYCLE", default_value_t = 0, requires = "trace")]
+    pub trace_after: u64,
+}
+
+#[derive(Debug, Args)]
+pub struct SettingsArgs {
+    /// Configuration file (defaults to $XDG_CONFIG_HOME/chip8/config.toml)
+    #[arg(long)]
+    pub config: Option<PathBuf>,
+
+    /// chip-8-database `database/` directory (defaults to $XDG_DATA_HOME/chip8/database)
+    #[arg(long)]
+    pub database: Option<PathBuf>,
+
+    /// CPU frequency, in instructions per second
+    #[arg(long)]
+    pub speed: Option<u32>,
+
+    /// Instruction timing: "fixed" runs --speed instructions per second, "cosmac-vip" the
+    /// machine cycles of each instruction on a COSMAC VIP
+    #[arg(long, value_name = "fixed|cosmac-vip")]
+    pub timing: Option<String>,
+
+    /// 0NNN machine code calls: stop with an error, skip them, or run them on an emulated
+    /// RCA CDP1802
+    #[arg(long, value_name = "fault|ignore|cdp1802")]
+    pub machine_code: Option<String>,
+
+    /// Speed multiplier while fast-forwarding, 0 is uncapped
+    #[arg(long)]
+    pub fast_forward: Option<u32>,
+
+    /// Window scale factor: 1, 2, 4, 8, 16 or 32
+    #[arg(long)]
+    pub scale: Option<u8>,
+
+    /// Background and foreground colors, e.g. "#000000,#FFFFFF"
+    #[arg(long, value_delimiter = ',')]
+    pub palette: Option<Vec<String>>,
+
+    /// Keyboard keys for chip8 keys 0 to F, comma separated
+    #[arg(long, value_delimiter = ',')]
+    pub keymap: Option<Vec<String>>,
+
+    /// Set a quirk, e.g. "--quirk shift=off" (repeatable)
+    #[arg(long = "quirk", value_name = "NAME=on|off")]
+    pub quirks: Vec<String>,
+}
+
+impl Cli {
+    pub fn command(self) -> Command {
+        match (self.command, self.rom) {
+            (Some(command), _) => command,
+            (None, Some(rom)) => Command::Run(RunArgs {
+                rom,
+                settings: self.settings,
+                trace: self.trace,
+                script: self.script,
+            }),
+            (None, None) => <Cli as CommandFactory>::command()
+                .error(
+                    clap::error::ErrorKind::MissingRequiredArgument,
+                    "a ROM or a subcommand is required",
+                )
+                .exit(),
+        }
+    }
+}
+
+fn parse_class(class: &str) -> Result<u8, String> {
+    match u8::from_str_radix(class, 16) {
+        Ok(class) if class < 0x10 => Ok(class),
+        _ => Err(format!("invalid opcode class '{}', expected 0 to F", class)),
+    }
+}
+
+impl TraceArgs {
+    pub fn tracer(&self) -> Result<Option<trace::Tracer>, String> {
+        let path = match &self.trace {
+            Some(path) => path,
+            None => return Ok(None),
+        };
+        let filter = trace::Filter {
+            range: self.trace_range.clone(),
+            classes: self.trace_opcodes.clone(),
+            after: self.trace_after,
+        };
+        trace::Tracer::create(path, self.trace_format, filter)
+            .map(Some)
+            .map_err(|e| format!("cannot create {}: {}", path.display(), e))
+    }
+}
+
+impl SettingsArgs {
+    pub fn settings(&self) -> Result<Settings, String> {
+        let palette = match self.palette.as_deref() {
+            Some([bg, fg]) => Some([bg.clone(), fg.clone()]),
+            Some(_) => return Err("palette needs exactly 2 colors".to_string()),
+            None => None,
+        };
+        let mut settings = Settings {
+            speed: self.speed,
+            timing: self.timing.clone(),
+            machine_code: self.machine_code.clone(),
+            fast_forward: self.fast_forward,
+            scale: self.scale,
+            palette,
+            keymap: self.keymap.clone(),
+            ..Settings::default()
+        };
+        for quirk in &self.quirks {
+            let (name, value) = quirk
+                .split_once('=')
+                .ok_or_else(|| format!("invalid quirk '{}', expected NAME=on|off", quirk))?;
+            let value = match value {
+                "on" | "true" | "1" => true,
+                "off" | "false" | "0" => false,
+                _ => {
+                    return Err(format!(
+                        "invalid quirk value '{}', expected on or off",
+                        value
+                    ))
+                }
+            };
+            settings.quirks.set(name, value)?;
+        }
+        Ok(settings)
+    }
+}
@@ -0,0 +1,407 @@
+pub use chip8::QuirkSettings;
+use chip8::{MachineCode, Quirks, Timing};
+use serde::Deserialize;
+use sha1::{Digest, Sha1};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+// Settings are resolved from the lowest to the highest priority:
+//   1. built-in defaults (see `Config::default`)
+//   2. the [default] section of the configuration file
+//   3. the ROM database entry matching the loaded ROM
+//   4. the [rom.<sha1>] section matching the loaded ROM
+//   5. command line flags
+// Each layer only overrides the values it explicitly sets.
+
+// A partial set of settings, as found in a configuration file section or on the command line.
+#[derive(Debug, Default, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Settings {
+    // CPU frequency, in instructions per second
+    pub speed: Option<u32>,
+    // "fixed" to run `speed` instructions per second, "cosmac-vip" for the original timing
+    pub timing: Option<String>,
+    // 0NNN machine code calls: "fault", "ignore" or "cdp1802" to run them
+    pub machine_code: Option<String>,
+    // speed multiplier while fast-forwarding, 0 is uncapped
+    pub fast_forward: Option<u32>,
+    // window scale factor: 1, 2, 4, 8, 16 or 32
+    pub scale: Option<u8>,
+    // [background, foreground] as "#RRGGBB"
+    pub palette: Option<[String; 2]>,
+    // keyboard key names for chip8 keys 0x0 to 0xF
+    pub keymap: Option<Vec<String>>,
+    pub quirks: QuirkSettings,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ConfigFile {
+    // chip-8-database `database/` directory, relative to the configuration file
+    pub database: Option<PathBuf>,
+    pub default: Settings,
+    // per-ROM sections, keyed by the lowercase hex SHA-1 of the ROM
+    pub rom: HashMap<String, Settings>,
+}
+
+// Fully resolved settings, ready to be used by the emulator.
+#[derive(Debug, Clone)]
+pub struct Config {
+    pub speed: u32,
+    pub timing: Timing,
+    pub machine_code: MachineCode,
+    pub fast_forward: u32,
+    pub scale: u8,
+    pub palette: [u32; 2],
+    pub keymap: [minifb::Key; 16],
+    // additional keys for the game controls given by the ROM database
+    pub controls: Vec<(minifb::Key, usize)>,
+    pub quirks: Quirks,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        use minifb::Key::*;
+        Config {
+            speed: 500,
+            timing: Timing::Fixed,
+            machine_code: MachineCode::Fault,
+            fast_forward: 0,
+            scale: 8,
+            palette: [0x000000, 0xFFFFFF],
+            keymap: [X, Key1, Key2, Key3, Q, W, E, A, S, D, Z, C, Key4, R, F, V],
+            controls: Vec::new(),
+            quirks: Quirks::default(),
+        }
+    }
+}
+
+impl Settings {
+    // Override self with every value set in other.
+    pub fn merge(&mut self, other: &Settings) {
+        self.speed = other.speed.or(self.speed);
+        self.timing = other.timing.clone().or(self.timing.take());
+        self.machine_code = other.machine_code.clone().or(self.machine_code.take());
+        self.fast_forward = other.fast_forward.or(self.fast_forward);
+        self.scale = other.scale.or(self.scale);
+        self.palette = other.palette.clone().or(self.palette.take());
+        self.keymap = other.keymap.clone().or(self.keymap.take());
+        self.quirks.merge(&other.quirks);
+    }
+
+    pub fn resolve(&self) -> Result<Config, String> {
+        let mut config = Config::default();
+        if let Some(speed) = self.speed {
+            if speed == 0 {
+                return Err("speed must be greater than 0".to_string());
+            }
+            config.speed = speed;
+        }
+        if let Some(timing) = &self.timing {
+            config.timing = timing.parse()?;
+        }
+        if let Some(machine_code) = &self.machine_code {
+            config.machine_code = machine_code.parse()?;
+        }
+        if let Some(fast_forward) = self.fast_forward {
+            config.fast_forward = fast_forward;
+        }
+        if let Some(scale) = self.scale {
+            if ![1, 2, 4, 8, 16, 32].contains(&scale) {
+                return Err(format!(
+                    "invalid scale {}, expected 1, 2, 4, 8, 16 or 32",
+                    scale
+                ));
+            }
+            config.scale = scale;
+        }
+        if let Some([bg, fg]) = &self.palette {
+            config.palette = [parse_color(bg)?, parse_color(fg)?];
+        }
+        if let Some(keymap) = &self.keymap {
+            if keymap.len() != 16 {
+                return Err(format!("keymap needs 16 keys, got {}", keymap.len()));
+            }
+            for (i, name) in keymap.iter().enumerate() {
+                config.keymap[i] = parse_key(name)?;
+            }
+        }
+        config.quirks = self.quirks.apply(config.quirks);
+        Ok(config)
+    }
+}
+
+impl ConfigFile {
+    pub fn load(path: &Path) -> Result<ConfigFile, String> {
+        let content = std::fs::read_to_string(path)
+            .map_err(|e| format!("cannot read {}: {}", path.display(), e))?;
+        let mut file: ConfigFile = toml::from_str(&content)
+            .map_err(|e| format!("invalid config {}: {}", path.display(), e))?;
+        if let (Some(database), Some(dir)) = (&file.database, path.parent()) {
+            file.database = Some(dir.join(database));
+        }
+        Ok(file)
+    }
+
+    // Settings for a given ROM, without command line overrides.
+    pub fn settings_for(&self, rom_hash: &str, detected: Option<&Settings>) -> Settings {
+        let mut settings = self.default.clone();
+        if let Some(detected) = detected {
+            settings.merge(detected);
+        }
+        if let Some(rom) = self.rom.get(rom_hash) {
+            settings.merge(rom);
+        }
+        settings
+    }
+}
+
+// $XDG_CONFIG_HOME/chip8/config.toml, falling back to ~/.config/chip8/config.toml
+pub fn default_path() -> Option<PathBuf> {
+    let base = match std::env::var_os("XDG_CONFIG_HOME") {
+        Some(dir) if !dir.is_empty() => PathBuf::from(dir),
+        _ => PathBuf::from(std::env::var_os("HOME")?).join(".config"),
+    };
+    Some(base.join("chip8").join("config.toml"))
+}
+
+pub fn rom_hash(rom: &[u8]) -> String {
+    Sha1::digest(rom)
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect()
+}
+
+fn parse_color(color: &str) -> Result<u32, String> {
+    let error = || format!("invalid color '{}', expected #RRGGBB", color);
+    let hex = color.strip_prefix('#').unwrap_or(color);
+    let value = u32::from_str_radix(hex, 16).map_err(|_| error())?;
+    match hex.len() {
+        6 => Ok(value),
+        // #RGB shorthand
+        3 => Ok(((value & 0xF00) << 8 | (value & 0x0F0) << 4 | (value & 0x00F)) * 0x11),
+        _ => Err(error()),
+    }
+}
+
+// Keys bound to the game controls names of the ROM database.
+pub fn control_key(name: &str) -> Option<minifb::Key> {
+    match name {
+        "up" => Some(minifb::Key::Up),
+        "down" => Some(minifb::Key::Down),
+        "left" => Some(minifb::Key::Left),
+        "right" => Some(minifb::Key::Right),
+        "a" => Some(minifb::Key::Space),
+        "b" => Some(minifb::Key::LeftShift),
+        _ => None,
+    }
+}
+
+fn parse_key(name: &str) -> Result<minifb::Key, String> {
+    use minifb::Key::*;
+    let key = match name.to_ascii_uppercase().as_str() {
+        "0" => Key0,
+        "1" => Key1,
+        "2" => Key2,
+        "3" => Key3,
+        "4" => Key4,
+        "5" => Key5,
+        "6" => Key6,
+        "7" => Key7,
+        "8" => Key8,
+        "9" => Key9,
+        "A" => A,
+        "B" => B,
+        "C" => C,
+        "D" => D,
+        "E" => E,
+        "F" => F,
+        "G" => G,
+        "H" => H,
+        "I" => I,
+        "J" => J,
+        "K" => K,
+        "L" => L,
+        "M" => M,
+        "N" => N,
+        "O" => O,
+        "P" => P,
+        "Q" => Q,
+        "R" => R,
+        "S" => S,
+        "T" => T,
+        "U" => U,
+        "V" => V,
+        "W" => W,
+        "X" => X,
+        "Y" => Y,
+        "Z" => Z,
+        "NUMPAD0" => NumPad0,
+        "NUMPAD1" => NumPad1,
+        "NUMPAD2" => NumPad2,
+        "NUMPAD3" => NumPad3,
+        "NUMPAD4" => NumPad4,
+        "NUMPAD5" => NumPad5,
+        "NUMPAD6" => NumPad6,
+        "NUMPAD7" => NumPad7,
+        "NUMPAD8" => NumPad8,
+        "NUMPAD9" => NumPad9,
+        "UP" => Up,
+        "DOWN" => Down,
+        "LEFT" => Left,
+        "RIGHT" => Right,
+        "SPACE" => Space,
+        "ENTER" => Enter,
+        "TAB" => Tab,
+        "COMMA" => Comma,
+        "PERIOD" => Period,
+        "SLASH" => Slash,
+        "SEMICOLON" => Semicolon,
+        _ => return Err(format!("unknown key '{}'", name)),
+    };
+    Ok(key)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cli::Cli;
+    use clap::Parser;
+
+    const HASH: &str = "0123456789abcdef0123456789abcdef01234567";
+
+    fn file() -> ConfigFile {
+        toml::from_str(&format!(
+            r##"
+            [default]
+            speed = 600
+            scale = 4
+            palette = ["#000", "#fff"]
+            quirks = {{ shift = false, vf_reset = true }}
+
+            [rom.{}]
+            speed = 700
+            quirks = {{ vf_reset = false }}
+            "##,
+            HASH
+        ))
+        .unwrap()
+    }
+
+    fn cli(args: &[&str]) -> Settings {
+        let cli = Cli::try_parse_from(["chip8"].iter().chain(args).chain(&["rom.ch8"])).unwrap();
+        cli.settings.settings().unwrap()
+    }
+
+    #[test]
+    fn layers() {
+        let file = file();
+        let detected = Settings {
+            speed: Some(650),
+            scale: Some(2),
+            timing: Some("cosmac-vip".to_string()),
+            quirks: QuirkSettings {
+                shift: Some(true),
+                jump: Some(true),
+                ..QuirkSettings::default()
+            },
+            ..Settings::default()
+        };
+
+        // [default] only
+        let config = file.settings_for("unknown", None).resolve().unwrap();
+        assert_eq!((config.speed, config.scale), (600, 4));
+        assert_eq!(config.palette, [0x000000, 0xFFFFFF]);
+        assert!(!config.quirks.shift && config.quirks.vf_reset);
+        assert_eq!(config.timing, Timing::Fixed);
+
+        // the database entry overrides [default]
+        let config = file.settings_for("unknown", Some(&detected));
+        let config = config.resolve().unwrap();
+        assert_eq!((config.speed, config.scale), (650, 2));
+        assert_eq!(config.timing, Timing::CosmacVip);
+        assert!(config.quirks.shift && config.quirks.jump && config.quirks.vf_reset);
+
+        // the ROM section overrides the database entry
+        let settings = file.settings_for(HASH, Some(&detected));
+        let config = settings.resolve().unwrap();
+        assert_eq!((config.speed, config.scale), (700, 2));
+        assert!(config.quirks.shift && !config.quirks.vf_reset);
+
+        // and the command line overrides everything
+        let mut settings = settings;
+        settings.merge(&cli(&["--speed", "900", "--quirk", "vf_reset=on"]));
+        let config = settings.resolve().unwrap();
+        assert_eq!((config.speed, config.scale), (900, 2));
+        assert!(config.quirks.vf_reset && config.quirks.jump);
+        assert_eq!(config.palette, [0x000000, 0xFFFFFF]);
+    }
+
+    #[test]
+    fn colors() {
+        assert_eq!(parse_color("#102030"), Ok(0x102030));
+        assert_eq!(parse_color("a0b0c0"), Ok(0xA0B0C0));
+        assert_eq!(parse_color("#f80"), Ok(0xFF8800));
+        for color in ["", "#", "#12345", "#1234567", "#GGGGGG", "red", "#-12"] {
+            assert!(parse_color(color).is_err(), "{}", color);
+        }
+        let settings = Settings {
+            palette: Some(["#000".to_string(), "white".to_string()]),
+            ..Settings::default()
+        };
+        assert_eq!(
+            settings.resolve().unwrap_err(),
+            "invalid color 'white', expected #RRGGBB"
+        );
+    }
+
+    #[test]
+    fn keys() {
+        assert_eq!(parse_key("q"), Ok(minifb::Key::Q));
+        assert_eq!(parse_key("NumPad5"), Ok(minifb::Key::NumPad5));
+        assert_eq!(parse_key("F13"), Err("unknown key 'F13'".to_string()));
+        assert!(parse_key("").is_err());
+        let settings = Settings {
+            keymap: Some(vec!["X".to_string(); 15]),
+            ..Settings::default()
+        };
+        assert_eq!(
+            settings.resolve().unwrap_err(),
+            "keymap needs 16 keys, got 15"
+        );
+    }
+
+    #[test]
+    fn quirk_names() {
+        let mut quirks = QuirkSettings::default();
+        quirks.set("clip", false).unwrap();
+        assert_eq!(quirks.clip, Some(false));
+        assert_eq!(
+            quirks.set("wrap", true),
+            Err("unknown quirk 'wrap'".to_string())
+        );
+        let cli = Cli::try_parse_from(["chip8", "--quirk", "shift", "rom.ch8"]).unwrap();
+        assert!(cli.settings.settings().is_err());
+        let cli = Cli::try_parse_from(["chip8", "--quirk", "shift=maybe", "rom.ch8"]).unwrap();
+        assert!(cli.settings.settings().is_err());
+        assert!(toml::from_str::<ConfigFile>("[default.quirks]\nwrap = true\n").is_err());
+    }
+
+    #[test]
+    fn database_path() {
+        let dir = std::env::temp_dir().join(format!("chip8-config-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("config.toml");
+        std::fs::write(&path, "database = \"chip-8-database/database\"\n").unwrap();
+        let file = ConfigFile::load(&path);
+        std::fs::remove_dir_all(&dir).unwrap();
+        let database = file.unwrap().database;
+        assert_eq!(database, Some(dir.join("chip-8-database/database")));
+
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(&path, "database = \"/data/chip8\"\n").unwrap();
+        let file = ConfigFile::load(&path);
+        std::fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(file.unwrap().database, Some(PathBuf::from("/data/chip8")));
+    }
+}
@@ -0,0 +1,206 @@
+// Coverage report of a ROM: an annotated disassembly, its summary, and an lcov tracefile.
+//
+// Listing lines start with the x (executed), r (read) and w (written) flags and the number of
+// executions of the instruction, followed by the address, the bytes and the disassembly.
+// Bytes which were not executed are listed as data, up to DATA_BYTES per line.
+use chip8::{self, Coverage};
+use std::fmt::Write;
+
+const DATA_BYTES: usize = 4;
+
+// One line of the listing.
+struct Line {
+    address: usize,
+    length: usize,
+    // None for data
+    executions: Option<u32>,
+}
+
+pub struct Report {
+    lines: Vec<Line>,
+    start: usize,
+    end: usize,
+}
+
+impl Report {
+    // Coverage of the ROM loaded at `start`, `length` bytes long.
+    pub fn new(coverage: &Coverage, start: usize, length: usize) -> Report {
+        let end = (start + length).min(chip8::MEM_SIZE);
+        let flags = |a: usize| {
+            (
+                coverage.is_executed(a),
+                coverage.is_read(a),
+                coverage.is_written(a),
+            )
+        };
+        let mut lines = Vec::new();
+        let mut address = start;
+        while address < end {
+            let line = if coverage.executions(address) > 0 && address + 1 < end {
+                Line {
+                    address,
+                    length: 2,
+                    executions: Some(coverage.executions(address)),
+                }
+            } else {
+                // group the following data bytes used the same way
+                let length = (address..end)
+                    .take(DATA_BYTES)
+                    .take_while(|&a| {
+                        a == address || (coverage.executions(a) == 0 && flags(a) == flags(address))
+                    })
+                    .count();
+                Line {
+                    address,
+                    length,
+                    executions: None,
+                }
+            };
+            address += line.length;
+            lines.push(line);
+        }
+        Report { lines, start, end }
+    }
+
+    // Disassembly of the ROM as loaded, before any self modification.
+    pub fn listing(&self, coverage: &Coverage, rom: &[u8]) -> String {
+        let mut out = String::new();
+        for line in &self.lines {
+            let a = line.address;
+            let flags: String = [
+                (coverage.is_executed(a), 'x'),
+                (coverage.is_read(a), 'r'),
+                (coverage.is_written(a), 'w'),
+            ]
+            .iter()
+            .map(|&(set, c)| if set { c } else { '-' })
+            .collect();
+            let bytes = &rom[a - self.start..a - self.start + line.length];
+            match line.executions {
+                Some(executions) => {
+                    let opcode = (bytes[0] as u16) << 8 | bytes[1] as u16;
+                    writeln!(
+                        out,
+                        "{} {:>8}  {:03X}: {:04X}         {}",
+                        flags,
+                        executions,
+                        a,
+                        opcode,
+                        chip8::disassemble(opcode)
+                    )
+                }
+                None => {
+                    let hex: Vec<String> = bytes.iter().map(|b| format!("{:02X}", b)).collect();
+                    writeln!(out, "{} {:>8}  {:03X}: {}", flags, "", a, hex.join(" "))
+                }
+            }
+            .unwrap();
+        }
+        out
+    }
+
+    // Share of the ROM bytes executed, read, written and used in any way.
+    pub fn summary(&self, coverage: &Coverage) -> String {
+        let total = self.end - self.start;
+        let count = |f: &dyn Fn(usize) -> bool| (self.start..self.end).filter(|&a| f(a)).count();
+        let mut out = String::from("Summary coverage rate:\n");
+        for (name, hit) in [
+            ("executed", count(&|a| coverage.is_executed(a))),
+            ("read", count(&|a| coverage.is_read(a))),
+            ("written", count(&|a| coverage.is_written(a))),
+            ("covered", count(&|a| coverage.is_covered(a))),
+        ] {
+            writeln!(
+                out,
+                "  {:.<11}: {:.1}% ({} of {} bytes)",
+                name,
+                100.0 * hit as f64 / total.max(1) as f64,
+                hit,
+                total
+            )
+            .unwrap();
+        }
+        out
+    }
+
+    // lcov tracefile, whose line numbers are the listing lines. Data lines count as hit once
+    // they are read or written.
+    pub fn lcov(&self, coverage: &Coverage, source: &str) -> String {
+        let mut out = format!("TN:\nSF:{}\n", source);
+        let mut hit = 0;
+        for (n, line) in self.lines.iter().enumerate() {
+            let count = match line.executions {
+                Some(executions) => executions,
+                None => (line.address..line.address + line.length).any(|a| coverage.is_covered(a))
+                    as u32,
+            };
+            hit += (count > 0) as usize;
+            writeln!(out, "DA:{},{}", n + 1, count).unwrap();
+        }
+        writeln!(out, "LF:{}\nLH:{}\nend_of_record", self.lines.len(), hit).unwrap();
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn report() {
+        let rom = [
+            0xA2, 0x0A, // I = 0x20A
+            0xF1, 0x65, // V0, V1 = [I], [I + 1]
+            0xF0, 0x55, // [I + 2] = V0
+            0x12, 0x06, // loop
+            0x00, 0x00, // unused
+            0x12, 0x34, // read
+            0x00, // written
+        ];
+        let mut chip8 = chip8::init();
+        chip8.load_rom(rom.to_vec()).unwrap();
+        chip8.set_coverage_tracking(true);
+        chip8.run_frame(10).unwrap();
+        let coverage = chip8.coverage().unwrap();
+        let report = Report::new(coverage, chip8::START_ROM, rom.len());
+        assert_eq!(
+            report.listing(coverage, &rom),
+            "\
+x--        1  200: A20A         LD I, 0x20A
+x--        1  202: F165         LD V1, [I]
+x--        1  204: F055         LD [I], V0
+x--        7  206: 1206         JP 0x206
+---           208: 00 00
+-r-           20A: 12 34
+--w           20C: 00
+"
+        );
+        assert_eq!(
+            report.summary(coverage),
+            "\
+Summary coverage rate:
+  executed...: 61.5% (8 of 13 bytes)
+  read.......: 15.4% (2 of 13 bytes)
+  written....: 7.7% (1 of 13 bytes)
+  covered....: 84.6% (11 of 13 bytes)
+"
+        );
+        assert_eq!(
+            report.lcov(coverage, "tiny.ch8"),
+            "\
+TN:
+SF:tiny.ch8
+DA:1,1
+DA:2,1
+DA:3,1
+DA:4,7
+DA:5,0
+DA:6,1
+DA:7,1
+LF:7
+LH:6
+end_of_record
+"
+        );
+    }
+}
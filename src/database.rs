@@ -0,0 +1,332 @@
+// ROM metadata database, read from the `database/` directory of a checkout of
+// https://github.com/chip-8/chip-8-database. scripts/fetch-database.sh downloads it to
+// default_path(), where it is found without any setting.
+use crate::config::{QuirkSettings, Settings};
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+// Platforms this emulator is able to run: 64x32 CHIP-8 only.
+const SUPPORTED_PLATFORMS: [&str; 4] = ["originalChip8", "hybridVIP", "modernChip8", "chip48"];
+
+#[derive(Debug, Default, Clone, Deserialize)]
+#[serde(default, rename_all = "camelCase")]
+pub struct PlatformQuirks {
+    pub shift: Option<bool>,
+    pub memory_increment_by_x: Option<bool>,
+    pub memory_leave_i_unchanged: Option<bool>,
+    pub wrap: Option<bool>,
+    pub jump: Option<bool>,
+    pub vblank: Option<bool>,
+    pub logic: Option<bool>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Platform {
+    pub id: String,
+    pub name: String,
+    #[serde(default)]
+    pub default_tickrate: Option<u32>,
+    #[serde(default)]
+    pub quirks: PlatformQuirks,
+}
+
+#[derive(Debug, Default, Clone, Deserialize)]
+#[serde(default)]
+pub struct Colors {
+    pub pixels: Vec<String>,
+    pub buzzer: Option<String>,
+    pub silence: Option<String>,
+}
+
+#[derive(Debug, Default, Clone, Deserialize)]
+#[serde(default, rename_all = "camelCase")]
+pub struct Rom {
+    pub file: Option<String>,
+    pub platforms: Vec<String>,
+    pub quirky_platforms: HashMap<String, PlatformQuirks>,
+    pub tickrate: Option<u32>,
+    // game controls, e.g. "up" => 5
+    pub keys: HashMap<String, u8>,
+    pub colors: Option<Colors>,
+}
+
+#[derive(Debug, Default, Clone, Deserialize)]
+#[serde(default)]
+pub struct Program {
+    pub title: String,
+    pub description: Option<String>,
+    pub release: Option<String>,
+    pub authors: Vec<String>,
+    pub roms: HashMap<String, Rom>,
+}
+
+pub struct Database {
+    programs: Vec<Program>,
+    // SHA-1 to index in programs
+    hashes: HashMap<String, usize>,
+    platforms: Vec<Platform>,
+}
+
+// Everything known about a given ROM.
+#[derive(Debug, Clone)]
+pub struct Entry {
+    pub program: Program,
+    pub rom: Rom,
+    // the first platform of the ROM this emulator supports, if any
+    pub platform: Option<Platform>,
+}
+
+// $XDG_DATA_HOME/chip8/database, falling back to ~/.local/share/chip8/database
+pub fn default_path() -> Option<PathBuf> {
+    let base = match std::env::var_os("XDG_DATA_HOME") {
+        Some(dir) if !dir.is_empty() => PathBuf::from(dir),
+        _ => PathBuf::from(std::env::var_os("HOME")?).join(".local/share"),
+    };
+    Some(base.join("chip8").join("database"))
+}
+
+impl Database {
+    // Load the database/ directory of a chip-8-database checkout.
+    pub fn load(dir: &Path) -> Result<Database, String> {
+        let read = |file: &str| {
+            let path = dir.join(file);
+            std::fs::read_to_string(&path)
+                .map_err(|e| format!("cannot read {}: {}", path.display(), e))
+        };
+        Database::parse(
+            &read("programs.json")?,
+            &read("sha1-hashes.json")?,
+            &read("platforms.json")?,
+        )
+    }
+
+    fn parse(programs: &str, hashes: &str, platforms: &str) -> Result<Database, String> {
+        let error = |file: &str, e: serde_json::Error| format!("invalid database {}: {}", file, e);
+        Ok(Database {
+            programs: serde_json::from_str(programs).map_err(|e| error("programs.json", e))?,
+            hashes: serde_json::from_str(hashes).map_err(|e| error("sha1-hashes.json", e))?,
+            platforms: serde_json::from_str(platforms).map_err(|e| error("platforms.json", e))?,
+        })
+    }
+
+    pub fn platform(&self, id: &str) -> Option<&Platform> {
+        self.platforms.iter().find(|p| p.id == id)
+    }
+
+    pub fn lookup(&self, rom_hash: &str) -> Option<Entry> {
+        let program = self.programs.get(*self.hashes.get(rom_hash)?)?;
+        let rom = program.roms.get(rom_hash)?;
+        let platform = rom
+            .platforms
+            .iter()
+            .find(|id| SUPPORTED_PLATFORMS.contains(&id.as_str()))
+            .and_then(|id| self.platform(id))
+            .cloned();
+        Some(Entry {
+            program: program.clone(),
+            rom: rom.clone(),
+            platform,
+        })
+    }
+}
+
+impl PlatformQuirks {
+    fn merge(&mut self, other: &PlatformQuirks) {
+        self.shift = other.shift.or(self.shift);
+        self.memory_increment_by_x = other.memory_increment_by_x.or(self.memory_increment_by_x);
+        self.memory_leave_i_unchanged = other
+            .memory_leave_i_unchanged
+            .or(self.memory_leave_i_unchanged);
+        self.wrap = other.wrap.or(self.wrap);
+        self.jump = other.jump.or(self.jump);
+        self.vblank = other.vblank.or(self.vblank);
+        self.logic = other.logic.or(self.logic);
+    }
+
+    fn settings(&self) -> QuirkSettings {
+        QuirkSettings {
+            shift: self.shift,
+            memory_increment: self.memory_leave_i_unchanged.map(|unchanged| !unchanged),
+            jump: self.jump,
+            vf_reset: self.logic,
+            clip: self.wrap.map(|wrap| !wrap),
+        }
+    }
+}
+
+impl Entry {
+    pub fn is_supported(&self) -> bool {
+        self.platform.is_some()
+    }
+
+    // Quirks of the platform that are not emulated.
+    pub fn warnings(&self) -> Vec<String> {
+        let quirks = self.quirks();
+        let mut warnings = Vec::new();
+        if quirks.memory_increment_by_x == Some(true)
+            && quirks.memory_leave_i_unchanged != Some(true)
+        {
+            warnings.push(
+                "FX55/FX65 should increment I by X, they increment it by X + 1 instead".to_string(),
+            );
+        }
+        warnings
+    }
+
+    // Platform quirks, with the ROM specific overrides applied.
+    pub fn quirks(&self) -> PlatformQuirks {
+        let mut quirks = PlatformQuirks::default();
+        if let Some(platform) = &self.platform {
+            quirks = platform.quirks.clone();
+            if let Some(overrides) = self.rom.quirky_platforms.get(&platform.id) {
+                quirks.merge(overrides);
+            }
+        }
+        quirks
+    }
+
+    // Instructions per frame (60 Hz)
+    pub fn tickrate(&self) -> Option<u32> {
+        self.rom
+            .tickrate
+            .or_else(|| self.platform.as_ref()?.default_tickrate)
+    }
+
+    pub fn settings(&self) -> Settings {
+        let palette = match self.rom.colors.as_ref().map(|c| c.pixels.as_slice()) {
+            Some([bg, fg, ..]) => Some([bg.clone(), fg.clone()]),
+            _ => None,
+        };
+        let quirks = self.quirks();
+        // the display wait of the COSMAC VIP interpreter comes with its timing
+        let timing = quirks
+            .vblank
+            .map(|vblank| if vblank { "cosmac-vip" } else { "fixed" }.to_string());
+        Settings {
+            speed: self.tickrate().map(|tickrate| tickrate * 60),
+            timing,
+            palette,
+            quirks: quirks.settings(),
+            ..Settings::default()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::ConfigFile;
+    use chip8::Timing;
+
+    const LOGO: &str = "0123456789abcdef0123456789abcdef01234567";
+    const PONG: &str = "89abcdef0123456789abcdef0123456789abcdef";
+    const SCHIP: &str = "fedcba9876543210fedcba9876543210fedcba98";
+
+    // The quirks of the platforms are the upstream ones.
+    fn database() -> Database {
+        let programs = format!(
+            r##"[
+            {{"title": "IBM Logo", "authors": ["IBM"], "roms": {{
+                "{LOGO}": {{"platforms": ["originalChip8"], "colors": {{"pixels": ["#000080", "#ffffff"]}}}}
+            }}}},
+            {{"title": "Pong", "roms": {{
+                "{PONG}": {{"platforms": ["chip48"], "tickrate": 20, "keys": {{"up": 1}},
+                    "quirkyPlatforms": {{"chip48": {{"shift": false, "jump": false}}}}}}
+            }}}},
+            {{"title": "Super", "roms": {{"{SCHIP}": {{"platforms": ["superchip"]}}}}}}
+            ]"##
+        );
+        let hashes = format!(r#"{{"{LOGO}": 0, "{PONG}": 1, "{SCHIP}": 2}}"#);
+        let platforms = r#"[
+            {"id": "originalChip8", "name": "Cosmac VIP CHIP-8", "defaultTickrate": 15,
+             "quirks": {"shift": false, "memoryIncrementByX": false, "memoryLeaveIUnchanged": false,
+                        "wrap": false, "jump": false, "vblank": true, "logic": true}},
+            {"id": "chip48", "name": "CHIP-48", "defaultTickrate": 30,
+             "quirks": {"shift": true, "memoryIncrementByX": true, "memoryLeaveIUnchanged": false,
+                        "wrap": false, "jump": true, "vblank": false, "logic": false}},
+            {"id": "superchip", "name": "SUPER-CHIP 1.1", "defaultTickrate": 30, "quirks": {}}
+        ]"#;
+        Database::parse(&programs, &hashes, platforms).unwrap()
+    }
+
+    #[test]
+    fn lookup() {
+        let database = database();
+        assert!(database.lookup(&"0".repeat(40)).is_none());
+
+        let entry = database.lookup(LOGO).unwrap();
+        assert_eq!(entry.program.title, "IBM Logo");
+        assert_eq!(entry.program.authors, ["IBM"]);
+        assert_eq!(entry.platform.as_ref().unwrap().id, "originalChip8");
+        assert!(entry.warnings().is_empty());
+        let settings = entry.settings();
+        assert_eq!(settings.speed, Some(15 * 60));
+        assert_eq!(settings.timing.as_deref(), Some("cosmac-vip"));
+        assert_eq!(
+            settings.palette,
+            Some(["#000080".to_string(), "#ffffff".to_string()])
+        );
+        let quirks = settings.quirks;
+        assert_eq!(quirks.shift, Some(false));
+        assert_eq!(quirks.memory_increment, Some(true));
+        assert_eq!(quirks.jump, Some(false));
+        assert_eq!(quirks.vf_reset, Some(true));
+        assert_eq!(quirks.clip, Some(true));
+
+        let entry = database.lookup(SCHIP).unwrap();
+        assert!(!entry.is_supported());
+        assert_eq!(entry.settings().speed, None);
+    }
+
+    #[test]
+    fn platform_overrides() {
+        let entry = database().lookup(PONG).unwrap();
+        let settings = entry.settings();
+        // the tickrate of the ROM wins over the one of the platform
+        assert_eq!(settings.speed, Some(20 * 60));
+        assert_eq!(settings.timing.as_deref(), Some("fixed"));
+        assert_eq!(settings.quirks.shift, Some(false));
+        assert_eq!(settings.quirks.jump, Some(false));
+        assert_eq!(settings.quirks.vf_reset, Some(false));
+        assert_eq!(entry.rom.keys["up"], 1);
+        assert_eq!(entry.warnings().len(), 1);
+    }
+
+    #[test]
+    fn precedence() {
+        let file: ConfigFile = toml::from_str(&format!(
+            r#"
+            [default]
+            speed = 600
+            timing = "fixed"
+            quirks = {{ vf_reset = false, jump = true }}
+
+            [rom.{LOGO}]
+            quirks = {{ vf_reset = false }}
+            "#
+        ))
+        .unwrap();
+        let database = database();
+        let resolve = |hash| {
+            let entry = database.lookup(hash).unwrap();
+            file.settings_for(hash, Some(&entry.settings()))
+                .resolve()
+                .unwrap()
+        };
+
+        // the database entry wins over [default], the ROM section over the database entry
+        let config = resolve(LOGO);
+        assert_eq!(config.speed, 15 * 60);
+        assert_eq!(config.timing, Timing::CosmacVip);
+        assert!(!config.quirks.jump);
+        assert!(!config.quirks.vf_reset);
+
+        // [default] stays for what the database entry does not set
+        let config = resolve(SCHIP);
+        assert_eq!(config.speed, 600);
+        assert_eq!(config.timing, Timing::Fixed);
+        assert!(config.quirks.jump);
+    }
+}
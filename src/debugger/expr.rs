@@ -0,0 +1,321 @@
+// Expressions used as breakpoint and watchpoint conditions, e.g. `V3 == 0x10 && I > 0x300`.
+//
+// Operands are numbers (decimal or 0x prefixed hexadecimal), registers (V0 to VF, I, PC, SP,
+// DT, ST) and memory bytes (`[address]`). Operators, from the lowest to the highest precedence:
+//   ||   &&   == !=   < <= > >=   |   ^   &   + -   unary ! and -
+use chip8::{Chip8, MEM_SIZE};
+use std::fmt;
+use std::str::FromStr;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Register {
+    V(usize),
+    I,
+    Pc,
+    Sp,
+    Dt,
+    St,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UnaryOp {
+    Not,
+    Neg,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BinaryOp {
+    Or,
+    And,
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+    BitOr,
+    BitXor,
+    BitAnd,
+    Add,
+    Sub,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Expr {
+    Number(i64),
+    Register(Register),
+    Memory(Box<Expr>),
+    Unary(UnaryOp, Box<Expr>),
+    Binary(BinaryOp, Box<Expr>, Box<Expr>),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Token {
+    Number(i64),
+    Register(Register),
+    Op(&'static str),
+}
+
+// Binary operators grouped by precedence, from the lowest to the highest.
+const PRECEDENCE: [&[(&str, BinaryOp)]; 8] = [
+    &[("||", BinaryOp::Or)],
+    &[("&&", BinaryOp::And)],
+    &[("==", BinaryOp::Eq), ("!=", BinaryOp::Ne)],
+    &[
+        ("<=", BinaryOp::Le),
+        (">=", BinaryOp::Ge),
+        ("<", BinaryOp::Lt),
+        (">", BinaryOp::Gt),
+    ],
+    &[("|", BinaryOp::BitOr)],
+    &[("^", BinaryOp::BitXor)],
+    &[("&", BinaryOp::BitAnd)],
+    &[("+", BinaryOp::Add), ("-", BinaryOp::Sub)],
+];
+
+// Longest operators first, so "&&" is not read as two "&".
+const OPERATORS: [&str; 18] = [
+    "||", "&&", "==", "!=", "<=", ">=", "<", ">", "|", "^", "&", "+", "-", "!", "(", ")", "[", "]",
+];
+
+impl Register {
+    pub fn read(self, chip8: &Chip8) -> u16 {
+        match self {
+            Register::V(x) => chip8.register(x) as u16,
+            Register::I => chip8.index(),
+            Register::Pc => chip8.program_counter(),
+            Register::Sp => chip8.stack_pointer(),
+            Register::Dt => chip8.delay_timer() as u16,
+            Register::St => chip8.sound_timer() as u16,
+        }
+    }
+}
+
+impl FromStr for Register {
+    type Err = String;
+
+    fn from_str(name: &str) -> Result<Register, String> {
+        let upper = name.to_ascii_uppercase();
+        match upper.as_str() {
+            "I" => Ok(Register::I),
+            "PC" => Ok(Register::Pc),
+            "SP" => Ok(Register::Sp),
+            "DT" => Ok(Register::Dt),
+            "ST" => Ok(Register::St),
+            _ => match upper
+                .strip_prefix('V')
+                .map(|x| usize::from_str_radix(x, 16))
+            {
+                Some(Ok(x)) if x < 16 && upper.len() == 2 => Ok(Register::V(x)),
+                _ => Err(format!("unknown register '{}'", name)),
+            },
+        }
+    }
+}
+
+impl fmt::Display for Register {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Register::V(x) => write!(f, "V{:X}", x),
+            Register::I => write!(f, "I"),
+            Register::Pc => write!(f, "PC"),
+            Register::Sp => write!(f, "SP"),
+            Register::Dt => write!(f, "DT"),
+            Register::St => write!(f, "ST"),
+        }
+    }
+}
+
+impl Expr {
+    pub fn eval(&self, chip8: &Chip8) -> i64 {
+        match self {
+            Expr::Number(n) => *n,
+            Expr::Register(register) => register.read(chip8) as i64,
+            Expr::Memory(address) => match address.eval(chip8) {
+                a if (0..MEM_SIZE as i64).contains(&a) => chip8.memory(a as usize) as i64,
+                _ => 0,
+            },
+            Expr::Unary(UnaryOp::Not, e) => (e.eval(chip8) == 0) as i64,
+            Expr::Unary(UnaryOp::Neg, e) => e.eval(chip8).wrapping_neg(),
+            // short-circuit
+            Expr::Binary(BinaryOp::Or, a, b) => (a.eval(chip8) != 0 || b.eval(chip8) != 0) as i64,
+            Expr::Binary(BinaryOp::And, a, b) => (a.eval(chip8) != 0 && b.eval(chip8) != 0) as i64,
+            Expr::Binary(op, a, b) => {
+                let (a, b) = (a.eval(chip8), b.eval(chip8));
+                match op {
+                    BinaryOp::Eq => (a == b) as i64,
+                    BinaryOp::Ne => (a != b) as i64,
+                    BinaryOp::Lt => (a < b) as i64,
+                    BinaryOp::Le => (a <= b) as i64,
+                    BinaryOp::Gt => (a > b) as i64,
+                    BinaryOp::Ge => (a >= b) as i64,
+                    BinaryOp::BitOr => a | b,
+                    BinaryOp::BitXor => a ^ b,
+                    BinaryOp::BitAnd => a & b,
+                    BinaryOp::Add => a.wrapping_add(b),
+                    BinaryOp::Sub => a.wrapping_sub(b),
+                    BinaryOp::Or | BinaryOp::And => unreachable!(),
+                }
+            }
+        }
+    }
+
+    pub fn is_true(&self, chip8: &Chip8) -> bool {
+        self.eval(chip8) != 0
+    }
+}
+
+impl FromStr for Expr {
+    type Err = String;
+
+    fn from_str(text: &str) -> Result<Expr, String> {
+        let tokens = tokenize(text)?;
+        let mut parser = Parser { tokens, pos: 0 };
+        let expr = parser.binary(0)?;
+        match parser.tokens.get(parser.pos) {
+            None => Ok(expr),
+            Some(token) => Err(format!("unexpected {:?}", token)),
+        }
+    }
+}
+
+fn tokenize(text: &str) -> Result<Vec<Token>, String> {
+    let mut tokens = Vec::new();
+    let mut rest = text.trim_start();
+    while !rest.is_empty() {
+        if let Some(op) = OPERATORS.iter().find(|op| rest.starts_with(*op)) {
+            tokens.push(Token::Op(op));
+            rest = &rest[op.len()..];
+        } else {
+            let end = rest
+                .find(|c: char| !c.is_ascii_alphanumeric())
+                .unwrap_or(rest.len());
+            if end == 0 {
+                return Err(format!(
+                    "unexpected character '{}'",
+                    rest.chars().next().unwrap()
+                ));
+            }
+            let word = &rest[..end];
+            let token = if word.starts_with(|c: char| c.is_ascii_digit()) {
+                let number = match word.strip_prefix("0x").or_else(|| word.strip_prefix("0X")) {
+                    Some(hex) => i64::from_str_radix(hex, 16),
+                    None => word.parse(),
+                };
+                Token::Number(number.map_err(|_| format!("invalid number '{}'", word))?)
+            } else {
+                Token::Register(word.parse()?)
+            };
+            tokens.push(token);
+            rest = &rest[end..];
+        }
+        rest = rest.trim_start();
+    }
+    Ok(tokens)
+}
+
+struct Parser {
+    tokens: Vec<Token>,
+    pos: usize,
+}
+
+impl Parser {
+    fn next(&mut self) -> Option<Token> {
+        let token = self.tokens.get(self.pos).cloned();
+        self.pos += 1;
+        token
+    }
+
+    fn peek_op(&self) -> Option<&'static str> {
+        match self.tokens.get(self.pos) {
+            Some(Token::Op(op)) => Some(op),
+            _ => None,
+        }
+    }
+
+    fn expect(&mut self, op: &str) -> Result<(), String> {
+        match self.next() {
+            Some(Token::Op(o)) if o == op => Ok(()),
+            _ => Err(format!("expected '{}'", op)),
+        }
+    }
+
+    // Binary operators of the given precedence level and above.
+    fn binary(&mut self, level: usize) -> Result<Expr, String> {
+        if level == PRECEDENCE.len() {
+            return self.unary();
+        }
+        let mut expr = self.binary(level + 1)?;
+        while let Some(&(_, op)) = self
+            .peek_op()
+            .and_then(|token| PRECEDENCE[level].iter().find(|(o, _)| *o == token))
+        {
+            self.pos += 1;
+            let rhs = self.binary(level + 1)?;
+            expr = Expr::Binary(op, Box::new(expr), Box::new(rhs));
+        }
+        Ok(expr)
+    }
+
+    fn unary(&mut self) -> Result<Expr, String> {
+        match self.next() {
+            Some(Token::Number(n)) => Ok(Expr::Number(n)),
+            Some(Token::Register(register)) => Ok(Expr::Register(register)),
+            Some(Token::Op("!")) => Ok(Expr::Unary(UnaryOp::Not, Box::new(self.unary()?))),
+            Some(Token::Op("-")) => Ok(Expr::Unary(UnaryOp::Neg, Box::new(self.unary()?))),
+            Some(Token::Op("(")) => {
+                let expr = self.binary(0)?;
+                self.expect(")")?;
+                Ok(expr)
+            }
+            Some(Token::Op("[")) => {
+                let address = self.binary(0)?;
+                self.expect("]")?;
+                Ok(Expr::Memory(Box::new(address)))
+            }
+            Some(token) => Err(format!("unexpected {:?}", token)),
+            None => Err("unexpected end of expression".to_string()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn eval(text: &str, chip8: &Chip8) -> i64 {
+        text.parse::<Expr>().unwrap().eval(chip8)
+    }
+
+    #[test]
+    fn precedence() {
+        let chip8 = chip8::init();
+        assert_eq!(eval("1 + 2 == 3", &chip8), 1);
+        assert_eq!(eval("1 || 0 && 0", &chip8), 1);
+        assert_eq!(eval("(1 || 0) && 0", &chip8), 0);
+        assert_eq!(eval("0x10 | 0x01 & 0x03", &chip8), 0x11);
+        assert_eq!(eval("!0 + -1", &chip8), 0);
+        assert_eq!(eval("2 - 1 - 1", &chip8), 0);
+    }
+
+    #[test]
+    fn machine_state() {
+        let mut chip8 = chip8::init();
+        chip8.set_register(3, 0x10);
+        chip8.set_index(0x301);
+        chip8.set_memory(0x301, 0x2A);
+        assert_eq!(eval("V3 == 0x10 && I > 0x300", &chip8), 1);
+        assert_eq!(eval("v3 != 16 || pc < 0x200", &chip8), 0);
+        assert_eq!(eval("[I] == 42", &chip8), 1);
+        assert_eq!(eval("[0x2000]", &chip8), 0);
+    }
+
+    #[test]
+    fn errors() {
+        assert!("V3 ==".parse::<Expr>().is_err());
+        assert!("VG".parse::<Expr>().is_err());
+        assert!("(1".parse::<Expr>().is_err());
+        assert!("1 2".parse::<Expr>().is_err());
+        assert!("1 $ 2".parse::<Expr>().is_err());
+    }
+}
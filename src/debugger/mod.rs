@@ -0,0 +1,240 @@
+// Breakpoints, memory and register watchpoints, with optional conditions and hit counts.
+mod expr;
+
+use chip8::{AccessKind, Chip8, MemoryAccess};
+pub use expr::{Expr, Register};
+use std::fmt;
+use std::ops::RangeInclusive;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WatchKind {
+    Read,
+    Write,
+    Access,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Trigger {
+    // stop before executing the instruction at this address
+    Breakpoint(u16),
+    // stop after an instruction accessed memory in this range
+    Watchpoint {
+        range: RangeInclusive<u16>,
+        kind: WatchKind,
+    },
+    // stop after an instruction changed a register, to the given value if any
+    Register {
+        register: Register,
+        value: Option<u16>,
+    },
+}
+
+#[derive(Debug, Clone)]
+pub struct Point {
+    pub id: usize,
+    pub trigger: Trigger,
+    // the point is only hit when the condition holds
+    pub condition: Option<Expr>,
+    pub hits: u64,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub enum Reason {
+    Breakpoint(u16),
+    Memory(MemoryAccess),
+    Register {
+        register: Register,
+        old: u16,
+        new: u16,
+    },
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct Hit {
+    pub id: usize,
+    pub hits: u64,
+    pub reason: Reason,
+}
+
+#[derive(Debug, Default)]
+pub struct Debugger {
+    points: Vec<Point>,
+    next_id: usize,
+}
+
+impl Debugger {
+    pub fn new() -> Debugger {
+        Debugger {
+            points: Vec::new(),
+            next_id: 1,
+        }
+    }
+
+    pub fn add(&mut self, trigger: Trigger, condition: Option<Expr>) -> usize {
+        let id = self.next_id;
+        self.next_id += 1;
+        self.points.push(Point {
+            id,
+            trigger,
+            condition,
+            hits: 0,
+        });
+        id
+    }
+
+    pub fn remove(&mut self, id: usize) -> bool {
+        let len = self.points.len();
+        self.points.retain(|p| p.id != id);
+        self.points.len() != len
+    }
+
+    // Remove the unconditional points with this exact trigger.
+    pub fn remove_trigger(&mut self, trigger: &Trigger) -> bool {
+        let len = self.points.len();
+        self.points
+            .retain(|p| p.condition.is_some() || p.trigger != *trigger);
+        self.points.len() != len
+    }
+
+    pub fn points(&self) -> &[Point] {
+        &self.points
+    }
+
+    // Values of the watched registers, to be compared after the next instruction.
+    pub fn snapshot(&self, chip8: &Chip8) -> Vec<u16> {
+        self.points
+            .iter()
+            .map(|p| match p.trigger {
+                Trigger::Register { register, .. } => register.read(chip8),
+                _ => 0,
+            })
+            .collect()
+    }
+
+    // Check the breakpoints, before executing the instruction at PC.
+    pub fn check_execute(&mut self, chip8: &Chip8) -> Option<Hit> {
+        let pc = chip8.program_counter();
+        self.points
+            .iter_mut()
+            .find_map(|point| match point.trigger {
+                Trigger::Breakpoint(address) if address == pc => {
+                    point.hit(chip8, Reason::Breakpoint(pc))
+                }
+                _ => None,
+            })
+    }
+
+    // Check the watchpoints, after an instruction has been executed.
+    // `snapshot` is the result of `snapshot` before executing it.
+    pub fn check_effects(&mut self, chip8: &Chip8, snapshot: &[u16]) -> Option<Hit> {
+        for (i, point) in self.points.iter_mut().enumerate() {
+            let reason = match &point.trigger {
+                Trigger::Breakpoint(_) => None,
+                Trigger::Watchpoint { range, kind } => chip8
+                    .accesses()
+                    .iter()
+                    .find(|a| {
+                        range.contains(&a.address)
+                            && match kind {
+                                WatchKind::Read => a.kind == AccessKind::Read,
+                                WatchKind::Write => a.kind == AccessKind::Write,
+                                WatchKind::Access => true,
+                            }
+                    })
+                    .map(|&a| Reason::Memory(a)),
+                Trigger::Register { register, value } => {
+                    let new = register.read(chip8);
+                    // points added since the snapshot are not triggered yet
+                    let old = snapshot.get(i).copied().unwrap_or(new);
+                    let changed = old != new && value.is_none_or(|v| v == new);
+                    changed.then_some(Reason::Register {
+                        register: *register,
+                        old,
+                        new,
+                    })
+                }
+            };
+            if let Some(hit) = reason.and_then(|reason| point.hit(chip8, reason)) {
+                return Some(hit);
+            }
+        }
+        None
+    }
+}
+
+impl Point {
+    fn hit(&mut self, chip8: &Chip8, reason: Reason) -> Option<Hit> {
+        if self.condition.as_ref().is_some_and(|c| !c.is_true(chip8)) {
+            return None;
+        }
+        self.hits += 1;
+        Some(Hit {
+            id: self.id,
+            hits: self.hits,
+            reason,
+        })
+    }
+}
+
+impl fmt::Display for Point {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}: ", self.id)?;
+        match &self.trigger {
+            Trigger::Breakpoint(address) => write!(f, "breakpoint at {:#05X}", address)?,
+            Trigger::Watchpoint { range, kind } => {
+                let kind = match kind {
+                    WatchKind::Read => "read",
+                    WatchKind::Write => "write",
+                    WatchKind::Access => "access",
+                };
+                write!(f, "{} watchpoint on {:#05X}", kind, range.start())?;
+                if range.start() != range.end() {
+                    write!(f, "-{:#05X}", range.end())?;
+                }
+            }
+            Trigger::Register { register, value } => {
+                write!(f, "watch {}", register)?;
+                if let Some(value) = value {
+                    write!(f, " == {:#X}", value)?;
+                }
+            }
+        }
+        if self.condition.is_some() {
+            write!(f, " (conditional)")?;
+        }
+        write!(f, ", hit {} times", self.hits)
+    }
+}
+
+impl fmt::Display for Hit {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.reason {
+            Reason::Breakpoint(address) => write!(f, "breakpoint {} at {:#05X}", self.id, address)?,
+            Reason::Memory(access) => {
+                let kind = match access.kind {
+                    AccessKind::Read => "read",
+                    AccessKind::Write => "write",
+                };
+                write!(
+                    f,
+                    "watchpoint {}: {} {:#05X} = {:#04X}",
+                    self.id, kind, access.address, access.value
+                )?
+            }
+            Reason::Register { register, old, new } => write!(
+                f,
+                "watchpoint {}: {} {:#X} -> {:#X}",
+                self.id, register, old, new
+            )?,
+        }
+        write!(f, " (hit {})", self.hits)
+    }
+}
+
+// Parse a memory range such as "0x300" or "0x300-0x302".
+pub fn parse_range(text: &str) -> Result<RangeInclusive<u16>, String> {
+    match text.split_once('-') {
+        Some(_) => crate::trace::parse_range(text),
+        None => crate::trace::parse_address(text).map(|a| a..=a),
+    }
+}
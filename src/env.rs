@@ -0,0 +1,440 @@
+// Reinforcement learning environment, in the style of OpenAI Gym.
+//
+// An episode runs a ROM from a seeded reset, with the keys of each step held for a number of
+// frames. The reward and the end of the episode are read from memory with probes described in a
+// TOML file, e.g. the score a game stores with FX33 before drawing it:
+//
+//     ipf = 10
+//     max_frames = 36000
+//     quirks = { shift = false }
+//
+//     [[reward]]
+//     address = 0x3F0
+//     format = "bcd"
+//     length = 3
+//
+//     [[done]]
+//     address = 0x3F8
+//     equals = 0
+//
+// Snapshots are a plain copy of the machine, a few tens of KiB with the decode cache, so that tree
+// searches can save and restore the state at every node.
+use crate::chip8::{
+    init, Chip8, Error, MachineCode, QuirkSettings, Quirks, Timing, HEIGHT, MEM_SIZE,
+};
+use serde::Deserialize;
+
+// The framebuffer, one row per u64 with the leftmost pixel in the most significant bit.
+pub type Observation = [u64; HEIGHT];
+
+// How a probe reads its value.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Format {
+    // one byte
+    #[default]
+    Byte,
+    // two bytes, big-endian as I and the opcodes are
+    Word,
+    // `length` decimal digits, one per byte, as FX33 stores them
+    Bcd,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct RewardProbe {
+    pub address: u16,
+    #[serde(default)]
+    pub format: Format,
+    #[serde(default = "default_length")]
+    pub length: u16,
+    // the reward is the change of the value times the scale
+    #[serde(default = "default_scale")]
+    pub scale: f64,
+}
+
+// Ends the episode when the value is equal to, below or above the given one.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct DoneProbe {
+    pub address: u16,
+    #[serde(default)]
+    pub format: Format,
+    #[serde(default = "default_length")]
+    pub length: u16,
+    pub equals: Option<u64>,
+    pub below: Option<u64>,
+    pub above: Option<u64>,
+}
+
+fn default_length() -> u16 {
+    3
+}
+
+fn default_scale() -> f64 {
+    1.0
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct SpecFile {
+    ipf: Option<u32>,
+    timing: Option<String>,
+    machine_code: Option<String>,
+    max_frames: Option<u32>,
+    #[serde(default)]
+    quirks: QuirkSettings,
+    #[serde(default)]
+    reward: Vec<RewardProbe>,
+    #[serde(default)]
+    done: Vec<DoneProbe>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct EnvSpec {
+    pub ipf: u32,
+    pub timing: Timing,
+    pub machine_code: MachineCode,
+    pub quirks: Quirks,
+    // episodes are cut after this many frames, None to never cut them
+    pub max_frames: Option<u32>,
+    pub reward: Vec<RewardProbe>,
+    pub done: Vec<DoneProbe>,
+}
+
+impl Default for EnvSpec {
+    fn default() -> Self {
+        EnvSpec {
+            ipf: 10,
+            timing: Timing::default(),
+            machine_code: MachineCode::default(),
+            quirks: Quirks::default(),
+            max_frames: None,
+            reward: Vec::new(),
+            done: Vec::new(),
+        }
+    }
+}
+
+impl EnvSpec {
+    pub fn parse(text: &str) -> Result<EnvSpec, String> {
+        let file: SpecFile = toml::from_str(text).map_err(|e| e.to_string())?;
+        let default = EnvSpec::default();
+        let spec = EnvSpec {
+            ipf: file.ipf.unwrap_or(default.ipf),
+            timing: match file.timing {
+                Some(timing) => timing.parse()?,
+                None => default.timing,
+            },
+            machine_code: match file.machine_code {
+                Some(machine_code) => machine_code.parse()?,
+                None => default.machine_code,
+            },
+            quirks: file.quirks.apply(default.quirks),
+            max_frames: file.max_frames,
+            reward: file.reward,
+            done: file.done,
+        };
+        let probes = spec
+            .reward
+            .iter()
+            .map(|p| (p.address, p.format, p.length))
+            .chain(spec.done.iter().map(|p| (p.address, p.format, p.length)));
+        for (address, format, length) in probes {
+            if address as usize + size(format, length) > MEM_SIZE {
+                return Err(format!("probe at {:#05X} reads past the memory", address));
+            }
+            if format == Format::Bcd && !(1..=19).contains(&length) {
+                return Err(format!(
+                    "probe at {:#05X}: BCD length must be 1 to 19",
+                    address
+                ));
+            }
+        }
+        for probe in &spec.done {
+            if probe.equals.is_none() && probe.below.is_none() && probe.above.is_none() {
+                return Err(format!(
+                    "done probe at {:#05X} needs equals, below or above",
+                    probe.address
+                ));
+            }
+        }
+        Ok(spec)
+    }
+}
+
+fn size(format: Format, length: u16) -> usize {
+    match format {
+        Format::Byte => 1,
+        Format::Word => 2,
+        Format::Bcd => length as usize,
+    }
+}
+
+fn read(chip8: &Chip8, address: u16, format: Format, length: u16) -> u64 {
+    let byte = |offset: usize| chip8.memory(address as usize + offset) as u64;
+    match format {
+        Format::Byte => byte(0),
+        Format::Word => byte(0) << 8 | byte(1),
+        // digits above 9 are garbage rather than BCD, capped so that 19 of them fit in a u64
+        Format::Bcd => (0..length as usize).fold(0, |value, i| value * 10 + byte(i).min(9)),
+    }
+}
+
+impl DoneProbe {
+    fn is_done(&self, chip8: &Chip8) -> bool {
+        let value = read(chip8, self.address, self.format, self.length);
+        self.equals == Some(value)
+            || self.below.is_some_and(|below| value < below)
+            || self.above.is_some_and(|above| value > above)
+    }
+}
+
+// A saved episode, to go back to with Env::restore.
+#[derive(Debug, Clone)]
+pub struct Snapshot {
+    chip8: Chip8,
+    frames: u32,
+    // the last values of the reward probes
+    values: Vec<u64>,
+    done: bool,
+    error: Option<Error>,
+}
+
+#[derive(Debug, Clone)]
+pub struct Env {
+    spec: EnvSpec,
+    // the machine with the ROM loaded, cloned on reset
+    initial: Chip8,
+    state: Snapshot,
+}
+
+impl Env {
+    pub fn new(rom: Vec<u8>, spec: EnvSpec) -> Result<Env, Error> {
+        let mut initial = init();
+        initial.set_quirks(spec.quirks);
+        initial.set_timing(spec.timing);
+        initial.set_machine_code(spec.machine_code);
+        initial.load_rom(rom)?;
+        let mut env = Env {
+            spec,
+            initial: initial.clone(),
+            state: Snapshot {
+                chip8: initial,
+                frames: 0,
+                values: Vec::new(),
+                done: false,
+                error: None,
+            },
+        };
+        env.reset(0);
+        Ok(env)
+    }
+
+    pub fn spec(&self) -> &EnvSpec {
+        &self.spec
+    }
+
+    // Start a new episode, with the random generator seeded for reproducible runs.
+    pub fn reset(&mut self, seed: u64) -> Observation {
+        let mut chip8 = self.initial.clone();
+        chip8.seed_rng(seed);
+        self.state = Snapshot {
+            values: self.values(&chip8),
+            chip8,
+            frames: 0,
+            done: false,
+            error: None,
+        };
+        self.observation()
+    }
+
+    // Hold the keys whose bit is set in `action_mask` (bit 0 for key 0) for `frames` frames,
+    // or until the episode ends. Returns the screen, the reward earned and whether the episode
+    // is over.
+    pub fn step(&mut self, action_mask: u16, frames: u32) -> (Observation, f64, bool) {
+        let mut reward = 0.0;
+        let state = &mut self.state;
+        state.chip8.reset_keypad();
+        for key in (0..16).filter(|key| action_mask & 1 << key != 0) {
+            state.chip8.press_key(key);
+        }
+        for _ in 0..frames {
+            if state.done {
+                break;
+            }
+            // a crashed ROM ends its episode, the error is kept for error()
+            if let Err(e) = state.chip8.run_frame(self.spec.ipf) {
+                state.error = Some(e);
+                state.done = true;
+                break;
+            }
+            state.frames += 1;
+            let chip8 = &state.chip8;
+            for (probe, before) in self.spec.reward.iter().zip(&mut state.values) {
+                let value = read(chip8, probe.address, probe.format, probe.length);
+                reward += (value as f64 - *before as f64) * probe.scale;
+                *before = value;
+            }
+            state.done = self.spec.done.iter().any(|probe| probe.is_done(chip8))
+                || self.spec.max_frames.is_some_and(|max| state.frames >= max);
+        }
+        (self.observation(), reward, self.state.done)
+    }
+
+    pub fn observation(&self) -> Observation {
+        *self.state.chip8.gfx_rows()
+    }
+
+    pub fn is_done(&self) -> bool {
+        self.state.done
+    }
+
+    // The error which ended the episode, if the ROM crashed.
+    pub fn error(&self) -> Option<Error> {
+        self.state.error
+    }
+
+    // Frames run since the last reset.
+    pub fn frames(&self) -> u32 {
+        self.state.frames
+    }
+
+    pub fn chip8(&self) -> &Chip8 {
+        &self.state.chip8
+    }
+
+    pub fn snapshot(&self) -> Snapshot {
+        self.state.clone()
+    }
+
+    pub fn restore(&mut self, snapshot: &Snapshot) {
+        self.state.clone_from(snapshot);
+    }
+
+    fn values(&self, chip8: &Chip8) -> Vec<u64> {
+        self.spec
+            .reward
+            .iter()
+            .map(|probe| read(chip8, probe.address, probe.format, probe.length))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Counts the frames key 5 is held in V0, stores the count as BCD at 0x300 and draws a
+    // diagonal, one pixel per frame. The loop is 10 instructions long whether the key is held
+    // or not, so that it runs once per frame.
+    // 0x200: LD V2, 5
+    // 0x202: LD I, 0x300
+    // 0x204: LD B, V0
+    // 0x206: LD I, 0x218
+    // 0x208: DRW V1, V1, 1
+    // 0x20A: SKNP V2
+    // 0x20C: ADD V0, 1
+    // 0x20E: SKP V2
+    // 0x210: ADD V5, 0
+    // 0x212: ADD V1, 1
+    // 0x214: JP 0x200
+    // 0x218: sprite
+    const COUNTER: [u8; 25] = [
+        0x62, 0x05, 0xA3, 0x00, 0xF0, 0x33, 0xA2, 0x18, 0xD1, 0x11, 0xE2, 0xA1, 0x70, 0x01, 0xE2,
+        0x9E, 0x75, 0x00, 0x71, 0x01, 0x12, 0x00, 0x00, 0x00, 0x80,
+    ];
+
+    const SPEC: &str = r#"
+        ipf = 10
+        max_frames = 100
+
+        [[reward]]
+        address = 0x300
+        format = "bcd"
+        scale = 0.5
+
+        [[done]]
+        address = 0x301
+        equals = 2
+    "#;
+
+    fn env() -> Env {
+        Env::new(COUNTER.to_vec(), EnvSpec::parse(SPEC).unwrap()).unwrap()
+    }
+
+    #[test]
+    fn parse_spec() {
+        let spec = EnvSpec::parse(SPEC).unwrap();
+        assert_eq!(spec.ipf, 10);
+        assert_eq!(spec.reward[0].format, Format::Bcd);
+        assert_eq!(spec.reward[0].length, 3);
+        assert_eq!(spec.done[0].format, Format::Byte);
+        assert!(EnvSpec::parse("[[done]]\naddress = 0x300").is_err());
+        assert!(EnvSpec::parse("[[reward]]\naddress = 0xFFF\nformat = \"word\"").is_err());
+        assert!(EnvSpec::parse("timing = \"pal\"").is_err());
+        let spec = EnvSpec::parse("timing = \"cosmac-vip\"\nquirks = { jump = true }").unwrap();
+        assert_eq!(spec.timing, Timing::CosmacVip);
+        assert!(spec.quirks.jump && spec.quirks.shift);
+    }
+
+    #[test]
+    fn bcd_reward_and_done() {
+        let mut env = env();
+        let observation = env.reset(0);
+        assert_eq!(observation, [0; HEIGHT]);
+
+        // no key, no score
+        let (observation, reward, done) = env.step(0, 3);
+        assert_eq!(reward, 0.0);
+        assert!(!done);
+        assert_eq!(observation[0], 0x8000_0000_0000_0000);
+
+        // one point per frame with key 5 held, stored on the next frame
+        let (_, reward, done) = env.step(1 << 5, 4);
+        assert_eq!(reward, 1.5);
+        assert!(!done);
+        assert_eq!(env.chip8().memory(0x302), 3);
+
+        // the episode ends when the score reaches 20, with the tens digit at 0x301
+        let (_, reward, done) = env.step(1 << 5, 100);
+        assert_eq!(reward, 8.5);
+        assert!(done);
+        assert_eq!(env.frames(), 24);
+        assert_eq!(env.step(1 << 5, 10).1, 0.0);
+        assert_eq!(env.error(), None);
+    }
+
+    #[test]
+    fn bcd_garbage() {
+        let mut chip8 = init();
+        chip8.load_rom(vec![0xFF; 19]).unwrap();
+        assert_eq!(
+            read(&chip8, 0x200, Format::Bcd, 19),
+            9_999_999_999_999_999_999
+        );
+        assert_eq!(read(&chip8, 0x200, Format::Bcd, 2), 99);
+    }
+
+    #[test]
+    fn snapshot_restore() {
+        let mut env = env();
+        env.reset(0);
+        env.step(1 << 5, 5);
+        let snapshot = env.snapshot();
+        let first = env.step(1 << 5, 5);
+        env.step(0, 20);
+        env.restore(&snapshot);
+        assert_eq!(env.frames(), 5);
+        assert_eq!(env.step(1 << 5, 5), first);
+    }
+
+    #[test]
+    fn crash_ends_episode() {
+        let mut env = Env::new(vec![0xFF, 0xFF], EnvSpec::default()).unwrap();
+        let (_, reward, done) = env.step(0, 10);
+        assert_eq!(reward, 0.0);
+        assert!(done);
+        assert!(matches!(env.error(), Some(Error::UnknownOpcode { .. })));
+    }
+}
@@ -0,0 +1,757 @@
+// GDB remote serial protocol stub, exposing the chip8 machine to debuggers over a local TCP socket.
+//
+// Registers, in `g` packet order: V0 to VF (8 bits), I (16 bits), PC (16 bits), SP, DT and ST
+// (8 bits), described to the debugger by the target.xml below. 16 bits values are little endian.
+use crate::debugger::{self, Debugger, Expr, Hit, Reason, Trigger, WatchKind};
+use crate::profile;
+use crate::symbols::Symbols;
+use crate::trace;
+use chip8::{self, AccessKind, Chip8};
+use std::io::{self, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::{thread, time};
+
+const N_REGISTERS: usize = 21;
+const REG_I: usize = 16;
+const REG_PC: usize = 17;
+const REG_SP: usize = 18;
+const REG_DT: usize = 19;
+const REG_ST: usize = 20;
+
+const FRAME_DURATION: time::Duration = time::Duration::from_micros(16_667);
+
+// Why the machine stopped, reported to the debugger as a signal.
+#[derive(Debug, Clone, Copy)]
+enum Stop {
+    Step,
+    Hit(Hit),
+    Interrupt,
+    Fault(chip8::Error),
+}
+
+pub struct Stub {
+    chip8: Chip8,
+    ipf: u32,
+    debugger: Debugger,
+    symbols: Symbols,
+    // the last instruction ended a frame, and the timers were updated
+    frame_ended: bool,
+    last_stop: Stop,
+}
+
+struct Connection {
+    stream: TcpStream,
+    no_ack: bool,
+}
+
+pub fn listen(port: u16) -> io::Result<TcpListener> {
+    TcpListener::bind(("127.0.0.1", port))
+}
+
+impl Stub {
+    pub fn new(mut chip8: Chip8, ipf: u32, symbols: Symbols) -> Stub {
+        // needed by the memory watchpoints
+        chip8.set_access_tracking(true);
+        Stub {
+            chip8,
+            ipf: ipf.max(1),
+            debugger: Debugger::new(),
+            symbols,
+            frame_ended: true,
+            last_stop: Stop::Step,
+        }
+    }
+
+    // Serve a single debugger connection, until it detaches or kills the machine.
+    pub fn serve(&mut self, listener: &TcpListener) -> io::Result<()> {
+        let (stream, _) = listener.accept()?;
+        stream.set_nodelay(true)?;
+        let mut connection = Connection {
+            stream,
+            no_ack: false,
+        };
+        while let Some(packet) = connection.read_packet()? {
+            match packet.as_str() {
+                // kill
+                "k" => return Ok(()),
+                // detach
+                p if p.starts_with('D') => return connection.send("OK"),
+                "QStartNoAckMode" => {
+                    connection.send("OK")?;
+                    connection.no_ack = true;
+                }
+                p if p.starts_with('c') || p.starts_with('s') => {
+                    if let Some(address) = p.get(1..).filter(|a| !a.is_empty()) {
+                        match u16::from_str_radix(address, 16) {
+                            Ok(address) => self.chip8.set_program_counter(address),
+                            Err(_) => {
+                                connection.send("E01")?;
+                                continue;
+                            }
+                        }
+                    }
+                    self.last_stop = if p.starts_with('s') {
+                        match self.step() {
+                            Ok(hit) => hit.map_or(Stop::Step, Stop::Hit),
+                            Err(e) => Stop::Fault(e),
+                        }
+                    } else {
+                        self.resume(&mut connection)?
+                    };
+                    let message = match self.last_stop {
+                        Stop::Fault(e) => Some(e.to_string()),
+                        Stop::Hit(hit) if !matches!(hit.reason, Reason::Breakpoint(_)) => {
+                            Some(hit.to_string())
+                        }
+                        _ => None,
+                    };
+                    if let Some(message) = message {
+                        connection
+                            .send(&format!("O{}", hex(format!("{}\n", message).as_bytes())))?;
+                    }
+                    connection.send(&stop_reply(self.last_stop))?;
+                }
+                p => {
+                    let reply = self.handle(p);
+                    connection.send(&reply)?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    // Handle a packet which does not resume the machine.
+    fn handle(&mut self, packet: &str) -> String {
+        if packet.is_empty() {
+            return String::new();
+        }
+        let (command, args) = packet.split_at(1);
+        let reply = match command {
+            "?" => Some(stop_reply(self.last_stop)),
+            "g" => Some(hex(&self.read_registers())),
+            "G" => unhex(args).map(|bytes| {
+                self.write_registers(&bytes);
+                "OK".to_string()
+            }),
+            "p" => usize::from_str_radix(args, 16)
+                .ok()
+                .and_then(|n| self.read_register(n))
+                .map(|bytes| hex(&bytes)),
+            "P" => args.split_once('=').and_then(|(n, value)| {
+                let n = usize::from_str_radix(n, 16).ok()?;
+                self.write_register(n, &unhex(value)?)?;
+                Some("OK".to_string())
+            }),
+            "m" => parse_range(args).and_then(|(address, length)| {
+                // the range comes from the debugger, and may overflow
+                let end = address.checked_add(length)?.min(chip8::MEM_SIZE);
+                if address >= end {
+                    return None;
+                }
+                let bytes: Vec<u8> = (address..end).map(|a| self.chip8.memory(a)).collect();
+                Some(hex(&bytes))
+            }),
+            "M" => args.split_once(':').and_then(|(range, data)| {
+                let (address, length) = parse_range(range)?;
+                let bytes = unhex(data)?;
+                if bytes.len() != length || address.checked_add(length)? > chip8::MEM_SIZE {
+                    return None;
+                }
+                for (i, byte) in bytes.into_iter().enumerate() {
+                    self.chip8.set_memory(address + i, byte);
+                }
+                Some("OK".to_string())
+            }),
+            "Z" | "z" => self.breakpoint(command == "Z", args),
+            "H" | "T" => Some("OK".to_string()),
+            "q" | "Q" => return self.query(packet),
+            _ => return String::new(),
+        };
+        reply.unwrap_or_else(|| "E01".to_string())
+    }
+
+    fn query(&mut self, packet: &str) -> String {
+        if packet.starts_with("qSupported") {
+            return "PacketSize=1000;qXfer:features:read+;swbreak+;QStartNoAckMode+".to_string();
+        }
+        if let Some(args) = packet.strip_prefix("qXfer:features:read:target.xml:") {
+            return match parse_range(args) {
+                Some((offset, length)) => {
+                    let xml = target_xml();
+                    let start = offset.min(xml.len());
+                    let end = offset.saturating_add(length).min(xml.len());
+                    let prefix = if end < xml.len() { 'm' } else { 'l' };
+                    format!("{}{}", prefix, escape(&xml[start..end]))
+                }
+                None => "E01".to_string(),
+            };
+        }
+        if let Some(command) = packet.strip_prefix("qRcmd,") {
+            return match unhex(command).and_then(|c| String::from_utf8(c).ok()) {
+                Some(command) => match self.monitor(&command) {
+                    output if output.is_empty() => "OK".to_string(),
+                    output => hex(output.as_bytes()),
+                },
+                None => "E01".to_string(),
+            };
+        }
+        match packet {
+            "qAttached" => "1".to_string(),
+            "qC" => "QC1".to_string(),
+            "qfThreadInfo" => "m1".to_string(),
+            "qsThreadInfo" => "l".to_string(),
+            "qOffsets" => "Text=0;Data=0;Bss=0".to_string(),
+            _ => String::new(),
+        }
+    }
+
+    // `monitor` commands, the returned text is displayed by the debugger.
+    fn monitor(&mut self, command: &str) -> String {
+        let (command, condition) = match command.split_once(" if ") {
+            Some((command, condition)) => match condition.parse() {
+                Ok(condition) => (command, Some(condition)),
+                Err(e) => return format!("invalid condition: {}\n", e),
+            },
+            None => (command, None),
+        };
+        let words: Vec<&str> = command.split_whitespace().collect();
+        match words.as_slice() {
+            ["break", address] => match trace::parse_address(address) {
+                Ok(address) => self.add_point(Trigger::Breakpoint(address), condition),
+                Err(e) => format!("{}\n", e),
+            },
+            ["watch", kind @ ("read" | "write" | "access"), range] => {
+                let kind = match *kind {
+                    "read" => WatchKind::Read,
+                    "write" => WatchKind::Write,
+                    _ => WatchKind::Access,
+                };
+                match debugger::parse_range(range) {
+                    Ok(range) => self.add_point(Trigger::Watchpoint { range, kind }, condition),
+                    Err(e) => format!("{}\n", e),
+                }
+            }
+            ["watch", register, value @ ..] if value.len() <= 1 => {
+                let register = match register.parse() {
+                    Ok(register) => register,
+                    Err(e) => return format!("{}\n", e),
+                };
+                let value = match value.first().map(|v| v.parse::<Expr>()) {
+                    Some(Ok(Expr::Number(value))) => Some(value as u16),
+                    Some(_) => return "invalid register value\n".to_string(),
+                    None => None,
+                };
+                self.add_point(Trigger::Register { register, value }, condition)
+            }
+            ["delete", id] => match id.parse().map(|id| self.debugger.remove(id)) {
+                Ok(true) => String::new(),
+                _ => format!("no breakpoint or watchpoint {}\n", id),
+            },
+            ["info"] => self
+                .debugger
+                .points()
+                .iter()
+                .map(|point| format!("{}\n", point))
+                .collect(),
+            ["eval", ..] => match command.trim_start()["eval".len()..].parse::<Expr>() {
+                Ok(expr) => {
+                    let value = expr.eval(&self.chip8);
+                    format!("{} ({:#X})\n", value, value)
+                }
+                Err(e) => format!("invalid expression: {}\n", e),
+            },
+            ["key", key, state] => {
+                let key = match usize::from_str_radix(key, 16) {
+                    Ok(key) if key < 16 => key,
+                    _ => return "invalid key, expected 0 to F\n".to_string(),
+                };
+                match *state {
+                    "on" | "down" => self.chip8.press_key(key),
+                    "off" | "up" => self.chip8.release_key(key),
+                    _ => return "invalid key state, expected on or off\n".to_string(),
+                }
+                String::new()
+            }
+            ["cycles"] => format!("{}\n", self.chip8.cycles()),
+            ["stack"] => profile::call_stack(&self.chip8, &self.symbols)
+                .iter()
+                .map(|frame| format!("{}\n", frame))
+                .collect(),
+            _ => concat!(
+                "monitor commands:\n",
+                "  key <0-F> <on|off>                         press or release a key\n",
+                "  cycles                                     number of executed instructions\n",
+                "  stack                                      call stack, with symbols\n",
+                "  break <addr> [if <expr>]                   conditional breakpoint\n",
+                "  watch <read|write|access> <addr>[-<end>] [if <expr>]\n",
+                "                                             memory watchpoint\n",
+                "  watch <reg> [<value>] [if <expr>]          register watchpoint\n",
+                "  delete <id>                                delete a breakpoint or watchpoint\n",
+                "  info                                       list breakpoints and watchpoints\n",
+                "  eval <expr>                                evaluate an expression\n",
+                "expressions use V0-VF, I, PC, SP, DT, ST, [addr] and C operators,\n",
+                "e.g. V3 == 0x10 && I > 0x300\n",
+            )
+            .to_string(),
+        }
+    }
+
+    fn add_point(&mut self, trigger: Trigger, condition: Option<Expr>) -> String {
+        let id = self.debugger.add(trigger, condition);
+        let point = self.debugger.points().iter().find(|p| p.id == id).unwrap();
+        format!("{}\n", point)
+    }
+
+    fn breakpoint(&mut self, insert: bool, args: &str) -> Option<String> {
+        let mut fields = args.split(',');
+        let kind = fields.next()?;
+        let address = u16::from_str_radix(fields.next()?, 16).ok()?;
+        let length = u16::from_str_radix(fields.next()?, 16).ok()?.max(1);
+        let range = address..=address.saturating_add(length - 1);
+        let trigger = match kind {
+            // software and hardware breakpoints behave the same
+            "0" | "1" => Trigger::Breakpoint(address),
+            "2" => Trigger::Watchpoint {
+                range,
+                kind: WatchKind::Write,
+            },
+            "3" => Trigger::Watchpoint {
+                range,
+                kind: WatchKind::Read,
+            },
+            "4" => Trigger::Watchpoint {
+                range,
+                kind: WatchKind::Access,
+            },
+            _ => return Some(String::new()),
+        };
+        if insert {
+            self.debugger.add(trigger, None);
+        } else {
+            self.debugger.remove_trigger(&trigger);
+        }
+        Some("OK".to_string())
+    }
+
+    // Execute a single instruction, updating the timers at the end of each frame.
+    // Returns the watchpoint hit by the instruction, if any.
+    fn step(&mut self) -> Result<Option<Hit>, chip8::Error> {
+        let snapshot = self.debugger.snapshot(&self.chip8);
+        if self.frame_ended {
+            self.chip8.start_frame(self.ipf);
+            self.frame_ended = false;
+        }
+        self.chip8.tick()?;
+        let hit = self.debugger.check_effects(&self.chip8, &snapshot);
+        if !self.chip8.frame_pending() {
+            self.frame_ended = true;
+            self.chip8.update_timer();
+        }
+        Ok(hit)
+    }
+
+    // Run at the configured speed until a breakpoint or watchpoint, a fault or an interruption.
+    fn resume(&mut self, connection: &mut Connection) -> io::Result<Stop> {
+        let mut frame_start = time::Instant::now();
+        // the instruction at PC may hold the breakpoint we are resuming from
+        let mut first = true;
+        loop {
+            if !first {
+                if let Some(hit) = self.debugger.check_execute(&self.chip8) {
+                    return Ok(Stop::Hit(hit));
+                }
+            }
+            first = false;
+            match self.step() {
+                Ok(Some(hit)) => return Ok(Stop::Hit(hit)),
+                Ok(None) => {}
+                Err(e) => return Ok(Stop::Fault(e)),
+            }
+            if self.frame_ended {
+                if connection.interrupted()? {
+                    return Ok(Stop::Interrupt);
+                }
+                let elapsed = frame_start.elapsed();
+                if elapsed < FRAME_DURATION {
+                    thread::sleep(FRAME_DURATION - elapsed);
+                }
+                frame_start = time::Instant::now();
+            }
+        }
+    }
+
+    fn read_register(&self, n: usize) -> Option<Vec<u8>> {
+        let chip8 = &self.chip8;
+        Some(match n {
+            0..=15 => vec![chip8.register(n)],
+            REG_I => chip8.index().to_le_bytes().to_vec(),
+            REG_PC => chip8.program_counter().to_le_bytes().to_vec(),
+            REG_SP => vec![chip8.stack_pointer() as u8],
+            REG_DT => vec![chip8.delay_timer()],
+            REG_ST => vec![chip8.sound_timer()],
+            _ => return None,
+        })
+    }
+
+    fn write_register(&mut self, n: usize, bytes: &[u8]) -> Option<()> {
+        let byte = *bytes.first()?;
+        let word = || Some(u16::from_le_bytes([byte, *bytes.get(1)?]));
+        match n {
+            0..=15 => self.chip8.set_register(n, byte),
+            REG_I => self.chip8.set_index(word()?),
+            REG_PC => self.chip8.set_program_counter(word()?),
+            REG_SP => self.chip8.set_stack_pointer(byte as u16),
+            REG_DT => self.chip8.set_delay_timer(byte),
+            REG_ST => self.chip8.set_sound_timer(byte),
+            _ => return None,
+        }
+        Some(())
+    }
+
+    fn read_registers(&self) -> Vec<u8> {
+        (0..N_REGISTERS)
+            .flat_map(|n| self.read_register(n).unwrap())
+            .collect()
+    }
+
+    fn write_registers(&mut self, mut bytes: &[u8]) {
+        for n in 0..N_REGISTERS {
+            let size = if n == REG_I || n == REG_PC { 2 } else { 1 };
+            if bytes.len() < size {
+                return;
+            }
+            self.write_register(n, &bytes[..size]);
+            bytes = &bytes[size..];
+        }
+    }
+}
+
+impl Connection {
+    fn read_byte(&mut self) -> io::Result<Option<u8>> {
+        let mut byte = [0];
+        match self.stream.read(&mut byte)? {
+            0 => Ok(None),
+            _ => Ok(Some(byte[0])),
+        }
+    }
+
+    // Next packet content, None once the debugger closed the connection.
+    fn read_packet(&mut self) -> io::Result<Option<String>> {
+        loop {
+            // skip acks and interruptions received while stopped
+            loop {
+                match self.read_byte()? {
+                    Some(b'$') => break,
+                    Some(_) => continue,
+                    None => return Ok(None),
+                }
+            }
+            // raw bytes are needed for the checksum, escape characters included
+            let mut raw = Vec::new();
+            loop {
+                match self.read_byte()? {
+                    Some(b'#') => break,
+                    Some(byte) => raw.push(byte),
+                    None => return Ok(None),
+                }
+            }
+            let mut checksum = [0; 2];
+            self.stream.read_exact(&mut checksum)?;
+            let expected = std::str::from_utf8(&checksum)
+                .ok()
+                .and_then(|c| u8::from_str_radix(c, 16).ok());
+            let mut data = Vec::new();
+            let mut bytes = raw.iter();
+            while let Some(&byte) = bytes.next() {
+                match byte {
+                    b'}' => data.extend(bytes.next().map(|b| b ^ 0x20)),
+                    _ => data.push(byte),
+                }
+            }
+            if !self.no_ack {
+                if expected != Some(checksum_of(&raw)) {
+                    self.stream.write_all(b"-")?;
+                    continue;
+                }
+                self.stream.write_all(b"+")?;
+            }
+            return Ok(Some(String::from_utf8_lossy(&data).into_owned()));
+        }
+    }
+
+    fn send(&mut self, data: &str) -> io::Result<()> {
+        let packet = format!("${}#{:02x}", data, checksum_of(data.as_bytes()));
+        loop {
+            self.stream.write_all(packet.as_bytes())?;
+            if self.no_ack {
+                return Ok(());
+            }
+            match self.read_byte()? {
+                Some(b'-') => continue,
+                _ => return Ok(()),
+            }
+        }
+    }
+
+    // Check, without blocking, whether the debugger asked to interrupt the machine.
+    fn interrupted(&mut self) -> io::Result<bool> {
+        self.stream.set_nonblocking(true)?;
+        let mut byte = [0];
+        let result = match self.stream.read(&mut byte) {
+            Ok(1) => Ok(byte[0] == 0x03),
+            Ok(_) => Ok(false),
+            Err(e) if e.kind() == io::ErrorKind::WouldBlock => Ok(false),
+            Err(e) => Err(e),
+        };
+        self.stream.set_nonblocking(false)?;
+        result
+    }
+}
+
+fn stop_reply(stop: Stop) -> String {
+    match stop {
+        Stop::Step => "S05".to_string(),
+        Stop::Hit(hit) => match hit.reason {
+            Reason::Breakpoint(_) => "T05swbreak:;".to_string(),
+            Reason::Memory(access) => {
+                let kind = match access.kind {
+                    AccessKind::Read => "rwatch",
+                    AccessKind::Write => "watch",
+                };
+                format!("T05{}:{:x};", kind, access.address)
+            }
+            Reason::Register { .. } => "S05".to_string(),
+        },
+        Stop::Interrupt => "S02".to_string(),
+        Stop::Fault(chip8::Error::UnknownOpcode { .. } | chip8::Error::MachineCode { .. }) => {
+            "S04".to_string()
+        }
+        Stop::Fault(_) => "S0b".to_string(),
+    }
+}
+
+fn target_xml() -> String {
+    let mut xml = String::from(
+        "<?xml version=\"1.0\"?>\n<!DOCTYPE target SYSTEM \"gdb-target.dtd\">\n<target version=\"1.0\">\n<feature name=\"org.chip8.core\">\n",
+    );
+    for n in 0..16 {
+        xml += &format!("<reg name=\"v{:x}\" bitsize=\"8\" type=\"uint8\"/>\n", n);
+    }
+    xml += "<reg name=\"i\" bitsize=\"16\" type=\"data_ptr\"/>\n";
+    xml += "<reg name=\"pc\" bitsize=\"16\" type=\"code_ptr\"/>\n";
+    xml += "<reg name=\"sp\" bitsize=\"8\" type=\"uint8\"/>\n";
+    xml += "<reg name=\"dt\" bitsize=\"8\" type=\"uint8\"/>\n";
+    xml += "<reg name=\"st\" bitsize=\"8\" type=\"uint8\"/>\n";
+    xml += "</feature>\n</target>\n";
+    xml
+}
+
+fn checksum_of(data: &[u8]) -> u8 {
+    data.iter().fold(0u8, |sum, &b| sum.wrapping_add(b))
+}
+
+fn escape(data: &str) -> String {
+    data.chars()
+        .flat_map(|c| match c {
+            '#' | '$' | '}' | '*' => vec!['}', (c as u8 ^ 0x20) as char],
+            _ => vec![c],
+        })
+        .collect()
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+fn unhex(hex: &str) -> Option<Vec<u8>> {
+    if !hex.len().is_multiple_of(2) {
+        return None;
+    }
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(hex.get(i..i + 2)?, 16).ok())
+        .collect()
+}
+
+// "ADDR,LENGTH" in hex
+fn parse_range(args: &str) -> Option<(usize, usize)> {
+    let (address, length) = args.split_once(',')?;
+    Some((
+        usize::from_str_radix(address, 16).ok()?,
+        usize::from_str_radix(length, 16).ok()?,
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Minimal scripted debugger, talking to the stub through a local socket.
+    struct Client {
+        stream: TcpStream,
+    }
+
+    impl Client {
+        fn request(&mut self, data: &str) -> String {
+            let packet = format!("${}#{:02x}", data, checksum_of(data.as_bytes()));
+            self.stream.write_all(packet.as_bytes()).unwrap();
+            let mut ack = [0];
+            self.stream.read_exact(&mut ack).unwrap();
+            assert_eq!(ack[0], b'+');
+            self.reply()
+        }
+
+        fn reply(&mut self) -> String {
+            let mut reply = Vec::new();
+            let mut byte = [0];
+            loop {
+                self.stream.read_exact(&mut byte).unwrap();
+                if byte[0] == b'#' {
+                    break;
+                }
+                reply.push(byte[0]);
+            }
+            let mut checksum = [0; 2];
+            self.stream.read_exact(&mut checksum).unwrap();
+            self.stream.write_all(b"+").unwrap();
+            let reply = String::from_utf8(reply).unwrap();
+            assert!(reply.starts_with('$'));
+            reply[1..].to_string()
+        }
+    }
+
+    fn start(rom: Vec<u8>) -> (Client, thread::JoinHandle<()>) {
+        let mut chip8 = chip8::init();
+        chip8.load_rom(rom).unwrap();
+        let listener = listen(0).unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let server = thread::spawn(move || {
+            Stub::new(chip8, 8, Symbols::default())
+                .serve(&listener)
+                .unwrap()
+        });
+        let stream = TcpStream::connect(("127.0.0.1", port)).unwrap();
+        (Client { stream }, server)
+    }
+
+    #[test]
+    fn scripted_session() {
+        // 0x200: LD VA, 0x05
+        // 0x202: ADD VA, 0x01
+        // 0x204: JP 0x202
+        let (mut client, server) = start(vec![0x6A, 0x05, 0x7A, 0x01, 0x12, 0x02]);
+
+        assert!(client.request("qSupported:swbreak+").contains("swbreak+"));
+        assert!(client
+            .request("qXfer:features:read:target.xml:0,1000")
+            .starts_with("l<?xml"));
+        assert_eq!(client.request("?"), "S05");
+        // V0..VF, I, PC = 0x200, SP, DT, ST
+        assert_eq!(
+            client.request("g"),
+            format!("{}00000002000000", "00".repeat(16))
+        );
+
+        assert_eq!(client.request("s"), "S05");
+        assert_eq!(client.request("pa"), "05");
+        assert_eq!(client.request("p11"), "0202");
+
+        assert_eq!(client.request("Z0,204,2"), "OK");
+        assert_eq!(client.request("c"), "T05swbreak:;");
+        assert_eq!(client.request("p11"), "0402");
+        assert_eq!(client.request("pa"), "06");
+        // resuming from a breakpoint runs the loop once more
+        assert_eq!(client.request("c"), "T05swbreak:;");
+        assert_eq!(client.request("pa"), "07");
+        assert_eq!(client.request("z0,204,2"), "OK");
+
+        assert_eq!(client.request("Pa=42"), "OK");
+        assert_eq!(client.request("pa"), "42");
+        assert_eq!(client.request("M300,2:abcd"), "OK");
+        assert_eq!(client.request("m300,2"), "abcd");
+        assert_eq!(client.request("m1000,2"), "E01");
+        // ranges overflowing the address space are refused
+        assert_eq!(client.request("mffffffffffffffff,2"), "E01");
+        assert_eq!(client.request("Mffffffffffffffff,2:abcd"), "E01");
+        assert_eq!(
+            client.request("qXfer:features:read:target.xml:ffffffffffffffff,ffffffffffffffff"),
+            "l"
+        );
+
+        // run freely until interrupted
+        client
+            .stream
+            .write_all(format!("$c#{:02x}", checksum_of(b"c")).as_bytes())
+            .unwrap();
+        let mut ack = [0];
+        client.stream.read_exact(&mut ack).unwrap();
+        thread::sleep(time::Duration::from_millis(50));
+        client.stream.write_all(&[0x03]).unwrap();
+        assert_eq!(client.reply(), "S02");
+
+        client.stream.write_all(b"$k#6b").unwrap();
+        server.join().unwrap();
+    }
+
+    fn monitor(client: &mut Client, command: &str) -> String {
+        let reply = client.request(&format!("qRcmd,{}", hex(command.as_bytes())));
+        match reply.as_str() {
+            "OK" => String::new(),
+            reply => String::from_utf8(unhex(reply).unwrap()).unwrap(),
+        }
+    }
+
+    #[test]
+    fn watchpoints() {
+        // 0x200: ADD V3, 0x08
+        // 0x202: LD I, 0x300
+        // 0x204: LD [I], V3
+        // 0x206: LD I, 0x300
+        // 0x208: LD V3, [I]
+        // 0x20A: JP 0x200
+        let (mut client, server) = start(vec![
+            0x73, 0x08, 0xA3, 0x00, 0xF3, 0x55, 0xA3, 0x00, 0xF3, 0x65, 0x12, 0x00,
+        ]);
+
+        assert_eq!(client.request("Z2,303,1"), "OK");
+        let output = client.request("c");
+        assert!(output.starts_with('O'));
+        assert_eq!(client.reply(), "T05watch:303;");
+        assert_eq!(client.request("p11"), "0602");
+        assert_eq!(client.request("p3"), "08");
+        assert_eq!(client.request("z2,303,1"), "OK");
+
+        assert!(monitor(&mut client, "watch read 0x300-0x301 if V3 == 0x10").contains("read"));
+        let output = client.request("c");
+        assert!(output.starts_with('O'));
+        assert_eq!(client.reply(), "T05rwatch:300;");
+        assert_eq!(client.request("p3"), "10");
+
+        assert!(monitor(&mut client, "watch V3 0x20").starts_with("3:"));
+        let output = client.request("c");
+        let message = String::from_utf8(unhex(&output[1..]).unwrap()).unwrap();
+        assert_eq!(message, "watchpoint 3: V3 0x18 -> 0x20 (hit 1)\n");
+        assert_eq!(client.reply(), "S05");
+
+        assert_eq!(monitor(&mut client, "eval [0x303] + V3"), "56 (0x38)\n");
+        assert_eq!(monitor(&mut client, "delete 2"), "");
+        assert!(monitor(&mut client, "info").contains("hit 1 times"));
+        assert!(monitor(&mut client, "break 0x208 if V3 ==").starts_with("invalid condition"));
+
+        client.stream.write_all(b"$k#6b").unwrap();
+        server.join().unwrap();
+    }
+
+    #[test]
+    fn fault_is_reported() {
+        // 0x200: unknown opcode
+        let (mut client, server) = start(vec![0xFF, 0xFF]);
+        let output = client.request("c");
+        assert!(output.starts_with('O'));
+        let message = String::from_utf8(unhex(&output[1..]).unwrap()).unwrap();
+        assert!(message.contains("unknown opcode FFFF"));
+        assert_eq!(client.reply(), "S04");
+        client.stream.write_all(b"$k#6b").unwrap();
+        server.join().unwrap();
+    }
+}
@@ -0,0 +1,5 @@
+// Chip8 emulation core, shared by the emulator frontends and the tests.
+mod chip8;
+mod env;
+pub use crate::chip8::*;
+pub use crate::env::{DoneProbe, Env, EnvSpec, Format, Observation, RewardProbe, Snapshot};
@@ -1,114 +1,638 @@
-mod chip8;
-use minifb;
-use std::sync::{mpsc, Arc, Mutex};
+mod batch;
+mod cli;
+mod config;
+mod coverage;
+mod database;
+mod debugger;
+mod gdb;
+mod movie;
+mod osd;
+mod panel;
+mod png;
+mod profile;
+mod script;
+mod speed;
+mod symbols;
+mod trace;
+use clap::Parser;
 use std::thread;
 use std::time;
 
 fn main() {
-    let cpu_duration: time::Duration = time::Duration::from_secs_f32(1. / 500.);
-    let timer_duration: time::Duration = time::Duration::from_secs_f64(1. / 60.);
-    let win_duration: time::Duration = time::Duration::from_secs_f64(1. / 1000.);
-
     // get command line arguments
-    let args: Vec<String> = std::env::args().collect();
+    let result = match cli::Cli::parse().command() {
+        cli::Command::Run(args) => run(args),
+        cli::Command::Info(args) => info(args),
+        cli::Command::TraceDump(args) => trace_dump(args),
+        cli::Command::Gdb(args) => debug(args),
+        cli::Command::Profile(args) => profile(args),
+        cli::Command::Coverage(args) => coverage(args),
+        cli::Command::Bench(args) => bench(args),
+        cli::Command::Batch(args) => batch(args),
+    };
+    if let Err(e) = result {
+        eprintln!("Error: {}", e);
+        std::process::exit(1);
+    }
+}
+
+// A ROM along with its resolved configuration.
+struct Setup {
+    rom: Vec<u8>,
+    hash: String,
+    entry: Option<database::Entry>,
+    config: config::Config,
+}
+
+// The ROM database and the configuration file, shared by every ROM.
+struct Sources {
+    database: Option<database::Database>,
+    file: config::ConfigFile,
+    settings: config::Settings,
+}
+
+fn sources(args: &cli::SettingsArgs) -> Result<Sources, String> {
+    let file = match args.config.clone().or_else(config::default_path) {
+        // an explicitly given config file must exist
+        Some(path) if args.config.is_some() || path.exists() => config::ConfigFile::load(&path)?,
+        _ => config::ConfigFile::default(),
+    };
+    // the database given on the command line or in the config file must exist, the one of the
+    // default location is used when it was downloaded there
+    let database = match args.database.clone().or_else(|| file.database.clone()) {
+        Some(dir) => Some(database::Database::load(&dir)?),
+        None => match database::default_path() {
+            Some(dir) if dir.exists() => Some(database::Database::load(&dir)?),
+            _ => None,
+        },
+    };
+    Ok(Sources {
+        database,
+        file,
+        settings: args.settings()?,
+    })
+}
+
+fn setup(path: &std::path::Path, args: &cli::SettingsArgs) -> Result<Setup, String> {
+    resolve(path, &sources(args)?)
+}
+
+fn resolve(path: &std::path::Path, sources: &Sources) -> Result<Setup, String> {
+    let rom = std::fs::read(path).map_err(|e| format!("cannot read {}: {}", path.display(), e))?;
+    let hash = config::rom_hash(&rom);
+    let entry = sources
+        .database
+        .as_ref()
+        .and_then(|database| database.lookup(&hash));
+
+    let detected = entry.as_ref().map(|e| e.settings());
+    let mut settings = sources.file.settings_for(&hash, detected.as_ref());
+    settings.merge(&sources.settings);
+    let mut config = settings.resolve()?;
+
+    if let Some(entry) = &entry {
+        for (name, &key) in &entry.rom.keys {
+            match config::control_key(name) {
+                Some(k) if key < 16 && !config.keymap.contains(&k) => {
+                    config.controls.push((k, key as usize))
+                }
+                _ => {}
+            }
+        }
+    }
+
+    Ok(Setup {
+        rom,
+        hash,
+        entry,
+        config,
+    })
+}
+
+fn info(args: cli::InfoArgs) -> Result<(), String> {
+    let sources = sources(&args.settings)?;
+    if sources.database.is_none() {
+        return Err(
+            "no ROM database found, see scripts/fetch-database.sh or --database".to_string(),
+        );
+    }
+    let Setup {
+        rom,
+        hash,
+        entry,
+        config,
+    } = resolve(&args.rom, &sources)?;
+
+    println!("File:      {}", args.rom.display());
+    println!("Size:      {} bytes", rom.len());
+    println!("SHA-1:     {}", hash);
+    match &entry {
+        Some(entry) => {
+            let program = &entry.program;
+            println!("Title:     {}", program.title);
+            if !program.authors.is_empty() {
+                println!("Authors:   {}", program.authors.join(", "));
+            }
+            if let Some(release) = &program.release {
+                println!("Release:   {}", release);
+            }
+            println!("Platforms: {}", entry.rom.platforms.join(", "));
+            match &entry.platform {
+                Some(platform) => println!("Detected:  {} ({})", platform.name, platform.id),
+                None => println!("Detected:  unsupported platform"),
+            }
+            if let Some(tickrate) = entry.tickrate() {
+                println!("Tickrate:  {} instructions per frame", tickrate);
+            }
+            let mut keys: Vec<_> = entry.rom.keys.iter().collect();
+            keys.sort();
+            for (name, key) in keys {
+                println!("Key:       {} = {:X}", name, key);
+            }
+            if let Some(colors) = &entry.rom.colors {
+                println!("Colors:    {}", colors.pixels.join(", "));
+            }
+            for warning in entry.warnings() {
+                println!("Warning:   {}", warning);
+            }
+        }
+        None => println!("Title:     unknown ROM"),
+    }
+
+    println!();
+    println!("Resolved configuration:");
+    println!("  speed:   {} instructions per second", config.speed);
+    println!("  timing:  {}", config.timing.name());
+    println!("  0NNN:    {}", config.machine_code.name());
+    println!("  scale:   {}", config.scale);
+    println!(
+        "  palette: #{:06X}, #{:06X}",
+        config.palette[0], config.palette[1]
+    );
+    println!("  keymap:  {:?}", config.keymap);
+    let quirks = config.quirks;
+    println!(
+        "  quirks:  shift={} memory_increment={} jump={} vf_reset={} clip={}",
+        quirks.shift, quirks.memory_increment, quirks.jump, quirks.vf_reset, quirks.clip
+    );
+    Ok(())
+}
+
+fn run(args: cli::RunArgs) -> Result<(), String> {
+    let Setup {
+        rom, entry, config, ..
+    } = setup(&args.rom, &args.settings)?;
+    if let Some(entry) = entry.as_ref().filter(|e| !e.is_supported()) {
+        eprintln!(
+            "Warning: {} targets {}, which is not supported",
+            entry.program.title,
+            entry.rom.platforms.join(", ")
+        );
+    }
+    for warning in entry.iter().flat_map(|e| e.warnings()) {
+        eprintln!("Warning: {}", warning);
+    }
+
     // Init chip8 system
     let mut chip8: chip8::Chip8 = chip8::init();
+    chip8.set_quirks(config.quirks);
+    chip8.set_timing(config.timing);
+    chip8.set_machine_code(config.machine_code);
     // Load ROM
-    chip8.load_rom(std::fs::read(&args[1]).unwrap());
-    // Create mutex lock
-    let chip8lock = Arc::new(Mutex::new(chip8));
+    chip8.load_rom(rom).map_err(|e| e.to_string())?;
+    let mut script = match &args.script {
+        Some(path) => Some(script::Script::load(
+            path,
+            &mut chip8,
+            config.palette,
+            config.scale as usize,
+        )?),
+        None => None,
+    };
+    // on_write hooks need the memory writes of every instruction
+    let track_writes = script.as_ref().is_some_and(|s| s.tracks_writes());
+    chip8.set_access_tracking(track_writes);
 
     // Init minifb window
+    let title = match &entry {
+        Some(entry) => format!("Chip8 Emulator - {}", entry.program.title),
+        None => "Chip8 Emulator".to_string(),
+    };
+    // the window is drawn at its final size so the OSD is not limited to chip8 pixels
+    let factor = config.scale as usize;
+    let (width, height) = (chip8::WIDTH * factor, chip8::HEIGHT * factor);
     let mut window = minifb::Window::new(
-        "Chip8 Emulator",
-        chip8::WIDTH,
-        chip8::HEIGHT,
+        &title,
+        width,
+        height,
         minifb::WindowOptions {
-            scale: minifb::Scale::X8,
             scale_mode: minifb::ScaleMode::AspectRatioStretch,
             ..minifb::WindowOptions::default()
         },
     )
-    .unwrap();
+    .map_err(|e| format!("cannot open window: {}", e))?;
 
-    // Init multithreading lock and channel
-    let (gfx_transmitter, gfx_receiver) = mpsc::channel();
-    let cpu_lock = Arc::clone(&chip8lock);
-    let timer_lock = Arc::clone(&chip8lock);
-    let keypad_lock = Arc::clone(&chip8lock);
+    let mut tracer = args.trace.tracer()?;
+    let mut speed = speed::Speed::new(config.speed, config.fast_forward, config.timing);
+    let mut osd = osd::Osd::new();
+    let mut gfx = chip8.gfx_buffer(config.palette);
+    let mut rate = speed.label();
+    let mut panel: Option<panel::Panel> = None;
 
-    // CPU tick thread
-    thread::spawn(move || loop {
+    // GUI, keypad & emulation loop
+    while window.is_open() && !window.is_key_down(minifb::Key::Escape) {
         let now = time::Instant::now();
 
-        let mut chip8 = cpu_lock.lock().unwrap();
-        chip8.tick();
-        if chip8.draw_flag() {
-            gfx_transmitter.send(chip8.gfx_buffer()).unwrap();
+        // speed control hotkeys
+        let pressed = |key| window.is_key_pressed(key, minifb::KeyRepeat::No);
+        if pressed(minifb::Key::P) {
+            speed.toggle_pause();
+        }
+        if pressed(minifb::Key::N) {
+            speed.step();
+        }
+        if pressed(minifb::Key::M) {
+            speed.cycle_slow_motion();
+        }
+        if pressed(minifb::Key::Equal) || pressed(minifb::Key::NumPadPlus) {
+            speed.faster();
+        }
+        if pressed(minifb::Key::Minus) || pressed(minifb::Key::NumPadMinus) {
+            speed.slower();
         }
-        drop(chip8);
+        if pressed(minifb::Key::F1) {
+            osd.toggle_counter();
+        }
+        if pressed(minifb::Key::F2) {
+            panel = match panel {
+                Some(_) => None,
+                None => match panel::Panel::open() {
+                    Ok(panel) => Some(panel),
+                    Err(e) => {
+                        osd.error(&e);
+                        None
+                    }
+                },
+            };
+            // the panel colors the written bytes
+            chip8.set_access_tracking(panel.is_some() || track_writes);
+        }
+        speed.set_fast_forward(window.is_key_down(minifb::Key::Tab));
 
-        let elapsed = now.elapsed();
-        if elapsed < cpu_duration {
-            thread::sleep(cpu_duration - elapsed);
-        } else {
-            eprintln!(
-                "CPU frequency has been messed up (duration {:?} instead of {:?})",
-                elapsed, cpu_duration
+        chip8.reset_keypad();
+        for key in window.get_keys() {
+            if let Some(i) = config.keymap.iter().position(|&k| k == key) {
+                chip8.press_key(i);
+            }
+            if let Some(&(_, i)) = config.controls.iter().find(|(k, _)| *k == key) {
+                chip8.press_key(i);
+            }
+        }
+        if let Some(script) = &script {
+            script.hold_keys(&mut chip8);
+        }
+
+        let frame_duration = speed.frame_duration();
+        let (count, uncapped) = match speed.frames() {
+            speed::Frames::Exact(frames) => (frames, false),
+            speed::Frames::Uncapped => (u32::MAX, true),
+        };
+        let mut frames = 0;
+        let cycles = chip8.cycles();
+        while frames < count && !(uncapped && now.elapsed() >= frame_duration) {
+            frames += 1;
+            // TODO start/stop beep
+            let result = emulate_frame(
+                &mut chip8,
+                speed.ipf(),
+                &mut tracer,
+                &mut panel,
+                &mut script,
             );
+            if let Err(e) = result {
+                // script errors end the run, so that a test script can fail it
+                if script.as_ref().is_some_and(|s| s.failed()) {
+                    return Err(e);
+                }
+                speed.pause();
+                osd.error(&e);
+                break;
+            }
+            if let Some(script) = &mut script {
+                if script.quit() {
+                    return Ok(());
+                }
+                if script.take_pause() {
+                    speed.pause();
+                    break;
+                }
+            }
         }
-    });
+        osd.count_frame(chip8.cycles() - cycles);
 
-    // Timer update thread
-    thread::spawn(move || loop {
-        let now = time::Instant::now();
+        if speed.label() != rate {
+            rate = speed.label();
+            osd.message(&rate);
+        }
+        osd.set_paused(speed.is_paused());
 
-        let mut chip8 = timer_lock.lock().unwrap();
-        let _must_beep = chip8.update_timer();
-        drop(chip8);
-        // TODO start/stop beep
+        if chip8.draw_flag() {
+            gfx = chip8.gfx_buffer(config.palette);
+        }
+        let mut buffer = osd::upscale(&gfx, chip8::WIDTH, chip8::HEIGHT, factor);
+        let mut canvas = osd::Canvas {
+            buffer: &mut buffer,
+            width,
+            height,
+            pixel: (factor / 4).max(1),
+        };
+        if let Some(script) = &script {
+            script.draw(&mut canvas, factor);
+        }
+        osd.draw(&mut canvas);
+        window
+            .update_with_buffer(&buffer, width, height)
+            .map_err(|e| format!("cannot update window: {}", e))?;
+        if let Some(p) = &mut panel {
+            p.update(&chip8)?;
+            if !p.is_open() {
+                panel = None;
+                chip8.set_access_tracking(track_writes);
+            }
+        }
 
         let elapsed = now.elapsed();
-        if elapsed < timer_duration {
-            thread::sleep(timer_duration - elapsed);
-        } else {
-            eprintln!(
-                "Timers frequency has been messed up (duration {:?} instead of {:?})",
-                elapsed, timer_duration
-            );
+        if elapsed < frame_duration {
+            thread::sleep(frame_duration - elapsed);
+        }
+    }
+    if let Some(tracer) = &mut tracer {
+        tracer
+            .flush()
+            .map_err(|e| format!("cannot write trace: {}", e))?;
+    }
+    Ok(())
+}
+
+// Run one frame, tracing every instruction when a tracer is given, recording its memory
+// writes when the debugger panel is open, and calling the hooks of the script.
+fn emulate_frame(
+    chip8: &mut chip8::Chip8,
+    ipf: u32,
+    tracer: &mut Option<trace::Tracer>,
+    panel: &mut Option<panel::Panel>,
+    script: &mut Option<script::Script>,
+) -> Result<bool, String> {
+    let steps = script.as_ref().is_some_and(|s| s.steps());
+    let sound = if tracer.is_none() && panel.is_none() && !steps {
+        chip8.run_frame(ipf).map_err(|e| e.to_string())?
+    } else {
+        chip8.start_frame(ipf);
+        while chip8.frame_pending() {
+            // stop at the breakpoints, the run loop then pauses
+            if let Some(script) = script.as_mut().filter(|_| steps) {
+                if script.before_tick(chip8)? {
+                    break;
+                }
+            }
+            if let Some(tracer) = tracer {
+                tracer
+                    .record(chip8)
+                    .map_err(|e| format!("cannot write trace: {}", e))?;
+            }
+            chip8.tick().map_err(|e| e.to_string())?;
+            if let Some(panel) = panel {
+                panel.record(chip8);
+            }
+            if let Some(script) = script.as_mut().filter(|_| steps) {
+                if script.after_tick(chip8)? {
+                    break;
+                }
+            }
+        }
+        chip8.update_timer()
+    };
+    if let Some(script) = script {
+        script.end_frame(chip8)?;
+    }
+    Ok(sound)
+}
+
+fn trace_dump(args: cli::TraceDumpArgs) -> Result<(), String> {
+    use std::io::Write;
+    let error = |e| format!("cannot read {}: {}", args.trace.display(), e);
+    let records = trace::read_binary(&args.trace).map_err(error)?;
+    let mut out = std::io::stdout().lock();
+    for record in records {
+        let record = record.map_err(error)?;
+        if writeln!(out, "{}", record.to_text()).is_err() {
+            // stdout closed, e.g. piped into head
+            break;
+        }
+    }
+    Ok(())
+}
+
+fn debug(args: cli::GdbArgs) -> Result<(), String> {
+    let Setup { rom, config, .. } = setup(&args.rom, &args.settings)?;
+    let mut chip8 = chip8::init();
+    chip8.set_quirks(config.quirks);
+    chip8.set_timing(config.timing);
+    chip8.set_machine_code(config.machine_code);
+    chip8.load_rom(rom).map_err(|e| e.to_string())?;
+    let symbols = symbols::Symbols::load_optional(args.symbols.as_deref())?;
+
+    let listener =
+        gdb::listen(args.port).map_err(|e| format!("cannot listen on {}: {}", args.port, e))?;
+    eprintln!("Waiting for a debugger on 127.0.0.1:{}", args.port);
+    // same instructions per frame as the window
+    let ipf = speed::Speed::new(config.speed, config.fast_forward, config.timing).ipf();
+    gdb::Stub::new(chip8, ipf, symbols)
+        .serve(&listener)
+        .map_err(|e| format!("debugger connection failed: {}", e))
+}
+
+fn profile(args: cli::ProfileArgs) -> Result<(), String> {
+    let Setup { rom, config, .. } = setup(&args.rom, &args.settings)?;
+    let mut chip8 = chip8::init();
+    chip8.set_quirks(config.quirks);
+    chip8.set_timing(config.timing);
+    chip8.set_machine_code(config.machine_code);
+    chip8.load_rom(rom).map_err(|e| e.to_string())?;
+    let symbols = symbols::Symbols::load_optional(args.symbols.as_deref())?;
+
+    let ipf = speed::Speed::new(config.speed, config.fast_forward, config.timing).ipf();
+    let mut profiler = profile::Profiler::new(&chip8);
+    'frames: for frame in 0..args.frames {
+        chip8.start_frame(ipf);
+        while chip8.frame_pending() {
+            if let Err(e) = profiler.tick(&mut chip8) {
+                eprintln!("Warning: stopped at frame {}: {}", frame, e);
+                break 'frames;
+            }
+        }
+        chip8.update_timer();
+    }
+
+    print!("{}", profiler.report(&chip8, &symbols));
+    if let Some(path) = &args.folded {
+        std::fs::write(path, profiler.folded(&symbols))
+            .map_err(|e| format!("cannot write {}: {}", path.display(), e))?;
+    }
+    Ok(())
+}
+
+fn coverage(args: cli::CoverageArgs) -> Result<(), String> {
+    let Setup { rom, config, .. } = setup(&args.rom, &args.settings)?;
+    let mut chip8 = chip8::init();
+    chip8.set_quirks(config.quirks);
+    chip8.set_timing(config.timing);
+    chip8.set_machine_code(config.machine_code);
+    chip8.load_rom(rom.clone()).map_err(|e| e.to_string())?;
+    chip8.set_coverage_tracking(true);
+    let movie = match &args.input {
+        Some(path) => movie::Movie::load(path)?,
+        None => movie::Movie::default(),
+    };
+
+    let ipf = speed::Speed::new(config.speed, config.fast_forward, config.timing).ipf();
+    for frame in 0..args.frames {
+        movie.apply(frame, &mut chip8);
+        if let Err(e) = chip8.run_frame(ipf) {
+            eprintln!("Warning: stopped at frame {}: {}", frame, e);
+            break;
+        }
+    }
+
+    let coverage = chip8.coverage().unwrap();
+    let report = coverage::Report::new(coverage, chip8::START_ROM, rom.len());
+    print!("{}", report.listing(coverage, &rom));
+    println!();
+    print!("{}", report.summary(coverage));
+    if let Some(path) = &args.lcov {
+        let source = args.rom.display().to_string();
+        std::fs::write(path, report.lcov(coverage, &source))
+            .map_err(|e| format!("cannot write {}: {}", path.display(), e))?;
+    }
+    Ok(())
+}
+
+fn bench(args: cli::BenchArgs) -> Result<(), String> {
+    let Setup { rom, config, .. } = setup(&args.rom, &args.settings)?;
+    // name, decode cache, JIT
+    #[allow(unused_mut)]
+    let mut modes = vec![("no cache", false, false), ("decode cache", true, false)];
+    #[cfg(all(feature = "jit", target_arch = "x86_64"))]
+    modes.push(("jit", true, true));
+
+    let mut baseline = None;
+    for (name, cache, jit) in modes {
+        let mut chip8 = chip8::init();
+        chip8.set_quirks(config.quirks);
+        chip8.set_timing(config.timing);
+        chip8.set_machine_code(config.machine_code);
+        chip8.set_decode_cache(cache);
+        chip8.seed_rng(0);
+        chip8.load_rom(rom.clone()).map_err(|e| e.to_string())?;
+        #[cfg(all(feature = "jit", target_arch = "x86_64"))]
+        let mut compiler = jit.then(chip8::Jit::new);
+        #[cfg(not(all(feature = "jit", target_arch = "x86_64")))]
+        let _ = jit;
+
+        let start = std::time::Instant::now();
+        for frame in 0..args.frames {
+            #[cfg(all(feature = "jit", target_arch = "x86_64"))]
+            let result = match &mut compiler {
+                Some(compiler) => compiler.run_frame(&mut chip8, args.ipf),
+                None => chip8.run_frame(args.ipf),
+            };
+            #[cfg(not(all(feature = "jit", target_arch = "x86_64")))]
+            let result = chip8.run_frame(args.ipf);
+            if let Err(e) = result {
+                eprintln!("Warning: stopped at frame {}: {}", frame, e);
+                break;
+            }
         }
+        let seconds = start.elapsed().as_secs_f64();
+        let rate = chip8.cycles() as f64 / seconds;
+        let baseline = *baseline.get_or_insert(rate);
+        println!(
+            "{:<13} {:>8.2} M instructions/s, {:.2}x ({} instructions in {:.3} s)",
+            format!("{}:", name),
+            rate / 1e6,
+            rate / baseline,
+            chip8.cycles(),
+            seconds
+        );
+    }
+    Ok(())
+}
+
+fn batch(args: cli::BatchArgs) -> Result<(), String> {
+    let sources = sources(&args.settings)?;
+    let mut paths = Vec::new();
+    find_roms(&args.dir, &mut paths)?;
+    let jobs = paths
+        .iter()
+        .map(|path| {
+            let relative = path.strip_prefix(&args.dir).unwrap_or(path);
+            let setup = resolve(path, &sources);
+            batch::Job {
+                path: relative.display().to_string(),
+                sha1: setup.as_ref().map(|s| s.hash.clone()).unwrap_or_default(),
+                title: setup
+                    .as_ref()
+                    .ok()
+                    .and_then(|s| Some(s.entry.as_ref()?.program.title.clone())),
+                setup: setup.map(|s| (s.rom, s.config)),
+            }
+        })
+        .collect();
+    let threads = args.jobs.unwrap_or_else(|| {
+        thread::available_parallelism()
+            .map(|n| n.get())
+            .unwrap_or(1)
     });
 
-    // GUI & keypad update loop
-    while window.is_open() && !window.is_key_down(minifb::Key::Escape) {
-        if let Ok(gfx) = gfx_receiver.recv_timeout(win_duration) {
-            window
-                .update_with_buffer(&gfx, chip8::WIDTH, chip8::HEIGHT)
-                .unwrap();
-        } else {
-            window.update();
-        }
-        let mut chip8 = keypad_lock.lock().unwrap();
-        chip8.reset_keypad();
-        window.get_keys().iter().for_each(|key| match key {
-            minifb::Key::Key1 => chip8.press_key(0x1),
-            minifb::Key::Key2 => chip8.press_key(0x2),
-            minifb::Key::Key3 => chip8.press_key(0x3),
-            minifb::Key::Key4 => chip8.press_key(0xC),
-            minifb::Key::Q => chip8.press_key(0x4),
-            minifb::Key::W => chip8.press_key(0x5),
-            minifb::Key::E => chip8.press_key(0x6),
-            minifb::Key::R => chip8.press_key(0xD),
-            minifb::Key::A => chip8.press_key(0x7),
-            minifb::Key::S => chip8.press_key(0x8),
-            minifb::Key::D => chip8.press_key(0x9),
-            minifb::Key::F => chip8.press_key(0xE),
-            minifb::Key::Z => chip8.press_key(0xA),
-            minifb::Key::X => chip8.press_key(0x0),
-            minifb::Key::C => chip8.press_key(0xB),
-            minifb::Key::V => chip8.press_key(0xF),
-            _ => {}
-        });
-        drop(chip8);
+    let start = time::Instant::now();
+    let mut report = batch::run(jobs, args.frames, threads);
+    let elapsed = start.elapsed();
+    if let Some(path) = &args.previous {
+        report.diff(&batch::Report::load(path)?);
+    }
+    print!("{}", report.summary());
+    println!("in {:.1} s on {} threads", elapsed.as_secs_f64(), threads);
+
+    let write = |path: &std::path::Path, text: String| {
+        std::fs::write(path, text).map_err(|e| format!("cannot write {}: {}", path.display(), e))
+    };
+    if let Some(path) = &args.json {
+        write(path, report.json())?;
+    }
+    if let Some(path) = &args.html {
+        write(path, report.html())?;
+    }
+    match report.regressions() {
+        0 => Ok(()),
+        n => Err(format!("{} ROMs differ from the previous report", n)),
+    }
+}
+
+// Every .ch8 file under `dir`, sorted.
+fn find_roms(dir: &std::path::Path, roms: &mut Vec<std::path::PathBuf>) -> Result<(), String> {
+    let entries =
+        std::fs::read_dir(dir).map_err(|e| format!("cannot read {}: {}", dir.display(), e))?;
+    let mut paths: Vec<_> = entries.filter_map(|e| Some(e.ok()?.path())).collect();
+    paths.sort();
+    for path in paths {
+        if path.is_dir() {
+            find_roms(&path, roms)?;
+        } else if path
+            .extension()
+            .is_some_and(|e| e.eq_ignore_ascii_case("ch8"))
+        {
+            roms.push(path);
+        }
     }
+    Ok(())
 }
@@ -0,0 +1,96 @@
+// Input movie, the keys held on each frame of a headless run.
+//
+// One line per change of the keypad state: the frame number, then the hex keys held from that
+// frame on, until the next line. Empty lines and lines starting with '#' are ignored.
+//   # frame keys
+//   0
+//   60 5
+//   90 46
+//   120
+use chip8::Chip8;
+use std::path::Path;
+
+#[derive(Debug, Clone, Default)]
+pub struct Movie {
+    // keypad states by increasing frame
+    changes: Vec<(u32, [bool; 16])>,
+}
+
+impl Movie {
+    pub fn load(path: &Path) -> Result<Movie, String> {
+        let text = std::fs::read_to_string(path)
+            .map_err(|e| format!("cannot read {}: {}", path.display(), e))?;
+        text.parse()
+            .map_err(|e| format!("invalid input movie {}: {}", path.display(), e))
+    }
+
+    // Keypad state for this frame.
+    pub fn keys(&self, frame: u32) -> [bool; 16] {
+        match self.changes.partition_point(|(f, _)| *f <= frame) {
+            0 => [false; 16],
+            i => self.changes[i - 1].1,
+        }
+    }
+
+    // Press and release the keys of the chip8 keypad for this frame.
+    pub fn apply(&self, frame: u32, chip8: &mut Chip8) {
+        for (key, down) in self.keys(frame).into_iter().enumerate() {
+            if down {
+                chip8.press_key(key);
+            } else {
+                chip8.release_key(key);
+            }
+        }
+    }
+}
+
+impl std::str::FromStr for Movie {
+    type Err = String;
+
+    fn from_str(text: &str) -> Result<Movie, String> {
+        let mut changes: Vec<(u32, [bool; 16])> = Vec::new();
+        for (n, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let (frame, keys) = line.split_once(char::is_whitespace).unwrap_or((line, ""));
+            let frame: u32 = frame
+                .parse()
+                .map_err(|_| format!("line {}: invalid frame '{}'", n + 1, frame))?;
+            if changes.last().is_some_and(|(last, _)| *last >= frame) {
+                return Err(format!("line {}: frames must be increasing", n + 1));
+            }
+            let mut keypad = [false; 16];
+            for key in keys.chars().filter(|c| !c.is_whitespace()) {
+                let key = key
+                    .to_digit(16)
+                    .ok_or_else(|| format!("line {}: invalid key '{}'", n + 1, key))?;
+                keypad[key as usize] = true;
+            }
+            changes.push((frame, keypad));
+        }
+        Ok(Movie { changes })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn keys_are_held_until_the_next_change() {
+        let movie: Movie = "# frame keys\n10 5\n20 4 6\n30\n".parse().unwrap();
+        let held = |frame| -> Vec<usize> {
+            let keys = movie.keys(frame);
+            (0..16).filter(|&k| keys[k]).collect()
+        };
+        assert!(held(0).is_empty());
+        assert_eq!(held(10), vec![5]);
+        assert_eq!(held(19), vec![5]);
+        assert_eq!(held(25), vec![4, 6]);
+        assert!(held(1000).is_empty());
+        assert!("10\n5\n".parse::<Movie>().is_err());
+        assert!("0 G\n".parse::<Movie>().is_err());
+    }
+}
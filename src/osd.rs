@@ -0,0 +1,327 @@
+use std::time;
+
+const MESSAGE_DURATION: time::Duration = time::Duration::from_secs(2);
+const TEXT_COLOR: u32 = 0xFFFFFF;
+const PAUSE_COLOR: u32 = 0xFFCC00;
+const ERROR_COLOR: u32 = 0xFF5555;
+
+// Built-in 3x5 bitmap font, one row of 3 bits per byte.
+const GLYPH_WIDTH: usize = 3;
+const GLYPH_HEIGHT: usize = 5;
+const FONT: [(char, [u8; GLYPH_HEIGHT]); 56] = [
+    ('0', [0b111, 0b101, 0b101, 0b101, 0b111]),
+    ('1', [0b010, 0b110, 0b010, 0b010, 0b111]),
+    ('2', [0b111, 0b001, 0b111, 0b100, 0b111]),
+    ('3', [0b111, 0b001, 0b011, 0b001, 0b111]),
+    ('4', [0b101, 0b101, 0b111, 0b001, 0b001]),
+    ('5', [0b111, 0b100, 0b111, 0b001, 0b111]),
+    ('6', [0b111, 0b100, 0b111, 0b101, 0b111]),
+    ('7', [0b111, 0b001, 0b010, 0b010, 0b010]),
+    ('8', [0b111, 0b101, 0b111, 0b101, 0b111]),
+    ('9', [0b111, 0b101, 0b111, 0b001, 0b111]),
+    ('A', [0b010, 0b101, 0b111, 0b101, 0b101]),
+    ('B', [0b110, 0b101, 0b110, 0b101, 0b110]),
+    ('C', [0b011, 0b100, 0b100, 0b100, 0b011]),
+    ('D', [0b110, 0b101, 0b101, 0b101, 0b110]),
+    ('E', [0b111, 0b100, 0b110, 0b100, 0b111]),
+    ('F', [0b111, 0b100, 0b110, 0b100, 0b100]),
+    ('G', [0b011, 0b100, 0b101, 0b101, 0b011]),
+    ('H', [0b101, 0b101, 0b111, 0b101, 0b101]),
+    ('I', [0b111, 0b010, 0b010, 0b010, 0b111]),
+    ('J', [0b001, 0b001, 0b001, 0b101, 0b010]),
+    ('K', [0b101, 0b101, 0b110, 0b101, 0b101]),
+    ('L', [0b100, 0b100, 0b100, 0b100, 0b111]),
+    ('M', [0b101, 0b111, 0b111, 0b101, 0b101]),
+    ('N', [0b110, 0b101, 0b101, 0b101, 0b101]),
+    ('O', [0b010, 0b101, 0b101, 0b101, 0b010]),
+    ('P', [0b110, 0b101, 0b110, 0b100, 0b100]),
+    ('Q', [0b010, 0b101, 0b101, 0b110, 0b011]),
+    ('R', [0b110, 0b101, 0b110, 0b101, 0b101]),
+    ('S', [0b011, 0b100, 0b010, 0b001, 0b110]),
+    ('T', [0b111, 0b010, 0b010, 0b010, 0b010]),
+    ('U', [0b101, 0b101, 0b101, 0b101, 0b111]),
+    ('V', [0b101, 0b101, 0b101, 0b101, 0b010]),
+    ('W', [0b101, 0b101, 0b111, 0b111, 0b101]),
+    ('X', [0b101, 0b101, 0b010, 0b101, 0b101]),
+    ('Y', [0b101, 0b101, 0b010, 0b010, 0b010]),
+    ('Z', [0b111, 0b001, 0b010, 0b100, 0b111]),
+    (' ', [0b000, 0b000, 0b000, 0b000, 0b000]),
+    ('.', [0b000, 0b000, 0b000, 0b000, 0b010]),
+    (',', [0b000, 0b000, 0b000, 0b010, 0b100]),
+    (':', [0b000, 0b010, 0b000, 0b010, 0b000]),
+    ('!', [0b010, 0b010, 0b010, 0b000, 0b010]),
+    ('?', [0b110, 0b001, 0b010, 0b000, 0b010]),
+    ('-', [0b000, 0b000, 0b111, 0b000, 0b000]),
+    ('+', [0b000, 0b010, 0b111, 0b010, 0b000]),
+    ('=', [0b000, 0b111, 0b000, 0b111, 0b000]),
+    ('/', [0b001, 0b001, 0b010, 0b100, 0b100]),
+    ('%', [0b101, 0b001, 0b010, 0b100, 0b101]),
+    ('(', [0b001, 0b010, 0b010, 0b010, 0b001]),
+    (')', [0b100, 0b010, 0b010, 0b010, 0b100]),
+    ('[', [0b011, 0b010, 0b010, 0b010, 0b011]),
+    (']', [0b110, 0b010, 0b010, 0b010, 0b110]),
+    ('#', [0b101, 0b111, 0b101, 0b111, 0b101]),
+    ('_', [0b000, 0b000, 0b000, 0b000, 0b111]),
+    ('\'', [0b010, 0b010, 0b000, 0b000, 0b000]),
+    ('"', [0b101, 0b101, 0b000, 0b000, 0b000]),
+    ('>', [0b100, 0b010, 0b001, 0b010, 0b100]),
+];
+
+// A frame buffer the OSD can be drawn on.
+pub struct Canvas<'a> {
+    pub buffer: &'a mut [u32],
+    pub width: usize,
+    pub height: usize,
+    // size of a font pixel, in canvas pixels
+    pub pixel: usize,
+}
+
+// On-screen display composited over the scaled chip8 framebuffer.
+pub struct Osd {
+    // messages along with their expiration time
+    messages: Vec<(String, u32, time::Instant)>,
+    paused: bool,
+    show_counter: bool,
+    counter: String,
+    // frames and instructions executed since last counter update
+    frames: u32,
+    instructions: u64,
+    since: time::Instant,
+}
+
+impl Osd {
+    pub fn new() -> Osd {
+        Osd {
+            messages: Vec::new(),
+            paused: false,
+            show_counter: false,
+            counter: String::new(),
+            frames: 0,
+            instructions: 0,
+            since: time::Instant::now(),
+        }
+    }
+
+    pub fn message(&mut self, text: &str) {
+        self.push(text, TEXT_COLOR);
+    }
+
+    pub fn error(&mut self, text: &str) {
+        self.push(text, ERROR_COLOR);
+    }
+
+    fn push(&mut self, text: &str, color: u32) {
+        self.messages.push((
+            text.to_string(),
+            color,
+            time::Instant::now() + MESSAGE_DURATION,
+        ));
+    }
+
+    pub fn set_paused(&mut self, paused: bool) {
+        self.paused = paused;
+    }
+
+    pub fn toggle_counter(&mut self) {
+        self.show_counter = !self.show_counter;
+    }
+
+    // Record a displayed frame, and the chip8 instructions executed for it.
+    pub fn count_frame(&mut self, instructions: u64) {
+        self.frames += 1;
+        self.instructions += instructions;
+        let elapsed = self.since.elapsed();
+        if elapsed >= time::Duration::from_secs(1) {
+            let secs = elapsed.as_secs_f64();
+            self.counter = format!(
+                "{:.0} FPS {:.0} IPS",
+                self.frames as f64 / secs,
+                self.instructions as f64 / secs
+            );
+            self.frames = 0;
+            self.instructions = 0;
+            self.since = time::Instant::now();
+        }
+    }
+
+    pub fn draw(&mut self, canvas: &mut Canvas) {
+        let now = time::Instant::now();
+        self.messages.retain(|(_, _, expiration)| *expiration > now);
+
+        let line = (GLYPH_HEIGHT + 2) * canvas.pixel;
+        if self.show_counter && !self.counter.is_empty() {
+            canvas.text(canvas.pixel, canvas.pixel, &self.counter, TEXT_COLOR);
+        }
+        if self.paused {
+            let x = canvas
+                .width
+                .saturating_sub(text_width("PAUSED", canvas.pixel) + canvas.pixel);
+            canvas.text(x, canvas.pixel, "PAUSED", PAUSE_COLOR);
+        }
+        // most recent message at the bottom
+        let mut y = canvas.height;
+        for (text, color, _) in self.messages.iter().rev() {
+            if y < line * 2 {
+                break;
+            }
+            y -= line;
+            canvas.text(canvas.pixel, y, text, *color);
+        }
+    }
+}
+
+fn glyph(c: char) -> Option<&'static [u8; GLYPH_HEIGHT]> {
+    FONT.iter().find(|(g, _)| *g == c).map(|(_, glyph)| glyph)
+}
+
+fn text_width(text: &str, pixel: usize) -> usize {
+    text.chars().count() * (GLYPH_WIDTH + 1) * pixel
+}
+
+impl Canvas<'_> {
+    // Draw text with its top left corner at (x, y), over a darkened background.
+    pub fn text(&mut self, x: usize, y: usize, text: &str, color: u32) {
+        let width = text_width(text, self.pixel) + self.pixel;
+        let height = (GLYPH_HEIGHT + 2) * self.pixel;
+        self.darken(
+            x.saturating_sub(self.pixel),
+            y.saturating_sub(self.pixel),
+            width,
+            height,
+        );
+
+        for (i, c) in text.chars().enumerate() {
+            let c = c.to_ascii_uppercase();
+            let glyph = glyph(c).or_else(|| glyph('?')).unwrap();
+            let gx = x + i * (GLYPH_WIDTH + 1) * self.pixel;
+            for (row, bits) in glyph.iter().enumerate() {
+                for col in 0..GLYPH_WIDTH {
+                    if bits & (0b100 >> col) != 0 {
+                        self.fill(
+                            gx + col * self.pixel,
+                            y + row * self.pixel,
+                            self.pixel,
+                            self.pixel,
+                            color,
+                        );
+                    }
+                }
+            }
+        }
+    }
+
+    fn fill(&mut self, x: usize, y: usize, width: usize, height: usize, color: u32) {
+        for j in y..(y + height).min(self.height) {
+            for i in x..(x + width).min(self.width) {
+                self.buffer[i + j * self.width] = color;
+            }
+        }
+    }
+
+    fn darken(&mut self, x: usize, y: usize, width: usize, height: usize) {
+        for j in y..(y + height).min(self.height) {
+            for i in x..(x + width).min(self.width) {
+                let pixel = &mut self.buffer[i + j * self.width];
+                *pixel = (*pixel >> 2) & 0x3F3F3F;
+            }
+        }
+    }
+}
+
+// Nearest neighbour upscaling of the chip8 framebuffer, so the OSD can be drawn at a
+// higher resolution than the chip8 pixels.
+pub fn upscale(gfx: &[u32], width: usize, height: usize, factor: usize) -> Vec<u32> {
+    let mut buffer = vec![0; width * height * factor * factor];
+    for y in 0..height * factor {
+        for x in 0..width * factor {
+            buffer[x + y * width * factor] = gfx[x / factor + (y / factor) * width];
+        }
+    }
+    buffer
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn canvas(buffer: &mut [u32], width: usize, height: usize) -> Canvas<'_> {
+        Canvas {
+            buffer,
+            width,
+            height,
+            pixel: 1,
+        }
+    }
+
+    #[test]
+    fn upscaling() {
+        let gfx = [1, 2, 3, 4, 5, 6];
+        let buffer = upscale(&gfx, 3, 2, 2);
+        assert_eq!(buffer.len(), 6 * 4);
+        assert_eq!(&buffer[0..6], [1, 1, 2, 2, 3, 3]);
+        assert_eq!(&buffer[6..12], [1, 1, 2, 2, 3, 3]);
+        assert_eq!(&buffer[12..18], [4, 4, 5, 5, 6, 6]);
+        assert_eq!(&buffer[18..24], [4, 4, 5, 5, 6, 6]);
+        assert_eq!(upscale(&gfx, 3, 2, 1), gfx);
+    }
+
+    #[test]
+    fn text_clipping() {
+        let (width, height) = (10, 8);
+        let mut buffer = vec![0xFFFFFF; width * height];
+        // "8" is 3x5, only its top left 2x2 pixels fit: ## then #.
+        canvas(&mut buffer, width, height).text(8, 6, "8", 0x123456);
+        let drawn: Vec<usize> = (0..buffer.len())
+            .filter(|&i| buffer[i] == 0x123456)
+            .collect();
+        assert_eq!(drawn, [68, 69, 78]);
+        // the background is darkened from one pixel before the text, up to the edges
+        assert_eq!(buffer[7 + 5 * width], 0x3F3F3F);
+        assert_eq!(buffer[6 + 5 * width], 0xFFFFFF);
+        assert_eq!(buffer[7 + 4 * width], 0xFFFFFF);
+
+        // text starting past the edges draws nothing
+        let mut buffer = vec![0; width * height];
+        canvas(&mut buffer, width, height).text(11, 9, "TEXT", 0x123456);
+        assert!(buffer.iter().all(|&pixel| pixel == 0));
+    }
+
+    #[test]
+    fn messages_expire() {
+        let (width, height) = (64, 32);
+        let mut buffer = vec![0; width * height];
+        let mut osd = Osd::new();
+        osd.message("HELLO");
+        osd.error("FAILED");
+        osd.draw(&mut canvas(&mut buffer, width, height));
+        assert!(buffer.contains(&TEXT_COLOR) && buffer.contains(&ERROR_COLOR));
+
+        osd.messages[0].2 = time::Instant::now();
+        let mut buffer = vec![0; width * height];
+        osd.draw(&mut canvas(&mut buffer, width, height));
+        assert_eq!(osd.messages.len(), 1);
+        assert!(!buffer.contains(&TEXT_COLOR) && buffer.contains(&ERROR_COLOR));
+    }
+
+    #[test]
+    fn pause_indicator() {
+        let (width, height) = (64, 32);
+        let mut osd = Osd::new();
+        osd.set_paused(true);
+        let mut buffer = vec![0; width * height];
+        osd.draw(&mut canvas(&mut buffer, width, height));
+        // at the top right corner
+        let drawn: Vec<usize> = (0..buffer.len())
+            .filter(|&i| buffer[i] == PAUSE_COLOR)
+            .collect();
+        assert!(!drawn.is_empty());
+        assert!(drawn
+            .iter()
+            .all(|&i| i % width >= width - text_width("PAUSED", 1) - 1 && i / width <= 5));
+
+        osd.set_paused(false);
+        let mut buffer = vec![0; width * height];
+        osd.draw(&mut canvas(&mut buffer, width, height));
+        assert!(!buffer.contains(&PAUSE_COLOR));
+    }
+}
@@ -0,0 +1,234 @@
+// Debugger panel, a second window showing the machine state while the game runs.
+//
+// The memory view follows I, PageUp and PageDown scroll it, Home follows I again.
+use crate::osd::Canvas;
+use chip8::{self, AccessKind, Chip8};
+use std::fmt::Write;
+
+const WIDTH: usize = 800;
+const HEIGHT: usize = 600;
+// size of a font pixel
+const PIXEL: usize = 2;
+const COLUMN: usize = 4 * PIXEL;
+const LINE: usize = 7 * PIXEL;
+
+const LABEL_COLOR: u32 = 0x8888AA;
+const TEXT_COLOR: u32 = 0xDDDDDD;
+const CURRENT_COLOR: u32 = 0xFFCC00;
+const KEY_DOWN_COLOR: u32 = 0x55FF55;
+const WRITTEN_COLOR: u32 = 0xFF5555;
+
+// Instructions shown before and after PC.
+const DISASSEMBLY_CONTEXT: usize = 8;
+const MEMORY_ROWS: usize = 16;
+// Frames during which written bytes stay colored.
+const WRITTEN_FRAMES: u8 = 30;
+// Column of the disassembly and memory views.
+const RIGHT: usize = 30 * COLUMN;
+// Keypad keys, as laid out on the COSMAC VIP.
+const KEYPAD: [[usize; 4]; 4] = [
+    [0x1, 0x2, 0x3, 0xC],
+    [0x4, 0x5, 0x6, 0xD],
+    [0x7, 0x8, 0x9, 0xE],
+    [0xA, 0x0, 0xB, 0xF],
+];
+
+pub struct Panel {
+    window: minifb::Window,
+    buffer: Vec<u32>,
+    // frames since each byte was last written, saturated at WRITTEN_FRAMES
+    written: Vec<u8>,
+    // first address of the memory view, None to follow I
+    memory_start: Option<usize>,
+}
+
+impl Panel {
+    pub fn open() -> Result<Panel, String> {
+        let window = minifb::Window::new(
+            "Chip8 Debugger",
+            WIDTH,
+            HEIGHT,
+            minifb::WindowOptions::default(),
+        )
+        .map_err(|e| format!("cannot open debugger window: {}", e))?;
+        Ok(Panel {
+            window,
+            buffer: vec![0; WIDTH * HEIGHT],
+            written: vec![WRITTEN_FRAMES; chip8::MEM_SIZE],
+            memory_start: None,
+        })
+    }
+
+    pub fn is_open(&self) -> bool {
+        self.window.is_open()
+    }
+
+    // Record the memory writes of the last executed instruction, access tracking must be enabled.
+    pub fn record(&mut self, chip8: &Chip8) {
+        for access in chip8.accesses() {
+            if access.kind == AccessKind::Write {
+                self.written[access.address as usize] = 0;
+            }
+        }
+    }
+
+    // Draw the machine state, once per frame.
+    pub fn update(&mut self, chip8: &Chip8) -> Result<(), String> {
+        let pressed = |key| self.window.is_key_pressed(key, minifb::KeyRepeat::Yes);
+        let page = MEMORY_ROWS * 16;
+        let start = self
+            .memory_start
+            .unwrap_or_else(|| follow(chip8.index() as usize));
+        if pressed(minifb::Key::PageUp) {
+            self.memory_start = Some(start.saturating_sub(page));
+        }
+        if pressed(minifb::Key::PageDown) {
+            self.memory_start = Some((start + page).min(chip8::MEM_SIZE - page));
+        }
+        if pressed(minifb::Key::Home) {
+            self.memory_start = None;
+        }
+
+        self.buffer.fill(0);
+        let mut canvas = Canvas {
+            buffer: &mut self.buffer,
+            width: WIDTH,
+            height: HEIGHT,
+            pixel: PIXEL,
+        };
+        draw_registers(&mut canvas, chip8);
+        draw_disassembly(&mut canvas, chip8);
+        let start = self
+            .memory_start
+            .unwrap_or_else(|| follow(chip8.index() as usize));
+        draw_memory(&mut canvas, chip8, &self.written, start);
+
+        for age in self.written.iter_mut() {
+            *age = age.saturating_add(1).min(WRITTEN_FRAMES);
+        }
+        self.window
+            .update_with_buffer(&self.buffer, WIDTH, HEIGHT)
+            .map_err(|e| format!("cannot update debugger window: {}", e))
+    }
+}
+
+// Memory view start showing I on its third row.
+fn follow(index: usize) -> usize {
+    (index & !0xF)
+        .saturating_sub(2 * 16)
+        .min(chip8::MEM_SIZE - MEMORY_ROWS * 16)
+}
+
+fn row(n: usize) -> usize {
+    PIXEL + n * LINE
+}
+
+fn draw_registers(canvas: &mut Canvas, chip8: &Chip8) {
+    let mut text = String::new();
+    let line = |canvas: &mut Canvas, n: usize, label: &str, value: &str| {
+        canvas.text(PIXEL, row(n), label, LABEL_COLOR);
+        canvas.text(PIXEL + 7 * COLUMN, row(n), value, TEXT_COLOR);
+    };
+    line(canvas, 0, "PC", &format!("{:03X}", chip8.program_counter()));
+    line(canvas, 1, "I", &format!("{:03X}", chip8.index()));
+    line(
+        canvas,
+        2,
+        "DT ST",
+        &format!("{:02X} {:02X}", chip8.delay_timer(), chip8.sound_timer()),
+    );
+    line(canvas, 3, "CYCLES", &chip8.cycles().to_string());
+    let registers = chip8.registers();
+    for (n, chunk) in registers.chunks(4).enumerate() {
+        text.clear();
+        for (i, v) in chunk.iter().enumerate() {
+            write!(text, "{:X}:{:02X} ", n * 4 + i, v).unwrap();
+        }
+        line(canvas, 5 + n, if n == 0 { "V" } else { "" }, &text);
+    }
+
+    canvas.text(PIXEL, row(10), "STACK", LABEL_COLOR);
+    let stack = chip8.call_stack();
+    for (n, address) in stack.iter().rev().enumerate() {
+        canvas.text(
+            PIXEL + 7 * COLUMN,
+            row(10 + n),
+            &format!("{:03X}", address),
+            TEXT_COLOR,
+        );
+    }
+    if stack.is_empty() {
+        canvas.text(PIXEL + 7 * COLUMN, row(10), "-", TEXT_COLOR);
+    }
+
+    // the stack holds at most 16 entries
+    let top = 27;
+    canvas.text(PIXEL, row(top), "KEYPAD", LABEL_COLOR);
+    let keypad = chip8.keypad();
+    for (y, keys) in KEYPAD.iter().enumerate() {
+        for (x, &key) in keys.iter().enumerate() {
+            let color = if keypad[key] {
+                KEY_DOWN_COLOR
+            } else {
+                LABEL_COLOR
+            };
+            canvas.text(
+                PIXEL + (7 + 2 * x) * COLUMN,
+                row(top + y),
+                &format!("{:X}", key),
+                color,
+            );
+        }
+    }
+}
+
+fn draw_disassembly(canvas: &mut Canvas, chip8: &Chip8) {
+    let pc = chip8.program_counter() as usize;
+    let first = pc.saturating_sub(2 * DISASSEMBLY_CONTEXT) | (pc & 1);
+    for (n, address) in (first..chip8::MEM_SIZE - 1)
+        .step_by(2)
+        .take(2 * DISASSEMBLY_CONTEXT + 1)
+        .enumerate()
+    {
+        let opcode = (chip8.memory(address) as u16) << 8 | chip8.memory(address + 1) as u16;
+        let (marker, color) = if address == pc {
+            ('>', CURRENT_COLOR)
+        } else {
+            (' ', TEXT_COLOR)
+        };
+        let text = format!(
+            "{}{:03X} {:04X} {}",
+            marker,
+            address,
+            opcode,
+            chip8::disassemble(opcode)
+        );
+        canvas.text(RIGHT, row(n), &text, color);
+    }
+}
+
+fn draw_memory(canvas: &mut Canvas, chip8: &Chip8, written: &[u8], start: usize) {
+    let top = 2 * DISASSEMBLY_CONTEXT + 3;
+    let x = RIGHT;
+    canvas.text(x, row(top - 1), "MEMORY", LABEL_COLOR);
+    for r in 0..MEMORY_ROWS {
+        let address = start + r * 16;
+        canvas.text(x, row(top + r), &format!("{:03X}", address), LABEL_COLOR);
+        for i in 0..16 {
+            let a = address + i;
+            let color = if written[a] < WRITTEN_FRAMES {
+                WRITTEN_COLOR
+            } else {
+                TEXT_COLOR
+            };
+            // an extra column between the two halves of a row
+            let column = 4 + 3 * i + i / 8;
+            canvas.text(
+                x + column * COLUMN,
+                row(top + r),
+                &format!("{:02X}", chip8.memory(a)),
+                color,
+            );
+        }
+    }
+}
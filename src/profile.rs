@@ -0,0 +1,227 @@
+// Subroutine profiler, counting the instructions and cycles spent per subroutine and per address.
+// Cycles are COSMAC VIP machine cycles, whatever the timing the ROM runs with.
+//
+// Subroutines are identified by their entry point, and followed through the stack pointer
+// changes of each instruction. The code executed before the first call is the root subroutine.
+use crate::symbols::Symbols;
+use chip8::{self, Chip8};
+use std::collections::BTreeMap;
+use std::fmt::Write;
+
+// Number of addresses listed in the report.
+const HOT_ADDRESSES: usize = 20;
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Counts {
+    pub instructions: u64,
+    pub cycles: u64,
+}
+
+#[derive(Debug, Clone, Default)]
+struct Subroutine {
+    calls: u64,
+    // spent in the subroutine itself
+    own: Counts,
+    // spent in the subroutine and the ones it called
+    total: Counts,
+}
+
+pub struct Profiler {
+    // entry points of the active subroutines, outermost first
+    frames: Vec<u16>,
+    addresses: BTreeMap<u16, Counts>,
+    subroutines: BTreeMap<u16, Subroutine>,
+    // counts per call stack, for the folded stacks output
+    stacks: BTreeMap<Vec<u16>, Counts>,
+}
+
+impl Counts {
+    fn add(&mut self, other: Counts) {
+        self.instructions += other.instructions;
+        self.cycles += other.cycles;
+    }
+}
+
+impl Profiler {
+    pub fn new(chip8: &Chip8) -> Profiler {
+        Profiler {
+            frames: vec![chip8.program_counter()],
+            addresses: BTreeMap::new(),
+            subroutines: BTreeMap::new(),
+            stacks: BTreeMap::new(),
+        }
+    }
+
+    // Execute the instruction at PC, accounting it to the current subroutine.
+    pub fn tick(&mut self, chip8: &mut Chip8) -> Result<(), chip8::Error> {
+        let (pc, sp, cycles) = (
+            chip8.program_counter(),
+            chip8.stack_pointer(),
+            chip8.machine_cycles(),
+        );
+        chip8.tick()?;
+        self.account(
+            pc,
+            Counts {
+                instructions: 1,
+                cycles: chip8.machine_cycles() - cycles,
+            },
+        );
+
+        let new_sp = chip8.stack_pointer();
+        if new_sp > sp {
+            let entry = chip8.program_counter();
+            self.frames.push(entry);
+            self.subroutines.entry(entry).or_default().calls += 1;
+        } else if new_sp < sp && self.frames.len() > 1 {
+            self.frames.pop();
+        }
+        Ok(())
+    }
+
+    fn account(&mut self, pc: u16, counts: Counts) {
+        self.addresses.entry(pc).or_default().add(counts);
+        let current = *self.frames.last().unwrap();
+        self.subroutines.entry(current).or_default().own.add(counts);
+        // recursive subroutines are only counted once in the inclusive counts
+        for (i, &entry) in self.frames.iter().enumerate() {
+            if !self.frames[..i].contains(&entry) {
+                self.subroutines.entry(entry).or_default().total.add(counts);
+            }
+        }
+        match self.stacks.get_mut(&self.frames[..]) {
+            Some(stack) => stack.add(counts),
+            None => {
+                self.stacks.insert(self.frames.clone(), counts);
+            }
+        }
+    }
+
+    fn name(&self, symbols: &Symbols, entry: u16) -> String {
+        match symbols.label(entry) {
+            Some(label) => label.to_string(),
+            None if entry == self.frames[0] => "main".to_string(),
+            None => symbols.name(entry),
+        }
+    }
+
+    // Flat report: the subroutines by decreasing own cycles, then the hottest addresses.
+    pub fn report(&self, chip8: &Chip8, symbols: &Symbols) -> String {
+        let total: u64 = self.addresses.values().map(|c| c.cycles).sum();
+        let percent = |cycles: u64| 100.0 * cycles as f64 / total.max(1) as f64;
+        let mut out = String::new();
+
+        let mut subroutines: Vec<_> = self.subroutines.iter().collect();
+        subroutines.sort_by_key(|(_, s)| std::cmp::Reverse(s.own.cycles));
+        writeln!(
+            out,
+            "{:>6} {:>12} {:>12} {:>12} {:>12} {:>8}  subroutine",
+            "self%", "self instr", "self cycles", "total instr", "total cycles", "calls"
+        )
+        .unwrap();
+        for (&entry, s) in subroutines {
+            writeln!(
+                out,
+                "{:>5.1}% {:>12} {:>12} {:>12} {:>12} {:>8}  {}",
+                percent(s.own.cycles),
+                s.own.instructions,
+                s.own.cycles,
+                s.total.instructions,
+                s.total.cycles,
+                s.calls,
+                self.name(symbols, entry)
+            )
+            .unwrap();
+        }
+
+        let mut addresses: Vec<_> = self.addresses.iter().collect();
+        addresses.sort_by_key(|(_, c)| std::cmp::Reverse(c.cycles));
+        writeln!(out).unwrap();
+        writeln!(
+            out,
+            "{:>6} {:>12} {:>12}  address",
+            "self%", "instr", "cycles"
+        )
+        .unwrap();
+        for (&address, counts) in addresses.into_iter().take(HOT_ADDRESSES) {
+            let opcode = (chip8.memory(address as usize) as u16) << 8
+                | chip8.memory(address as usize + 1) as u16;
+            writeln!(
+                out,
+                "{:>5.1}% {:>12} {:>12}  {:03X} {:<24} {:04X} {}",
+                percent(counts.cycles),
+                counts.instructions,
+                counts.cycles,
+                address,
+                symbols.resolve(address),
+                opcode,
+                chip8::disassemble(opcode)
+            )
+            .unwrap();
+        }
+        out
+    }
+
+    // One "outer;inner cycles" line per call stack, the input format of flamegraph tools.
+    pub fn folded(&self, symbols: &Symbols) -> String {
+        let mut out = String::new();
+        for (stack, counts) in &self.stacks {
+            let names: Vec<String> = stack.iter().map(|&e| self.name(symbols, e)).collect();
+            writeln!(out, "{} {}", names.join(";"), counts.cycles).unwrap();
+        }
+        out
+    }
+}
+
+// Call stack of the machine, innermost first: the current instruction, then the return
+// address of each pending call.
+pub fn call_stack(chip8: &Chip8, symbols: &Symbols) -> Vec<String> {
+    let pc = chip8.program_counter();
+    std::iter::once(pc)
+        .chain(chip8.call_stack().iter().rev().map(|&call| call + 2))
+        .enumerate()
+        .map(|(i, address)| format!("#{} {:03X} in {}", i, address, symbols.resolve(address)))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn nested_calls() {
+        // 0x200: CALL 0x206
+        // 0x202: CALL 0x208
+        // 0x204: JP 0x204
+        // 0x206: CALL 0x208
+        // 0x208: RET
+        let mut chip8 = chip8::init();
+        chip8
+            .load_rom(vec![
+                0x22, 0x06, 0x22, 0x08, 0x12, 0x04, 0x22, 0x08, 0x00, 0xEE,
+            ])
+            .unwrap();
+        let symbols: Symbols = "0x206 outer\n0x208 inner\n".parse().unwrap();
+        let mut profiler = Profiler::new(&chip8);
+        for _ in 0..2 {
+            profiler.tick(&mut chip8).unwrap();
+        }
+        assert_eq!(
+            call_stack(&chip8, &symbols),
+            vec!["#0 208 in inner", "#1 208 in inner", "#2 202 in 0x202"]
+        );
+
+        // outer falls through the RET of inner; CALL, RET and JP take 94, 78 and 80 cycles
+        for _ in 0..6 {
+            profiler.tick(&mut chip8).unwrap();
+        }
+        assert_eq!(
+            profiler.folded(&symbols),
+            "main 348\nmain;outer 172\nmain;outer;inner 78\nmain;inner 78\n"
+        );
+        let outer = &profiler.subroutines[&0x206];
+        assert_eq!((outer.calls, outer.own.instructions), (1, 2));
+        assert_eq!(outer.total.instructions, 3);
+        assert_eq!(profiler.subroutines[&0x208].calls, 2);
+    }
+}
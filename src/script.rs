@@ -0,0 +1,509 @@
+// Rhai scripts run along with a ROM, with `chip8 run game.ch8 --script bot.rhai`.
+//
+// The script is run once when the ROM is loaded, then the emulator calls the hooks it defines:
+//   fn on_frame(frame)           after each 60 Hz frame, counted from 0
+//   fn on_execute(pc, opcode)    before each instruction
+//   fn on_write(address, value)  after each memory write of an instruction
+//   fn on_breakpoint(pc)         before an instruction with a breakpoint, see breakpoint(); the
+//                                emulator pauses there if it calls pause(), or without the hook
+// Hooks share their state through `this`, a map kept from one call to the next. The functions
+// the script can call are registered in Script::engine() and listed in the README.
+use crate::debugger::{Debugger, Expr, Trigger};
+use crate::osd;
+use crate::png;
+use chip8::{AccessKind, Chip8, HEIGHT, MEM_SIZE, WIDTH};
+use rhai::{CallFnOptions, Dynamic, Engine, EvalAltResult, FuncArgs, Map, Scope, AST};
+use std::cell::RefCell;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+
+const TEXT_COLOR: u32 = 0xFFFFFF;
+// name and parameters of each hook
+const HOOKS: [(&str, &[&str]); 4] = [
+    ("on_frame", &["frame"]),
+    ("on_execute", &["pc", "opcode"]),
+    ("on_write", &["address", "value"]),
+    ("on_breakpoint", &["pc"]),
+];
+
+type Fallible<T> = Result<T, Box<EvalAltResult>>;
+
+// What the functions called by the script work on.
+struct State {
+    // the machine, swapped with the one of the emulator while the script runs
+    chip8: Chip8,
+    debugger: Debugger,
+    // keys held by the script, on top of the keyboard
+    keys: u16,
+    // text over the screen, at chip8 pixel coordinates
+    overlay: Vec<(usize, usize, String, u32)>,
+    palette: [u32; 2],
+    scale: usize,
+    paused: bool,
+    quit: bool,
+}
+
+pub struct Script {
+    path: PathBuf,
+    engine: Engine,
+    ast: AST,
+    scope: Scope<'static>,
+    this: Dynamic,
+    state: Rc<RefCell<State>>,
+    // which of HOOKS the script defines
+    hooks: [bool; 4],
+    frame: i64,
+    failed: bool,
+    // paused at a breakpoint, which is not hit again when resuming
+    resuming: bool,
+}
+
+fn in_range(value: i64, end: usize, what: &str) -> Fallible<usize> {
+    match usize::try_from(value) {
+        Ok(value) if value < end => Ok(value),
+        _ => Err(format!("invalid {} {}", what, value).into()),
+    }
+}
+
+fn byte(value: i64) -> Fallible<u8> {
+    u8::try_from(value).map_err(|_| format!("{} does not fit in a byte", value).into())
+}
+
+fn word(value: i64) -> Fallible<u16> {
+    u16::try_from(value).map_err(|_| format!("{} does not fit in 16 bits", value).into())
+}
+
+impl Script {
+    // Compile the script and run it, with `chip8` holding the loaded ROM.
+    pub fn load(
+        path: &Path,
+        chip8: &mut Chip8,
+        palette: [u32; 2],
+        scale: usize,
+    ) -> Result<Script, String> {
+        let text = std::fs::read_to_string(path)
+            .map_err(|e| format!("cannot read {}: {}", path.display(), e))?;
+        let state = Rc::new(RefCell::new(State {
+            chip8: chip8::init(),
+            debugger: Debugger::new(),
+            keys: 0,
+            overlay: Vec::new(),
+            palette,
+            scale,
+            paused: false,
+            quit: false,
+        }));
+        let engine = Script::engine(&state);
+        let ast = engine
+            .compile(&text)
+            .map_err(|e| format!("{}: {}", path.display(), e))?;
+
+        let mut hooks = [false; 4];
+        for function in ast.iter_functions() {
+            if let Some(i) = HOOKS.iter().position(|(name, _)| *name == function.name) {
+                let (name, params) = HOOKS[i];
+                if function.params.len() != params.len() {
+                    return Err(format!(
+                        "{}: expected fn {}({})",
+                        path.display(),
+                        name,
+                        params.join(", ")
+                    ));
+                }
+                hooks[i] = true;
+            }
+        }
+
+        let mut script = Script {
+            path: path.to_path_buf(),
+            engine,
+            ast,
+            scope: Scope::new(),
+            this: Dynamic::from_map(Map::new()),
+            state,
+            hooks,
+            frame: 0,
+            failed: false,
+            resuming: false,
+        };
+        script.with_machine(chip8, |script| {
+            script
+                .engine
+                .run_ast_with_scope(&mut script.scope, &script.ast)
+        })?;
+        Ok(script)
+    }
+
+    fn engine(state: &Rc<RefCell<State>>) -> Engine {
+        let mut engine = Engine::new();
+
+        // registers
+        let s = state.clone();
+        engine.register_fn("reg", move |x: i64| -> Fallible<i64> {
+            Ok(s.borrow().chip8.register(in_range(x, 16, "register")?) as i64)
+        });
+        let s = state.clone();
+        engine.register_fn("set_reg", move |x: i64, value: i64| -> Fallible<()> {
+            let x = in_range(x, 16, "register")?;
+            s.borrow_mut().chip8.set_register(x, byte(value)?);
+            Ok(())
+        });
+        let s = state.clone();
+        engine.register_fn("index", move || s.borrow().chip8.index() as i64);
+        let s = state.clone();
+        engine.register_fn("set_index", move |value: i64| -> Fallible<()> {
+            s.borrow_mut().chip8.set_index(word(value)?);
+            Ok(())
+        });
+        let s = state.clone();
+        engine.register_fn("pc", move || s.borrow().chip8.program_counter() as i64);
+        let s = state.clone();
+        engine.register_fn("set_pc", move |value: i64| -> Fallible<()> {
+            s.borrow_mut().chip8.set_program_counter(word(value)?);
+            Ok(())
+        });
+        let s = state.clone();
+        engine.register_fn("delay_timer", move || s.borrow().chip8.delay_timer() as i64);
+        let s = state.clone();
+        engine.register_fn("set_delay_timer", move |value: i64| -> Fallible<()> {
+            s.borrow_mut().chip8.set_delay_timer(byte(value)?);
+            Ok(())
+        });
+        let s = state.clone();
+        engine.register_fn("sound_timer", move || s.borrow().chip8.sound_timer() as i64);
+        let s = state.clone();
+        engine.register_fn("set_sound_timer", move |value: i64| -> Fallible<()> {
+            s.borrow_mut().chip8.set_sound_timer(byte(value)?);
+            Ok(())
+        });
+        let s = state.clone();
+        engine.register_fn("cycles", move || s.borrow().chip8.cycles() as i64);
+
+        // memory
+        let s = state.clone();
+        engine.register_fn("peek", move |address: i64| -> Fallible<i64> {
+            let address = in_range(address, MEM_SIZE, "address")?;
+            Ok(s.borrow().chip8.memory(address) as i64)
+        });
+        let s = state.clone();
+        engine.register_fn("poke", move |address: i64, value: i64| -> Fallible<()> {
+            let address = in_range(address, MEM_SIZE, "address")?;
+            s.borrow_mut().chip8.set_memory(address, byte(value)?);
+            Ok(())
+        });
+
+        // keypad
+        let s = state.clone();
+        engine.register_fn("key_down", move |key: i64| -> Fallible<bool> {
+            Ok(s.borrow().chip8.is_key_down(in_range(key, 16, "key")?))
+        });
+        let s = state.clone();
+        engine.register_fn("press", move |key: i64| -> Fallible<()> {
+            let key = in_range(key, 16, "key")?;
+            let mut state = s.borrow_mut();
+            state.keys |= 1 << key;
+            state.chip8.press_key(key);
+            Ok(())
+        });
+        let s = state.clone();
+        engine.register_fn("release", move |key: i64| -> Fallible<()> {
+            let key = in_range(key, 16, "key")?;
+            let mut state = s.borrow_mut();
+            state.keys &= !(1 << key);
+            state.chip8.release_key(key);
+            Ok(())
+        });
+
+        // overlay and screenshots
+        let s = state.clone();
+        engine.register_fn("text", move |x: i64, y: i64, text: &str| -> Fallible<()> {
+            draw_text(&s, x, y, text, TEXT_COLOR as i64)
+        });
+        let s = state.clone();
+        engine.register_fn(
+            "text",
+            move |x: i64, y: i64, text: &str, color: i64| -> Fallible<()> {
+                draw_text(&s, x, y, text, color)
+            },
+        );
+        let s = state.clone();
+        engine.register_fn("screenshot", move |path: &str| -> Fallible<()> {
+            let state = s.borrow();
+            let [background, foreground] = state.palette;
+            let gfx: Vec<u32> = state
+                .chip8
+                .gfx_rows()
+                .iter()
+                .flat_map(|row| (0..WIDTH).map(move |x| row >> (WIDTH - 1 - x) & 1 != 0))
+                .map(|lit| if lit { foreground } else { background })
+                .collect();
+            let scale = state.scale;
+            let pixels = osd::upscale(&gfx, WIDTH, HEIGHT, scale);
+            png::write(Path::new(path), &pixels, WIDTH * scale, HEIGHT * scale)
+                .map_err(|e| format!("cannot write {}: {}", path, e).into())
+        });
+
+        // breakpoints and control
+        let s = state.clone();
+        engine.register_fn("breakpoint", move |address: i64| -> Fallible<i64> {
+            let address = in_range(address, MEM_SIZE, "address")? as u16;
+            let id = s
+                .borrow_mut()
+                .debugger
+                .add(Trigger::Breakpoint(address), None);
+            Ok(id as i64)
+        });
+        let s = state.clone();
+        engine.register_fn(
+            "breakpoint",
+            move |address: i64, condition: &str| -> Fallible<i64> {
+                let address = in_range(address, MEM_SIZE, "address")? as u16;
+                let condition: Expr = condition
+                    .parse()
+                    .map_err(|e| format!("invalid condition '{}': {}", condition, e))?;
+                let id = s
+                    .borrow_mut()
+                    .debugger
+                    .add(Trigger::Breakpoint(address), Some(condition));
+                Ok(id as i64)
+            },
+        );
+        let s = state.clone();
+        engine.register_fn("remove_breakpoint", move |id: i64| {
+            usize::try_from(id).is_ok_and(|id| s.borrow_mut().debugger.remove(id))
+        });
+        let s = state.clone();
+        engine.register_fn("pause", move || s.borrow_mut().paused = true);
+        let s = state.clone();
+        engine.register_fn("quit", move || s.borrow_mut().quit = true);
+
+        engine
+    }
+
+    // Run `f` with the machine of the emulator in the state, for the functions of the script.
+    fn with_machine<T>(
+        &mut self,
+        chip8: &mut Chip8,
+        f: impl FnOnce(&mut Script) -> Fallible<T>,
+    ) -> Result<T, String> {
+        std::mem::swap(chip8, &mut self.state.borrow_mut().chip8);
+        let result = f(self);
+        std::mem::swap(chip8, &mut self.state.borrow_mut().chip8);
+        result.map_err(|e| {
+            self.failed = true;
+            format!("{}: {}", self.path.display(), e)
+        })
+    }
+
+    fn call(&mut self, chip8: &mut Chip8, hook: &str, args: impl FuncArgs) -> Result<(), String> {
+        self.with_machine(chip8, |script| {
+            // the top level statements were run by load() already
+            let options = CallFnOptions::new()
+                .eval_ast(false)
+                .bind_this_ptr(&mut script.this);
+            script
+                .engine
+                .call_fn_with_options::<Dynamic>(
+                    options,
+                    &mut script.scope,
+                    &script.ast,
+                    hook,
+                    args,
+                )
+                .map(|_| ())
+        })
+    }
+
+    // Whether the frames must be run one instruction at a time, for the hooks below.
+    pub fn steps(&self) -> bool {
+        self.hooks[1] || self.hooks[2] || !self.state.borrow().debugger.points().is_empty()
+    }
+
+    // Whether the machine must record its memory accesses, for on_write.
+    pub fn tracks_writes(&self) -> bool {
+        self.hooks[2]
+    }
+
+    // Call on_breakpoint and on_execute, before executing the instruction at PC. Returns whether
+    // the emulator has to pause before executing it, at a breakpoint.
+    pub fn before_tick(&mut self, chip8: &mut Chip8) -> Result<bool, String> {
+        let hit = match std::mem::take(&mut self.resuming) {
+            true => None,
+            false => self.state.borrow_mut().debugger.check_execute(chip8),
+        };
+        if hit.is_some() {
+            if self.hooks[3] {
+                let pc = chip8.program_counter() as i64;
+                self.call(chip8, "on_breakpoint", (pc,))?;
+            } else {
+                self.state.borrow_mut().paused = true;
+            }
+            if self.state.borrow().paused {
+                self.resuming = true;
+                return Ok(true);
+            }
+        }
+        if let (true, Some(opcode)) = (self.hooks[1], chip8.opcode()) {
+            let pc = chip8.program_counter() as i64;
+            self.call(chip8, "on_execute", (pc, opcode as i64))?;
+        }
+        Ok(false)
+    }
+
+    // Call on_write for the memory writes of the executed instruction. Returns whether the
+    // emulator has to pause, the script having called pause().
+    pub fn after_tick(&mut self, chip8: &mut Chip8) -> Result<bool, String> {
+        if self.hooks[2] {
+            let writes: Vec<_> = chip8
+                .accesses()
+                .iter()
+                .filter(|a| a.kind == AccessKind::Write)
+                .map(|a| (a.address as i64, a.value as i64))
+                .collect();
+            for (address, value) in writes {
+                self.call(chip8, "on_write", (address, value))?;
+            }
+        }
+        Ok(self.state.borrow().paused)
+    }
+
+    // Call on_frame, which draws the overlay again.
+    pub fn end_frame(&mut self, chip8: &mut Chip8) -> Result<(), String> {
+        let frame = self.frame;
+        self.frame += 1;
+        if self.hooks[0] {
+            self.state.borrow_mut().overlay.clear();
+            self.call(chip8, "on_frame", (frame,))?;
+        }
+        Ok(())
+    }
+
+    // Press the keys held by the script, after the keypad was set from the keyboard.
+    pub fn hold_keys(&self, chip8: &mut Chip8) {
+        let keys = self.state.borrow().keys;
+        for key in (0..16).filter(|key| keys & 1 << key != 0) {
+            chip8.press_key(key);
+        }
+    }
+
+    // Draw the overlay text, `factor` being the size of a chip8 pixel on the canvas.
+    pub fn draw(&self, canvas: &mut osd::Canvas, factor: usize) {
+        for (x, y, text, color) in &self.state.borrow().overlay {
+            canvas.text(x * factor, y * factor, text, *color);
+        }
+    }
+
+    // Whether the script asked to pause since the last call.
+    pub fn take_pause(&mut self) -> bool {
+        std::mem::take(&mut self.state.borrow_mut().paused)
+    }
+
+    pub fn quit(&self) -> bool {
+        self.state.borrow().quit
+    }
+
+    // Whether the script stopped on an error, which ends the run.
+    pub fn failed(&self) -> bool {
+        self.failed
+    }
+}
+
+fn draw_text(state: &Rc<RefCell<State>>, x: i64, y: i64, text: &str, color: i64) -> Fallible<()> {
+    let x = in_range(x, WIDTH, "column")?;
+    let y = in_range(y, HEIGHT, "row")?;
+    let color = u32::try_from(color)
+        .ok()
+        .filter(|&c| c <= 0xFFFFFF)
+        .ok_or_else(|| format!("invalid color {:#X}", color))?;
+    state
+        .borrow_mut()
+        .overlay
+        .push((x, y, text.to_string(), color));
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn load(text: &str, chip8: &mut Chip8) -> Result<Script, String> {
+        let path = std::env::temp_dir().join(format!("chip8-script-{}.rhai", std::process::id()));
+        std::fs::write(&path, text).unwrap();
+        let script = Script::load(&path, chip8, [0, 0xFFFFFF], 1);
+        std::fs::remove_file(&path).unwrap();
+        script
+    }
+
+    #[test]
+    fn hooks() {
+        let mut chip8 = chip8::init();
+        // 0x200: LD V0, 1 ; LD I, 0x300 ; LD [I], V0 ; JP 0x200
+        chip8
+            .load_rom(vec![0x60, 0x01, 0xA3, 0x00, 0xF0, 0x55, 0x12, 0x00])
+            .unwrap();
+        let mut script = load(
+            r#"
+            poke(0x201, 7);
+            breakpoint(0x206, "v0 == 7");
+            press(5);
+            fn on_frame(frame) {
+                text(0, 0, "frame " + frame);
+                if frame == 1 { quit(); }
+            }
+            fn on_execute(pc, opcode) {
+                this.executed = (this.executed ?? 0) + 1;
+            }
+            fn on_write(address, value) {
+                this.written = address;
+                set_reg(1, value);
+            }
+            fn on_breakpoint(pc) {
+                set_reg(2, this.executed);
+                pause();
+            }
+            "#,
+            &mut chip8,
+        )
+        .unwrap();
+        assert!(script.steps() && script.tracks_writes());
+        chip8.set_access_tracking(true);
+
+        // the frame stops before the breakpoint, as emulate_frame does
+        chip8.start_frame(4);
+        while chip8.frame_pending() && !script.before_tick(&mut chip8).unwrap() {
+            chip8.tick().unwrap();
+            assert!(!script.after_tick(&mut chip8).unwrap());
+        }
+        script.end_frame(&mut chip8).unwrap();
+        assert_eq!(chip8.program_counter(), 0x206);
+        assert_eq!(chip8.register(1), 7);
+        assert_eq!(chip8.register(2), 3);
+        assert!(chip8.is_key_down(5));
+        assert!(script.take_pause() && !script.take_pause());
+        // resuming runs the instruction of the breakpoint
+        assert!(!script.before_tick(&mut chip8).unwrap());
+        chip8.tick().unwrap();
+        assert_eq!(chip8.program_counter(), 0x200);
+        assert!(!script.quit());
+        assert_eq!(script.state.borrow().overlay[0].2, "frame 0");
+        script.end_frame(&mut chip8).unwrap();
+        assert!(script.quit());
+
+        let e = script
+            .call(&mut chip8, "on_write", (0_i64, 256_i64))
+            .unwrap_err();
+        assert!(e.contains("256 does not fit in a byte"), "{}", e);
+        assert!(script.failed());
+        // without on_breakpoint, breakpoints pause the emulator
+        let mut script = load("breakpoint(0x202);", &mut chip8).unwrap();
+        chip8.set_program_counter(0x200);
+        assert!(!script.before_tick(&mut chip8).unwrap());
+        chip8.tick().unwrap();
+        assert!(script.before_tick(&mut chip8).unwrap());
+        assert_eq!(chip8.program_counter(), 0x202);
+        assert!(script.take_pause());
+
+        assert!(load("fn on_frame() {}", &mut chip8).is_err());
+        assert!(load("peek(4096);", &mut chip8).is_err());
+    }
+}
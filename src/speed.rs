@@ -0,0 +1,206 @@
+use chip8::Timing;
+use std::time;
+
+const FRAME_RATE: f64 = 60.;
+// Available slow motion factors, cycled through with the slow motion hotkey.
+const SLOW_MOTION: [f64; 3] = [1., 0.5, 0.25];
+
+// How many frames the emulator has to run before the next window update.
+pub enum Frames {
+    Exact(u32),
+    // as many frames as possible until the next window update
+    Uncapped,
+}
+
+// Emulation speed, driven by the hotkeys of the window.
+#[derive(Debug)]
+pub struct Speed {
+    // instructions per frame, with the fixed timing
+    ipf: u32,
+    timing: Timing,
+    paused: bool,
+    // frame advance requested while paused
+    step: bool,
+    fast_forward: bool,
+    // fast-forward multiplier, 0 is uncapped
+    fast_forward_factor: u32,
+    slow_motion: usize,
+}
+
+impl Speed {
+    pub fn new(speed: u32, fast_forward_factor: u32, timing: Timing) -> Speed {
+        Speed {
+            ipf: ((speed as f64 / FRAME_RATE).round() as u32).max(1),
+            timing,
+            paused: false,
+            step: false,
+            fast_forward: false,
+            fast_forward_factor,
+            slow_motion: 0,
+        }
+    }
+
+    pub fn ipf(&self) -> u32 {
+        self.ipf
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.paused
+    }
+
+    pub fn pause(&mut self) {
+        self.paused = true;
+    }
+
+    pub fn toggle_pause(&mut self) {
+        self.paused = !self.paused;
+    }
+
+    // Run exactly one frame, only while paused.
+    pub fn step(&mut self) {
+        if self.paused {
+            self.step = true;
+        }
+    }
+
+    pub fn set_fast_forward(&mut self, enabled: bool) {
+        self.fast_forward = enabled;
+    }
+
+    pub fn cycle_slow_motion(&mut self) {
+        self.slow_motion = (self.slow_motion + 1) % SLOW_MOTION.len();
+    }
+
+    pub fn faster(&mut self) {
+        self.ipf += 1;
+    }
+
+    pub fn slower(&mut self) {
+        self.ipf = (self.ipf - 1).max(1);
+    }
+
+    pub fn frames(&mut self) -> Frames {
+        if self.paused {
+            let step = std::mem::take(&mut self.step);
+            Frames::Exact(step as u32)
+        } else if self.fast_forward {
+            match self.fast_forward_factor {
+                0 => Frames::Uncapped,
+                factor => Frames::Exact(factor),
+            }
+        } else {
+            Frames::Exact(1)
+        }
+    }
+
+    // Wall-clock time between two window updates.
+    pub fn frame_duration(&self) -> time::Duration {
+        let factor = if self.fast_forward || self.paused {
+            1.
+        } else {
+            SLOW_MOTION[self.slow_motion]
+        };
+        time::Duration::from_secs_f64(1. / (FRAME_RATE * factor))
+    }
+
+    // Human readable emulation rate, e.g. "0.5x, 8 ipf" or "1x, cosmac-vip timing".
+    pub fn label(&self) -> String {
+        let rate = if self.paused {
+            "paused".to_string()
+        } else if self.fast_forward {
+            match self.fast_forward_factor {
+                0 => "fast-forward".to_string(),
+                factor => format!("{}x", factor),
+            }
+        } else {
+            format!("{}x", SLOW_MOTION[self.slow_motion])
+        };
+        match self.timing {
+            Timing::Fixed => format!("{}, {} ipf", rate, self.ipf),
+            timing => format!("{}, {} timing", rate, timing.name()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn frames(speed: &mut Speed) -> Option<u32> {
+        match speed.frames() {
+            Frames::Exact(n) => Some(n),
+            Frames::Uncapped => None,
+        }
+    }
+
+    // window updates per second
+    fn rate(speed: &Speed) -> f64 {
+        (1. / speed.frame_duration().as_secs_f64()).round()
+    }
+
+    #[test]
+    fn ipf() {
+        // rounded to whole instructions per frame, at least 1
+        assert_eq!(Speed::new(500, 0, Timing::Fixed).ipf(), 8);
+        assert_eq!(Speed::new(540, 0, Timing::Fixed).ipf(), 9);
+        assert_eq!(Speed::new(600, 0, Timing::Fixed).ipf(), 10);
+        assert_eq!(Speed::new(1, 0, Timing::Fixed).ipf(), 1);
+
+        let mut speed = Speed::new(500, 0, Timing::Fixed);
+        speed.faster();
+        assert_eq!(speed.ipf(), 9);
+        for _ in 0..20 {
+            speed.slower();
+        }
+        assert_eq!(speed.ipf(), 1);
+        assert_eq!(speed.label(), "1x, 1 ipf");
+        assert_eq!(
+            Speed::new(500, 0, Timing::CosmacVip).label(),
+            "1x, cosmac-vip timing"
+        );
+    }
+
+    #[test]
+    fn pause_and_step() {
+        let mut speed = Speed::new(600, 0, Timing::Fixed);
+        // stepping only works while paused
+        speed.step();
+        assert_eq!(frames(&mut speed), Some(1));
+        speed.toggle_pause();
+        assert!(speed.is_paused());
+        assert_eq!(frames(&mut speed), Some(0));
+        speed.step();
+        assert_eq!(frames(&mut speed), Some(1));
+        assert_eq!(frames(&mut speed), Some(0));
+        assert_eq!(speed.label(), "paused, 10 ipf");
+        speed.toggle_pause();
+        assert_eq!(frames(&mut speed), Some(1));
+    }
+
+    #[test]
+    fn fast_forward_and_slow_motion() {
+        let mut speed = Speed::new(600, 4, Timing::Fixed);
+        speed.set_fast_forward(true);
+        assert_eq!(frames(&mut speed), Some(4));
+        assert_eq!(speed.label(), "4x, 10 ipf");
+        speed.set_fast_forward(false);
+
+        speed.cycle_slow_motion();
+        assert_eq!(frames(&mut speed), Some(1));
+        assert_eq!(rate(&speed), 30.);
+        assert_eq!(speed.label(), "0.5x, 10 ipf");
+        speed.cycle_slow_motion();
+        assert_eq!(rate(&speed), 15.);
+        // fast-forward ignores the slow motion
+        speed.set_fast_forward(true);
+        assert_eq!(rate(&speed), 60.);
+        speed.set_fast_forward(false);
+        speed.cycle_slow_motion();
+        assert_eq!(speed.label(), "1x, 10 ipf");
+
+        let mut uncapped = Speed::new(600, 0, Timing::Fixed);
+        uncapped.set_fast_forward(true);
+        assert_eq!(frames(&mut uncapped), None);
+        assert_eq!(uncapped.label(), "fast-forward, 10 ipf");
+    }
+}
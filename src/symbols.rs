@@ -0,0 +1,69 @@
+// Labels of ROM addresses, read from a symbol file with one "ADDRESS LABEL" pair per line:
+//   0x200 main
+//   0x2A4 draw_player
+// Empty lines and lines starting with '#' are ignored.
+use crate::trace;
+use std::collections::BTreeMap;
+use std::path::Path;
+
+#[derive(Debug, Clone, Default)]
+pub struct Symbols {
+    labels: BTreeMap<u16, String>,
+}
+
+impl Symbols {
+    pub fn load(path: &Path) -> Result<Symbols, String> {
+        let text = std::fs::read_to_string(path)
+            .map_err(|e| format!("cannot read {}: {}", path.display(), e))?;
+        text.parse()
+            .map_err(|e| format!("invalid symbol file {}: {}", path.display(), e))
+    }
+
+    // Load the symbol file if one is given, no symbols otherwise.
+    pub fn load_optional(path: Option<&Path>) -> Result<Symbols, String> {
+        path.map_or_else(|| Ok(Symbols::default()), Symbols::load)
+    }
+
+    // Label of this exact address.
+    pub fn label(&self, address: u16) -> Option<&str> {
+        self.labels.get(&address).map(String::as_str)
+    }
+
+    // Label of a subroutine entry point, or its address.
+    pub fn name(&self, address: u16) -> String {
+        match self.label(address) {
+            Some(label) => label.to_string(),
+            None => format!("{:#05X}", address),
+        }
+    }
+
+    // An address relative to the closest label before it, e.g. "draw_player+0x6".
+    pub fn resolve(&self, address: u16) -> String {
+        match self.labels.range(..=address).next_back() {
+            Some((&start, label)) if start == address => label.clone(),
+            Some((&start, label)) => format!("{}+{:#X}", label, address - start),
+            None => format!("{:#05X}", address),
+        }
+    }
+}
+
+impl std::str::FromStr for Symbols {
+    type Err = String;
+
+    fn from_str(text: &str) -> Result<Symbols, String> {
+        let mut labels = BTreeMap::new();
+        for (n, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let (address, label) = line
+                .split_once(char::is_whitespace)
+                .ok_or_else(|| format!("line {}: expected ADDRESS LABEL", n + 1))?;
+            let address =
+                trace::parse_address(address).map_err(|e| format!("line {}: {}", n + 1, e))?;
+            labels.insert(address, label.trim().to_string());
+        }
+        Ok(Symbols { labels })
+    }
+}
@@ -0,0 +1,326 @@
+// Execution trace, one record per executed instruction, captured right before its execution.
+//
+// The text format writes one line per instruction:
+//   <cycle> <PC>: <opcode> <mnemonic> V:<V0..VF> I:<I> SP:<SP> DT:<DT> ST:<ST>
+//
+// The binary format starts with the "C8TR" magic and a version byte, followed by fixed size
+// little endian records of RECORD_SIZE bytes:
+//   cycle: u64, pc: u16, opcode: u16, V0..VF: [u8; 16], I: u16, SP: u8, DT: u8, ST: u8
+use chip8::{self, Chip8};
+use std::fs::File;
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::ops::RangeInclusive;
+use std::path::Path;
+
+const MAGIC: &[u8; 4] = b"C8TR";
+const VERSION: u8 = 1;
+const RECORD_SIZE: usize = 33;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum Format {
+    Text,
+    Binary,
+}
+
+#[derive(Debug, Clone)]
+pub struct Record {
+    pub cycle: u64,
+    pub pc: u16,
+    pub opcode: u16,
+    pub registers: [u8; 16],
+    pub index: u16,
+    pub sp: u8,
+    pub delay_timer: u8,
+    pub sound_timer: u8,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Filter {
+    // only trace instructions within this address range
+    pub range: Option<RangeInclusive<u16>>,
+    // only trace these opcode classes (first nibble of the opcode)
+    pub classes: Option<Vec<u8>>,
+    // only trace from this cycle on
+    pub after: u64,
+}
+
+pub struct Tracer {
+    out: BufWriter<File>,
+    format: Format,
+    filter: Filter,
+}
+
+impl Record {
+    // State of the machine before executing the instruction at PC.
+    pub fn capture(chip8: &Chip8) -> Option<Record> {
+        Some(Record {
+            cycle: chip8.cycles(),
+            pc: chip8.program_counter(),
+            opcode: chip8.opcode()?,
+            registers: chip8.registers(),
+            index: chip8.index(),
+            sp: chip8.stack_pointer() as u8,
+            delay_timer: chip8.delay_timer(),
+            sound_timer: chip8.sound_timer(),
+        })
+    }
+
+    pub fn to_text(&self) -> String {
+        let registers: Vec<String> = self
+            .registers
+            .iter()
+            .map(|v| format!("{:02X}", v))
+            .collect();
+        format!(
+            "{:>10} {:03X}: {:04X} {:<16} V:{} I:{:03X} SP:{:X} DT:{:02X} ST:{:02X}",
+            self.cycle,
+            self.pc,
+            self.opcode,
+            chip8::disassemble(self.opcode),
+            registers.join(" "),
+            self.index,
+            self.sp,
+            self.delay_timer,
+            self.sound_timer
+        )
+    }
+
+    fn to_bytes(&self) -> [u8; RECORD_SIZE] {
+        let mut bytes = [0; RECORD_SIZE];
+        bytes[0..8].copy_from_slice(&self.cycle.to_le_bytes());
+        bytes[8..10].copy_from_slice(&self.pc.to_le_bytes());
+        bytes[10..12].copy_from_slice(&self.opcode.to_le_bytes());
+        bytes[12..28].copy_from_slice(&self.registers);
+        bytes[28..30].copy_from_slice(&self.index.to_le_bytes());
+        bytes[30] = self.sp;
+        bytes[31] = self.delay_timer;
+        bytes[32] = self.sound_timer;
+        bytes
+    }
+
+    fn from_bytes(bytes: &[u8; RECORD_SIZE]) -> Record {
+        let u16_at = |i: usize| u16::from_le_bytes([bytes[i], bytes[i + 1]]);
+        let mut registers = [0; 16];
+        registers.copy_from_slice(&bytes[12..28]);
+        Record {
+            cycle: u64::from_le_bytes(bytes[0..8].try_into().unwrap()),
+            pc: u16_at(8),
+            opcode: u16_at(10),
+            registers,
+            index: u16_at(28),
+            sp: bytes[30],
+            delay_timer: bytes[31],
+            sound_timer: bytes[32],
+        }
+    }
+}
+
+impl Filter {
+    fn accepts(&self, record: &Record) -> bool {
+        record.cycle >= self.after
+            && self.range.as_ref().is_none_or(|r| r.contains(&record.pc))
+            && self
+                .classes
+                .as_ref()
+                .is_none_or(|c| c.contains(&((record.opcode >> 12) as u8)))
+    }
+}
+
+impl Tracer {
+    pub fn create(path: &Path, format: Format, filter: Filter) -> io::Result<Tracer> {
+        let mut out = BufWriter::new(File::create(path)?);
+        if format == Format::Binary {
+            out.write_all(MAGIC)?;
+            out.write_all(&[VERSION])?;
+        }
+        Ok(Tracer {
+            out,
+            format,
+            filter,
+        })
+    }
+
+    // Record the instruction about to be executed.
+    pub fn record(&mut self, chip8: &Chip8) -> io::Result<()> {
+        let record = match Record::capture(chip8) {
+            Some(record) if self.filter.accepts(&record) => record,
+            _ => return Ok(()),
+        };
+        match self.format {
+            Format::Text => writeln!(self.out, "{}", record.to_text()),
+            Format::Binary => self.out.write_all(&record.to_bytes()),
+        }
+    }
+
+    pub fn flush(&mut self) -> io::Result<()> {
+        self.out.flush()
+    }
+}
+
+// Read back a binary trace, one record at a time.
+pub fn read_binary(path: &Path) -> io::Result<Records<BufReader<File>>> {
+    Records::new(BufReader::new(File::open(path)?))
+}
+
+pub struct Records<R> {
+    input: R,
+    count: u64,
+}
+
+impl<R: Read> Records<R> {
+    pub fn new(mut input: R) -> io::Result<Records<R>> {
+        let mut header = [0; 5];
+        input.read_exact(&mut header)?;
+        if &header[0..4] != MAGIC || header[4] != VERSION {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "not a chip8 binary trace",
+            ));
+        }
+        Ok(Records { input, count: 0 })
+    }
+}
+
+impl<R: Read> Iterator for Records<R> {
+    type Item = io::Result<Record>;
+
+    fn next(&mut self) -> Option<io::Result<Record>> {
+        let mut bytes = [0; RECORD_SIZE];
+        let mut len = 0;
+        while len < RECORD_SIZE {
+            match self.input.read(&mut bytes[len..]) {
+                Ok(0) => break,
+                Ok(n) => len += n,
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+                Err(e) => return Some(Err(e)),
+            }
+        }
+        match len {
+            0 => None,
+            RECORD_SIZE => {
+                self.count += 1;
+                Some(Ok(Record::from_bytes(&bytes)))
+            }
+            // only a whole record ends the trace
+            _ => Some(Err(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                format!(
+                    "truncated record after {} records ({} of {} bytes)",
+                    self.count, len, RECORD_SIZE
+                ),
+            ))),
+        }
+    }
+}
+
+// Parse an address range such as "0x200-0x2FF".
+pub fn parse_range(range: &str) -> Result<RangeInclusive<u16>, String> {
+    let (start, end) = range
+        .split_once('-')
+        .ok_or_else(|| format!("invalid range '{}', expected START-END", range))?;
+    Ok(parse_address(start)?..=parse_address(end)?)
+}
+
+pub fn parse_address(address: &str) -> Result<u16, String> {
+    let address = address.trim();
+    let hex = address
+        .strip_prefix("0x")
+        .or_else(|| address.strip_prefix("0X"))
+        .unwrap_or(address);
+    u16::from_str_radix(hex, 16).map_err(|_| format!("invalid address '{}'", address))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // V0 = 5, then V0 += 1 and I = 0x300 in a loop
+    const ROM: [u8; 8] = [0x60, 0x05, 0x70, 0x01, 0xA3, 0x00, 0x12, 0x02];
+
+    fn trace(name: &str, format: Format, filter: Filter, cycles: usize) -> std::path::PathBuf {
+        let path = std::env::temp_dir().join(format!("chip8-{}-{}", name, std::process::id()));
+        let mut tracer = Tracer::create(&path, format, filter).unwrap();
+        let mut chip8 = chip8::init();
+        chip8.load_rom(ROM.to_vec()).unwrap();
+        for _ in 0..cycles {
+            tracer.record(&chip8).unwrap();
+            chip8.tick().unwrap();
+        }
+        tracer.flush().unwrap();
+        path
+    }
+
+    fn records(path: &Path) -> Vec<Record> {
+        read_binary(path).unwrap().map(Result::unwrap).collect()
+    }
+
+    #[test]
+    fn text_and_binary() {
+        let text = trace("round-trip.log", Format::Text, Filter::default(), 10);
+        let binary = trace("round-trip.bin", Format::Binary, Filter::default(), 10);
+        let lines: Vec<String> = std::fs::read_to_string(&text)
+            .unwrap()
+            .lines()
+            .map(str::to_string)
+            .collect();
+        let records = records(&binary);
+        assert_eq!(lines.len(), 10);
+        assert_eq!(
+            records.iter().map(Record::to_text).collect::<Vec<_>>(),
+            lines
+        );
+        assert!(lines[1].starts_with("         1 202: 7001 ADD V0, 0x01"));
+        assert!(lines[1].contains(" V:05 00 "));
+
+        // a record cut short is an error, not the end of the trace
+        let mut bytes = std::fs::read(&binary).unwrap();
+        bytes.truncate(bytes.len() - 1);
+        let mut truncated = Records::new(bytes.as_slice()).unwrap();
+        assert_eq!(truncated.by_ref().take(9).count(), 9);
+        let error = truncated.next().unwrap().unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::UnexpectedEof);
+        assert!(Records::new(&b"C8TR\x02"[..]).is_err());
+        std::fs::remove_file(text).unwrap();
+        std::fs::remove_file(binary).unwrap();
+    }
+
+    #[test]
+    fn filters() {
+        let filtered = |name, filter| {
+            let path = trace(name, Format::Binary, filter, 20);
+            let records = records(&path);
+            std::fs::remove_file(path).unwrap();
+            records
+        };
+
+        let records = filtered(
+            "range.bin",
+            Filter {
+                range: Some(0x204..=0x206),
+                ..Filter::default()
+            },
+        );
+        assert!(!records.is_empty());
+        assert!(records.iter().all(|r| r.pc == 0x204 || r.pc == 0x206));
+
+        let records = filtered(
+            "classes.bin",
+            Filter {
+                classes: Some(vec![0x7, 0x1]),
+                ..Filter::default()
+            },
+        );
+        assert!(!records.is_empty());
+        assert!(records.iter().all(|r| matches!(r.opcode, 0x7001 | 0x1202)));
+
+        let records = filtered(
+            "after.bin",
+            Filter {
+                after: 15,
+                ..Filter::default()
+            },
+        );
+        let cycles: Vec<u64> = records.iter().map(|r| r.cycle).collect();
+        assert_eq!(cycles, (15..20).collect::<Vec<_>>());
+    }
+}
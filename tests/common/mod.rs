@@ -0,0 +1,252 @@
+// Helpers shared by the integration tests: an assembler for the mnemonics printed by
+// `chip8::disassemble`, a headless runner and the expected screens of the test ROMs.
+#![allow(dead_code)]
+
+use chip8::{Chip8, Quirks, HEIGHT, START_ROM, WIDTH};
+use std::collections::HashMap;
+use std::path::PathBuf;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Operand {
+    V(u16),
+    I,
+    // [I]
+    Memory,
+    Dt,
+    St,
+    K,
+    F,
+    B,
+    Value(u16),
+}
+
+// Assemble a program loaded at START_ROM. Besides the instructions, a line may hold a
+// `label:` and `db` data bytes; comments start with ';'.
+pub fn assemble(source: &str) -> Result<Vec<u8>, String> {
+    let mut labels = HashMap::new();
+    // the first pass only needs the sizes, to find the label addresses
+    for pass in 0..2 {
+        let mut rom = Vec::new();
+        for (n, line) in source.lines().enumerate() {
+            let error = |e: String| format!("line {}: {}", n + 1, e);
+            let mut line = line.split(';').next().unwrap().trim();
+            if let Some((label, rest)) = line.split_once(':') {
+                if pass == 0 {
+                    let address = (START_ROM + rom.len()) as u16;
+                    if labels.insert(label.trim().to_string(), address).is_some() {
+                        return Err(error(format!("duplicate label '{}'", label)));
+                    }
+                }
+                line = rest.trim();
+            }
+            if line.is_empty() {
+                continue;
+            }
+            let (mnemonic, operands) = line.split_once(char::is_whitespace).unwrap_or((line, ""));
+            let operands: Vec<Operand> = operands
+                .split(',')
+                .map(str::trim)
+                .filter(|o| !o.is_empty())
+                .map(|o| operand(o, &labels, pass == 0))
+                .collect::<Result<_, _>>()
+                .map_err(error)?;
+            if mnemonic.eq_ignore_ascii_case("db") {
+                for operand in operands {
+                    match operand {
+                        Operand::Value(byte) if byte <= 0xFF => rom.push(byte as u8),
+                        _ => return Err(error("invalid byte".to_string())),
+                    }
+                }
+            } else {
+                let opcode = encode(&mnemonic.to_ascii_uppercase(), &operands).map_err(error)?;
+                rom.extend(opcode.to_be_bytes());
+            }
+        }
+        if pass == 1 {
+            return Ok(rom);
+        }
+    }
+    unreachable!()
+}
+
+fn operand(text: &str, labels: &HashMap<String, u16>, first_pass: bool) -> Result<Operand, String> {
+    let upper = text.to_ascii_uppercase();
+    Ok(match upper.as_str() {
+        "I" => Operand::I,
+        "[I]" => Operand::Memory,
+        "DT" => Operand::Dt,
+        "ST" => Operand::St,
+        "K" => Operand::K,
+        "F" => Operand::F,
+        "B" => Operand::B,
+        _ if upper.len() == 2 && upper.starts_with('V') => Operand::V(
+            u16::from_str_radix(&upper[1..], 16)
+                .map_err(|_| format!("invalid register '{}'", text))?,
+        ),
+        _ => {
+            let number = if let Some(hex) = upper.strip_prefix("0X") {
+                u16::from_str_radix(hex, 16).ok()
+            } else if let Some(binary) = upper.strip_prefix("0B") {
+                u16::from_str_radix(&binary.replace('_', ""), 2).ok()
+            } else {
+                upper.parse().ok()
+            };
+            match number.or_else(|| labels.get(text).copied()) {
+                Some(value) => Operand::Value(value),
+                // labels defined further down are not known yet
+                None if first_pass => Operand::Value(0),
+                None => return Err(format!("unknown label '{}'", text)),
+            }
+        }
+    })
+}
+
+fn encode(mnemonic: &str, operands: &[Operand]) -> Result<u16, String> {
+    use Operand::*;
+    let xy = |x: u16, y: u16, n: u16| x << 8 | y << 4 | n;
+    let check = |value: u16, max: u16| {
+        if value <= max {
+            Ok(value)
+        } else {
+            Err(format!("value {:#X} out of range", value))
+        }
+    };
+    Ok(match (mnemonic, operands) {
+        ("CLS", []) => 0x00E0,
+        ("RET", []) => 0x00EE,
+        ("SYS", [Value(a)]) => check(*a, 0xFFF)?,
+        ("JP", [Value(a)]) => 0x1000 | check(*a, 0xFFF)?,
+        ("JP", [V(0), Value(a)]) => 0xB000 | check(*a, 0xFFF)?,
+        ("CALL", [Value(a)]) => 0x2000 | check(*a, 0xFFF)?,
+        ("SE", [V(x), Value(nn)]) => 0x3000 | x << 8 | check(*nn, 0xFF)?,
+        ("SNE", [V(x), Value(nn)]) => 0x4000 | x << 8 | check(*nn, 0xFF)?,
+        ("SE", [V(x), V(y)]) => 0x5000 | xy(*x, *y, 0),
+        ("LD", [V(x), Value(nn)]) => 0x6000 | x << 8 | check(*nn, 0xFF)?,
+        ("ADD", [V(x), Value(nn)]) => 0x7000 | x << 8 | check(*nn, 0xFF)?,
+        ("LD", [V(x), V(y)]) => 0x8000 | xy(*x, *y, 0),
+        ("OR", [V(x), V(y)]) => 0x8000 | xy(*x, *y, 1),
+        ("AND", [V(x), V(y)]) => 0x8000 | xy(*x, *y, 2),
+        ("XOR", [V(x), V(y)]) => 0x8000 | xy(*x, *y, 3),
+        ("ADD", [V(x), V(y)]) => 0x8000 | xy(*x, *y, 4),
+        ("SUB", [V(x), V(y)]) => 0x8000 | xy(*x, *y, 5),
+        ("SHR", [V(x), V(y)]) => 0x8000 | xy(*x, *y, 6),
+        ("SHR", [V(x)]) => 0x8000 | xy(*x, *x, 6),
+        ("SUBN", [V(x), V(y)]) => 0x8000 | xy(*x, *y, 7),
+        ("SHL", [V(x), V(y)]) => 0x8000 | xy(*x, *y, 0xE),
+        ("SHL", [V(x)]) => 0x8000 | xy(*x, *x, 0xE),
+        ("SNE", [V(x), V(y)]) => 0x9000 | xy(*x, *y, 0),
+        ("LD", [I, Value(a)]) => 0xA000 | check(*a, 0xFFF)?,
+        ("RND", [V(x), Value(nn)]) => 0xC000 | x << 8 | check(*nn, 0xFF)?,
+        ("DRW", [V(x), V(y), Value(n)]) => 0xD000 | xy(*x, *y, check(*n, 0xF)?),
+        ("SKP", [V(x)]) => 0xE09E | x << 8,
+        ("SKNP", [V(x)]) => 0xE0A1 | x << 8,
+        ("LD", [V(x), Dt]) => 0xF007 | x << 8,
+        ("LD", [V(x), K]) => 0xF00A | x << 8,
+        ("LD", [Dt, V(x)]) => 0xF015 | x << 8,
+        ("LD", [St, V(x)]) => 0xF018 | x << 8,
+        ("ADD", [I, V(x)]) => 0xF01E | x << 8,
+        ("LD", [F, V(x)]) => 0xF029 | x << 8,
+        ("LD", [B, V(x)]) => 0xF033 | x << 8,
+        ("LD", [Memory, V(x)]) => 0xF055 | x << 8,
+        ("LD", [V(x), Memory]) => 0xF065 | x << 8,
+        ("DW", [Value(word)]) => *word,
+        _ => return Err(format!("invalid instruction {} {:?}", mnemonic, operands)),
+    })
+}
+
+// Test ROM sources, in tests/roms/. `check.asm` is appended to every program.
+pub fn rom(name: &str) -> Vec<u8> {
+    let dir = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests/roms");
+    let read = |file: &str| std::fs::read_to_string(dir.join(file)).unwrap();
+    let source = read(&format!("{}.asm", name)) + &read("check.asm");
+    assemble(&source).unwrap_or_else(|e| panic!("{}.asm: {}", name, e))
+}
+
+// Run a ROM headless for the given number of frames, with the keys held on each frame.
+pub fn run(rom: &[u8], quirks: Quirks, frames: u32, keys: impl Fn(u32) -> Vec<usize>) -> Chip8 {
+    let mut chip8 = chip8::init();
+    chip8.set_quirks(quirks);
+    chip8.load_rom(rom.to_vec()).unwrap();
+    for frame in 0..frames {
+        chip8.reset_keypad();
+        for key in keys(frame) {
+            chip8.press_key(key);
+        }
+        chip8.run_frame(IPF).unwrap();
+    }
+    chip8
+}
+
+// Instructions per frame of the headless runs.
+pub const IPF: u32 = 20;
+
+// The screen as text, one line per row, '#' for lit pixels.
+pub fn screen(chip8: &Chip8) -> String {
+    let mut text = String::new();
+    for y in 0..HEIGHT {
+        for x in 0..WIDTH {
+            text.push(if chip8.gfx(x + y * WIDTH) { '#' } else { '.' });
+        }
+        text.push('\n');
+    }
+    text
+}
+
+// A screen drawn by the tests, independently of the emulator.
+pub struct Screen([[bool; WIDTH]; HEIGHT]);
+
+// Glyphs of the marks drawn by check.asm, and of the font.
+pub const CHECK_OK: [u8; 4] = [0b00001000, 0b00010000, 0b10100000, 0b01000000];
+pub const CHECK_FAIL: [u8; 4] = [0b10100000, 0b01000000, 0b10100000, 0b00000000];
+pub const FONT_7: [u8; 5] = [0xF0, 0x10, 0x20, 0x40, 0x40];
+pub const FONT_8: [u8; 5] = [0xF0, 0x90, 0xF0, 0x90, 0xF0];
+pub const FONT_C: [u8; 5] = [0xF0, 0x80, 0x80, 0x80, 0xF0];
+
+impl Screen {
+    pub fn new() -> Screen {
+        Screen([[false; WIDTH]; HEIGHT])
+    }
+
+    // XOR a sprite, wrapping around the edges.
+    pub fn draw(&mut self, x: usize, y: usize, sprite: &[u8]) {
+        for (i, row) in sprite.iter().enumerate() {
+            for j in 0..8 {
+                if row & 0x80 >> j != 0 {
+                    let pixel = &mut self.0[(y + i) % HEIGHT][(x + j) % WIDTH];
+                    *pixel = !*pixel;
+                }
+            }
+        }
+    }
+
+    // The marks of check.asm for the given results, from the cell at (x, y) on.
+    pub fn marks(&mut self, mut x: usize, mut y: usize, results: &[bool]) {
+        for &ok in results {
+            self.draw(x, y, if ok { &CHECK_OK } else { &CHECK_FAIL });
+            x += 6;
+            if x == 60 {
+                x = 0;
+                y += 6;
+            }
+        }
+    }
+
+    pub fn text(&self) -> String {
+        let mut text = String::new();
+        for row in &self.0 {
+            text.extend(row.iter().map(|&lit| if lit { '#' } else { '.' }));
+            text.push('\n');
+        }
+        text
+    }
+}
+
+pub fn assert_screen(chip8: &Chip8, expected: &Screen) {
+    let (actual, expected) = (screen(chip8), expected.text());
+    assert!(
+        actual == expected,
+        "screen:\n{}\nexpected:\n{}",
+        actual,
+        expected
+    );
+}
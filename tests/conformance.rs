@@ -0,0 +1,132 @@
+// Conformance ROMs run headless, their screen compared with the screen they are expected to
+// draw, built by the tests from the layout of the ROMs rather than recorded from the emulator.
+//
+// The ROMs, in tests/roms/, are small self-checking programs modelled on Timendus'
+// chip8-test-suite, whose own ROMs are run by tests/suite.rs.
+mod common;
+
+use chip8::Quirks;
+use common::{assemble, assert_screen, rom, run, Screen, FONT_7, FONT_8, FONT_C};
+
+fn no_keys(_: u32) -> Vec<usize> {
+    Vec::new()
+}
+
+// Number of checks, i.e. of marks drawn, of a ROM.
+fn checks(name: &str) -> usize {
+    let path = format!("{}/tests/roms/{}.asm", env!("CARGO_MANIFEST_DIR"), name);
+    let source = std::fs::read_to_string(path).unwrap();
+    source
+        .lines()
+        .filter(|line| line.trim() == "CALL check")
+        .count()
+}
+
+// Every check of the ROM passed.
+fn all_passed(name: &str) -> Screen {
+    let mut screen = Screen::new();
+    screen.marks(0, 0, &vec![true; checks(name)]);
+    screen
+}
+
+// The quirks ROM draws a check mark for each quirk which is on.
+fn quirks_detected(quirks: Quirks) -> Screen {
+    let mut screen = Screen::new();
+    let detected = [
+        quirks.shift,
+        quirks.memory_increment,
+        quirks.jump,
+        quirks.vf_reset,
+        quirks.clip,
+    ];
+    screen.marks(0, 0, &detected);
+    screen
+}
+
+#[test]
+fn logo() {
+    let chip8 = run(&rom("logo"), Quirks::default(), 10, no_keys);
+    // a frame around the screen, and "C8" in the middle
+    let mut expected = Screen::new();
+    for x in (0..64).step_by(8) {
+        expected.draw(x, 0, &[0xFF]);
+        expected.draw(x, 31, &[0xFF]);
+    }
+    expected.draw(0, 1, &[0x80; 30]);
+    expected.draw(63, 1, &[0x80; 30]);
+    expected.draw(26, 13, &FONT_C);
+    expected.draw(32, 13, &FONT_8);
+    assert_screen(&chip8, &expected);
+}
+
+#[test]
+fn opcodes() {
+    let chip8 = run(&rom("opcodes"), Quirks::default(), 30, no_keys);
+    assert_screen(&chip8, &all_passed("opcodes"));
+}
+
+#[test]
+fn flags() {
+    let chip8 = run(&rom("flags"), Quirks::default(), 30, no_keys);
+    assert_screen(&chip8, &all_passed("flags"));
+}
+
+#[test]
+fn quirks_default() {
+    let chip8 = run(&rom("quirks"), Quirks::default(), 10, no_keys);
+    assert_screen(&chip8, &quirks_detected(Quirks::default()));
+}
+
+#[test]
+fn quirks_cosmac_vip() {
+    let quirks = Quirks {
+        shift: false,
+        memory_increment: true,
+        jump: false,
+        vf_reset: true,
+        clip: true,
+    };
+    let chip8 = run(&rom("quirks"), quirks, 10, no_keys);
+    assert_screen(&chip8, &quirks_detected(quirks));
+}
+
+#[test]
+fn quirks_all_off() {
+    let quirks = Quirks {
+        shift: false,
+        memory_increment: false,
+        jump: false,
+        vf_reset: false,
+        clip: false,
+    };
+    let chip8 = run(&rom("quirks"), quirks, 10, no_keys);
+    assert_screen(&chip8, &quirks_detected(quirks));
+}
+
+#[test]
+fn keypad() {
+    // key 7 is held from frame 5 to frame 29
+    let keys = |frame| match frame {
+        5..=29 => vec![7],
+        _ => Vec::new(),
+    };
+    let chip8 = run(&rom("keypad"), Quirks::default(), 60, keys);
+    // the key waited for, then the marks of the checks
+    let mut expected = Screen::new();
+    expected.draw(0, 0, &FONT_7);
+    expected.marks(8, 0, &vec![true; checks("keypad")]);
+    assert_screen(&chip8, &expected);
+}
+
+// The test assembler reads back what the disassembler prints.
+#[test]
+fn disassembly_round_trip() {
+    for opcode in 0..=0xFFFF {
+        let text = chip8::disassemble(opcode);
+        if text.starts_with("DW") {
+            continue;
+        }
+        let rom = assemble(&text).unwrap_or_else(|e| panic!("{}: {}", text, e));
+        assert_eq!(rom, opcode.to_be_bytes(), "{}", text);
+    }
+}
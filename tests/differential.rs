@@ -0,0 +1,96 @@
+// The core against the reference model, on random programs. The fuzz targets of fuzz/ do the
+// same with coverage guided inputs.
+use chip8::{compare_with_reference, Quirks, START_ROM};
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+
+const PROGRAMS: u64 = 1000;
+const INSTRUCTIONS: usize = 128;
+const FRAMES: usize = 10;
+const IPF: u32 = 20;
+
+// Random valid instructions, whose jumps stay within the program.
+fn program(rng: &mut StdRng) -> Vec<u8> {
+    let end = START_ROM as u16 + 2 * INSTRUCTIONS as u16;
+    let mut rom = Vec::new();
+    for _ in 0..INSTRUCTIONS {
+        let mut opcode: u16 = match rng.gen_range(0..100) {
+            0 => 0x00E0,
+            1..=3 => 0x00EE,
+            _ => loop {
+                let opcode = rng.gen();
+                let text = chip8::disassemble(opcode);
+                if !text.starts_with("DW") && !text.starts_with("SYS") {
+                    break opcode;
+                }
+            },
+        };
+        if matches!(opcode >> 12, 0x1 | 0x2 | 0xB) {
+            let target = rng.gen_range(START_ROM as u16..end) & !1;
+            opcode = opcode & 0xF000 | target;
+        }
+        // I somewhere in the program or the font, sometimes near the end of the memory
+        if opcode >> 12 == 0xA && rng.gen_bool(0.9) {
+            opcode = 0xA000 | rng.gen_range(0x050..end);
+        }
+        rom.extend(opcode.to_be_bytes());
+    }
+    rom
+}
+
+fn quirks(bits: u8) -> Quirks {
+    Quirks {
+        shift: bits & 1 != 0,
+        memory_increment: bits & 2 != 0,
+        jump: bits & 4 != 0,
+        vf_reset: bits & 8 != 0,
+        clip: bits & 16 != 0,
+    }
+}
+
+#[test]
+fn random_programs() {
+    let mut rng = StdRng::seed_from_u64(0x0C8);
+    for n in 0..PROGRAMS {
+        let rom = program(&mut rng);
+        let quirks = quirks(rng.gen());
+        let keys: Vec<u16> = (0..FRAMES)
+            .map(|_| if rng.gen_bool(0.3) { rng.gen() } else { 0 })
+            .collect();
+        if let Err(difference) = compare_with_reference(&rom, quirks, n, IPF, &keys) {
+            panic!("program {} with {:?}: {}", n, quirks, difference);
+        }
+    }
+}
+
+#[test]
+fn memory_bounds() {
+    // I = 0xFFE, then instructions accessing up to 0xFFF or 0x1000
+    for (opcode, fits) in [
+        (0xF033, false),
+        (0xD012, true),
+        (0xD013, false),
+        (0xF155, true),
+        (0xF255, false),
+        (0xF165, true),
+        (0xF265, false),
+    ] {
+        let mut rom = vec![0xAF, 0xFE];
+        rom.extend(u16::to_be_bytes(opcode));
+        let mut chip8 = chip8::init();
+        chip8.load_rom(rom.clone()).unwrap();
+        chip8.tick().unwrap();
+        let result = chip8.tick();
+        assert_eq!(result.is_ok(), fits, "{:04X}: {:?}", opcode, result);
+        if !fits {
+            assert_eq!(
+                result,
+                Err(chip8::Error::MemoryOutOfBounds {
+                    pc: 0x202,
+                    address: chip8::MEM_SIZE
+                })
+            );
+        }
+        compare_with_reference(&rom, Quirks::default(), 0, 2, &[0]).unwrap();
+    }
+}
@@ -0,0 +1,18 @@
+
+; Draw a check mark when VC is 1, a cross otherwise, in the next cell of a grid of
+; 10 columns. VE and VD hold the position of the cell.
+check:
+  LD I, check_ok
+  SE VC, 1
+  LD I, check_fail
+  DRW VE, VD, 4
+  ADD VE, 6
+  SE VE, 60
+  RET
+  LD VE, 0
+  ADD VD, 6
+  RET
+check_ok:
+  db 0b00001000, 0b00010000, 0b10100000, 0b01000000
+check_fail:
+  db 0b10100000, 0b01000000, 0b10100000, 0b00000000
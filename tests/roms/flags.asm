@@ -0,0 +1,217 @@
+; VF flag conformance, in the spirit of Timendus' flags test: each check verifies the
+; result in V0 and the flag in VF, and draws a mark, see check.asm.
+  CLS
+  LD VD, 0
+  LD VE, 0
+
+; 8XY4 without carry
+  LD V0, 0x10
+  LD V1, 0x20
+  LD VF, 0x55
+  ADD V0, V1
+  LD VC, 0
+  SE V0, 0x30
+  JP add1
+  SNE VF, 0
+  LD VC, 1
+add1:
+  CALL check
+
+; 8XY4 with carry
+  LD V0, 0xFF
+  LD V1, 0x01
+  LD VF, 0x55
+  ADD V0, V1
+  LD VC, 0
+  SE V0, 0x00
+  JP add2
+  SNE VF, 1
+  LD VC, 1
+add2:
+  CALL check
+
+; 8XY4 with the largest carry
+  LD V0, 0xFF
+  LD V1, 0xFF
+  LD VF, 0x55
+  ADD V0, V1
+  LD VC, 0
+  SE V0, 0xFE
+  JP add3
+  SNE VF, 1
+  LD VC, 1
+add3:
+  CALL check
+
+; 8XY5 without borrow
+  LD V0, 0x30
+  LD V1, 0x10
+  LD VF, 0x55
+  SUB V0, V1
+  LD VC, 0
+  SE V0, 0x20
+  JP sub1
+  SNE VF, 1
+  LD VC, 1
+sub1:
+  CALL check
+
+; 8XY5 of equal values, without borrow
+  LD V0, 0x10
+  LD V1, 0x10
+  LD VF, 0x55
+  SUB V0, V1
+  LD VC, 0
+  SE V0, 0x00
+  JP sub2
+  SNE VF, 1
+  LD VC, 1
+sub2:
+  CALL check
+
+; 8XY5 with borrow
+  LD V0, 0x10
+  LD V1, 0x30
+  LD VF, 0x55
+  SUB V0, V1
+  LD VC, 0
+  SE V0, 0xE0
+  JP sub3
+  SNE VF, 0
+  LD VC, 1
+sub3:
+  CALL check
+
+; 8XY7 without borrow
+  LD V0, 0x10
+  LD V1, 0x30
+  LD VF, 0x55
+  SUBN V0, V1
+  LD VC, 0
+  SE V0, 0x20
+  JP subn1
+  SNE VF, 1
+  LD VC, 1
+subn1:
+  CALL check
+
+; 8XY7 with borrow
+  LD V0, 0x30
+  LD V1, 0x10
+  LD VF, 0x55
+  SUBN V0, V1
+  LD VC, 0
+  SE V0, 0xE0
+  JP subn2
+  SNE VF, 0
+  LD VC, 1
+subn2:
+  CALL check
+
+; 8XY6 shifting out a 1
+  LD V0, 0x05
+  LD VF, 0x55
+  SHR V0
+  LD VC, 0
+  SE V0, 0x02
+  JP shr1
+  SNE VF, 1
+  LD VC, 1
+shr1:
+  CALL check
+
+; 8XY6 shifting out a 0
+  LD V0, 0x04
+  LD VF, 0x55
+  SHR V0
+  LD VC, 0
+  SE V0, 0x02
+  JP shr2
+  SNE VF, 0
+  LD VC, 1
+shr2:
+  CALL check
+
+; 8XYE shifting out a 1
+  LD V0, 0x81
+  LD VF, 0x55
+  SHL V0
+  LD VC, 0
+  SE V0, 0x02
+  JP shl1
+  SNE VF, 1
+  LD VC, 1
+shl1:
+  CALL check
+
+; 8XYE shifting out a 0
+  LD V0, 0x41
+  LD VF, 0x55
+  SHL V0
+  LD VC, 0
+  SE V0, 0x82
+  JP shl2
+  SNE VF, 0
+  LD VC, 1
+shl2:
+  CALL check
+
+; 7XNN leaves VF alone
+  LD V0, 0xFF
+  LD VF, 0x55
+  ADD V0, 0x02
+  LD VC, 0
+  SE V0, 0x01
+  JP add_nn
+  SNE VF, 0x55
+  LD VC, 1
+add_nn:
+  CALL check
+
+; VF as the target of 8XY4, 8XY5, 8XY6 and 8XYE holds the flag, not the result
+  LD VF, 0xF0
+  LD V1, 0x20
+  ADD VF, V1
+  LD VC, 0
+  SNE VF, 1
+  LD VC, 1
+  CALL check
+
+  LD VF, 0x10
+  LD V1, 0x20
+  SUB VF, V1
+  LD VC, 0
+  SNE VF, 0
+  LD VC, 1
+  CALL check
+
+  LD VF, 0x02
+  LD V1, 0x02
+  SHR VF, V1
+  LD VC, 0
+  SNE VF, 0
+  LD VC, 1
+  CALL check
+
+  LD VF, 0x40
+  LD V1, 0x40
+  SHL VF, V1
+  LD VC, 0
+  SNE VF, 0
+  LD VC, 1
+  CALL check
+
+; VF as the operand of 8XY4 is read before the flag is written
+  LD V0, 0xF0
+  LD VF, 0x20
+  ADD V0, VF
+  LD VC, 0
+  SE V0, 0x10
+  JP add_vf
+  SNE VF, 1
+  LD VC, 1
+add_vf:
+  CALL check
+
+halt:
+  JP halt
@@ -0,0 +1,43 @@
+; Keypad conformance: wait for a key with FX0A and draw it, then check EX9E and EXA1
+; while it is held and once it is released, see check.asm.
+  CLS
+  LD V0, K
+  LD F, V0
+  LD V1, 0
+  LD V2, 0
+  DRW V1, V2, 5
+  LD VD, 0
+  LD VE, 8
+
+; EX9E skips while the key is held
+  LD VC, 0
+  SKP V0
+  JP held
+  LD VC, 1
+held:
+  CALL check
+
+; EXA1 skips for a key which is not held
+  LD V3, 0x3
+  LD VC, 0
+  SKNP V3
+  JP not_held
+  LD VC, 1
+not_held:
+  CALL check
+
+; wait for the release
+release:
+  SKNP V0
+  JP release
+
+; EX9E does not skip once the key is released
+  LD VC, 1
+  SKP V0
+  JP released
+  LD VC, 0
+released:
+  CALL check
+
+halt:
+  JP halt
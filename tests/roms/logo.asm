@@ -0,0 +1,43 @@
+; Drawing test, in the spirit of Timendus' IBM logo test: a frame around the screen and
+; "C8" in the middle, drawn with 00E0, 6XNN, ANNN, 7XNN, 3XNN, 1NNN, FX29 and DXYN.
+  CLS
+  LD I, horizontal
+  LD V0, 0
+  LD V1, 0
+  LD V2, 31
+top_bottom:
+  DRW V0, V1, 1
+  DRW V0, V2, 1
+  ADD V0, 8
+  SE V0, 64
+  JP top_bottom
+
+  LD I, vertical
+  LD V0, 0
+  LD V1, 1
+  LD V2, 63
+sides:
+  DRW V0, V1, 15
+  DRW V2, V1, 15
+  ADD V1, 15
+  SE V1, 31
+  JP sides
+
+  LD V3, 26
+  LD V4, 13
+  LD V0, 0xC
+  LD F, V0
+  DRW V3, V4, 5
+  LD V3, 32
+  LD V0, 0x8
+  LD F, V0
+  DRW V3, V4, 5
+
+halt:
+  JP halt
+
+horizontal:
+  db 0xFF
+vertical:
+  db 0x80, 0x80, 0x80, 0x80, 0x80, 0x80, 0x80, 0x80
+  db 0x80, 0x80, 0x80, 0x80, 0x80, 0x80, 0x80
@@ -0,0 +1,232 @@
+; Opcode conformance, in the spirit of Timendus' corax+ test: each check draws a mark,
+; see check.asm. VC holds the result of a check.
+  CLS
+  LD VD, 0
+  LD VE, 0
+
+; 3XNN skips when equal
+  LD V0, 0x2A
+  LD VC, 0
+  SE V0, 0x2A
+  JP skip1
+  LD VC, 1
+skip1:
+  CALL check
+
+; 3XNN does not skip when different
+  LD VC, 1
+  SE V0, 0x2B
+  JP skip2
+  LD VC, 0
+skip2:
+  CALL check
+
+; 4XNN skips when different
+  LD VC, 0
+  SNE V0, 0x2B
+  JP skip3
+  LD VC, 1
+skip3:
+  CALL check
+
+; 4XNN does not skip when equal
+  LD VC, 1
+  SNE V0, 0x2A
+  JP skip4
+  LD VC, 0
+skip4:
+  CALL check
+
+; 5XY0 skips when equal
+  LD V1, 0x2A
+  LD VC, 0
+  SE V0, V1
+  JP skip5
+  LD VC, 1
+skip5:
+  CALL check
+
+; 9XY0 skips when different
+  LD V1, 0x2B
+  LD VC, 0
+  SNE V0, V1
+  JP skip6
+  LD VC, 1
+skip6:
+  CALL check
+
+; 7XNN wraps around
+  LD V0, 0xF0
+  ADD V0, 0x20
+  LD VC, 0
+  SNE V0, 0x10
+  LD VC, 1
+  CALL check
+
+; 8XY0
+  LD V1, 0x55
+  LD V0, V1
+  LD VC, 0
+  SNE V0, 0x55
+  LD VC, 1
+  CALL check
+
+; 8XY1
+  LD V0, 0x0C
+  LD V1, 0x0A
+  OR V0, V1
+  LD VC, 0
+  SNE V0, 0x0E
+  LD VC, 1
+  CALL check
+
+; 8XY2
+  LD V0, 0x0C
+  AND V0, V1
+  LD VC, 0
+  SNE V0, 0x08
+  LD VC, 1
+  CALL check
+
+; 8XY3
+  LD V0, 0x0C
+  XOR V0, V1
+  LD VC, 0
+  SNE V0, 0x06
+  LD VC, 1
+  CALL check
+
+; 8XY4
+  LD V0, 0x10
+  LD V1, 0x20
+  ADD V0, V1
+  LD VC, 0
+  SNE V0, 0x30
+  LD VC, 1
+  CALL check
+
+; 8XY5
+  LD V0, 0x30
+  LD V1, 0x10
+  SUB V0, V1
+  LD VC, 0
+  SNE V0, 0x20
+  LD VC, 1
+  CALL check
+
+; 8XY7
+  LD V0, 0x10
+  LD V1, 0x30
+  SUBN V0, V1
+  LD VC, 0
+  SNE V0, 0x20
+  LD VC, 1
+  CALL check
+
+; 8XY6
+  LD V0, 0x05
+  SHR V0
+  LD VC, 0
+  SNE V0, 0x02
+  LD VC, 1
+  CALL check
+
+; 8XYE
+  LD V0, 0x41
+  SHL V0
+  LD VC, 0
+  SNE V0, 0x82
+  LD VC, 1
+  CALL check
+
+; ANNN, FX1E, FX55 and FX65
+  LD I, 0xE00
+  LD V0, 0x10
+  ADD I, V0
+  LD V0, 0x77
+  LD [I], V0
+  LD V0, 0
+  LD I, 0xE10
+  LD V0, [I]
+  LD VC, 0
+  SNE V0, 0x77
+  LD VC, 1
+  CALL check
+
+; FX33
+  LD V0, 234
+  LD I, 0xE00
+  LD B, V0
+  LD I, 0xE00
+  LD V2, [I]
+  LD VC, 0
+  SE V0, 2
+  JP bcd_done
+  SE V1, 3
+  JP bcd_done
+  SNE V2, 4
+  LD VC, 1
+bcd_done:
+  CALL check
+
+; 2NNN and 00EE
+  LD V0, 0
+  CALL increment
+  CALL increment
+  LD VC, 0
+  SNE V0, 2
+  LD VC, 1
+  CALL check
+
+; 1NNN
+  LD VC, 0
+  JP jump_target
+  JP jump_done
+jump_target:
+  LD VC, 1
+jump_done:
+  CALL check
+
+; BNNN
+  LD V0, 4
+  JP V0, jump_table
+jump_table:
+  LD VC, 0
+  JP jump_table_done
+  LD VC, 1
+jump_table_done:
+  CALL check
+
+; FX29
+  LD V0, 0xA
+  LD F, V0
+  LD V1, [I]
+  LD VC, 0
+  SNE V0, 0xF0
+  LD VC, 1
+  CALL check
+
+; FX15 and FX07
+  LD V0, 0x20
+  LD DT, V0
+  LD V1, DT
+  LD VC, 1
+  SNE V1, 0
+  LD VC, 0
+  CALL check
+
+; CXNN
+  RND V0, 0x0F
+  LD V1, 0xF0
+  AND V1, V0
+  LD VC, 0
+  SNE V1, 0
+  LD VC, 1
+  CALL check
+
+halt:
+  JP halt
+
+increment:
+  ADD V0, 1
+  RET
@@ -0,0 +1,70 @@
+; Quirks detection, in the spirit of Timendus' quirks test: one mark per quirk, a check
+; mark when the quirk is on, see check.asm. In order: shift, memory_increment, jump,
+; vf_reset and clip.
+
+; clip: a sprite crossing the right edge wraps around to x = 0 when clipping is off
+  CLS
+  LD V0, 60
+  LD V1, 0
+  LD I, line
+  DRW V0, V1, 1
+  LD V0, 0
+  LD I, dot
+  DRW V0, V1, 1
+  LD V9, VF
+  CLS
+  LD VD, 0
+  LD VE, 0
+
+; shift: VX is shifted in place instead of VY
+  LD V0, 0x08
+  LD V1, 0x02
+  SHR V0, V1
+  LD VC, 0
+  SNE V0, 0x04
+  LD VC, 1
+  CALL check
+
+; memory_increment: FX55 leaves I after the stored registers
+  LD I, 0xE00
+  LD V0, 0xAA
+  LD V1, 0xBB
+  LD [I], V1
+  LD V0, [I]
+  LD VC, 0
+  SE V0, 0xAA
+  LD VC, 1
+  CALL check
+
+; jump: BXNN jumps to XNN + VX instead of XNN + V0, the table is within 0x200-0x2FF
+  LD V0, 0
+  LD V2, 4
+  JP V0, jump_table
+jump_table:
+  LD VC, 0
+  JP jump_done
+  LD VC, 1
+jump_done:
+  CALL check
+
+; vf_reset: 8XY1, 8XY2 and 8XY3 reset VF
+  LD VF, 0x55
+  OR V0, V1
+  LD VC, 0
+  SNE VF, 0
+  LD VC, 1
+  CALL check
+
+; clip, measured above: VF is set when the sprite wrapped around
+  LD VC, 0
+  SNE V9, 0
+  LD VC, 1
+  CALL check
+
+halt:
+  JP halt
+
+line:
+  db 0xFF
+dot:
+  db 0x80
@@ -0,0 +1,134 @@
+// Timendus' chip8-test-suite (https://github.com/Timendus/chip8-test-suite), whose ROMs are
+// downloaded into tests/suite/ by scripts/fetch-test-suite.sh; the tests are ignored by default
+// and fail without them (`cargo test --test suite -- --ignored`). Each ROM has to run without
+// error, end on the screen recorded in tests/suite-screens/, and the core has to agree with the
+// reference model after every instruction.
+mod common;
+
+use chip8::Quirks;
+use common::{run, screen, IPF};
+use std::path::PathBuf;
+
+// The quirks of the original COSMAC VIP interpreter, which the CHIP-8 tests of the suite expect.
+const COSMAC_VIP: Quirks = Quirks {
+    shift: false,
+    memory_increment: true,
+    jump: false,
+    vf_reset: true,
+    clip: true,
+};
+
+fn path(relative: &str) -> PathBuf {
+    PathBuf::from(env!("CARGO_MANIFEST_DIR")).join(relative)
+}
+
+fn suite_rom(file: &str) -> Vec<u8> {
+    let path = path("tests/suite").join(file);
+    std::fs::read(&path)
+        .unwrap_or_else(|e| panic!("{}: {}, see scripts/fetch-test-suite.sh", path.display(), e))
+}
+
+// Compare the final screen with tests/suite-screens/<name>.txt. With CHIP8_RECORD_SCREENS set,
+// the screen is written there instead, to be checked by hand (every test passed) and committed.
+fn assert_golden(name: &str, actual: &str) {
+    let path = path("tests/suite-screens").join(format!("{}.txt", name));
+    if std::env::var_os("CHIP8_RECORD_SCREENS").is_some() {
+        std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+        std::fs::write(&path, actual).unwrap();
+        return;
+    }
+    let expected = std::fs::read_to_string(&path).unwrap_or_else(|e| {
+        panic!(
+            "{}: {}, record it with CHIP8_RECORD_SCREENS=1, screen:\n{}",
+            path.display(),
+            e,
+            actual
+        )
+    });
+    assert!(
+        actual == expected,
+        "{}: screen:\n{}\nexpected:\n{}",
+        name,
+        actual,
+        expected
+    );
+}
+
+// Run a ROM of the suite for `frames` frames, `press` holding a key for a few frames at the
+// given frames, e.g. to choose an entry of a menu; `name` is the one of the golden screen.
+fn check(name: &str, file: &str, quirks: Quirks, frames: u32, press: &[(u32, usize)]) {
+    let rom = suite_rom(file);
+    let held = |frame: u32| -> Vec<usize> {
+        press
+            .iter()
+            .filter(|&&(at, _)| (at..at + 5).contains(&frame))
+            .map(|&(_, key)| key)
+            .collect()
+    };
+    let chip8 = run(&rom, quirks, frames, held);
+    assert_golden(name, &screen(&chip8));
+
+    let keys: Vec<u16> = (0..frames)
+        .map(|frame| held(frame).iter().fold(0, |mask, key| mask | 1 << key))
+        .collect();
+    if let Err(difference) = chip8::compare_with_reference(&rom, quirks, 0, IPF, &keys) {
+        panic!("{}: {}", file, difference);
+    }
+}
+
+#[test]
+#[ignore = "needs the ROMs of scripts/fetch-test-suite.sh"]
+fn chip8_logo() {
+    check("chip8-logo", "1-chip8-logo.ch8", COSMAC_VIP, 60, &[]);
+}
+
+#[test]
+#[ignore = "needs the ROMs of scripts/fetch-test-suite.sh"]
+fn ibm_logo() {
+    check("ibm-logo", "2-ibm-logo.ch8", COSMAC_VIP, 60, &[]);
+}
+
+#[test]
+#[ignore = "needs the ROMs of scripts/fetch-test-suite.sh"]
+fn corax_plus() {
+    check("corax+", "3-corax+.ch8", COSMAC_VIP, 60, &[]);
+}
+
+#[test]
+#[ignore = "needs the ROMs of scripts/fetch-test-suite.sh"]
+fn flags() {
+    check("flags", "4-flags.ch8", COSMAC_VIP, 60, &[]);
+}
+
+#[test]
+#[ignore = "needs the ROMs of scripts/fetch-test-suite.sh"]
+fn quirks() {
+    // 1 chooses CHIP-8 in the menu
+    check(
+        "quirks-cosmac-vip",
+        "5-quirks.ch8",
+        COSMAC_VIP,
+        600,
+        &[(10, 1)],
+    );
+    check(
+        "quirks-default",
+        "5-quirks.ch8",
+        Quirks::default(),
+        600,
+        &[(10, 1)],
+    );
+}
+
+#[test]
+#[ignore = "needs the ROMs of scripts/fetch-test-suite.sh"]
+fn keypad() {
+    // 3 chooses the FX0A test in the menu, which then waits for a key
+    check(
+        "keypad",
+        "6-keypad.ch8",
+        COSMAC_VIP,
+        120,
+        &[(10, 3), (60, 5)],
+    );
+}